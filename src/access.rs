@@ -0,0 +1,95 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Options for low-vision, motion sensitive and keyboard-only users.
+
+use piston_window::{Context, G2d, Rectangle};
+
+#[derive(Debug, Default)]
+pub struct Access {
+    // A thick yellow on black frame around the image under the cursor.
+    pub outline: bool,
+
+    // Animated images stay on their first frame.
+    pub reduced_motion: bool,
+
+    // Print the path of each newly focused image to stdout, for screen readers and scripts.
+    pub announce: bool,
+
+    announced: Option<String>,
+}
+
+impl Access {
+    pub fn new(outline: bool, reduced_motion: bool, announce: bool) -> Self {
+        Self {
+            outline,
+            reduced_motion,
+            announce,
+            announced: None,
+        }
+    }
+
+    // The path to announce when the focus moved to another image.
+    fn announcement(&mut self, path: Option<&str>) -> Option<String> {
+        if self.announced.as_deref() == path {
+            return None;
+        }
+        self.announced = path.map(str::to_owned);
+        self.announced.clone()
+    }
+
+    pub fn announce(&mut self, path: Option<&str>) {
+        if let Some(path) = self.announcement(path) {
+            println!("{}", path);
+        }
+    }
+}
+
+// Frame the grid square at `pos` with sides of `size`, `scale` times as thick as by default.
+pub fn draw_outline(pos: [f64; 2], size: f64, scale: f64, c: &Context, g: &mut G2d) {
+    let rect = [pos[0], pos[1], size, size];
+    Rectangle::new_border([0.0, 0.0, 0.0, 1.0], 4.0 * scale).draw(
+        rect,
+        &c.draw_state,
+        c.transform,
+        g,
+    );
+    Rectangle::new_border([1.0, 0.92, 0.0, 1.0], 2.0 * scale).draw(
+        rect,
+        &c.draw_state,
+        c.transform,
+        g,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcement() {
+        let mut access = Access::default();
+        assert_eq!(access.announcement(None), None);
+        assert_eq!(
+            access.announcement(Some("/a.jpg")),
+            Some("/a.jpg".to_owned())
+        );
+        assert_eq!(access.announcement(Some("/a.jpg")), None);
+        assert_eq!(access.announcement(None), None);
+        assert_eq!(
+            access.announcement(Some("/a.jpg")),
+            Some("/a.jpg".to_owned())
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Viewing adjustments applied when drawing and uploading textures, these never touch the image
+// files or the thumbnail cache.
+
+use ::image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Adjustments {
+    // Exposure compensation in stops.
+    pub exposure: f64,
+
+    // Added to values, -1 to 1.
+    pub brightness: f64,
+
+    // Scales values around mid gray, 1 is unchanged.
+    pub contrast: f64,
+
+    // Values are raised to 1 / gamma, 1 is unchanged.
+    pub gamma: f64,
+
+    // Scales distance from gray, 0 is monochrome and 1 unchanged.
+    pub saturation: f64,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // RGBA color the textures are multiplied by.
+    pub fn color(&self) -> [f32; 4] {
+        let k = self.exposure.exp2() as f32;
+        [k, k, k, 1.0]
+    }
+
+    // The adjustments `apply` uses, exposure is applied with `color` instead.
+    pub fn tone(&self) -> Self {
+        Self {
+            exposure: 0.0,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_tone_default(&self) -> bool {
+        self.tone().is_default()
+    }
+
+    pub fn clamp(&mut self) {
+        self.brightness = self.brightness.clamp(-1.0, 1.0);
+        self.contrast = self.contrast.clamp(0.0, 4.0);
+        self.gamma = self.gamma.clamp(0.1, 10.0);
+        self.saturation = self.saturation.clamp(0.0, 4.0);
+    }
+
+    // Non-default values, for the window title, with names translated by `tr`.
+    pub fn describe(&self, tr: impl Fn(&str) -> &str) -> String {
+        let default = Self::default();
+        let values = [
+            ("exposure", self.exposure, default.exposure),
+            ("brightness", self.brightness, default.brightness),
+            ("contrast", self.contrast, default.contrast),
+            ("gamma", self.gamma, default.gamma),
+            ("saturation", self.saturation, default.saturation),
+        ];
+        let changed: Vec<String> = values
+            .iter()
+            .filter(|(_, value, default)| value != default)
+            .map(|(name, value, _)| format!("{} {:.2}", tr(name), value))
+            .collect();
+        if changed.is_empty() {
+            tr("no adjustments").to_owned()
+        } else {
+            changed.join(", ")
+        }
+    }
+
+    // Brightness, contrast, gamma and saturation, applied to tiles as they're loaded.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let lut: Vec<f32> = (0..256)
+            .map(|v| {
+                let v = (v as f64 / 255.0).powf(1.0 / self.gamma);
+                let v = (v - 0.5) * self.contrast + 0.5 + self.brightness;
+                v.clamp(0.0, 1.0) as f32
+            })
+            .collect();
+
+        let saturation = self.saturation as f32;
+        for ::image::Rgba(p) in image.pixels_mut() {
+            let [r, g, b] = [p[0], p[1], p[2]].map(|v| lut[v as usize]);
+            let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            let [r, g, b] = [r, g, b].map(|v| y + (v - y) * saturation);
+            p[0] = (r.clamp(0.0, 1.0) * 255.0).round() as u8;
+            p[1] = (g.clamp(0.0, 1.0) * 255.0).round() as u8;
+            p[2] = (b.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(adjustments: &Adjustments, rgba: [u8; 4]) -> [u8; 4] {
+        let mut image = RgbaImage::from_pixel(1, 1, ::image::Rgba(rgba));
+        adjustments.apply(&mut image);
+        image.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn tone() {
+        let mut adjustments = Adjustments {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        assert!(adjustments.is_tone_default());
+        assert_eq!(apply(&adjustments, [10, 128, 250, 7]), [10, 128, 250, 7]);
+
+        adjustments.brightness = 0.1;
+        assert!(!adjustments.is_tone_default());
+
+        assert_eq!(apply(&adjustments, [0, 128, 250, 7]), [25, 154, 255, 7]);
+
+        let adjustments = Adjustments {
+            contrast: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(apply(&adjustments, [20, 100, 200, 255]), [0, 86, 236, 255]);
+
+        let adjustments = Adjustments {
+            saturation: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(apply(&adjustments, [255, 0, 0, 255]), [54, 54, 54, 255]);
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Frames of animated GIF, PNG and WebP images.
+
+use crate::{E, R};
+use ::image::codecs::gif::GifDecoder;
+use ::image::codecs::png::PngDecoder;
+use ::image::codecs::webp::WebPDecoder;
+use ::image::{AnimationDecoder, DynamicImage, Frames};
+use std::io::{BufReader, Cursor};
+
+// Frames are kept small, every frame of a visible animation stays loaded.
+pub static MAX_FRAME_SIZE: u32 = 512;
+pub static MAX_FRAMES: usize = 128;
+
+// Browsers treat very short delays as unset.
+static MIN_DELAY_MS: u32 = 20;
+static DEFAULT_DELAY_MS: u32 = 100;
+
+pub type Frame = (DynamicImage, u32);
+
+fn delay_ms(frame: &::image::Frame) -> u32 {
+    let (num, den) = frame.delay().numer_denom_ms();
+    let ms = num.checked_div(den).unwrap_or(0);
+    if ms < MIN_DELAY_MS {
+        DEFAULT_DELAY_MS
+    } else {
+        ms
+    }
+}
+
+fn decode(path: &str) -> R<Option<Frames<'static>>> {
+    let lower = path.to_lowercase();
+    let open = || {
+        std::fs::File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| E::ImageError(e.into()))
+    };
+
+    Ok(if lower.ends_with(".gif") {
+        Some(
+            GifDecoder::new(open()?)
+                .map_err(E::ImageError)?
+                .into_frames(),
+        )
+    } else if lower.ends_with(".webp") {
+        let decoder = WebPDecoder::new(open()?).map_err(E::ImageError)?;
+        if !decoder.has_animation() {
+            return Ok(None);
+        }
+        Some(decoder.into_frames())
+    } else if lower.ends_with(".png") || lower.ends_with(".apng") {
+        let decoder = PngDecoder::new(open()?).map_err(E::ImageError)?;
+        if !decoder.is_apng() {
+            return Ok(None);
+        }
+        Some(decoder.apng().into_frames())
+    } else {
+        None
+    })
+}
+
+// Whether the header allows for frames, without decoding any. GIFs only tell by decoding them.
+pub fn may_animate(path: &str, bytes: &[u8]) -> bool {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".gif") {
+        true
+    } else if lower.ends_with(".webp") {
+        // The VP8X chunk's animation flag, the decoder reads the whole image to tell.
+        bytes.get(12..16) == Some(b"VP8X") && bytes.get(20).is_some_and(|f| f & 0x02 != 0)
+    } else if lower.ends_with(".png") || lower.ends_with(".apng") {
+        PngDecoder::new(Cursor::new(bytes)).is_ok_and(|decoder| decoder.is_apng())
+    } else {
+        false
+    }
+}
+
+// Downscaled frames and their delays, None for still images.
+pub fn frames(path: &str) -> R<Option<Vec<Frame>>> {
+    let frames = match decode(path)? {
+        Some(frames) => frames,
+        None => return Ok(None),
+    };
+
+    let mut ret = Vec::new();
+    for frame in frames.take(MAX_FRAMES) {
+        let frame = frame.map_err(E::ImageError)?;
+        let delay = delay_ms(&frame);
+        let image = DynamicImage::ImageRgba8(frame.into_buffer());
+        let image = if image.width() > MAX_FRAME_SIZE || image.height() > MAX_FRAME_SIZE {
+            image.thumbnail(MAX_FRAME_SIZE, MAX_FRAME_SIZE)
+        } else {
+            image
+        };
+        ret.push((image, delay));
+    }
+
+    // Single frame GIFs are still images.
+    if ret.len() < 2 {
+        return Ok(None);
+    }
+
+    Ok(Some(ret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{Delay, RgbaImage};
+
+    #[test]
+    fn delays() {
+        let frame = |ms| {
+            ::image::Frame::from_parts(
+                RgbaImage::new(1, 1),
+                0,
+                0,
+                Delay::from_numer_denom_ms(ms, 1),
+            )
+        };
+        assert_eq!(delay_ms(&frame(0)), DEFAULT_DELAY_MS);
+        assert_eq!(delay_ms(&frame(10)), DEFAULT_DELAY_MS);
+        assert_eq!(delay_ms(&frame(40)), 40);
+    }
+
+    #[test]
+    fn animated_headers() {
+        let mut png = Cursor::new(Vec::new());
+        RgbaImage::new(2, 2)
+            .write_to(&mut png, ::image::ImageOutputFormat::Png)
+            .unwrap();
+        assert!(!may_animate("a.png", png.get_ref()));
+        assert!(may_animate("a.gif", b""));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\0".to_vec();
+        assert!(!may_animate("a.webp", &webp));
+        webp[20] = 0x02;
+        assert!(may_animate("a.webp", &webp));
+    }
+}
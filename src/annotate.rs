@@ -0,0 +1,251 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Review marks drawn over the single image view, saved next to the image in a JSON sidecar so
+// the original is never touched.
+
+use log::*;
+use piston_window::{line_from_to, Context, G2d};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+static COLOR: [f32; 4] = [1.0, 0.2, 0.4, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Pen,
+    Arrow,
+    Text,
+}
+
+impl Tool {
+    // Cycle through the tools and back to off.
+    pub fn next(tool: Option<Self>) -> Option<Self> {
+        match tool {
+            None => Some(Tool::Pen),
+            Some(Tool::Pen) => Some(Tool::Arrow),
+            Some(Tool::Arrow) => Some(Tool::Text),
+            Some(Tool::Text) => None,
+        }
+    }
+}
+
+// Positions are in image pixels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Annotation {
+    Stroke(Vec<[f64; 2]>),
+    Arrow([f64; 2], [f64; 2]),
+    Note([f64; 2], String),
+}
+
+#[derive(Debug, Default)]
+pub struct Layer {
+    sidecar: PathBuf,
+    items: Vec<Annotation>,
+
+    // Being drawn or typed.
+    current: Option<Annotation>,
+}
+
+fn sidecar(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", path, crate::SIDECAR_SUFFIX))
+}
+
+impl Layer {
+    pub fn load(path: &str) -> Self {
+        let sidecar = sidecar(path);
+
+        let items = match std::fs::read(&sidecar) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                error!("unable to parse {:?}: {:?}", sidecar, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        Self {
+            sidecar,
+            items,
+            current: None,
+        }
+    }
+
+    fn save(&self) {
+        let res = if self.items.is_empty() {
+            match std::fs::remove_file(&self.sidecar) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                res => res,
+            }
+        } else {
+            let json = serde_json::to_vec_pretty(&self.items).expect("annotations to json");
+            std::fs::write(&self.sidecar, json)
+        };
+
+        if let Err(e) = res {
+            error!("unable to save {:?}: {:?}", self.sidecar, e);
+        }
+    }
+
+    pub fn begin(&mut self, tool: Tool, pos: [f64; 2]) {
+        self.commit();
+        self.current = Some(match tool {
+            Tool::Pen => Annotation::Stroke(vec![pos]),
+            Tool::Arrow => Annotation::Arrow(pos, pos),
+            Tool::Text => Annotation::Note(pos, String::new()),
+        });
+    }
+
+    pub fn drag(&mut self, pos: [f64; 2]) {
+        match &mut self.current {
+            Some(Annotation::Stroke(points)) => points.push(pos),
+            Some(Annotation::Arrow(_, end)) => *end = pos,
+            _ => {}
+        }
+    }
+
+    // Finish a stroke or arrow when the mouse button is released, notes wait for Enter.
+    pub fn end(&mut self) {
+        if !self.is_typing() {
+            self.commit();
+        }
+    }
+
+    pub fn is_typing(&self) -> bool {
+        matches!(self.current, Some(Annotation::Note(..)))
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        if let Some(Annotation::Note(_, note)) = &mut self.current {
+            // Enter and Backspace also arrive as text on some platforms.
+            note.extend(text.chars().filter(|c| !c.is_control()));
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(Annotation::Note(_, note)) = &mut self.current {
+            note.pop();
+        }
+    }
+
+    // Keep the current annotation, dropping empty notes and clicks.
+    pub fn commit(&mut self) {
+        let keep = match &self.current {
+            Some(Annotation::Stroke(points)) => points.len() > 1,
+            Some(Annotation::Arrow(start, end)) => start != end,
+            Some(Annotation::Note(_, note)) => !note.is_empty(),
+            None => false,
+        };
+        if let Some(annotation) = self.current.take() {
+            if keep {
+                self.items.push(annotation);
+                self.save();
+            }
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if self.current.take().is_none() && self.items.pop().is_some() {
+            self.save();
+        }
+    }
+
+    // Draw the marks, returns the notes as window positions and text for the caller to render.
+    pub fn draw(
+        &self,
+        origin: [f64; 2],
+        scale: f64,
+        c: &Context,
+        g: &mut G2d,
+    ) -> Vec<([f64; 2], String)> {
+        let to_window = |[x, y]: [f64; 2]| [origin[0] + x * scale, origin[1] + y * scale];
+        let mut notes = Vec::new();
+
+        for annotation in self.items.iter().chain(&self.current) {
+            match annotation {
+                Annotation::Stroke(points) => {
+                    for pair in points.windows(2) {
+                        let (from, to) = (to_window(pair[0]), to_window(pair[1]));
+                        line_from_to(COLOR, 1.5, from, to, c.transform, g);
+                    }
+                }
+                Annotation::Arrow(start, end) => {
+                    let (start, end) = (to_window(*start), to_window(*end));
+                    line_from_to(COLOR, 1.5, start, end, c.transform, g);
+
+                    let angle = (start[1] - end[1]).atan2(start[0] - end[0]);
+                    for side in [-0.5, 0.5] {
+                        let head = [
+                            end[0] + 12.0 * (angle + side).cos(),
+                            end[1] + 12.0 * (angle + side).sin(),
+                        ];
+                        line_from_to(COLOR, 1.5, end, head, c.transform, g);
+                    }
+                }
+                Annotation::Note(pos, note) => {
+                    let text = if Some(annotation) == self.current.as_ref() {
+                        format!("{}_", note)
+                    } else {
+                        note.clone()
+                    };
+                    notes.push((to_window(*pos), text));
+                }
+            }
+        }
+
+        notes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit() {
+        let mut layer = Layer {
+            sidecar: std::env::temp_dir().join(format!("pix-annotate-{}.json", std::process::id())),
+            ..Default::default()
+        };
+
+        // Clicks without dragging are dropped.
+        layer.begin(Tool::Pen, [1.0, 1.0]);
+        layer.end();
+        assert!(layer.items.is_empty());
+
+        layer.begin(Tool::Arrow, [1.0, 1.0]);
+        layer.drag([5.0, 5.0]);
+        layer.end();
+
+        layer.begin(Tool::Text, [2.0, 2.0]);
+        layer.end();
+        assert!(layer.is_typing());
+        layer.type_text("ok?");
+        layer.backspace();
+        layer.commit();
+
+        let items = vec![
+            Annotation::Arrow([1.0, 1.0], [5.0, 5.0]),
+            Annotation::Note([2.0, 2.0], String::from("ok")),
+        ];
+        assert_eq!(layer.items, items);
+
+        let json = std::fs::read(&layer.sidecar).unwrap();
+        let saved: Vec<Annotation> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(saved, items);
+
+        layer.undo();
+        layer.undo();
+        assert!(!layer.sidecar.exists());
+    }
+}
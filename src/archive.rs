@@ -0,0 +1,195 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Cover images of ebooks (EPUB) and comic book archives (CBZ), and the merged image of zipped
+// layered documents (Krita, OpenRaster).
+
+use crate::{E, R};
+use std::io::Read;
+
+type Archive = zip::ZipArchive<std::io::BufReader<std::fs::File>>;
+
+// Largest entry decompressed into memory, the sizes in the zip headers aren't trusted.
+static MAX_ENTRY_SIZE: u64 = 256 << 20;
+
+// Ebooks and comics, these are read in other applications.
+pub fn is_book(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".epub") || path.ends_with(".cbz")
+}
+
+pub fn is_layered(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".kra") || path.ends_with(".ora")
+}
+
+pub fn is_archive(path: &str) -> bool {
+    is_book(path) || is_layered(path)
+}
+
+fn is_image(name: &str) -> bool {
+    let name = name.to_lowercase();
+    [".jpg", ".jpeg", ".png", ".gif", ".webp", ".bmp"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+fn read(archive: &mut Archive, name: &str) -> R<Vec<u8>> {
+    let entry = archive.by_name(name).map_err(E::ArchiveError)?;
+    let mut buf = Vec::new();
+    entry
+        .take(MAX_ENTRY_SIZE + 1)
+        .read_to_end(&mut buf)
+        .map_err(|e| E::ArchiveError(e.into()))?;
+    if buf.len() as u64 > MAX_ENTRY_SIZE {
+        return Err(E::ArchiveError(zip::result::ZipError::InvalidArchive(
+            "entry too large",
+        )));
+    }
+    Ok(buf)
+}
+
+// Value of attribute `name` within a single xml tag.
+fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!("{}=", name);
+    let mut rest = tag;
+    while let Some(i) = rest.find(&needle) {
+        let preceded_by_space = rest[..i].ends_with(char::is_whitespace);
+        rest = &rest[i + needle.len()..];
+        let quote = rest.chars().next()?;
+        if !preceded_by_space || (quote != '"' && quote != '\'') {
+            continue;
+        }
+        let end = rest[1..].find(quote)?;
+        return Some(&rest[1..1 + end]);
+    }
+    None
+}
+
+// Xml tags named `name`, without the angle brackets.
+fn tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    xml.split('<')
+        .filter(move |tag| {
+            tag.strip_prefix(name)
+                .and_then(|rest| rest.chars().next())
+                .is_some_and(char::is_whitespace)
+        })
+        .map(|tag| tag.split('>').next().unwrap_or(tag))
+}
+
+// Find the cover href in an EPUB package document, either EPUB3 `properties="cover-image"` or
+// the EPUB2 `<meta name="cover">` convention.
+fn opf_cover(opf: &str) -> Option<&str> {
+    let items = || tags(opf, "item");
+
+    if let Some(item) = items().find(|item| {
+        attr(item, "properties").is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+    }) {
+        return attr(item, "href");
+    }
+
+    let id = tags(opf, "meta")
+        .find(|meta| attr(meta, "name") == Some("cover"))
+        .and_then(|meta| attr(meta, "content"))?;
+
+    items()
+        .find(|item| attr(item, "id") == Some(id))
+        .and_then(|item| attr(item, "href"))
+}
+
+fn epub_cover(archive: &mut Archive) -> R<Option<String>> {
+    let container = read(archive, "META-INF/container.xml")?;
+    let container = String::from_utf8_lossy(&container);
+
+    let opf_path = match tags(&container, "rootfile").find_map(|tag| attr(tag, "full-path")) {
+        Some(opf_path) => opf_path.to_owned(),
+        None => return Ok(None),
+    };
+
+    let opf = read(archive, &opf_path)?;
+    let opf = String::from_utf8_lossy(&opf);
+
+    Ok(opf_cover(&opf).map(|href| {
+        // Hrefs are relative to the package document.
+        match opf_path.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/{}", dir, href),
+            None => href.to_owned(),
+        }
+    }))
+}
+
+// Fall back to an image named like a cover, or the first image.
+fn first_image(archive: &Archive) -> Option<String> {
+    let mut names: Vec<&str> = archive.file_names().filter(|n| is_image(n)).collect();
+    names.sort();
+
+    names
+        .iter()
+        .find(|name| name.to_lowercase().contains("cover"))
+        .or_else(|| names.first())
+        .map(|name| name.to_string())
+}
+
+pub fn cover(path: &str) -> R<::image::DynamicImage> {
+    let file = std::fs::File::open(path).map_err(|e| E::ArchiveError(e.into()))?;
+    let mut archive =
+        zip::ZipArchive::new(std::io::BufReader::new(file)).map_err(E::ArchiveError)?;
+
+    let mut name = None;
+    if path.to_lowercase().ends_with(".epub") {
+        name = epub_cover(&mut archive).unwrap_or(None);
+    } else if is_layered(path) {
+        name = Some(String::from("mergedimage.png"));
+    }
+
+    let name = match name.or_else(|| first_image(&archive)) {
+        Some(name) => name,
+        None => return Err(E::MissingData(path.to_owned())),
+    };
+
+    let data = read(&mut archive, &name)?;
+    ::image::load_from_memory(&data).map_err(E::ImageError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epub3_cover() {
+        let opf = r#"<manifest>
+            <item id="c" href="text/c.xhtml" media-type="application/xhtml+xml"/>
+            <item id="img" href="images/cover.jpg" properties="cover-image"/>
+        </manifest>"#;
+        assert_eq!(opf_cover(opf), Some("images/cover.jpg"));
+    }
+
+    #[test]
+    fn epub2_cover() {
+        let opf = r#"<metadata><meta name="cover" content="cover-img"/></metadata>
+            <manifest>
+            <item id="cover" href="cover.xhtml"/>
+            <item href='front.png' id='cover-img'/>
+            </manifest>"#;
+        assert_eq!(opf_cover(opf), Some("front.png"));
+    }
+
+    #[test]
+    fn no_cover() {
+        assert_eq!(
+            opf_cover("<manifest><item id=\"a\" href=\"a.xhtml\"/></manifest>"),
+            None
+        );
+    }
+}
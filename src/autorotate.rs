@@ -0,0 +1,380 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix autorotate`, turns the pixels of images the way their EXIF orientation says and resets
+// the tag, for software that ignores it.
+
+use crate::database::Database;
+use crate::thumbnailer::{Settings, Thumbnailer};
+use crate::{File, E, R};
+use ::image::{DynamicImage, ImageFormat};
+use log::*;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct Options {
+    // Only list the images that would be turned.
+    pub dry_run: bool,
+
+    // Let jpegtran drop the edge pixels of JPEGs that can't be turned losslessly.
+    pub trim: bool,
+
+    // Encode PNGs and single-page TIFFs again, losing their ICC profile and metadata.
+    pub reencode: bool,
+}
+
+// EXIF orientation of an image, 1 when it has none.
+pub fn orientation(bytes: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+                .value
+                .get_uint(0)
+        })
+        .unwrap_or(1)
+}
+
+// The jpegtran transform undoing an orientation.
+fn jpegtran_args(orientation: u32) -> &'static [&'static str] {
+    match orientation {
+        2 => &["-flip", "horizontal"],
+        3 => &["-rotate", "180"],
+        4 => &["-flip", "vertical"],
+        5 => &["-transpose"],
+        6 => &["-rotate", "90"],
+        7 => &["-transverse"],
+        8 => &["-rotate", "270"],
+        _ => &[],
+    }
+}
+
+// The pixels of `image` the way an orientation says to show them.
+pub fn apply(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// Set the orientation in the EXIF of a JPEG to 1, false if there is none.
+fn reset_jpeg_orientation(bytes: &mut [u8]) -> bool {
+    let mut pos = 2;
+    while let (Some(0xff), Some(&marker)) = (bytes.get(pos), bytes.get(pos + 1)) {
+        // The image data follows the start of scan.
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+        let len = match bytes.get(pos + 2..pos + 4) {
+            Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+            None => break,
+        };
+        let end = usize::min(pos + 2 + len, bytes.len());
+        if marker == 0xe1 && bytes.get(pos + 4..pos + 10) == Some(b"Exif\0\0") {
+            return reset_tiff_orientation(&mut bytes[pos + 10..end]);
+        }
+        pos = end;
+    }
+    false
+}
+
+// An unsigned integer of `len` bytes at `i`.
+fn uint_at(tiff: &[u8], i: usize, len: usize, le: bool) -> Option<usize> {
+    let bytes = tiff.get(i..i + len)?;
+    let fold = |n: usize, &b: &u8| n << 8 | b as usize;
+    Some(if le {
+        bytes.iter().rev().fold(0, fold)
+    } else {
+        bytes.iter().fold(0, fold)
+    })
+}
+
+fn reset_tiff_orientation(tiff: &mut [u8]) -> bool {
+    let le = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return false,
+    };
+    let entry = uint_at(tiff, 4, 4, le).and_then(|ifd| {
+        let count = uint_at(tiff, ifd, 2, le)?;
+        (0..count)
+            .map(|n| ifd + 2 + n * 12)
+            .find(|&entry| uint_at(tiff, entry, 2, le) == Some(0x0112))
+    });
+    let one = if le {
+        1u16.to_le_bytes()
+    } else {
+        1u16.to_be_bytes()
+    };
+    match entry.and_then(|entry| tiff.get_mut(entry + 8..entry + 10)) {
+        Some(value) => {
+            value.copy_from_slice(&one);
+            true
+        }
+        None => false,
+    }
+}
+
+// Whether a TIFF has more than one page, which encoding it again would drop.
+fn multi_page_tiff(bytes: &[u8]) -> R<bool> {
+    let decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(bytes)).map_err(E::TiffError)?;
+    Ok(decoder.more_images())
+}
+
+// The contents of `path` upright. JPEGs are transformed losslessly by jpegtran, with `trim`
+// dropping the edge blocks that don't fit when the size isn't a multiple of the block size. PNGs
+// and single-page TIFFs are only decoded and encoded again with `reencode`, losing their
+// metadata and with it the orientation.
+fn upright(path: &str, bytes: &[u8], orientation: u32, options: &Options) -> R<Vec<u8>> {
+    let format = ImageFormat::from_path(path).map_err(E::ImageError)?;
+    match format {
+        ImageFormat::Jpeg => {
+            let jpegtran = |edges: &str| {
+                Command::new("jpegtran")
+                    .args(["-copy", "all", edges])
+                    .args(jpegtran_args(orientation))
+                    .arg(path)
+                    .output()
+                    .map_err(|e| E::CommandError(format!("jpegtran: {}", e)))
+            };
+            let mut output = jpegtran("-perfect")?;
+            if !output.status.success() {
+                if !options.trim {
+                    return Err(E::CommandError(String::from(
+                        "can't be turned losslessly, --trim drops its edge pixels",
+                    )));
+                }
+                warn!("{:?} can't be turned perfectly, trimming its edges", path);
+                output = jpegtran("-trim")?;
+            }
+            if !output.status.success() {
+                return Err(E::CommandError(format!(
+                    "jpegtran: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+            let mut bytes = output.stdout;
+            reset_jpeg_orientation(&mut bytes);
+            Ok(bytes)
+        }
+        ImageFormat::Png | ImageFormat::Tiff => {
+            if !options.reencode {
+                return Err(E::CommandError(String::from(
+                    "can't be turned losslessly, --reencode encodes it again without metadata",
+                )));
+            }
+            if format == ImageFormat::Tiff && multi_page_tiff(bytes)? {
+                return Err(E::CommandError(String::from(
+                    "encoding it again would drop all pages but the first",
+                )));
+            }
+            let image =
+                ::image::load_from_memory_with_format(bytes, format).map_err(E::ImageError)?;
+            let mut out = std::io::Cursor::new(Vec::new());
+            apply(image, orientation)
+                .write_to(&mut out, format)
+                .map_err(E::ImageError)?;
+            Ok(out.into_inner())
+        }
+        _ => Err(E::CommandError(format!("can't rewrite {:?} files", format))),
+    }
+}
+
+// Replace the file next to it first so it's never left half written, with the permissions of
+// the original.
+fn rewrite(path: &str, bytes: &[u8]) -> R<()> {
+    let permissions = std::fs::metadata(path).map_err(E::IoError)?.permissions();
+    let tmp = format!("{}.pix-tmp", path);
+    std::fs::write(&tmp, bytes)
+        .and_then(|_| std::fs::set_permissions(&tmp, permissions))
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            E::IoError(e)
+        })
+}
+
+// Make the thumbnails of `files` and wait until they are written.
+fn thumbnail(db: &Arc<Database>, files: Vec<Arc<File>>, settings: Settings) {
+    let uid_base = db.reserve(files.len());
+    let mut thumbnailer = Thumbnailer::new(Arc::clone(db), uid_base, num_cpus::get(), settings);
+
+    let (mut queued, mut done) = (0, 0);
+    while done < files.len() {
+        while queued < files.len() && !thumbnailer.is_full() {
+            thumbnailer.make_thumbs(queued, &files[queued]);
+            queued += 1;
+        }
+        let results = thumbnailer.recv();
+        if results.is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        for (i, res) in results {
+            if let Err(e) = res {
+                error!("thumbnail {:?}: {}", files[i].path, e);
+            }
+            done += 1;
+        }
+    }
+    thumbnailer.shutdown();
+}
+
+// Turn every rotated or mirrored image of `files` upright and thumbnail it again, returns how
+// many were fixed.
+pub fn run(
+    db: &Arc<Database>,
+    files: Vec<Arc<File>>,
+    options: &Options,
+    settings: Settings,
+) -> usize {
+    let mut fixed = Vec::new();
+    for file in files {
+        let bytes = match std::fs::read(&file.path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("read {:?}: {}", file.path, e);
+                continue;
+            }
+        };
+        let orientation = orientation(&bytes);
+        if orientation <= 1 || orientation > 8 {
+            continue;
+        }
+        if options.dry_run {
+            println!("{}: orientation {}", file.path, orientation);
+            continue;
+        }
+
+        let res = upright(&file.path, &bytes, orientation, options).and_then(|bytes| {
+            rewrite(&file.path, &bytes)?;
+            File::stat(&file.path).map_err(E::IoError)
+        });
+        match res {
+            Ok(file) => {
+                println!("{}: turned upright", file.path);
+                fixed.push(Arc::new(file));
+            }
+            Err(e) => error!("autorotate {:?}: {}", file.path, e),
+        }
+    }
+
+    let n = fixed.len();
+    if n > 0 {
+        thumbnail(db, fixed, settings);
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{GenericImageView, Rgba, RgbaImage};
+
+    // A JPEG header with an EXIF orientation and no image data.
+    fn jpeg(le: bool, orientation: u16) -> Vec<u8> {
+        let u16_bytes = |v: u16| if le { v.to_le_bytes() } else { v.to_be_bytes() };
+        let mut tiff: Vec<u8> = if le { b"II".to_vec() } else { b"MM".to_vec() };
+        tiff.extend(u16_bytes(42));
+        tiff.extend(if le {
+            8u32.to_le_bytes()
+        } else {
+            8u32.to_be_bytes()
+        });
+        tiff.extend(u16_bytes(1));
+        // Orientation, a short, one of them.
+        tiff.extend(u16_bytes(0x0112));
+        tiff.extend(u16_bytes(3));
+        tiff.extend(if le {
+            1u32.to_le_bytes()
+        } else {
+            1u32.to_be_bytes()
+        });
+        tiff.extend(u16_bytes(orientation));
+        tiff.extend([0, 0, 0, 0, 0, 0]);
+
+        let mut bytes = vec![0xff, 0xd8, 0xff, 0xe1];
+        bytes.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        bytes.extend(b"Exif\0\0");
+        bytes.extend(tiff);
+        bytes.extend([0xff, 0xd9]);
+        bytes
+    }
+
+    #[test]
+    fn reset() {
+        for le in [true, false] {
+            let mut bytes = jpeg(le, 6);
+            assert_eq!(orientation(&bytes), 6);
+            assert!(reset_jpeg_orientation(&mut bytes));
+            assert_eq!(orientation(&bytes), 1);
+        }
+
+        let mut bytes = vec![0xff, 0xd8, 0xff, 0xd9];
+        assert!(!reset_jpeg_orientation(&mut bytes));
+        assert_eq!(orientation(&bytes), 1);
+    }
+
+    #[test]
+    fn refused() {
+        let options = Options {
+            dry_run: false,
+            trim: false,
+            reencode: false,
+        };
+        assert!(upright("/a.png", b"", 6, &options).is_err());
+
+        // Two pages, only the first would be kept.
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        {
+            let mut tiff = tiff::encoder::TiffEncoder::new(&mut bytes).unwrap();
+            for _ in 0..2 {
+                tiff.write_image::<tiff::encoder::colortype::Gray8>(1, 1, &[0])
+                    .unwrap();
+            }
+        }
+        let bytes = bytes.into_inner();
+        assert!(multi_page_tiff(&bytes).unwrap());
+        let options = Options {
+            reencode: true,
+            ..options
+        };
+        assert!(upright("/a.tif", &bytes, 6, &options).is_err());
+    }
+
+    #[test]
+    fn turned() {
+        // Red on the left, blue on the right, as stored.
+        let mut stored = RgbaImage::new(2, 1);
+        stored.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        stored.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+        let stored = DynamicImage::ImageRgba8(stored);
+
+        // Orientation 6 is shown turned clockwise, red on top.
+        let upright = apply(stored.clone(), 6);
+        assert_eq!(upright.dimensions(), (1, 2));
+        assert_eq!(upright.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+
+        // Orientation 7 is mirrored and turned counterclockwise, red at the bottom.
+        let upright = apply(stored, 7);
+        assert_eq!(upright.get_pixel(0, 1), Rgba([255, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix bench`, times each stage of making thumbnails on one thread so releases can be compared.
+
+use crate::database::Database;
+use crate::thumbnailer::{Settings, Thumbnailer};
+use crate::TileMap;
+use log::*;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Stage {
+    time: Duration,
+    files: usize,
+    // Bytes going into the stage.
+    bytes: u64,
+}
+
+impl Stage {
+    fn time<T>(&mut self, bytes: u64, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let ret = f();
+        self.time += start.elapsed();
+        self.files += 1;
+        self.bytes += bytes;
+        ret
+    }
+
+    fn report(&self, name: &str) -> String {
+        let secs = self.time.as_secs_f64();
+        let per_sec = |n: f64| if secs > 0.0 { n / secs } else { 0.0 };
+        format!(
+            "{:<14} {:>6} files {:>9.3}s {:>9.1} files/s {:>9.1} MB/s",
+            name,
+            self.files,
+            secs,
+            per_sec(self.files as f64),
+            per_sec(self.bytes as f64 / 1_000_000.0),
+        )
+    }
+}
+
+// Thumbnail the first `sample` images under `dir` into a throwaway database and print the time
+// spent in each stage. Animation frames and further pages are left out.
+pub fn run(dir: PathBuf, sample: usize, settings: Settings) {
+    let mut scan = Stage::default();
+    let mut read = Stage::default();
+    let mut decode = Stage::default();
+    let mut encode = Stage::default();
+    let mut write = Stage::default();
+
+    let files = scan.time(0, || crate::find_images(vec![dir]));
+    scan.files = files.len();
+
+    let db = Database::temporary().expect("temporary db");
+
+    for (uid, file) in files.iter().take(sample).enumerate() {
+        let bytes = match read.time(file.file_size, || std::fs::read(&file.path)) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("read {:?}: {}", file.path, e);
+                continue;
+            }
+        };
+
+        let decoded = decode.time(bytes.len() as u64, || {
+            Thumbnailer::decode(&file.path, Some(&bytes), settings)
+        });
+        let image = match decoded {
+            Ok((image, _, _)) => image,
+            Err(e) => {
+                warn!("decode {:?}: {}", file.path, e);
+                continue;
+            }
+        };
+
+        let pixels = image.width() as u64 * image.height() as u64 * 4;
+        let mut chunk_ids: BTreeMap<u32, u16> = BTreeMap::new();
+        let mut tiles: TileMap<Cursor<Vec<u8>>> = BTreeMap::new();
+        encode.time(pixels, || {
+            Thumbnailer::make_pyramid(image, uid as u64, settings, &mut chunk_ids, &mut tiles)
+        });
+
+        let tile_bytes = tiles.values().map(|tile| tile.get_ref().len() as u64).sum();
+        write.time(tile_bytes, || {
+            for (id, tile) in &tiles {
+                db.set(*id, tile.get_ref()).expect("db set");
+            }
+            db.flush().expect("db flush");
+        });
+    }
+
+    println!("{}", scan.report("scan"));
+    println!("{}", read.report("read"));
+    println!("{}", decode.report("decode"));
+    println!("{}", encode.report("resize+encode"));
+    println!("{}", write.report("db write"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut stage = Stage::default();
+        assert_eq!(stage.time(2_000_000, || 7), 7);
+        stage.time = Duration::from_millis(500);
+        assert_eq!(
+            stage.report("read"),
+            "read                1 files     0.500s       2.0 files/s       4.0 MB/s"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Memory budget shared by the images being decoded, so a few huge panoramas can't decode at
+// the same time.
+
+use crate::animation::{self, MAX_FRAMES, MAX_FRAME_SIZE};
+use crate::psd;
+use crate::thumbnailer::{Thumbnailer, MAX_PAGES};
+use std::io::Cursor;
+use std::sync::{Condvar, Mutex};
+
+// Bytes per pixel held while thumbnailing: the decoded image, an RGBA copy and the first
+// downsampled level, assuming 8 bits per channel.
+static BYTES_PER_PIXEL: u64 = 12;
+
+// DICOM and FITS are stretched from f64 values, DICOM holds them twice while rescaling.
+#[cfg(any(feature = "dicom", feature = "fits"))]
+static VALUE_BYTES_PER_PIXEL: u64 = 16;
+
+// Kept animation frames are RGBA.
+static FRAME_BYTES_PER_PIXEL: u64 = 4;
+
+fn pixels([w, h]: [u32; 2]) -> u64 {
+    w as u64 * h as u64
+}
+
+// The size the image crate reads from the header.
+fn dimensions(bytes: &[u8]) -> Option<[u32; 2]> {
+    ::image::io::Reader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+        .map(|(w, h)| [w, h])
+}
+
+// The size `Thumbnailer::open_scaled` decodes a JPEG at.
+fn jpeg_dimensions(bytes: &[u8], max_decode: u32) -> Option<[u32; 2]> {
+    use jpeg_decoder::PixelFormat;
+
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(bytes));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let scaled = matches!(info.pixel_format, PixelFormat::L8 | PixelFormat::RGB24);
+    if max_decode == 0 || !scaled {
+        return Some([info.width as u32, info.height as u32]);
+    }
+
+    let max_decode = u16::try_from(max_decode).unwrap_or(u16::MAX);
+    let (w, h) = decoder.scale(max_decode, max_decode).ok()?;
+    Some([w as u32, h as u32])
+}
+
+// The largest of the first MAX_PAGES pages, they are thumbnailed one at a time.
+fn tiff_dimensions(bytes: &[u8]) -> Option<[u32; 2]> {
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes)).ok()?;
+    let mut max = [0, 0];
+    for _ in 0..MAX_PAGES {
+        let (w, h) = decoder.dimensions().ok()?;
+        if pixels([w, h]) > pixels(max) {
+            max = [w, h];
+        }
+        if !decoder.more_images() || decoder.next_image().is_err() {
+            break;
+        }
+    }
+    Some(max)
+}
+
+// What the kept frames of an animation take, on top of decoding one frame at a time.
+fn frames_cost(size: [u32; 2]) -> u64 {
+    let fit = |v: u32| v.min(MAX_FRAME_SIZE);
+    let frame = pixels([fit(size[0]), fit(size[1])]);
+    MAX_FRAMES as u64 * frame * FRAME_BYTES_PER_PIXEL
+}
+
+// Estimate the memory needed to thumbnail a file from its header, or its size when the format
+// isn't known. JPEGs count at the size they are decoded at with `max_decode`.
+pub fn estimate(path: &str, bytes: &[u8], max_decode: u32) -> u64 {
+    let fallback = bytes.len() as u64 * BYTES_PER_PIXEL;
+
+    if psd::is_psd(path) {
+        return psd::dimensions(bytes).map_or(fallback, |size| pixels(size) * BYTES_PER_PIXEL);
+    }
+    #[cfg(feature = "dicom")]
+    if crate::dicom::is_dicom(path) {
+        return crate::dicom::dimensions(bytes).map_or(fallback, |size| {
+            pixels(size) * (VALUE_BYTES_PER_PIXEL + BYTES_PER_PIXEL)
+        });
+    }
+    #[cfg(feature = "fits")]
+    if crate::fits::is_fits(path) {
+        return crate::fits::dimensions(bytes).map_or(fallback, |size| {
+            pixels(size) * (VALUE_BYTES_PER_PIXEL + BYTES_PER_PIXEL)
+        });
+    }
+
+    let size = if Thumbnailer::is_jpeg(path) {
+        jpeg_dimensions(bytes, max_decode)
+    } else if Thumbnailer::is_tiff(path) {
+        tiff_dimensions(bytes)
+    } else {
+        dimensions(bytes)
+    };
+    let Some(size) = size else {
+        return fallback;
+    };
+
+    let cost = pixels(size) * BYTES_PER_PIXEL;
+    if animation::may_animate(path, bytes) {
+        cost + frames_cost(size)
+    } else {
+        cost
+    }
+}
+
+pub struct Budget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl Budget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    // Images over the whole budget are still allowed when nothing else is in flight.
+    fn try_take(&self, used: &mut u64, bytes: u64) -> bool {
+        if *used == 0 || *used + bytes <= self.limit {
+            *used += bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Block until `bytes` fit in the budget.
+    pub fn acquire(&self, bytes: u64) {
+        let mut used = self.used.lock().unwrap();
+        while !self.try_take(&mut used, bytes) {
+            used = self.released.wait(used).unwrap();
+        }
+    }
+
+    pub fn release(&self, bytes: u64) {
+        let mut used = self.used.lock().unwrap();
+        *used = used.saturating_sub(bytes);
+        self.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take() {
+        let budget = Budget::new(100);
+        let mut used = 0;
+        assert!(budget.try_take(&mut used, 60));
+        assert!(!budget.try_take(&mut used, 60));
+        assert!(budget.try_take(&mut used, 40));
+
+        // Too big for the budget, but alone.
+        let mut used = 0;
+        assert!(budget.try_take(&mut used, 1000));
+        assert!(!budget.try_take(&mut used, 1));
+    }
+
+    #[test]
+    fn estimate_png() {
+        let mut png = Cursor::new(Vec::new());
+        ::image::RgbImage::new(40, 30)
+            .write_to(&mut png, ::image::ImageOutputFormat::Png)
+            .unwrap();
+        assert_eq!(
+            estimate("a.png", png.get_ref(), 0),
+            40 * 30 * BYTES_PER_PIXEL
+        );
+        assert_eq!(estimate("a.txt", b"text", 0), 4 * BYTES_PER_PIXEL);
+    }
+
+    #[test]
+    fn estimate_scaled_jpeg() {
+        let mut jpeg = Cursor::new(Vec::new());
+        ::image::RgbImage::new(800, 400)
+            .write_to(&mut jpeg, ::image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let full = 800 * 400 * BYTES_PER_PIXEL;
+        assert_eq!(estimate("a.jpg", jpeg.get_ref(), 0), full);
+        assert_eq!(estimate("a.jpg", jpeg.get_ref(), 1000), full);
+        // 1/4 keeps the long edge at 200.
+        assert_eq!(estimate("a.jpg", jpeg.get_ref(), 150), full / 16);
+    }
+
+    #[test]
+    fn estimate_tiff_pages() {
+        let mut tiff = Cursor::new(Vec::new());
+        let mut encoder = tiff::encoder::TiffEncoder::new(&mut tiff).unwrap();
+        encoder
+            .write_image::<tiff::encoder::colortype::Gray8>(10, 10, &[0; 100])
+            .unwrap();
+        encoder
+            .write_image::<tiff::encoder::colortype::Gray8>(20, 30, &[0; 600])
+            .unwrap();
+        assert_eq!(
+            estimate("a.tif", tiff.get_ref(), 0),
+            20 * 30 * BYTES_PER_PIXEL
+        );
+    }
+
+    #[test]
+    fn estimate_psd() {
+        let mut psd = b"8BPS\0\x01\0\0\0\0\0\0\0\x03".to_vec();
+        psd.extend(300u32.to_be_bytes());
+        psd.extend(200u32.to_be_bytes());
+        assert_eq!(estimate("a.psd", &psd, 0), 200 * 300 * BYTES_PER_PIXEL);
+    }
+}
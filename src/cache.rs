@@ -0,0 +1,184 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix cache export/import`, moves the thumbnails of a directory between databases as a tar
+// file, e.g. to ship a prebuilt cache with an image archive.
+
+use crate::database::Database;
+use crate::{File, Metadata, TileRef, E, R};
+use bincode::{deserialize, serialize};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+// One per image, named by its path relative to the exported directory.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    modified: u64,
+    file_size: u64,
+    metadata: Metadata,
+    tiles: Vec<(TileRef, Vec<u8>)>,
+}
+
+// Write the thumbnails of all images under `dir` to `tar`, returns how many were written.
+pub fn export(db: &Database, tar: &Path, dir: &Path) -> R<usize> {
+    let root = dir.canonicalize().map_err(E::IoError)?;
+    let out = std::fs::File::create(tar).map_err(E::IoError)?;
+    let mut builder = tar::Builder::new(std::io::BufWriter::new(out));
+
+    let mut n = 0;
+    for file in crate::find_images(vec![root.clone()]) {
+        let Some(metadata) = db.get_metadata(&file)? else {
+            continue;
+        };
+
+        let mut tiles = Vec::new();
+        for tile_ref in metadata.tile_refs() {
+            let data = db
+                .get(*tile_ref)?
+                .ok_or_else(|| E::MissingData(format!("{:?}", tile_ref)))?;
+            tiles.push((*tile_ref, data.to_vec()));
+        }
+
+        let entry = Entry {
+            modified: file.modified,
+            file_size: file.file_size,
+            metadata,
+            tiles,
+        };
+        let encoded: Vec<u8> = serialize(&entry).map_err(E::EncodeError)?;
+
+        let name = Path::new(&file.path)
+            .strip_prefix(&root)
+            .expect("found under root");
+        let mut header = tar::Header::new_gnu();
+        header.set_size(encoded.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(file.modified);
+        builder
+            .append_data(&mut header, name, encoded.as_slice())
+            .map_err(E::IoError)?;
+        n += 1;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|mut out| std::io::Write::flush(&mut out))
+        .map_err(E::IoError)?;
+    Ok(n)
+}
+
+// Give the tiles of an imported image the id `uid` of this database. The tiles of an image share
+// one id, like the thumbnailer makes them.
+fn rebase(entry: &mut Entry, uid: u64) {
+    let metadata = &mut entry.metadata;
+    let tile_refs = std::iter::once(&mut metadata.thumbs)
+        .chain(&mut metadata.pages)
+        .flatten()
+        .flat_map(|thumb| &mut thumb.tile_refs)
+        .chain(entry.tiles.iter_mut().map(|(tile_ref, _)| tile_ref));
+    for tile_ref in tile_refs {
+        *tile_ref = tile_ref.with_index(uid);
+    }
+}
+
+// Read thumbnails exported with `export` for images now under `dir`. Images that already have
+// thumbnails are skipped, returns how many were imported.
+pub fn import(db: &Database, tar: &Path, dir: &Path) -> R<usize> {
+    let root = dir.canonicalize().map_err(E::IoError)?;
+    let open = || -> R<_> {
+        let input = std::fs::File::open(tar).map_err(E::IoError)?;
+        Ok(tar::Archive::new(std::io::BufReader::new(input)))
+    };
+
+    // Ids are reserved for every image at once, skipped images leave theirs unused.
+    let count = open()?.entries().map_err(E::IoError)?.count();
+    let uid_base = db.reserve(count);
+
+    let mut archive = open()?;
+    let mut n = 0;
+    for (i, entry) in archive.entries().map_err(E::IoError)?.enumerate() {
+        let mut entry = entry.map_err(E::IoError)?;
+        let path = root.join(entry.path().map_err(E::IoError)?);
+
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).map_err(E::IoError)?;
+        let mut entry: Entry = deserialize(&buf).map_err(E::DecodeError)?;
+
+        let file = File {
+            path: path.to_string_lossy().into_owned(),
+            modified: entry.modified,
+            file_size: entry.file_size,
+        };
+        if db.get_metadata(&file)?.is_some() {
+            debug!("Already have thumbnails of {:?}", file.path);
+            continue;
+        }
+
+        rebase(&mut entry, uid_base + i as u64);
+        let tiles = entry
+            .tiles
+            .iter()
+            .map(|(tile_ref, data)| (*tile_ref, data.as_slice()));
+        db.set_thumbnail(&file, &entry.metadata, tiles)?;
+        n += 1;
+    }
+
+    db.flush()?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use crate::Thumb;
+    use pix_core::Pow2;
+
+    #[test]
+    fn export_import() {
+        let dir = TestDir::new("cache");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/a.png"), b"not really").unwrap();
+        let tar = dir.join("cache.tar");
+
+        let files = crate::find_images(vec![dir.to_path_buf()]);
+        assert_eq!(files.len(), 1);
+        let tile_ref = TileRef::new(Pow2(7), 5, 0);
+        let metadata = || Metadata {
+            thumbs: vec![Thumb {
+                img_size: [128, 128],
+                tile_refs: vec![tile_ref],
+            }],
+            ..Default::default()
+        };
+
+        let from = Database::temporary().unwrap();
+        from.set_thumbnail(&files[0], &metadata(), [(tile_ref, &b"tile"[..])])
+            .unwrap();
+        assert_eq!(export(&from, &tar, &dir).unwrap(), 1);
+
+        let to = Database::temporary().unwrap();
+        to.reserve(100);
+        assert_eq!(import(&to, &tar, &dir).unwrap(), 1);
+        assert_eq!(import(&to, &tar, &dir).unwrap(), 0);
+
+        let imported = to.get_metadata(&files[0]).unwrap().unwrap();
+        let moved = imported.thumbs[0].tile_refs[0];
+        assert_eq!(moved, tile_ref.with_index(100));
+        assert_eq!(&*to.get(moved).unwrap().unwrap(), b"tile");
+        assert_ne!(imported, metadata());
+    }
+}
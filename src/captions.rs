@@ -0,0 +1,221 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Titles, descriptions and keywords of captioned images, read while thumbnailing so filters can
+// search them. XMP packets are found anywhere in the file, which covers JPEG, TIFF, PNG and most
+// raw formats. IPTC records are read from Photoshop resources, EXIF image descriptions last.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Captions {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+}
+
+impl Captions {
+    // Whether any field contains `text`, which is lowercase.
+    pub fn contains(&self, text: &str) -> bool {
+        let matches = |field: &String| field.to_lowercase().contains(text);
+        self.title.iter().any(matches)
+            || self.description.iter().any(matches)
+            || self.keywords.iter().any(matches)
+    }
+
+    // Whether a keyword is `keyword`, which is lowercase.
+    pub fn has_keyword(&self, keyword: &str) -> bool {
+        self.keywords.iter().any(|k| k.to_lowercase() == keyword)
+    }
+
+    // Fields missing here are taken from `other`, which is only read when some are.
+    fn or(mut self, other: impl FnOnce() -> Captions) -> Captions {
+        if self.title.is_some() && self.description.is_some() && !self.keywords.is_empty() {
+            return self;
+        }
+        let other = other();
+        self.title = self.title.or(other.title);
+        self.description = self.description.or(other.description);
+        if self.keywords.is_empty() {
+            self.keywords = other.keywords;
+        }
+        self
+    }
+}
+
+pub fn read(data: &[u8]) -> Captions {
+    let _s = crate::stats::ScopedDuration::new("read_captions");
+    xmp(data).or(|| iptc(data)).or(|| exif(data))
+}
+
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+fn xmp(data: &[u8]) -> Captions {
+    let packet = find(data, b"<x:xmpmeta", 0).and_then(|start| {
+        let end = find(data, b"</x:xmpmeta>", start)?;
+        std::str::from_utf8(&data[start..end]).ok()
+    });
+    let packet = match packet {
+        Some(packet) => packet,
+        None => return Captions::default(),
+    };
+    let first = |tag| xmp_items(packet, tag).into_iter().next();
+    Captions {
+        title: first("dc:title"),
+        description: first("dc:description"),
+        keywords: xmp_items(packet, "dc:subject"),
+    }
+}
+
+// The rdf:li items of a property, the first is the default language of alternatives.
+fn xmp_items(packet: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let property = packet.split_once(&open).and_then(|(_, rest)| {
+        let (property, _) = rest.split_once(&close)?;
+        Some(property)
+    });
+    let mut items = Vec::new();
+    let mut rest = property.unwrap_or_default();
+    while let Some((_, item)) = rest.split_once("<rdf:li") {
+        let Some((_, item)) = item.split_once('>') else {
+            break;
+        };
+        let Some((value, tail)) = item.split_once("</rdf:li>") else {
+            break;
+        };
+        let value = unescape(value.trim());
+        if !value.is_empty() {
+            items.push(value);
+        }
+        rest = tail;
+    }
+    items
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// IPTC-IIM datasets in the Photoshop resource 0x0404.
+fn iptc(data: &[u8]) -> Captions {
+    let mut ret = Captions::default();
+    let start = match find(data, b"8BIM\x04\x04", 0) {
+        Some(start) => start + 6,
+        None => return ret,
+    };
+    // A padded Pascal string name, then the size.
+    let name_len = data.get(start).map_or(0, |&len| len as usize);
+    let size_at = start + (1 + name_len).next_multiple_of(2);
+    let size = match data.get(size_at..size_at + 4) {
+        Some(size) => u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize,
+        None => return ret,
+    };
+    let block = &data[size_at + 4..usize::min(size_at + 4 + size, data.len())];
+
+    let mut pos = 0;
+    while let Some(&[0x1c, record, dataset, hi, lo]) = block.get(pos..pos + 5) {
+        let len = u16::from_be_bytes([hi, lo]) as usize;
+        let Some(value) = block.get(pos + 5..pos + 5 + len) else {
+            break;
+        };
+        let value = String::from_utf8_lossy(value).trim().to_owned();
+        match (record, dataset) {
+            _ if value.is_empty() => {}
+            (2, 5) => ret.title = Some(value),
+            (2, 120) => ret.description = Some(value),
+            (2, 25) => ret.keywords.push(value),
+            _ => {}
+        }
+        pos += 5 + len;
+    }
+    ret
+}
+
+fn exif(data: &[u8]) -> Captions {
+    let exif = match exif::Reader::new().read_from_container(&mut std::io::Cursor::new(data)) {
+        Ok(exif) => exif,
+        Err(_) => return Captions::default(),
+    };
+    let description = exif
+        .get_field(exif::Tag::ImageDescription, exif::In::PRIMARY)
+        .and_then(|field| match field.value {
+            exif::Value::Ascii(ref strings) => strings.first().cloned(),
+            _ => None,
+        })
+        .map(|s| String::from_utf8_lossy(&s).trim().to_owned())
+        .filter(|s| !s.is_empty());
+    Captions {
+        description,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_xmp() {
+        let data = br#"....<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description>
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Harbour at dawn</rdf:li></rdf:Alt></dc:title>
+            <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Boats &amp; fog</rdf:li></rdf:Alt></dc:description>
+            <dc:subject><rdf:Bag><rdf:li>boats</rdf:li><rdf:li>Fog</rdf:li></rdf:Bag></dc:subject>
+            </rdf:Description></rdf:RDF></x:xmpmeta>...."#;
+        let captions = read(data);
+        assert_eq!(captions.title.as_deref(), Some("Harbour at dawn"));
+        assert_eq!(captions.description.as_deref(), Some("Boats & fog"));
+        assert_eq!(captions.keywords, vec!["boats", "Fog"]);
+        assert!(captions.contains("dawn"));
+        assert!(captions.contains("& fog"));
+        assert!(!captions.contains("sunset"));
+        assert!(captions.has_keyword("fog"));
+        assert!(!captions.has_keyword("bo"));
+    }
+
+    #[test]
+    fn reads_iptc() {
+        let dataset = |n: u8, value: &[u8]| {
+            let mut ret = vec![0x1c, 2, n];
+            ret.extend((value.len() as u16).to_be_bytes());
+            ret.extend(value);
+            ret
+        };
+        let iim = [
+            dataset(5, b"Market"),
+            dataset(25, b"food"),
+            dataset(25, b"stalls"),
+        ]
+        .concat();
+        let mut data = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+        data.extend((iim.len() as u32).to_be_bytes());
+        data.extend(&iim);
+
+        let captions = read(&data);
+        assert_eq!(captions.title.as_deref(), Some("Market"));
+        assert_eq!(captions.description, None);
+        assert_eq!(captions.keywords, vec!["food", "stalls"]);
+
+        assert_eq!(read(b"nothing here"), Captions::default());
+    }
+}
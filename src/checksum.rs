@@ -0,0 +1,192 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// SHA-256 of originals for archivists, to find files whose contents changed without their
+// modification time or size changing, i.e. bit rot.
+
+use crate::database::Database;
+use crate::File;
+use log::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub sha256: [u8; 32],
+
+    // Of the file when it was hashed, a different time or size means it was edited.
+    pub modified: u64,
+    pub file_size: u64,
+
+    // A later hash didn't match although the file looked unchanged.
+    pub mismatch: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    // Hashed for the first time, or again after the file was edited.
+    Recorded,
+    Verified,
+    Mismatch,
+}
+
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
+// Streamed, so large files aren't held in memory.
+pub fn sha256_file(path: impl AsRef<Path>) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+pub fn hex(sha256: &[u8; 32]) -> String {
+    sha256.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Compare a new hash of `file` to the stored one, returns what to store.
+pub fn check(old: Option<&Checksum>, file: &File, sha256: [u8; 32]) -> (Outcome, Checksum) {
+    let new = Checksum {
+        sha256,
+        modified: file.modified,
+        file_size: file.file_size,
+        mismatch: false,
+    };
+    match old {
+        Some(old) if old.modified == file.modified && old.file_size == file.file_size => {
+            if old.sha256 == sha256 {
+                let old = Checksum {
+                    mismatch: false,
+                    ..old.clone()
+                };
+                (Outcome::Verified, old)
+            } else {
+                // The original hash is kept so the file keeps failing until restored.
+                let old = Checksum {
+                    mismatch: true,
+                    ..old.clone()
+                };
+                (Outcome::Mismatch, old)
+            }
+        }
+        _ => (Outcome::Recorded, new),
+    }
+}
+
+// Store the hash of `file`, logging mismatches.
+pub fn record(db: &Database, file: &File, sha256: [u8; 32]) -> Outcome {
+    let old = db.get_checksum(file).unwrap_or_else(|e| {
+        error!("get checksum for {:?}: {:?}", file.path, e);
+        None
+    });
+    let (outcome, checksum) = check(old.as_ref(), file, sha256);
+    if outcome == Outcome::Mismatch {
+        warn!(
+            "checksum mismatch, bit rot? {:?} was {}",
+            file.path,
+            hex(&checksum.sha256)
+        );
+    }
+    if old.as_ref() != Some(&checksum) {
+        if let Err(e) = db.set_checksum(file, &checksum) {
+            error!("set checksum for {:?}: {:?}", file.path, e);
+        }
+    }
+    outcome
+}
+
+// `pix verify`, hash every image under `dirs` and compare to the stored hashes. Returns the
+// files that don't match.
+pub fn verify(db: &Database, dirs: Vec<PathBuf>) -> Vec<Arc<File>> {
+    let files = crate::find_images(dirs);
+    let outcomes: Vec<(Arc<File>, Outcome)> = files
+        .into_par_iter()
+        .filter_map(|file| match sha256_file(&file.path) {
+            Ok(sha256) => {
+                let outcome = record(db, &file, sha256);
+                Some((file, outcome))
+            }
+            Err(e) => {
+                error!("read {:?}: {}", file.path, e);
+                None
+            }
+        })
+        .collect();
+
+    let count = |outcome| outcomes.iter().filter(|(_, o)| *o == outcome).count();
+    info!(
+        "{} verified, {} recorded, {} mismatched",
+        count(Outcome::Verified),
+        count(Outcome::Recorded),
+        count(Outcome::Mismatch)
+    );
+    db.flush().expect("db flush");
+
+    outcomes
+        .into_iter()
+        .filter(|(_, outcome)| *outcome == Outcome::Mismatch)
+        .map(|(file, _)| file)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let file = File {
+            path: String::from("/here"),
+            modified: 1234,
+            file_size: 3,
+        };
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let dir = crate::testdir::TestDir::new("checksum");
+        std::fs::write(dir.join("abc"), b"abc").unwrap();
+        assert_eq!(sha256_file(dir.join("abc")).unwrap(), sha256(b"abc"));
+
+        let (outcome, stored) = check(None, &file, sha256(b"abc"));
+        assert_eq!(outcome, Outcome::Recorded);
+        assert_eq!(
+            check(Some(&stored), &file, sha256(b"abc")).0,
+            Outcome::Verified
+        );
+
+        let (outcome, rotten) = check(Some(&stored), &file, sha256(b"abd"));
+        assert_eq!(outcome, Outcome::Mismatch);
+        assert!(rotten.mismatch);
+        assert_eq!(rotten.sha256, stored.sha256);
+
+        // Edited files are hashed again.
+        let edited = File {
+            modified: 5678,
+            ..file.clone()
+        };
+        let (outcome, again) = check(Some(&rotten), &edited, sha256(b"abd"));
+        assert_eq!(outcome, Outcome::Recorded);
+        assert!(!again.mismatch);
+
+        // Restored from a backup.
+        let (outcome, restored) = check(Some(&rotten), &file, sha256(b"abc"));
+        assert_eq!(outcome, Outcome::Verified);
+        assert!(!restored.mismatch);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Runs of consecutive images from the same directory, drawn as colored blobs instead of
+// thumbnails when the grid is zoomed far out.
+
+use crate::filter::year_from_secs;
+use crate::File;
+use std::path::Path;
+
+// Neighboring clusters alternate through these.
+static COLORS: [[f32; 4]; 6] = [
+    [0.36, 0.52, 0.71, 1.0],
+    [0.80, 0.52, 0.29, 1.0],
+    [0.42, 0.64, 0.39, 1.0],
+    [0.71, 0.37, 0.40, 1.0],
+    [0.55, 0.46, 0.69, 1.0],
+    [0.70, 0.66, 0.33, 1.0],
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cluster {
+    // Index of the first image and the number of images.
+    pub start: usize,
+    pub len: usize,
+
+    pub dir: String,
+
+    // Oldest and newest modification year.
+    years: [i64; 2],
+
+    color: usize,
+}
+
+fn dir(file: &File) -> String {
+    Path::new(&file.path)
+        .parent()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl Cluster {
+    // Split images in grid order into clusters.
+    pub fn split<'a>(files: impl IntoIterator<Item = &'a File>) -> Vec<Self> {
+        let mut clusters: Vec<Self> = Vec::new();
+
+        for (i, file) in files.into_iter().enumerate() {
+            let dir = dir(file);
+            let year = year_from_secs(file.modified);
+            match clusters.last_mut() {
+                Some(cluster) if cluster.dir == dir => {
+                    cluster.len += 1;
+                    cluster.years = [
+                        i64::min(cluster.years[0], year),
+                        i64::max(cluster.years[1], year),
+                    ];
+                }
+                _ => clusters.push(Self {
+                    start: i,
+                    len: 1,
+                    dir,
+                    years: [year, year],
+                    color: clusters.len() % COLORS.len(),
+                }),
+            }
+        }
+
+        clusters
+    }
+
+    // The cluster holding image `i`.
+    pub fn find(clusters: &[Self], i: usize) -> Option<&Self> {
+        let n = clusters.partition_point(|cluster| cluster.start <= i);
+        clusters[..n].last()
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        COLORS[self.color]
+    }
+
+    pub fn label(&self) -> String {
+        let name = Path::new(&self.dir)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.dir.clone());
+        let [first, last] = self.years;
+        if first == last {
+            format!("{} ({}) {}", name, self.len, first)
+        } else {
+            format!("{} ({}) {}-{}", name, self.len, first, last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, modified: u64) -> File {
+        File {
+            path: path.to_owned(),
+            modified,
+            file_size: 0,
+        }
+    }
+
+    #[test]
+    fn split() {
+        let files = [
+            file("/a/1.jpg", 0),
+            file("/a/2.jpg", 1_672_531_200),
+            file("/b/1.jpg", 0),
+            file("/a/3.jpg", 0),
+        ];
+        let clusters = Cluster::split(&files);
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(clusters[0].label(), "a (2) 1970-2023");
+        assert_eq!(clusters[1].label(), "b (1) 1970");
+        assert_ne!(clusters[1].color(), clusters[2].color());
+
+        assert_eq!(Cluster::find(&clusters, 1).unwrap().start, 0);
+        assert_eq!(Cluster::find(&clusters, 2).unwrap().dir, "/b");
+        assert_eq!(Cluster::find(&clusters, 9).unwrap().start, 3);
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix diff`, lines up the images of two to four directory trees side by side to check backups and
+// exports, or to pick between export settings and lens tests.
+
+use crate::database::Database;
+use crate::fingerprint;
+use crate::image::Image;
+use crate::userdata::UserData;
+use crate::{File, MetadataState};
+use log::*;
+use piston_window::color;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Same,
+    // Different contents at the same path, or the same contents at different paths.
+    Changed,
+    Missing,
+}
+
+impl Status {
+    fn highlight(self) -> Option<[f32; 4]> {
+        match self {
+            Status::Same => None,
+            Status::Changed => Some(color::hex("FF9800")),
+            Status::Missing => Some(color::hex("F44336")),
+        }
+    }
+}
+
+// A file and its path relative to the compared directory.
+type Side = Option<(String, Arc<File>)>;
+
+// The files lined up across the compared directories.
+type Row = Vec<Side>;
+
+fn relative(root: &Path, file: &File) -> String {
+    Path::new(&file.path)
+        .strip_prefix(root)
+        .map(|rel| rel.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| file.path.clone())
+}
+
+fn content_key(file: &File) -> String {
+    match fingerprint::of_file(&file.path) {
+        Ok(hash) => format!("{:016x}", hash),
+        // Never matched.
+        Err(_) => format!("unreadable:{}", file.path),
+    }
+}
+
+// Line up the files of every side by `key`, in order of their relative paths.
+fn pair(sides: Vec<Vec<(String, Arc<File>)>>, key: impl Fn(&str, &File) -> String) -> Vec<Row> {
+    let n = sides.len();
+    let mut rows: BTreeMap<String, Row> = BTreeMap::new();
+    for (i, side) in sides.into_iter().enumerate() {
+        for (rel, file) in side {
+            let mut k = key(&rel, &file);
+            // Duplicates on one side get rows of their own.
+            while rows.get(&k).is_some_and(|row| row[i].is_some()) {
+                k.push('+');
+            }
+            rows.entry(k).or_insert_with(|| vec![None; n])[i] = Some((rel, file));
+        }
+    }
+
+    let mut rows: Vec<Row> = rows.into_values().collect();
+    rows.sort_by(|x, y| {
+        let rel = |row: &Row| row.iter().flatten().next().map(|(rel, _)| rel.clone());
+        rel(x).cmp(&rel(y))
+    });
+    rows
+}
+
+fn status(row: &Row, by_content: bool) -> Status {
+    let files: Vec<&(String, Arc<File>)> = row.iter().flatten().collect();
+    if files.len() < row.len() {
+        return Status::Missing;
+    }
+    let (rel_a, a) = files[0];
+    let changed = files[1..].iter().any(|(rel_b, b)| {
+        if by_content {
+            rel_a != rel_b
+        } else {
+            a.file_size != b.file_size
+                || fingerprint::of_file(&a.path).ok() != fingerprint::of_file(&b.path).ok()
+        }
+    });
+    if changed {
+        Status::Changed
+    } else {
+        Status::Same
+    }
+}
+
+// Images of the `dirs` in rows, the first directory on the left. Files missing on one side are
+// shown as broken images at the path they'd have.
+pub fn load(db: &Database, dirs: &[PathBuf], by_content: bool) -> Vec<Image> {
+    let roots: Vec<PathBuf> = dirs
+        .iter()
+        .map(|dir| dir.canonicalize().expect("diff directory"))
+        .collect();
+    let sides = roots
+        .iter()
+        .map(|root| {
+            crate::find_images(vec![root.clone()])
+                .into_iter()
+                .map(|file| (relative(root, &file), file))
+                .collect()
+        })
+        .collect();
+
+    let rows = if by_content {
+        pair(sides, |_, file| content_key(file))
+    } else {
+        pair(sides, |rel, _| rel.to_owned())
+    };
+
+    let (mut changed, mut missing) = (0, 0);
+    let mut images = Vec::with_capacity(rows.len() * roots.len());
+    for row in rows {
+        let status = status(&row, by_content);
+        match status {
+            Status::Same => {}
+            Status::Changed => changed += 1,
+            Status::Missing => missing += 1,
+        }
+
+        let rel = row.iter().flatten().next().expect("row").0.clone();
+        for (side, root) in row.into_iter().zip(&roots) {
+            let i = images.len();
+            let mut image = match side {
+                Some((_, file)) => {
+                    let metadata = crate::scan::load_metadata(db, &file, false);
+                    let user_data = db.get_user_data(&file).unwrap_or_default();
+                    Image::from(i, file, metadata, user_data)
+                }
+                None => {
+                    let file = File {
+                        path: root.join(&rel).to_string_lossy().into_owned(),
+                        ..File::default()
+                    };
+                    let metadata = MetadataState::Errored;
+                    Image::from(i, Arc::new(file), metadata, UserData::default())
+                }
+            };
+            image.highlight = status.highlight();
+            images.push(image);
+        }
+    }
+    info!(
+        "Diff of {:?}: {} rows, {} changed, {} missing a side",
+        roots,
+        images.len() / roots.len(),
+        changed,
+        missing
+    );
+
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn side(paths: &[(&str, u64)]) -> Vec<(String, Arc<File>)> {
+        paths
+            .iter()
+            .map(|&(rel, file_size)| {
+                let file = File {
+                    path: format!("/{}", rel),
+                    modified: 0,
+                    file_size,
+                };
+                (rel.to_owned(), Arc::new(file))
+            })
+            .collect()
+    }
+
+    fn rels(rows: &[Row]) -> Vec<Vec<Option<&str>>> {
+        rows.iter()
+            .map(|row| {
+                row.iter()
+                    .map(|side| side.as_ref().map(|(rel, _)| rel.as_str()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn by_path() {
+        let a = side(&[("a.jpg", 1), ("b.jpg", 1)]);
+        let b = side(&[("b.jpg", 1), ("c.jpg", 1)]);
+        let rows = pair(vec![a, b], |rel, _| rel.to_owned());
+        assert_eq!(
+            rels(&rows),
+            vec![
+                vec![Some("a.jpg"), None],
+                vec![Some("b.jpg"), Some("b.jpg")],
+                vec![None, Some("c.jpg")],
+            ]
+        );
+        assert_eq!(status(&rows[0], false), Status::Missing);
+    }
+
+    #[test]
+    fn several() {
+        let a = side(&[("a.jpg", 1), ("b.jpg", 1)]);
+        let b = side(&[("a.jpg", 1)]);
+        let c = side(&[("a.jpg", 1), ("b.jpg", 1), ("b.jpg", 1)]);
+        let rows = pair(vec![a, b, c], |rel, _| rel.to_owned());
+        assert_eq!(
+            rels(&rows),
+            vec![
+                vec![Some("a.jpg"), Some("a.jpg"), Some("a.jpg")],
+                vec![Some("b.jpg"), None, Some("b.jpg")],
+                vec![None, None, Some("b.jpg")],
+            ]
+        );
+        assert_eq!(status(&rows[1], true), Status::Missing);
+    }
+
+    #[test]
+    fn by_content() {
+        // Sizes stand in for contents.
+        let a = side(&[("a.jpg", 1), ("b.jpg", 2), ("d.jpg", 2)]);
+        let b = side(&[("renamed.jpg", 1), ("b.jpg", 2)]);
+        let rows = pair(vec![a, b], |_, file| file.file_size.to_string());
+        assert_eq!(
+            rels(&rows),
+            vec![
+                vec![Some("a.jpg"), Some("renamed.jpg")],
+                vec![Some("b.jpg"), Some("b.jpg")],
+                vec![Some("d.jpg"), None],
+            ]
+        );
+        assert_eq!(status(&rows[0], true), Status::Changed);
+        assert_eq!(status(&rows[1], true), Status::Same);
+        assert_eq!(status(&rows[2], true), Status::Missing);
+    }
+}
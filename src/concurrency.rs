@@ -0,0 +1,204 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Adaptive thumbnailer concurrency. Decoders compete with drawing for the CPU, so while the user
+// pans and zooms and frames come late, decoders are paused one at a time. Once input stops they
+// resume one at a time, up to `--threads`, so cold indexing runs at full speed when idle.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// The limit changes by one at most this often.
+static STEP: Duration = Duration::from_millis(250);
+
+// Input longer ago than this counts as idle.
+static QUIET: Duration = Duration::from_secs(1);
+
+// Frames this much later than the frame rate asks for are slow.
+static SLOW: f64 = 1.5;
+
+// Longer gaps between frames are pauses in drawing rather than slow frames.
+static PAUSE: Duration = Duration::from_millis(250);
+
+// Caps the decoders working at once, the others wait for a slot.
+pub struct Gate {
+    // Decoders working and the limit.
+    state: Mutex<(usize, usize)>,
+    changed: Condvar,
+}
+
+impl Gate {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new((0, usize::max(1, limit))),
+            changed: Condvar::new(),
+        }
+    }
+
+    // Block until a decoder may start.
+    pub fn enter(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.0 >= state.1 {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.0 += 1;
+    }
+
+    pub fn leave(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_sub(1);
+        self.changed.notify_all();
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.state.lock().unwrap().1 = usize::max(1, limit);
+        self.changed.notify_all();
+    }
+}
+
+// Picks the number of decoders from input and frame times.
+pub struct Governor {
+    // Adjust the limit, otherwise all decoders always work.
+    pub adaptive: bool,
+    max: usize,
+    limit: usize,
+    target: Duration,
+    last_input: Option<Instant>,
+    last_frame: Option<Instant>,
+    // A slow frame was drawn since the last step.
+    slow: bool,
+    last_step: Option<Instant>,
+}
+
+impl Governor {
+    pub fn new(max: usize, fps: u64) -> Self {
+        let max = usize::max(1, max);
+        Self {
+            adaptive: true,
+            max,
+            limit: max,
+            target: Duration::from_secs_f64(1.0 / u64::max(1, fps) as f64),
+            last_input: None,
+            last_frame: None,
+            slow: false,
+            last_step: None,
+        }
+    }
+
+    // Change the most decoders and the frame rate, e.g. on battery. Returns the new number of
+    // decoders when it changes right away, more are let in gradually when adaptive.
+    pub fn set_budget(&mut self, max: usize, fps: u64) -> Option<usize> {
+        self.max = usize::max(1, max);
+        self.target = Duration::from_secs_f64(1.0 / u64::max(1, fps) as f64);
+        let limit = match self.adaptive {
+            true => usize::min(self.limit, self.max),
+            false => self.max,
+        };
+        if limit == self.limit {
+            return None;
+        }
+        self.limit = limit;
+        Some(limit)
+    }
+
+    pub fn input(&mut self, now: Instant) {
+        self.last_input = Some(now);
+    }
+
+    // A frame was drawn at `now`.
+    pub fn frame(&mut self, now: Instant) {
+        if let Some(last) = self.last_frame {
+            let interval = now.saturating_duration_since(last);
+            if interval > self.target.mul_f64(SLOW) && interval < PAUSE {
+                self.slow = true;
+            }
+        }
+        self.last_frame = Some(now);
+    }
+
+    // The new number of decoders when it changes.
+    pub fn step(&mut self, now: Instant) -> Option<usize> {
+        if !self.adaptive || self.last_step.is_some_and(|last| now < last + STEP) {
+            return None;
+        }
+        self.last_step = Some(now);
+
+        let interacting = self.last_input.is_some_and(|last| now < last + QUIET);
+        let limit = if !interacting {
+            usize::min(self.limit + 1, self.max)
+        } else if self.slow {
+            usize::max(self.limit - 1, 1)
+        } else {
+            self.limit
+        };
+        self.slow = false;
+
+        if limit == self.limit {
+            return None;
+        }
+        self.limit = limit;
+        Some(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_with_input() {
+        let mut governor = Governor::new(3, 50);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Smooth frames while panning.
+        governor.input(at(0));
+        governor.frame(at(0));
+        governor.frame(at(20));
+        assert_eq!(governor.step(at(20)), None);
+
+        // Slow frames, one decoder less per step.
+        governor.frame(at(60));
+        assert_eq!(governor.step(at(100)), None);
+        assert_eq!(governor.step(at(300)), Some(2));
+        governor.frame(at(310));
+        governor.frame(at(350));
+        assert_eq!(governor.step(at(600)), Some(1));
+        governor.frame(at(640));
+        assert_eq!(governor.step(at(900)), None);
+
+        // A pause in drawing isn't slow.
+        governor.input(at(900));
+        governor.frame(at(1800));
+        assert_eq!(governor.step(at(1800)), None);
+
+        // Idle, back up to the maximum.
+        assert_eq!(governor.step(at(2100)), Some(2));
+        assert_eq!(governor.step(at(2400)), Some(3));
+        assert_eq!(governor.step(at(2700)), None);
+
+        // On battery, down to the new maximum right away and up gradually later.
+        assert_eq!(governor.set_budget(1, 30), Some(1));
+        assert_eq!(governor.step(at(3000)), None);
+        assert_eq!(governor.set_budget(3, 50), None);
+        assert_eq!(governor.step(at(3300)), Some(2));
+        assert_eq!(governor.step(at(3600)), Some(3));
+
+        governor.adaptive = false;
+        governor.input(at(2700));
+        governor.frame(at(2710));
+        governor.frame(at(2750));
+        assert_eq!(governor.step(at(3000)), None);
+    }
+}
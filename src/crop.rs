@@ -0,0 +1,258 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Crop rectangles dragged over the single image view, saved by re-encoding the original.
+
+use crate::{Thumbnailer, E, R};
+use ::image::ImageFormat;
+use piston_window::{rectangle, Context, G2d, Rectangle};
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Aspect {
+    #[default]
+    Free,
+    Square,
+    ThreeTwo,
+    FourThree,
+    SixteenNine,
+}
+
+impl Aspect {
+    pub fn next(self) -> Self {
+        match self {
+            Aspect::Free => Aspect::Square,
+            Aspect::Square => Aspect::ThreeTwo,
+            Aspect::ThreeTwo => Aspect::FourThree,
+            Aspect::FourThree => Aspect::SixteenNine,
+            Aspect::SixteenNine => Aspect::Free,
+        }
+    }
+
+    // Long side over short side.
+    fn ratio(self) -> Option<f64> {
+        match self {
+            Aspect::Free => None,
+            Aspect::Square => Some(1.0),
+            Aspect::ThreeTwo => Some(3.0 / 2.0),
+            Aspect::FourThree => Some(4.0 / 3.0),
+            Aspect::SixteenNine => Some(16.0 / 9.0),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aspect::Free => "free",
+            Aspect::Square => "1:1",
+            Aspect::ThreeTwo => "3:2",
+            Aspect::FourThree => "4:3",
+            Aspect::SixteenNine => "16:9",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Crop {
+    pub aspect: Aspect,
+
+    // Image pixel positions the drag started and ended at.
+    start: Option<[f64; 2]>,
+    end: [f64; 2],
+
+    pub dragging: bool,
+}
+
+impl Crop {
+    pub fn begin(&mut self, pos: [f64; 2]) {
+        self.start = Some(pos);
+        self.end = pos;
+        self.dragging = true;
+    }
+
+    pub fn drag(&mut self, pos: [f64; 2]) {
+        if self.dragging {
+            self.end = pos;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.start = None;
+        self.dragging = false;
+    }
+
+    // The dragged rectangle as x, y, width and height in pixels of an image of `img_size`,
+    // constrained to the image and the aspect ratio.
+    pub fn rect(&self, [w, h]: [u32; 2]) -> Option<[u32; 4]> {
+        let [sx, sy] = self.start?;
+        let (sx, sy) = (sx.clamp(0.0, w as f64), sy.clamp(0.0, h as f64));
+        let ex = self.end[0].clamp(0.0, w as f64);
+        let ey = self.end[1].clamp(0.0, h as f64);
+
+        let (mut dx, mut dy) = ((ex - sx).abs(), (ey - sy).abs());
+        if let Some(ratio) = self.aspect.ratio() {
+            // Orientation follows the drag, then shrink the longer side to fit.
+            let ratio = if dx >= dy { ratio } else { 1.0 / ratio };
+            if dx / ratio > dy {
+                dx = dy * ratio;
+            } else {
+                dy = dx / ratio;
+            }
+        }
+
+        let x = if ex < sx { sx - dx } else { sx };
+        let y = if ey < sy { sy - dy } else { sy };
+        let rect = [
+            x.round() as u32,
+            y.round() as u32,
+            dx.round() as u32,
+            dy.round() as u32,
+        ];
+        (rect[2] > 0 && rect[3] > 0).then_some(rect)
+    }
+
+    // Dim everything outside the rectangle, `origin` and `scale` map image pixels to the window.
+    pub fn draw(&self, img_size: [u32; 2], origin: [f64; 2], scale: f64, c: &Context, g: &mut G2d) {
+        let [x, y, w, h] = match self.rect(img_size) {
+            Some(rect) => rect.map(|v| v as f64 * scale),
+            None => return,
+        };
+        let (x, y) = (origin[0] + x, origin[1] + y);
+        let [win_w, win_h] = c.get_view_size();
+
+        let dim = [0.0, 0.0, 0.0, 0.6];
+        rectangle(dim, [0.0, 0.0, win_w, y], c.transform, g);
+        rectangle(dim, [0.0, y + h, win_w, win_h - y - h], c.transform, g);
+        rectangle(dim, [0.0, y, x, h], c.transform, g);
+        rectangle(dim, [x + w, y, win_w - x - w, h], c.transform, g);
+
+        Rectangle::new_border([1.0, 1.0, 1.0, 1.0], 1.0).draw(
+            [x, y, w, h],
+            &c.draw_state,
+            c.transform,
+            g,
+        );
+    }
+}
+
+fn can_write(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| format.can_write())
+}
+
+// A path next to `path` that doesn't exist yet, keeping the extension when it can be encoded.
+fn copy_path(path: &Path) -> std::path::PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = match path.extension() {
+        Some(ext) if can_write(path) => ext.to_string_lossy(),
+        _ => "png".into(),
+    };
+
+    let mut n = 1;
+    loop {
+        let name = if n == 1 {
+            format!("{}-crop.{}", stem, ext)
+        } else {
+            format!("{}-crop-{}.{}", stem, n, ext)
+        };
+        let copy = path.with_file_name(name);
+        if !copy.exists() {
+            return copy;
+        }
+        n += 1;
+    }
+}
+
+// Crop the original image and save it, over the original or as a copy. Returns the path written.
+pub fn save(path: &str, [x, y, w, h]: [u32; 4], overwrite: bool) -> R<String> {
+    let (image, _) = Thumbnailer::open(path)?;
+    let image = image.crop_imm(x, y, w, h);
+
+    let path = Path::new(path);
+    let dest = if overwrite {
+        path.to_path_buf()
+    } else {
+        copy_path(path)
+    };
+
+    // The default JPEG quality is too low for edits of photos.
+    if ImageFormat::from_path(&dest).ok() == Some(ImageFormat::Jpeg) {
+        let file = std::fs::File::create(&dest).map_err(|e| E::ImageError(e.into()))?;
+        let mut encoder =
+            ::image::codecs::jpeg::JpegEncoder::new_with_quality(std::io::BufWriter::new(file), 95);
+        encoder
+            .encode_image(&image.to_rgb8())
+            .map_err(E::ImageError)?;
+    } else {
+        image.save(&dest).map_err(E::ImageError)?;
+    }
+
+    Ok(dest.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crop(aspect: Aspect, start: [f64; 2], end: [f64; 2]) -> Crop {
+        let mut crop = Crop {
+            aspect,
+            ..Default::default()
+        };
+        crop.begin(start);
+        crop.drag(end);
+        crop
+    }
+
+    #[test]
+    fn rect() {
+        let size = [100, 50];
+        assert_eq!(Crop::default().rect(size), None);
+        assert_eq!(
+            crop(Aspect::Free, [10.0, 10.0], [10.2, 30.0]).rect(size),
+            None
+        );
+        assert_eq!(
+            crop(Aspect::Free, [60.0, 40.0], [20.0, 10.0]).rect(size),
+            Some([20, 10, 40, 30])
+        );
+        // Clamped to the image.
+        assert_eq!(
+            crop(Aspect::Free, [90.0, 40.0], [120.0, 60.0]).rect(size),
+            Some([90, 40, 10, 10])
+        );
+        assert_eq!(
+            crop(Aspect::ThreeTwo, [0.0, 0.0], [90.0, 30.0]).rect(size),
+            Some([0, 0, 45, 30])
+        );
+        assert_eq!(
+            crop(Aspect::ThreeTwo, [40.0, 40.0], [30.0, 0.0]).rect(size),
+            Some([30, 25, 10, 15])
+        );
+        assert_eq!(
+            crop(Aspect::Square, [50.0, 0.0], [0.0, 40.0]).rect(size),
+            Some([10, 0, 40, 40])
+        );
+    }
+
+    #[test]
+    fn copy_paths() {
+        assert_eq!(
+            copy_path(Path::new("/nonexistent/a.jpg")),
+            Path::new("/nonexistent/a-crop.jpg")
+        );
+        assert_eq!(
+            copy_path(Path::new("/nonexistent/a.psd")),
+            Path::new("/nonexistent/a-crop.png")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Encryption of thumbnails at rest with a key derived from a passphrase.
+
+use crate::{E, R};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+static NONCE_LEN: usize = 12;
+
+pub static PASSPHRASE_VAR: &str = "PIX_CACHE_PASSPHRASE";
+
+pub struct Cipher(ChaCha20Poly1305);
+
+pub fn new_salt() -> [u8; 16] {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+// The output of `command` run by the shell, e.g. a keyring lookup, or else $PIX_CACHE_PASSPHRASE.
+pub fn passphrase(command: Option<&str>) -> R<String> {
+    let Some(command) = command else {
+        return std::env::var(PASSPHRASE_VAR)
+            .map_err(|_| E::CryptError(format!("{} is not set", PASSPHRASE_VAR)));
+    };
+
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = std::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .output()
+        .map_err(E::IoError)?;
+    if !output.status.success() {
+        return Err(E::CryptError(format!("{:?} failed", command)));
+    }
+    let passphrase = String::from_utf8_lossy(&output.stdout);
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+impl Cipher {
+    pub fn new(passphrase: &str, salt: &[u8]) -> R<Self> {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| E::CryptError(e.to_string()))?;
+        Ok(Self(ChaCha20Poly1305::new(&key)))
+    }
+
+    // A fresh nonce followed by the ciphertext.
+    pub fn seal(&self, plain: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(self.0.encrypt(&nonce, plain).expect("encrypt"));
+        sealed
+    }
+
+    // None if `sealed` wasn't sealed with this key, e.g. left from before encrypting.
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.0.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_open() {
+        let salt = new_salt();
+        let cipher = Cipher::new("secret", &salt).unwrap();
+        let sealed = cipher.seal(b"pixels");
+        assert_ne!(&sealed[NONCE_LEN..], b"pixels");
+        assert_eq!(cipher.open(&sealed).unwrap(), b"pixels");
+        assert_ne!(cipher.seal(b"pixels"), sealed);
+
+        let wrong = Cipher::new("guess", &salt).unwrap();
+        assert_eq!(wrong.open(&sealed), None);
+        assert_eq!(cipher.open(b"plain"), None);
+
+        assert_eq!(passphrase(Some("echo secret")).unwrap(), "secret");
+        assert!(passphrase(Some("false")).is_err());
+    }
+}
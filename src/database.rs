@@ -12,18 +12,48 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Storage of thumbnails and everything else pix remembers about images.
+
+use crate::adjust::Adjustments;
+use crate::checksum::Checksum;
+use crate::crypt::{self, Cipher};
+use crate::stats;
+#[cfg(test)]
+use crate::testdir::TestDir;
+use crate::userdata::UserData;
 use crate::{File, Metadata, TileRef, E, R};
 use bincode::{deserialize, serialize};
 use log::*;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{TransactionError, Transactional};
+use sled::IVec;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 static MAX_ID: &[u8] = b"_MAX_ID";
+static SALT: &[u8] = b"_SALT";
+static KEY_CHECK: &[u8] = b"_KEY_CHECK";
 static METADATA_PREFIX: char = 'M';
 static TILE_PREFIX: char = 'T';
+static PRESET_PREFIX: char = 'P';
+static USER_DATA_PREFIX: char = 'U';
+static ADJUSTMENTS_PREFIX: char = 'A';
+static FAILURE_PREFIX: char = 'F';
+static CHECKSUM_PREFIX: char = 'C';
+static SETTING_PREFIX: char = 'S';
+static SCAN_PREFIX: char = 'L';
+static NOTE_PREFIX: char = 'N';
+static EDITED_PREFIX: char = 'E';
+
+// Files that failed this many times are not thumbnailed again until they change.
+static MAX_ATTEMPTS: u32 = 3;
 
 // Mixed into all keys, bump when making breaking database format changes.
-static DB_VERSION: u32 = 2;
+static DB_VERSION: u32 = 13;
 
 #[derive(Debug)]
 struct Key(String);
@@ -47,9 +77,57 @@ impl Key {
         ))
     }
 
+    // Like metadata a changed file gets a new key and is tried again.
+    fn for_failure(file: &File) -> Key {
+        Self(format!(
+            "{}{}:{}",
+            FAILURE_PREFIX,
+            file.path,
+            Self::hash_file(file)
+        ))
+    }
+
+    // Keyed by path only, user data should survive the file being modified.
+    fn for_user_data(file: &File) -> Key {
+        Self(format!("{}{}", USER_DATA_PREFIX, file.path))
+    }
+
+    // By path, so changed contents are noticed.
+    fn for_checksum(file: &File) -> Key {
+        Self(format!("{}{}", CHECKSUM_PREFIX, file.path))
+    }
+
+    fn for_adjustments(file: &File) -> Key {
+        Self(format!("{}{}", ADJUSTMENTS_PREFIX, file.path))
+    }
+
+    // By path like user data.
+    fn for_note(file: &File) -> Key {
+        Self(format!("{}{}", NOTE_PREFIX, file.path))
+    }
+
+    // By path like user data, when ratings, labels, notes or adjustments last changed.
+    fn for_edited(file: &File) -> Key {
+        Self(format!("{}{}", EDITED_PREFIX, file.path))
+    }
+
+    fn for_preset(name: &str) -> Key {
+        Self(format!("{}{}", PRESET_PREFIX, name))
+    }
+
+    fn for_setting(name: &str) -> Key {
+        Self(format!("{}{}", SETTING_PREFIX, name))
+    }
+
+    // The searched paths, one per line.
+    fn for_scan(paths: &[PathBuf]) -> Key {
+        let paths: Vec<_> = paths.iter().map(|path| path.to_string_lossy()).collect();
+        Self(format!("{}{}", SCAN_PREFIX, paths.join("\n")))
+    }
+
     fn for_thumb(tile_ref: TileRef) -> [u8; 9] {
         let mut k: [u8; 9] = [TILE_PREFIX as u8; 9];
-        (&mut k[1..9]).copy_from_slice(&tile_ref.0.to_be_bytes());
+        k[1..9].copy_from_slice(&tile_ref.0.to_be_bytes());
         k
     }
 }
@@ -68,14 +146,13 @@ fn key_for_file() {
             path: String::from("/here"),
             modified: 1234,
             file_size: 456,
-            ..Default::default()
         })
         .0,
-        "M/here:5289273993602405726"
+        "M/here:8963103821064414781"
     );
 }
 
-// Wrap database types.
+/// Wrap database types.
 pub struct Data(sled::IVec);
 
 impl Deref for Data {
@@ -85,35 +162,540 @@ impl Deref for Data {
     }
 }
 
+/// Why thumbnailing a file failed and how often.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Failure {
+    pub error: String,
+    pub attempts: u32,
+}
+
+impl Failure {
+    pub fn is_permanent(&self) -> bool {
+        self.attempts >= MAX_ATTEMPTS
+    }
+}
+
+/// Seconds since the unix epoch of the last change to each field added by hand, including
+/// removals, for merging with another machine's. None for fields never changed since times were
+/// kept.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Edited {
+    pub rating: Option<u64>,
+    pub label: Option<u64>,
+    pub note: Option<u64>,
+    pub adjustments: Option<u64>,
+}
+
+impl Edited {
+    /// Every field changed at `time`.
+    pub fn all(time: Option<u64>) -> Self {
+        Self {
+            rating: time,
+            label: time,
+            note: time,
+            adjustments: time,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Writes kept out of the on disk database when it is opened read-only.
+struct Overlay {
+    tree: sled::Tree,
+
+    // Keys removed from the base tree.
+    removed: sled::Tree,
+}
+
+impl Overlay {
+    fn new(db: &sled::Db, name: &str) -> sled::Result<Self> {
+        let tree = db.open_tree(name)?;
+        let removed = db.open_tree(format!("{}-removed", name))?;
+        Ok(Self { tree, removed })
+    }
+
+    fn get(&self, base: &sled::Tree, k: &[u8]) -> sled::Result<Option<IVec>> {
+        if let Some(v) = self.tree.get(k)? {
+            return Ok(Some(v));
+        }
+        if self.removed.contains_key(k)? {
+            return Ok(None);
+        }
+        base.get(k)
+    }
+
+    fn scan_prefix(&self, base: &sled::Tree, prefix: &[u8]) -> sled::Result<Vec<(IVec, IVec)>> {
+        let mut merged = BTreeMap::new();
+        for kv in base.scan_prefix(prefix) {
+            let (k, v) = kv?;
+            if !self.removed.contains_key(&k)? {
+                merged.insert(k, v);
+            }
+        }
+        for kv in self.tree.scan_prefix(prefix) {
+            let (k, v) = kv?;
+            merged.insert(k, v);
+        }
+        Ok(merged.into_iter().collect())
+    }
+}
+
+// Keys and values to write together.
+type Writes = Vec<(Vec<u8>, IVec)>;
+
+// A sled tree, with writes going to an overlay instead when read-only.
+struct Store {
+    base: sled::Tree,
+    overlay: Option<Overlay>,
+}
+
+impl Store {
+    // The default tree when `name` is None.
+    fn open(base: &sled::Db, overlay: Option<&sled::Db>, name: Option<&str>) -> sled::Result<Self> {
+        let tree = match name {
+            Some(name) => base.open_tree(name)?,
+            None => sled::Tree::clone(base),
+        };
+        let overlay = match overlay {
+            Some(db) => Some(Overlay::new(db, name.unwrap_or("default"))?),
+            None => None,
+        };
+        Ok(Self {
+            base: tree,
+            overlay,
+        })
+    }
+
+    fn get(&self, k: &[u8]) -> sled::Result<Option<IVec>> {
+        match &self.overlay {
+            Some(overlay) => overlay.get(&self.base, k),
+            None => self.base.get(k),
+        }
+    }
+
+    fn insert(&self, k: &[u8], v: impl Into<IVec>) -> sled::Result<()> {
+        match &self.overlay {
+            Some(overlay) => {
+                overlay.removed.remove(k)?;
+                overlay.tree.insert(k, v)?;
+            }
+            None => {
+                self.base.insert(k, v)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Insert `writes` here and `other_writes` into `other` in one transaction, after a crash
+    // either all of them are there or none.
+    fn insert_with(
+        &self,
+        writes: &Writes,
+        other: &Store,
+        other_writes: &Writes,
+    ) -> sled::Result<()> {
+        let res = match (&self.overlay, &other.overlay) {
+            (None, None) => (&self.base, &other.base).transaction(|(a, b)| {
+                for (k, v) in writes {
+                    a.insert(k.as_slice(), v.clone())?;
+                }
+                for (k, v) in other_writes {
+                    b.insert(k.as_slice(), v.clone())?;
+                }
+                Ok::<_, sled::transaction::ConflictableTransactionError<Infallible>>(())
+            }),
+            (Some(a), Some(b)) => (&a.tree, &a.removed, &b.tree, &b.removed).transaction(
+                |(a_tree, a_removed, b_tree, b_removed)| {
+                    for (k, v) in writes {
+                        a_removed.remove(k.as_slice())?;
+                        a_tree.insert(k.as_slice(), v.clone())?;
+                    }
+                    for (k, v) in other_writes {
+                        b_removed.remove(k.as_slice())?;
+                        b_tree.insert(k.as_slice(), v.clone())?;
+                    }
+                    Ok(())
+                },
+            ),
+            _ => unreachable!("stores of one database are all read-only or none"),
+        };
+        res.map_err(|e| match e {
+            TransactionError::Abort(never) => match never {},
+            TransactionError::Storage(e) => e,
+        })
+    }
+
+    fn remove(&self, k: &[u8]) -> sled::Result<()> {
+        match &self.overlay {
+            Some(overlay) => {
+                overlay.tree.remove(k)?;
+                overlay.removed.insert(k, &[])?;
+            }
+            None => {
+                self.base.remove(k)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_prefix<'a>(
+        &'a self,
+        prefix: &[u8],
+    ) -> Box<dyn Iterator<Item = sled::Result<(IVec, IVec)>> + 'a> {
+        match &self.overlay {
+            Some(overlay) => match overlay.scan_prefix(&self.base, prefix) {
+                Ok(kvs) => Box::new(kvs.into_iter().map(Ok)),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            },
+            None => Box::new(self.base.scan_prefix(prefix)),
+        }
+    }
+
+    fn flush(&self) -> sled::Result<()> {
+        match &self.overlay {
+            Some(overlay) => overlay.tree.flush()?,
+            None => self.base.flush()?,
+        };
+        Ok(())
+    }
+}
+
+/// The sled database pix keeps thumbnail tiles, metadata, failures and per-image edits in.
+/// Metadata is keyed by file path, modification time and size, tiles by `TileRef`, edits by path
+/// only.
 pub struct Database {
-    db: sled::Db,
+    // Metadata, user data, presets and failures.
+    db: Store,
+
+    // Thumbnail tiles, kept apart so metadata lookups at startup don't page through tiles.
+    tiles: Store,
+
+    // Seals metadata and tile values when the cache is encrypted.
+    cipher: Option<Cipher>,
+
+    // Files under it are keyed by their relative path in a portable cache.
+    root: Option<PathBuf>,
+}
+
+// A copy of the database at `path` in the temp directory, deleted when dropped.
+fn snapshot(path: &Path) -> R<sled::Db> {
+    fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            let to = to.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                copy_dir(&entry.path(), &to)?;
+            } else {
+                std::fs::copy(entry.path(), to)?;
+            }
+        }
+        Ok(())
+    }
+
+    static COPIES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let n = COPIES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let copy = std::env::temp_dir().join(format!("pix-snapshot-{}-{}", std::process::id(), n));
+    if let Err(e) = copy_dir(path, &copy) {
+        let _ = std::fs::remove_dir_all(&copy);
+        return Err(E::IoError(e));
+    }
+
+    sled::Config::new()
+        .path(&copy)
+        .temporary(true)
+        .open()
+        .map_err(E::DatabaseError)
 }
 
 impl Database {
+    fn with_stores(base: &sled::Db, overlay: Option<&sled::Db>) -> R<Self> {
+        Ok(Self {
+            db: Store::open(base, overlay, None).map_err(E::DatabaseError)?,
+            tiles: Store::open(base, overlay, Some("tiles")).map_err(E::DatabaseError)?,
+            cipher: None,
+            root: None,
+        })
+    }
+
+    /// Key files under `root` by their path relative to it, so a cache on a removable drive still
+    /// matches its photos when the drive is mounted elsewhere.
+    pub fn set_root(&mut self, root: PathBuf) {
+        info!("Paths relative to {:?}", root);
+        self.root = Some(root);
+    }
+
+    // Relative paths use forward slashes on every platform.
+    fn relative<'a>(&self, file: &'a File) -> Cow<'a, File> {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return Cow::Borrowed(file),
+        };
+        match Path::new(&file.path).strip_prefix(root) {
+            Ok(rel) => Cow::Owned(File {
+                path: crate::system::slashes(&rel.to_string_lossy()).into_owned(),
+                ..file.clone()
+            }),
+            Err(_) => Cow::Borrowed(file),
+        }
+    }
+
+    fn absolute(&self, path: &str) -> String {
+        match &self.root {
+            Some(root) if Path::new(path).is_relative() => root
+                .join(path.replace('/', std::path::MAIN_SEPARATOR_STR))
+                .to_string_lossy()
+                .into_owned(),
+            _ => path.to_owned(),
+        }
+    }
+
     pub fn open(path: &Path) -> R<Self> {
-        info!("database path: {:?}", path);
+        crate::errlog::event(
+            Level::Info,
+            "db_open",
+            serde_json::json!({"path": path, "readonly": false}),
+        );
 
-        let db = sled::open(path).map_err(E::DatabaseError)?;
+        let base = sled::open(path).map_err(E::DatabaseError)?;
+        let db = Self::with_stores(&base, None)?;
+        db.move_tiles()?;
 
-        Ok(Self { db })
+        Ok(db)
+    }
+
+    // Tiles used to be kept with the metadata.
+    fn move_tiles(&self) -> R<()> {
+        let mut n = 0;
+        for kv in self.db.scan_prefix(&[TILE_PREFIX as u8]) {
+            let (k, v) = kv.map_err(E::DatabaseError)?;
+            self.tiles.insert(&k, v).map_err(E::DatabaseError)?;
+            self.db.remove(&k).map_err(E::DatabaseError)?;
+            n += 1;
+        }
+        if n > 0 {
+            info!("Moved {} tiles out of the metadata", n);
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Nothing is written to the database at `path`, new thumbnails and edits are lost on exit.
+    pub fn open_readonly(path: &Path) -> R<Self> {
+        crate::errlog::event(
+            Level::Info,
+            "db_open",
+            serde_json::json!({"path": path, "readonly": true}),
+        );
+
+        // sled needs write access and its lock, e.g. a read-only mount or a cache another pix has
+        // open is read from a copy instead.
+        let base = match sled::open(path) {
+            Ok(base) => base,
+            Err(e) => {
+                warn!("Reading a copy of {:?}: {}", path, e);
+                snapshot(path)?
+            }
+        };
+        let overlay = sled::Config::new()
+            .temporary(true)
+            .open()
+            .map_err(E::DatabaseError)?;
+
+        Self::with_stores(&base, Some(&overlay))
+    }
+
+    /// Deleted when dropped.
+    pub fn temporary() -> R<Self> {
+        let base = sled::Config::new()
+            .temporary(true)
+            .open()
+            .map_err(E::DatabaseError)?;
+
+        Self::with_stores(&base, None)
+    }
+
+    /// Whether a passphrase was ever used with this database.
+    pub fn is_encrypted(&self) -> R<bool> {
+        Ok(self.db.get(SALT).map_err(E::DatabaseError)?.is_some())
+    }
+
+    /// Encrypt metadata and tiles, the ones written before are sealed the first time. Paths in
+    /// keys and user data stay readable.
+    pub fn encrypt(&mut self, passphrase: &str) -> R<()> {
+        let (salt, new) = match self.db.get(SALT).map_err(E::DatabaseError)? {
+            Some(salt) => (salt.to_vec(), false),
+            None => {
+                let salt = crypt::new_salt();
+                self.db.insert(SALT, &salt[..]).map_err(E::DatabaseError)?;
+                (salt.to_vec(), true)
+            }
+        };
+
+        let cipher = Cipher::new(passphrase, &salt)?;
+        match self.db.get(KEY_CHECK).map_err(E::DatabaseError)? {
+            Some(check) => {
+                if cipher.open(&check).is_none() {
+                    return Err(E::CryptError(String::from("wrong passphrase")));
+                }
+            }
+            None => {
+                self.db
+                    .insert(KEY_CHECK, cipher.seal(KEY_CHECK))
+                    .map_err(E::DatabaseError)?;
+            }
+        }
+
+        self.cipher = Some(cipher);
+        if new {
+            self.seal_existing()?;
+        }
+        Ok(())
+    }
+
+    // Seal the metadata and tiles left in plain text from before encrypting.
+    fn seal_existing(&self) -> R<()> {
+        let mut n = 0;
+        for (store, prefix) in [(&self.db, METADATA_PREFIX), (&self.tiles, TILE_PREFIX)] {
+            for kv in store.scan_prefix(&[prefix as u8]) {
+                let (k, v) = kv.map_err(E::DatabaseError)?;
+                store
+                    .insert(&k, self.seal(v.to_vec()))
+                    .map_err(E::DatabaseError)?;
+                n += 1;
+            }
+        }
+        if n > 0 {
+            info!("Encrypted {} existing entries", n);
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn seal(&self, plain: Vec<u8>) -> Vec<u8> {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(&plain),
+            None => plain,
+        }
+    }
+
+    // None for values not sealed with the current key.
+    fn unseal(&self, v: IVec) -> Option<IVec> {
+        match &self.cipher {
+            Some(cipher) => cipher.open(&v).map(IVec::from),
+            None => Some(v),
+        }
+    }
+
+    pub fn flush(&self) -> R<()> {
+        self.db.flush().map_err(E::DatabaseError)?;
+        self.tiles.flush().map_err(E::DatabaseError)?;
+        Ok(())
     }
 
     pub fn get_metadata(&self, file: &File) -> R<Option<Metadata>> {
-        let k = Key::for_file(file);
+        let k = Key::for_file(&self.relative(file));
 
-        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
-            let metadata: Metadata = deserialize(&*v).map_err(E::DecodeError)?;
+        let v = self.db.get(k.as_ref()).map_err(E::DatabaseError)?;
+        if let Some(v) = v.and_then(|v| self.unseal(v)) {
+            let metadata: Metadata = deserialize(&v).map_err(E::DecodeError)?;
 
+            stats::count("metadata_hits");
             Ok(Some(metadata))
         } else {
+            stats::count("metadata_misses");
+            if self.has_stale_metadata(file)? {
+                stats::count("metadata_stale");
+            }
             Ok(None)
         }
     }
 
-    pub fn set_metadata(&self, file: &File, metadata: &Metadata) -> R<()> {
-        let k = Key::for_file(file);
+    // Metadata of an older version of `file`, e.g. after a backup tool touched its mtime.
+    fn has_stale_metadata(&self, file: &File) -> R<bool> {
+        let prefix = format!("{}{}:", METADATA_PREFIX, self.relative(file).path);
+        match self.db.scan_prefix(prefix.as_bytes()).next() {
+            Some(kv) => kv.map(|_| true).map_err(E::DatabaseError),
+            None => Ok(false),
+        }
+    }
 
+    /// Store the metadata of `file` with its tiles. A crash never leaves metadata referencing
+    /// missing tiles, or tiles without metadata.
+    pub fn set_thumbnail<'a>(
+        &self,
+        file: &File,
+        metadata: &Metadata,
+        tiles: impl IntoIterator<Item = (TileRef, &'a [u8])>,
+    ) -> R<()> {
         let encoded: Vec<u8> = serialize(metadata).map_err(E::EncodeError)?;
+        let meta_writes = vec![(
+            Key::for_file(&self.relative(file)).as_ref().to_vec(),
+            IVec::from(self.seal(encoded)),
+        )];
+        let tile_writes = tiles
+            .into_iter()
+            .map(|(tile_ref, data)| {
+                let k = Key::for_thumb(tile_ref);
+                (k.as_ref().to_vec(), IVec::from(self.seal(data.to_vec())))
+            })
+            .collect();
+
+        self.db
+            .insert_with(&meta_writes, &self.tiles, &tile_writes)
+            .map_err(E::DatabaseError)?;
+
+        stats::count("thumbnails_made");
+        Ok(())
+    }
+
+    pub fn get_failure(&self, file: &File) -> R<Option<Failure>> {
+        let k = Key::for_failure(&self.relative(file));
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(Some(deserialize(&v).map_err(E::DecodeError)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Count another failed attempt to thumbnail `file`.
+    pub fn add_failure(&self, file: &File, error: &E) -> R<Failure> {
+        let k = Key::for_failure(&self.relative(file));
+
+        let mut failure = self.get_failure(file)?.unwrap_or_default();
+        failure.error = error.to_string();
+        failure.attempts += 1;
+
+        let encoded: Vec<u8> = serialize(&failure).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(failure)
+    }
+
+    /// Never try `file` again until it changes.
+    pub fn quarantine(&self, file: &File, error: &E) -> R<()> {
+        let k = Key::for_failure(&self.relative(file));
+
+        let failure = Failure {
+            error: error.to_string(),
+            attempts: MAX_ATTEMPTS,
+        };
+
+        let encoded: Vec<u8> = serialize(&failure).map_err(E::EncodeError)?;
 
         self.db
             .insert(k.as_ref(), encoded)
@@ -122,18 +704,327 @@ impl Database {
         Ok(())
     }
 
+    /// Paths and failures of all files that failed.
+    pub fn failures(&self) -> R<Vec<(String, Failure)>> {
+        let mut ret = Vec::new();
+
+        for kv in self.db.scan_prefix(&[FAILURE_PREFIX as u8]) {
+            let (k, v) = kv.map_err(E::DatabaseError)?;
+            let k = String::from_utf8_lossy(&k[1..]).into_owned();
+            let path = k.rsplit_once(':').map_or(k.as_str(), |(path, _)| path);
+            let failure: Failure = deserialize(&v).map_err(E::DecodeError)?;
+            ret.push((self.absolute(path), failure));
+        }
+
+        Ok(ret)
+    }
+
+    pub fn remove_failure(&self, file: &File) -> R<()> {
+        let k = Key::for_failure(&self.relative(file));
+        self.db.remove(k.as_ref()).map_err(E::DatabaseError)?;
+        Ok(())
+    }
+
+    /// Forget all failures so every file is tried again.
+    pub fn clear_failures(&self) -> R<usize> {
+        let mut n = 0;
+        for kv in self.db.scan_prefix(&[FAILURE_PREFIX as u8]) {
+            let (k, _) = kv.map_err(E::DatabaseError)?;
+            self.db.remove(&k).map_err(E::DatabaseError)?;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    pub fn get_checksum(&self, file: &File) -> R<Option<Checksum>> {
+        let k = Key::for_checksum(&self.relative(file));
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(Some(deserialize(&v).map_err(E::DecodeError)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_checksum(&self, file: &File, checksum: &Checksum) -> R<()> {
+        let k = Key::for_checksum(&self.relative(file));
+
+        let encoded: Vec<u8> = serialize(checksum).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Paths of files whose contents changed while they looked unchanged.
+    pub fn mismatched_checksums(&self) -> R<Vec<String>> {
+        let mut ret = Vec::new();
+
+        for kv in self.db.scan_prefix(&[CHECKSUM_PREFIX as u8]) {
+            let (k, v) = kv.map_err(E::DatabaseError)?;
+            let checksum: Checksum = deserialize(&v).map_err(E::DecodeError)?;
+            if checksum.mismatch {
+                ret.push(self.absolute(&String::from_utf8_lossy(&k[1..])));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn get_user_data(&self, file: &File) -> R<UserData> {
+        let k = Key::for_user_data(&self.relative(file));
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(deserialize(&v).map_err(E::DecodeError)?)
+        } else {
+            Ok(UserData::default())
+        }
+    }
+
+    pub fn set_user_data(&self, file: &File, user_data: &UserData) -> R<()> {
+        let old = self.get_user_data(file)?;
+        self.touch(file, |edited, now| {
+            if old.rating != user_data.rating {
+                edited.rating = now;
+            }
+            if old.label != user_data.label {
+                edited.label = now;
+            }
+        })?;
+        let k = Key::for_user_data(&self.relative(file));
+
+        if user_data.is_empty() {
+            self.db.remove(k.as_ref()).map_err(E::DatabaseError)?;
+            return Ok(());
+        }
+
+        let encoded: Vec<u8> = serialize(user_data).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    pub fn get_adjustments(&self, file: &File) -> R<Adjustments> {
+        let k = Key::for_adjustments(&self.relative(file));
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(deserialize(&v).map_err(E::DecodeError)?)
+        } else {
+            Ok(Adjustments::default())
+        }
+    }
+
+    pub fn set_adjustments(&self, file: &File, adjustments: &Adjustments) -> R<()> {
+        if self.get_adjustments(file)? != *adjustments {
+            self.touch(file, |edited, now| edited.adjustments = now)?;
+        }
+        let k = Key::for_adjustments(&self.relative(file));
+
+        if adjustments.is_default() {
+            self.db.remove(k.as_ref()).map_err(E::DatabaseError)?;
+            return Ok(());
+        }
+
+        let encoded: Vec<u8> = serialize(adjustments).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    pub fn get_note(&self, file: &File) -> R<Option<String>> {
+        let k = Key::for_note(&self.relative(file));
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(Some(deserialize(&v).map_err(E::DecodeError)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// An empty note removes it.
+    pub fn set_note(&self, file: &File, note: &str) -> R<()> {
+        if self.get_note(file)?.unwrap_or_default() != note {
+            self.touch(file, |edited, now| edited.note = now)?;
+        }
+        let k = Key::for_note(&self.relative(file));
+
+        if note.is_empty() {
+            self.db.remove(k.as_ref()).map_err(E::DatabaseError)?;
+            return Ok(());
+        }
+
+        let encoded: Vec<u8> = serialize(note).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// When the user data, note and adjustments were last changed.
+    pub fn get_edited(&self, file: &File) -> R<Edited> {
+        let k = Key::for_edited(&self.relative(file));
+
+        match self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            // A single time for all fields was kept at first.
+            Some(v) if v.len() == 8 => {
+                Ok(Edited::all(Some(deserialize(&v).map_err(E::DecodeError)?)))
+            }
+            Some(v) => Ok(deserialize(&v).map_err(E::DecodeError)?),
+            None => Ok(Edited::default()),
+        }
+    }
+
+    pub fn set_edited(&self, file: &File, edited: &Edited) -> R<()> {
+        let k = Key::for_edited(&self.relative(file));
+
+        if edited.is_empty() {
+            self.db.remove(k.as_ref()).map_err(E::DatabaseError)?;
+            return Ok(());
+        }
+
+        let encoded: Vec<u8> = serialize(edited).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    // Time the fields `f` sets as changed now.
+    fn touch(&self, file: &File, f: impl FnOnce(&mut Edited, Option<u64>)) -> R<()> {
+        let mut edited = self.get_edited(file)?;
+        f(&mut edited, Some(now()));
+        self.set_edited(file, &edited)
+    }
+
+    pub fn get_preset(&self, name: &str) -> R<Option<String>> {
+        let k = Key::for_preset(name);
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(Some(deserialize(&v).map_err(E::DecodeError)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_preset(&self, name: &str, spec: &str) -> R<()> {
+        let k = Key::for_preset(name);
+
+        let encoded: Vec<u8> = serialize(spec).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Preferences changed in the app, e.g. night mode, kept between runs.
+    pub fn get_setting(&self, name: &str) -> R<Option<String>> {
+        let k = Key::for_setting(name);
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            Ok(Some(deserialize(&v).map_err(E::DecodeError)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_setting(&self, name: &str, value: &str) -> R<()> {
+        let k = Key::for_setting(name);
+
+        let encoded: Vec<u8> = serialize(value).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// The files the last search of `paths` found.
+    pub fn get_scan(&self, paths: &[PathBuf]) -> R<Option<Vec<File>>> {
+        let k = Key::for_scan(&self.relative_paths(paths));
+
+        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+            let files: Vec<File> = deserialize(&v).map_err(E::DecodeError)?;
+            let files = files
+                .into_iter()
+                .map(|file| File {
+                    path: self.absolute(&file.path),
+                    ..file
+                })
+                .collect();
+            Ok(Some(files))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn relative_paths(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        match &self.root {
+            Some(root) => paths
+                .iter()
+                .map(|path| path.strip_prefix(root).unwrap_or(path).to_path_buf())
+                .collect(),
+            None => paths.to_vec(),
+        }
+    }
+
+    pub fn set_scan(&self, paths: &[PathBuf], files: &[Arc<File>]) -> R<()> {
+        let k = Key::for_scan(&self.relative_paths(paths));
+
+        let files: Vec<Cow<File>> = files.iter().map(|file| self.relative(file)).collect();
+        let encoded: Vec<u8> = serialize(&files).map_err(E::EncodeError)?;
+
+        self.db
+            .insert(k.as_ref(), encoded)
+            .map_err(E::DatabaseError)?;
+
+        Ok(())
+    }
+
+    pub fn presets(&self) -> R<Vec<(String, String)>> {
+        let mut ret = Vec::new();
+
+        for kv in self.db.scan_prefix(&[PRESET_PREFIX as u8]) {
+            let (k, v) = kv.map_err(E::DatabaseError)?;
+            let name = String::from_utf8_lossy(&k[1..]).into_owned();
+            let spec: String = deserialize(&v).map_err(E::DecodeError)?;
+            ret.push((name, spec));
+        }
+
+        Ok(ret)
+    }
+
     pub fn set(&self, tile_ref: TileRef, data: &[u8]) -> R<()> {
         let k = Key::for_thumb(tile_ref);
-        self.db.insert(&k, data).map_err(E::DatabaseError)?;
+        self.tiles
+            .insert(&k, self.seal(data.to_vec()))
+            .map_err(E::DatabaseError)?;
 
         Ok(())
     }
 
     pub fn get(&self, tile_ref: TileRef) -> R<Option<Data>> {
         let k = Key::for_thumb(tile_ref);
-        if let Some(v) = self.db.get(k.as_ref()).map_err(E::DatabaseError)? {
+        let v = self.tiles.get(k.as_ref()).map_err(E::DatabaseError)?;
+        if let Some(v) = v.and_then(|v| self.unseal(v)) {
+            stats::count("tile_hits");
             Ok(Some(Data(v)))
         } else {
+            stats::count("tile_misses");
             Ok(None)
         }
     }
@@ -154,6 +1045,268 @@ impl Database {
             .insert(MAX_ID, format!("{}", next_max_id).as_bytes())
             .unwrap();
 
-        std::dbg!(max_id)
+        max_id
+    }
+}
+
+#[test]
+fn failures() {
+    let db = Database::temporary().unwrap();
+    let file = File {
+        path: String::from("/here"),
+        modified: 1234,
+        file_size: 456,
+    };
+    let e = E::MissingData(String::from("tiles"));
+
+    assert_eq!(db.get_failure(&file).unwrap(), None);
+    for _ in 0..MAX_ATTEMPTS {
+        assert!(!db
+            .get_failure(&file)
+            .unwrap()
+            .unwrap_or_default()
+            .is_permanent());
+        db.add_failure(&file, &e).unwrap();
+    }
+    assert!(db.get_failure(&file).unwrap().unwrap().is_permanent());
+
+    // A modified file is tried again.
+    let modified = File {
+        modified: 5678,
+        ..file.clone()
+    };
+    assert_eq!(db.get_failure(&modified).unwrap(), None);
+
+    db.remove_failure(&file).unwrap();
+    assert_eq!(db.get_failure(&file).unwrap(), None);
+    db.quarantine(&modified, &e).unwrap();
+    let failures = db.failures().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, "/here");
+    assert!(failures[0].1.is_permanent());
+    db.remove_failure(&modified).unwrap();
+
+    db.add_failure(&file, &e).unwrap();
+    assert_eq!(db.clear_failures().unwrap(), 1);
+}
+
+#[test]
+fn readonly() {
+    let dir = TestDir::new("readonly");
+    let file = File {
+        path: String::from("/here"),
+        modified: 1234,
+        file_size: 456,
+    };
+    let e = E::MissingData(String::from("tiles"));
+
+    {
+        let db = Database::open(&dir).unwrap();
+        db.set(TileRef(1), b"base").unwrap();
+        db.quarantine(&file, &e).unwrap();
+        db.flush().unwrap();
     }
+
+    {
+        let db = Database::open_readonly(&dir).unwrap();
+        assert_eq!(db.get(TileRef(1)).unwrap().as_deref(), Some(&b"base"[..]));
+        db.set(TileRef(2), b"overlay").unwrap();
+        assert_eq!(
+            db.get(TileRef(2)).unwrap().as_deref(),
+            Some(&b"overlay"[..])
+        );
+
+        let metadata = Metadata::default();
+        db.set_thumbnail(&file, &metadata, [(TileRef(3), &b"tile"[..])])
+            .unwrap();
+        assert_eq!(db.get_metadata(&file).unwrap(), Some(metadata));
+        assert_eq!(db.get(TileRef(3)).unwrap().as_deref(), Some(&b"tile"[..]));
+
+        // Removing a key of the base database hides it.
+        assert_eq!(db.failures().unwrap().len(), 1);
+        assert_eq!(db.clear_failures().unwrap(), 1);
+        assert_eq!(db.failures().unwrap().len(), 0);
+        db.flush().unwrap();
+    }
+
+    let db = Database::open(&dir).unwrap();
+    assert!(db.get(TileRef(1)).unwrap().is_some());
+    assert!(db.get(TileRef(2)).unwrap().is_none());
+    assert_eq!(db.failures().unwrap().len(), 1);
+    assert!(db.get_metadata(&file).unwrap().is_none());
+    drop(db);
+
+    // A cache on a read-only mount, or one another pix holds the lock of.
+    let set_readonly = |readonly| {
+        let mut permissions = std::fs::metadata(&dir).unwrap().permissions();
+        permissions.set_readonly(readonly);
+        std::fs::set_permissions(&dir, permissions).unwrap();
+    };
+    set_readonly(true);
+    {
+        let db = Database::open_readonly(&dir).unwrap();
+        assert_eq!(db.get(TileRef(1)).unwrap().as_deref(), Some(&b"base"[..]));
+        db.set(TileRef(2), b"overlay").unwrap();
+        let copy = snapshot(&dir).unwrap();
+        assert!(copy
+            .open_tree("tiles")
+            .unwrap()
+            .get(Key::for_thumb(TileRef(1)))
+            .unwrap()
+            .is_some());
+    }
+    set_readonly(false);
+}
+
+#[test]
+fn encrypted() {
+    let mut db = Database::temporary().unwrap();
+    let file = File {
+        path: String::from("/here"),
+        modified: 1234,
+        file_size: 456,
+    };
+    let metadata = Metadata {
+        info: vec![(String::from("Comment"), String::from("plain"))],
+        ..Default::default()
+    };
+    db.set_thumbnail(&file, &metadata, [(TileRef(1), &b"plain"[..])])
+        .unwrap();
+    assert!(!db.is_encrypted().unwrap());
+
+    db.encrypt("secret").unwrap();
+    assert!(db.is_encrypted().unwrap());
+    // What was there before is sealed, no plain text is left.
+    assert_eq!(db.get(TileRef(1)).unwrap().as_deref(), Some(&b"plain"[..]));
+    assert_eq!(db.get_metadata(&file).unwrap(), Some(metadata));
+    let plain = |v: IVec| v.windows(5).any(|w| w == b"plain");
+    for kv in db.db.scan_prefix(&[METADATA_PREFIX as u8]) {
+        assert!(!plain(kv.unwrap().1));
+    }
+    for kv in db.tiles.scan_prefix(&[TILE_PREFIX as u8]) {
+        assert!(!plain(kv.unwrap().1));
+    }
+
+    db.set(TileRef(2), b"sealed").unwrap();
+    assert_eq!(db.get(TileRef(2)).unwrap().as_deref(), Some(&b"sealed"[..]));
+    assert!(!db
+        .tiles
+        .get(&Key::for_thumb(TileRef(2)))
+        .unwrap()
+        .unwrap()
+        .windows(6)
+        .any(|w| w == b"sealed"));
+
+    db.cipher = None;
+    db.encrypt("secret").unwrap();
+    db.cipher = None;
+    assert!(db.encrypt("guess").is_err());
+}
+
+#[test]
+fn move_tiles() {
+    let db = Database::temporary().unwrap();
+    let k = Key::for_thumb(TileRef(3));
+    db.db.insert(&k, &b"old"[..]).unwrap();
+    db.db.insert(MAX_ID, &b"7"[..]).unwrap();
+
+    db.move_tiles().unwrap();
+    assert_eq!(db.get(TileRef(3)).unwrap().as_deref(), Some(&b"old"[..]));
+    assert!(db.db.get(&k).unwrap().is_none());
+    assert_eq!(db.reserve(1), 7);
+}
+
+#[test]
+fn settings() {
+    let dir = TestDir::new("settings");
+    let db = Database::open(&dir).unwrap();
+
+    assert_eq!(db.get_setting("night").unwrap(), None);
+    db.set_setting("night", "true").unwrap();
+    assert_eq!(db.get_setting("night").unwrap().as_deref(), Some("true"));
+    // Not mistaken for a preset.
+    assert!(db.presets().unwrap().is_empty());
+}
+
+#[test]
+fn scans() {
+    let dir = TestDir::new("scans");
+    let db = Database::open(&dir).unwrap();
+
+    let paths = vec![PathBuf::from("/a"), PathBuf::from("/b")];
+    assert_eq!(db.get_scan(&paths).unwrap(), None);
+
+    let files = vec![Arc::new(File::default())];
+    db.set_scan(&paths, &files).unwrap();
+    assert_eq!(db.get_scan(&paths).unwrap(), Some(vec![File::default()]));
+    // Each set of paths has its own list.
+    assert_eq!(db.get_scan(&paths[..1]).unwrap(), None);
+}
+
+#[test]
+fn portable() {
+    let dir = TestDir::new("portable");
+    let mut db = Database::open(&dir).unwrap();
+    let file = |path: &str| File {
+        path: path.to_owned(),
+        modified: 1,
+        file_size: 2,
+    };
+    let user_data = UserData {
+        rating: 3,
+        ..Default::default()
+    };
+
+    db.set_root(PathBuf::from("/media/a"));
+    db.set_user_data(&file("/media/a/photos/x.jpg"), &user_data)
+        .unwrap();
+    db.add_failure(
+        &file("/media/a/photos/y.jpg"),
+        &E::MissingData("y".to_owned()),
+    )
+    .unwrap();
+
+    // The same drive mounted elsewhere.
+    db.set_root(PathBuf::from("/media/b"));
+    assert_eq!(
+        db.get_user_data(&file("/media/b/photos/x.jpg")).unwrap(),
+        user_data
+    );
+    let failures = db.failures().unwrap();
+    assert_eq!(
+        failures[0].0,
+        Path::new("/media/b/photos/y.jpg").to_string_lossy()
+    );
+}
+
+#[test]
+fn notes() {
+    let dir = TestDir::new("notes");
+    let db = Database::open(&dir).unwrap();
+    let file = File {
+        path: "/photos/x.jpg".to_owned(),
+        modified: 1,
+        file_size: 2,
+    };
+
+    assert_eq!(db.get_note(&file).unwrap(), None);
+    db.set_note(&file, "sharpest of the burst").unwrap();
+    assert_eq!(
+        db.get_note(&file).unwrap().as_deref(),
+        Some("sharpest of the burst")
+    );
+    db.set_note(&file, "").unwrap();
+    assert_eq!(db.get_note(&file).unwrap(), None);
+
+    // Edits are timed for merging, removals too.
+    let edited = db.get_edited(&file).unwrap();
+    assert!(edited.note.unwrap() > 0);
+    assert_eq!(edited.rating, None);
+    db.set_edited(&file, &Edited::default()).unwrap();
+    assert_eq!(db.get_edited(&file).unwrap(), Edited::default());
+
+    // Times kept before they were per field.
+    let k = Key::for_edited(&file);
+    db.db.insert(&k, serialize(&7u64).unwrap()).unwrap();
+    assert_eq!(db.get_edited(&file).unwrap(), Edited::all(Some(7)));
 }
@@ -0,0 +1,154 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Full resolution pixels of the single image view, decoded in the background for the tools that
+// need more than the thumbnail tiles.
+
+use crate::histogram::Histogram;
+use crate::Thumbnailer;
+use crossbeam_channel::{Receiver, Sender};
+use log::*;
+use std::collections::BTreeMap;
+
+pub struct Decoded {
+    pub image: ::image::RgbaImage,
+    pub histogram: Histogram,
+}
+
+impl Decoded {
+    fn open(path: String) -> Option<Self> {
+        let image = match Thumbnailer::open(&path) {
+            Ok((image, _)) => image.into_rgba8(),
+            Err(e) => {
+                error!("unable to decode {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        let histogram = Histogram::from_image(&image);
+        Some(Self { image, histogram })
+    }
+}
+
+pub struct Loader {
+    tx: Sender<(String, Option<Decoded>)>,
+    rx: Receiver<(String, Option<Decoded>)>,
+
+    // Decoded images by path, `None` while decoding or when decoding failed.
+    cache: BTreeMap<String, Option<Decoded>>,
+
+    current: Option<String>,
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        Self {
+            tx,
+            rx,
+            cache: BTreeMap::new(),
+            current: None,
+        }
+    }
+}
+
+impl Loader {
+    fn spawn(&mut self, path: &str) {
+        if self.cache.contains_key(path) {
+            return;
+        }
+        self.cache.insert(path.to_owned(), None);
+
+        let tx = self.tx.clone();
+        let path = path.to_owned();
+        std::thread::spawn(move || {
+            let decoded = Decoded::open(path.clone());
+            let _ = tx.send((path, decoded));
+        });
+    }
+
+    // Make `path` the current image, decoding it unless it was preloaded.
+    pub fn load(&mut self, path: &str) {
+        self.spawn(path);
+        self.current = Some(path.to_owned());
+    }
+
+    // Decode the file again after it was written.
+    pub fn reload(&mut self, path: &str) {
+        self.cache.remove(path);
+        self.load(path);
+    }
+
+    // Decode the images likely to be shown next, everything but those and the current image is
+    // dropped.
+    pub fn preload(&mut self, paths: &[&str]) {
+        let current = self.current.as_deref();
+        self.cache
+            .retain(|path, _| Some(path.as_str()) == current || paths.contains(&path.as_str()));
+        for path in paths {
+            self.spawn(path);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.current = None;
+    }
+
+    // Pick up finished decodes.
+    pub fn recv(&mut self) {
+        for (path, decoded) in self.rx.try_iter() {
+            // Dropped while it was decoding.
+            if let Some(slot) = self.cache.get_mut(&path) {
+                *slot = decoded;
+                crate::pacing::changed();
+            }
+        }
+    }
+
+    pub fn get(&self) -> Option<&Decoded> {
+        self.get_path(self.current.as_ref()?)
+    }
+
+    // The decoded image of `path` if it's current or preloaded and done decoding.
+    pub fn get_path(&self, path: &str) -> Option<&Decoded> {
+        self.cache.get(path)?.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preload() {
+        let mut loader = Loader::default();
+        loader.load("/nonexistent/a.png");
+        loader.preload(&["/nonexistent/b.png", "/nonexistent/c.png"]);
+        loader.preload(&["/nonexistent/c.png", "/nonexistent/d.png"]);
+
+        let paths: Vec<&str> = loader.cache.keys().map(|path| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/nonexistent/a.png",
+                "/nonexistent/c.png",
+                "/nonexistent/d.png"
+            ]
+        );
+
+        loader.clear();
+        assert!(loader.get().is_none());
+        assert!(loader.cache.is_empty());
+    }
+}
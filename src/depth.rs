@@ -0,0 +1,147 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Reduce 16-bit images to the 8-bit tiles without banding.
+
+use ::image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+
+type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Depth {
+    // Drop the low byte.
+    Truncate,
+    // Ordered dithering of the low byte.
+    #[default]
+    Dither,
+    // Stretch the used range to the full range, then dither. For scientific and medical images
+    // that only use a fraction of the 16-bit range.
+    Scale,
+}
+
+impl FromStr for Depth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(Depth::Truncate),
+            "dither" => Ok(Depth::Dither),
+            "scale" => Ok(Depth::Scale),
+            _ => Err(format!("unknown depth mode {:?}", s)),
+        }
+    }
+}
+
+static BAYER: [[u16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+pub fn is_16bit(image: &DynamicImage) -> bool {
+    matches!(
+        image,
+        DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_)
+    )
+}
+
+// Darkest and brightest color channel values.
+fn range(values: impl Iterator<Item = u16>) -> (u16, u16) {
+    values.fold((u16::MAX, u16::MIN), |(min, max), v| {
+        (std::cmp::min(min, v), std::cmp::max(max, v))
+    })
+}
+
+// The channel values mapped to black and white: the used range of `image` for `Scale`, so
+// measured once on the full image every level of the thumbnail pyramid maps pixels alike.
+pub fn levels(image: &DynamicImage, depth: Depth) -> (u16, u16) {
+    if depth != Depth::Scale {
+        return (0, u16::MAX);
+    }
+    match image {
+        DynamicImage::ImageLuma16(image) => range(image.pixels().map(|p| p.0[0])),
+        DynamicImage::ImageLumaA16(image) => range(image.pixels().map(|p| p.0[0])),
+        DynamicImage::ImageRgb16(image) => range(image.pixels().flat_map(|p| p.0)),
+        DynamicImage::ImageRgba16(image) => range(image.pixels().flat_map(|p| [p[0], p[1], p[2]])),
+        _ => (0, u16::MAX),
+    }
+}
+
+// Convert 16-bit images to 8-bit with `levels` mapped to black and white, other images are
+// returned unchanged.
+pub fn to_8bit(image: &DynamicImage, depth: Depth, (min, max): (u16, u16)) -> Option<DynamicImage> {
+    if !is_16bit(image) || depth == Depth::Truncate {
+        return None;
+    }
+
+    let src: Rgba16Image = image.to_rgba16();
+
+    let max = std::cmp::max(min, max);
+    let scale = u16::MAX as f32 / f32::max(1.0, (max - min) as f32);
+
+    let dst = RgbaImage::from_fn(src.width(), src.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *src.get_pixel(x, y);
+        // Threshold in 1/16ths of an 8-bit step.
+        let threshold = BAYER[(y % 4) as usize][(x % 4) as usize] * 16 + 8;
+        let quantize = |v: u16| {
+            let v = ((v.saturating_sub(min)) as f32 * scale).min(u16::MAX as f32) as u32;
+            let hi = v >> 8;
+            let lo = (v & 0xFF) as u16;
+            std::cmp::min(255, hi + (lo >= threshold) as u32) as u8
+        };
+        Rgba([quantize(r), quantize(g), quantize(b), (a >> 8) as u8])
+    });
+
+    Some(DynamicImage::ImageRgba8(dst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::Luma;
+
+    #[test]
+    fn dither_keeps_average() {
+        // Halfway between 8-bit levels 100 and 101.
+        let image =
+            DynamicImage::ImageLuma16(ImageBuffer::from_pixel(4, 4, Luma([100 * 256 + 128])));
+        let levels = levels(&image, Depth::Dither);
+        let image = to_8bit(&image, Depth::Dither, levels).unwrap().to_rgba8();
+        let sum: u32 = image.pixels().map(|p| p.0[0] as u32).sum();
+        assert_eq!(sum, 100 * 16 + 8);
+    }
+
+    #[test]
+    fn scale_stretches_range() {
+        let image = DynamicImage::ImageLuma16(ImageBuffer::from_fn(2, 1, |x, _| {
+            Luma([1000 + x as u16 * 3000])
+        }));
+        let levels = levels(&image, Depth::Scale);
+        assert_eq!(levels, (1000, 4000));
+        let scaled = to_8bit(&image, Depth::Scale, levels).unwrap().to_rgba8();
+        assert_eq!(scaled.get_pixel(0, 0).0[0], 0);
+        assert_eq!(scaled.get_pixel(1, 0).0[0], 255);
+
+        // A smaller level of the pyramid keeps the full image's mapping.
+        let level = DynamicImage::ImageLuma16(ImageBuffer::from_pixel(1, 1, Luma([1000])));
+        let level = to_8bit(&level, Depth::Scale, levels).unwrap().to_rgba8();
+        assert_eq!(level.get_pixel(0, 0).0[0], 0);
+    }
+
+    #[test]
+    fn leaves_8bit_alone() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(1, 1));
+        assert!(to_8bit(&image, Depth::Scale, (0, u16::MAX)).is_none());
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Minimal DICOM decoder for uncompressed monochrome images, with the window/level from the file
+// applied.
+
+use crate::{Info, E, R};
+use ::image::{DynamicImage, GrayImage};
+use std::collections::BTreeMap;
+
+type Tag = (u16, u16);
+
+static PIXEL_DATA: Tag = (0x7FE0, 0x0010);
+static TRANSFER_SYNTAX: Tag = (0x0002, 0x0010);
+static ITEM: Tag = (0xFFFE, 0xE000);
+static ITEM_END: Tag = (0xFFFE, 0xE00D);
+static SEQUENCE_END: Tag = (0xFFFE, 0xE0DD);
+static UNDEFINED: u32 = 0xFFFF_FFFF;
+
+static IMPLICIT_LE: &str = "1.2.840.10008.1.2";
+static EXPLICIT_LE: &str = "1.2.840.10008.1.2.1";
+
+// Tags shown in the info overlay.
+static INFO_TAGS: &[(Tag, &str)] = &[
+    ((0x0008, 0x0060), "Modality"),
+    ((0x0008, 0x0020), "Study date"),
+    ((0x0008, 0x1030), "Study"),
+    ((0x0008, 0x103E), "Series"),
+    ((0x0018, 0x0015), "Body part"),
+    ((0x0008, 0x0070), "Manufacturer"),
+    ((0x0028, 0x1050), "Window center"),
+    ((0x0028, 0x1051), "Window width"),
+];
+
+pub fn is_dicom(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".dcm") || path.ends_with(".dicom")
+}
+
+fn err(msg: &str) -> E {
+    E::DicomError(msg.to_owned())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    explicit: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn u16(&mut self) -> R<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> R<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn bytes(&mut self, n: usize) -> R<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| err("truncated file"))?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn header(&mut self) -> R<(Tag, u32)> {
+        let tag = (self.u16()?, self.u16()?);
+
+        // Item delimiters never have a VR.
+        if tag.0 == 0xFFFE || !self.explicit {
+            return Ok((tag, self.u32()?));
+        }
+
+        let vr = self.bytes(2)?;
+        let long = matches!(
+            vr,
+            b"OB"
+                | b"OD"
+                | b"OF"
+                | b"OL"
+                | b"OV"
+                | b"OW"
+                | b"SQ"
+                | b"SV"
+                | b"UC"
+                | b"UN"
+                | b"UR"
+                | b"UT"
+                | b"UV"
+        );
+        if long {
+            self.bytes(2)?;
+            Ok((tag, self.u32()?))
+        } else {
+            Ok((tag, self.u16()? as u32))
+        }
+    }
+
+    // Skip the contents of an undefined length sequence or item. Nested ones are tracked by their
+    // end tags on the heap, deep nesting in a crafted file would overflow the stack.
+    fn skip_undefined(&mut self, end: Tag) -> R<()> {
+        let mut ends = vec![end];
+        while let Some(&end) = ends.last() {
+            let (tag, len) = self.header()?;
+            if tag == end {
+                ends.pop();
+            } else if len == UNDEFINED {
+                ends.push(if tag == ITEM { ITEM_END } else { SEQUENCE_END });
+            } else {
+                self.bytes(len as usize)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Read top level elements up to and including the pixel data.
+    fn elements(&mut self, until: Tag) -> R<BTreeMap<Tag, &'a [u8]>> {
+        let mut ret = BTreeMap::new();
+        while self.pos < self.data.len() {
+            let (tag, len) = self.header()?;
+            if len == UNDEFINED {
+                if tag == PIXEL_DATA {
+                    return Err(err("compressed pixel data is not supported"));
+                }
+                self.skip_undefined(SEQUENCE_END)?;
+                continue;
+            }
+            ret.insert(tag, self.bytes(len as usize)?);
+            if tag == until {
+                break;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn string(elements: &BTreeMap<Tag, &[u8]>, tag: Tag) -> Option<String> {
+    elements.get(&tag).map(|v| {
+        String::from_utf8_lossy(v)
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_owned()
+    })
+}
+
+// Decimal strings may be multi-valued, only the first is used.
+fn number(elements: &BTreeMap<Tag, &[u8]>, tag: Tag) -> Option<f64> {
+    string(elements, tag)?
+        .split('\\')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn us(elements: &BTreeMap<Tag, &[u8]>, tag: Tag) -> Option<u16> {
+    elements
+        .get(&tag)
+        .and_then(|v| Some(u16::from_le_bytes([*v.first()?, *v.get(1)?])))
+}
+
+pub fn open(path: &str) -> R<(DynamicImage, Info)> {
+    let data = std::fs::read(path).map_err(|e| err(&e.to_string()))?;
+    decode(&data)
+}
+
+// The elements of the data set after the file meta group, up to and including the pixel data.
+fn elements(data: &[u8]) -> R<BTreeMap<Tag, &[u8]>> {
+    if data.get(128..132) != Some(b"DICM") {
+        return Err(err("missing DICM preamble"));
+    }
+
+    // The file meta group is always explicit little endian.
+    let mut reader = Reader {
+        data,
+        pos: 132,
+        explicit: true,
+    };
+    let (tag, len) = reader.header()?;
+    if tag != (0x0002, 0x0000) {
+        return Err(err("missing file meta group length"));
+    }
+    let group_len = u32::from_le_bytes(reader.bytes(len as usize)?.try_into().unwrap_or([0; 4]));
+    let meta_end = reader.pos + group_len as usize;
+
+    let mut meta = Reader {
+        data: data.get(..meta_end).ok_or_else(|| err("truncated file"))?,
+        pos: reader.pos,
+        explicit: true,
+    };
+    let meta = meta.elements(PIXEL_DATA)?;
+    let explicit = match string(&meta, TRANSFER_SYNTAX).as_deref() {
+        Some(s) if s == EXPLICIT_LE => true,
+        Some(s) if s == IMPLICIT_LE => false,
+        Some(s) => return Err(err(&format!("unsupported transfer syntax {}", s))),
+        None => return Err(err("missing transfer syntax")),
+    };
+
+    let mut reader = Reader {
+        data,
+        pos: meta_end,
+        explicit,
+    };
+    reader.elements(PIXEL_DATA)
+}
+
+// Columns and rows from the header, without reading the pixels.
+pub fn dimensions(data: &[u8]) -> Option<[u32; 2]> {
+    let elements = elements(data).ok()?;
+    let rows = us(&elements, (0x0028, 0x0010))?;
+    let cols = us(&elements, (0x0028, 0x0011))?;
+    Some([cols as u32, rows as u32])
+}
+
+pub fn decode(data: &[u8]) -> R<(DynamicImage, Info)> {
+    let elements = elements(data)?;
+
+    let rows = us(&elements, (0x0028, 0x0010)).ok_or_else(|| err("missing rows"))? as u32;
+    let cols = us(&elements, (0x0028, 0x0011)).ok_or_else(|| err("missing columns"))? as u32;
+    let bits = us(&elements, (0x0028, 0x0100)).unwrap_or(16);
+    let signed = us(&elements, (0x0028, 0x0103)) == Some(1);
+    let samples = us(&elements, (0x0028, 0x0002)).unwrap_or(1);
+    let photometric = string(&elements, (0x0028, 0x0004)).unwrap_or_default();
+    let pixels = elements
+        .get(&PIXEL_DATA)
+        .ok_or_else(|| err("missing pixel data"))?;
+
+    if samples != 1 {
+        return Err(err("only monochrome images are supported"));
+    }
+
+    let slope = number(&elements, (0x0028, 0x1053)).unwrap_or(1.0);
+    let intercept = number(&elements, (0x0028, 0x1052)).unwrap_or(0.0);
+
+    let count = (rows * cols) as usize;
+    let values: Vec<f64> = match (bits, signed) {
+        (8, false) => pixels.iter().take(count).map(|&v| v as f64).collect(),
+        (8, true) => pixels.iter().take(count).map(|&v| v as i8 as f64).collect(),
+        (16, false) => pixels
+            .chunks_exact(2)
+            .take(count)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        (16, true) => pixels
+            .chunks_exact(2)
+            .take(count)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        _ => return Err(err("unsupported bits allocated")),
+    };
+    if values.len() < count {
+        return Err(err("truncated pixel data"));
+    }
+    let values: Vec<f64> = values.iter().map(|v| v * slope + intercept).collect();
+
+    // Fall back to the full range when the file has no window.
+    let (center, width) = match (
+        number(&elements, (0x0028, 0x1050)),
+        number(&elements, (0x0028, 0x1051)),
+    ) {
+        (Some(center), Some(width)) if width >= 1.0 => (center, width),
+        _ => {
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            ((min + max) / 2.0, f64::max(1.0, max - min))
+        }
+    };
+    let invert = photometric == "MONOCHROME1";
+
+    let image = GrayImage::from_fn(cols, rows, |x, y| {
+        let v = values[(y * cols + x) as usize];
+        // DICOM PS3.3 C.11.2.1.2 linear window function, a threshold at width 1.
+        let v = if width > 1.0 {
+            ((v - (center - 0.5)) / (width - 1.0) + 0.5).clamp(0.0, 1.0)
+        } else if v <= center - 0.5 {
+            0.0
+        } else {
+            1.0
+        };
+        let v = if invert { 1.0 - v } else { v };
+        ::image::Luma([(v * 255.0).round() as u8])
+    });
+
+    let info = INFO_TAGS
+        .iter()
+        .filter_map(|(tag, name)| {
+            string(&elements, *tag)
+                .filter(|v| !v.is_empty())
+                .map(|v| (name.to_string(), v))
+        })
+        .collect();
+
+    Ok((DynamicImage::ImageLuma8(image), info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(out: &mut Vec<u8>, tag: Tag, vr: &[u8; 2], value: &[u8]) {
+        out.extend_from_slice(&tag.0.to_le_bytes());
+        out.extend_from_slice(&tag.1.to_le_bytes());
+        out.extend_from_slice(vr);
+        if vr == b"OW" || vr == b"SQ" {
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        } else {
+            out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        }
+        out.extend_from_slice(value);
+    }
+
+    fn dicom(width: &[u8]) -> Vec<u8> {
+        let mut meta = Vec::new();
+        element(&mut meta, TRANSFER_SYNTAX, b"UI", b"1.2.840.10008.1.2.1\0");
+
+        let mut out = vec![0u8; 128];
+        out.extend_from_slice(b"DICM");
+        element(
+            &mut out,
+            (0x0002, 0x0000),
+            b"UL",
+            &(meta.len() as u32).to_le_bytes(),
+        );
+        out.extend_from_slice(&meta);
+
+        element(&mut out, (0x0008, 0x0060), b"CS", b"CT");
+        // An empty sequence with undefined length.
+        out.extend_from_slice(&[0x08, 0x00, 0x15, 0x11, b'S', b'Q', 0, 0]);
+        out.extend_from_slice(&UNDEFINED.to_le_bytes());
+        out.extend_from_slice(&[0xFE, 0xFF, 0xDD, 0xE0, 0, 0, 0, 0]);
+        element(&mut out, (0x0028, 0x0010), b"US", &1u16.to_le_bytes());
+        element(&mut out, (0x0028, 0x0011), b"US", &2u16.to_le_bytes());
+        element(&mut out, (0x0028, 0x0100), b"US", &16u16.to_le_bytes());
+        element(&mut out, (0x0028, 0x1050), b"DS", b"500 ");
+        element(&mut out, (0x0028, 0x1051), b"DS", width);
+        let pixels: Vec<u8> = [0u16, 1000].iter().flat_map(|v| v.to_le_bytes()).collect();
+        element(&mut out, PIXEL_DATA, b"OW", &pixels);
+        out
+    }
+
+    #[test]
+    fn decode_windowed() {
+        let (image, info) = decode(&dicom(b"201\\50 ")).unwrap();
+        let image = image.to_luma8();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [0]);
+        assert_eq!(image.get_pixel(1, 0).0, [255]);
+        assert_eq!(info[0], ("Modality".to_owned(), "CT".to_owned()));
+    }
+
+    #[test]
+    fn window_width_one() {
+        let (image, _) = decode(&dicom(b"1 ")).unwrap();
+        let image = image.to_luma8();
+        assert_eq!(image.get_pixel(0, 0).0, [0]);
+        assert_eq!(image.get_pixel(1, 0).0, [255]);
+    }
+
+    #[test]
+    fn deeply_nested() {
+        // Items within items, each an 8 byte header without an end.
+        let data = [0xFE, 0xFF, 0x00, 0xE0, 0xFF, 0xFF, 0xFF, 0xFF].repeat(1_000_000);
+        let mut reader = Reader {
+            data: &data,
+            pos: 0,
+            explicit: false,
+        };
+        assert!(reader.skip_undefined(SEQUENCE_END).is_err());
+    }
+
+    #[test]
+    fn not_dicom() {
+        assert!(decode(&[0; 132]).is_err());
+    }
+}
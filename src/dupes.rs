@@ -0,0 +1,211 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix dupes`, steps through sets of identical files to keep one of each and trash the rest.
+
+use crate::checksum;
+use crate::database::Database;
+use crate::filter::date_from_secs;
+use crate::fingerprint;
+use crate::image::Image;
+use crate::File;
+use log::*;
+use piston_window::color;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Neighboring sets alternate between these frames.
+static COLORS: [&str; 2] = ["2196F3", "009688"];
+
+pub struct Dupes {
+    // Files with the same contents, in grid order.
+    sets: Vec<Vec<Arc<File>>>,
+}
+
+// Groups of more than one file with the same key, files whose key can't be read are skipped.
+fn group<K: Ord + Send>(
+    files: Vec<Arc<File>>,
+    key: impl Fn(&File) -> Option<K> + Sync,
+) -> Vec<Vec<Arc<File>>> {
+    let keys: Vec<Option<K>> = files.par_iter().map(|file| key(file)).collect();
+    let mut groups: BTreeMap<K, Vec<Arc<File>>> = BTreeMap::new();
+    for (file, key) in files.into_iter().zip(keys) {
+        match key {
+            Some(key) => groups.entry(key).or_default().push(file),
+            None => warn!("Unable to read {:?}, skipping it", file.path),
+        }
+    }
+    groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+// Group by size, then by a quick fingerprint, and only then hash the files whole.
+fn find(files: Vec<Arc<File>>) -> Vec<Vec<Arc<File>>> {
+    let mut sets: Vec<Vec<Arc<File>>> = group(files, |file| Some(file.file_size))
+        .into_iter()
+        .flat_map(|same_size| group(same_size, |file| fingerprint::of_file(&file.path).ok()))
+        .flat_map(|same_ends| group(same_ends, |file| checksum::sha256_file(&file.path).ok()))
+        .collect();
+    for set in &mut sets {
+        set.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    sets.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    sets
+}
+
+impl Dupes {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        let sets = find(crate::find_images(dirs));
+        info!(
+            "Found {} sets of duplicates, {} files",
+            sets.len(),
+            sets.iter().map(Vec::len).sum::<usize>()
+        );
+        Self { sets }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    // The images of every set one after the other, framed by set.
+    pub fn images(&self, db: &Database) -> Vec<Image> {
+        let mut images = Vec::new();
+        for (n, set) in self.sets.iter().enumerate() {
+            for file in set {
+                let metadata = crate::scan::load_metadata(db, file, false);
+                let user_data = db.get_user_data(file).unwrap_or_default();
+                let mut image = Image::from(images.len(), Arc::clone(file), metadata, user_data);
+                image.highlight = Some(color::hex(COLORS[n % COLORS.len()]));
+                images.push(image);
+            }
+        }
+        images
+    }
+
+    // The number of the set holding image `i`, and the images of that set.
+    pub fn set_of(&self, i: usize) -> Option<(usize, Range<usize>)> {
+        let mut start = 0;
+        for (n, set) in self.sets.iter().enumerate() {
+            let end = start + set.len();
+            if i < end {
+                return Some((n, start..end));
+            }
+            start = end;
+        }
+        None
+    }
+
+    // The first image of the set `delta` sets away from the one holding image `i`, wrapping
+    // around, or of the first set.
+    pub fn step(&self, i: Option<usize>, delta: isize) -> Option<usize> {
+        let len = self.sets.len() as isize;
+        if len == 0 {
+            return None;
+        }
+        let n = match i.and_then(|i| self.set_of(i)) {
+            Some((n, _)) => (n as isize + delta).rem_euclid(len) as usize,
+            None => 0,
+        };
+        self.start(n)
+    }
+
+    // The first image of set `n`, or of the last set if there are fewer.
+    pub fn start(&self, n: usize) -> Option<usize> {
+        let n = usize::min(n, self.sets.len().checked_sub(1)?);
+        Some(self.sets[..n].iter().map(Vec::len).sum())
+    }
+
+    // Forget a trashed file, a set with only one file left is done.
+    pub fn remove(&mut self, path: &str) {
+        for set in &mut self.sets {
+            set.retain(|file| file.path != path);
+        }
+        self.sets.retain(|set| set.len() > 1);
+    }
+}
+
+// Name, resolution, size and date of a file in a set, to pick the one to keep.
+pub fn line(image: &Image, focused: bool) -> String {
+    let name = Path::new(&image.file.path)
+        .file_name()
+        .map_or(image.file.path.clone(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+    let resolution = match image.get_metadata() {
+        Some(metadata) => {
+            let [w, h] = metadata.img_size();
+            format!("{} x {}", w, h)
+        }
+        None => "?".to_owned(),
+    };
+    format!(
+        "{} {}  {}  {}  {}",
+        if focused { ">" } else { " " },
+        name,
+        resolution,
+        crate::i18n::trf("{} KiB", &[&(image.file.file_size / 1024)]),
+        date_from_secs(image.file.modified)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn sets() {
+        let dir = TestDir::new("dupes");
+        let mut files = Vec::new();
+        for (name, bytes) in [
+            ("a", &b"same contents"[..]),
+            ("b", b"other content"),
+            ("c", b"same contents"),
+            ("d", b"unique"),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            files.push(Arc::new(File {
+                path: path.to_string_lossy().into_owned(),
+                modified: 0,
+                file_size: bytes.len() as u64,
+            }));
+        }
+
+        let sets = find(files.clone());
+        assert_eq!(sets, vec![vec![files[0].clone(), files[2].clone()]]);
+
+        let mut dupes = Dupes {
+            sets: vec![files[..2].to_vec(), files[2..].to_vec()],
+        };
+        assert_eq!(dupes.set_of(1), Some((0, 0..2)));
+        assert_eq!(dupes.set_of(3), Some((1, 2..4)));
+        assert_eq!(dupes.set_of(4), None);
+        assert_eq!(dupes.step(None, 1), Some(0));
+        assert_eq!(dupes.step(Some(1), 1), Some(2));
+        assert_eq!(dupes.step(Some(2), 1), Some(0));
+        assert_eq!(dupes.step(Some(0), -1), Some(2));
+        assert_eq!(dupes.start(5), Some(2));
+
+        dupes.remove(&files[1].path);
+        assert_eq!(dupes.len(), 1);
+        assert_eq!(dupes.set_of(0), Some((0, 0..2)));
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Warnings and errors kept for the log panel, users started from a desktop never see stderr.
+// Logs go to stderr or a file as text or JSON lines, events carry their fields as separate keys.
+
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// The oldest entries are dropped past this many.
+static MAX_ENTRIES: usize = 1000;
+
+lazy_static! {
+    static ref START: Instant = Instant::now();
+    static ref ENTRIES: Mutex<VecDeque<String>> = Mutex::default();
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    // One object per line, for log aggregation.
+    Json,
+}
+
+thread_local! {
+    // The name and fields of the event being logged on this thread.
+    static EVENT: RefCell<Option<(&'static str, Map<String, Value>)>> = const { RefCell::new(None) };
+}
+
+// Log `name` with `fields`, a JSON object. Text logs show the fields as key=value pairs.
+pub fn event(level: Level, name: &'static str, fields: Value) {
+    let fields = match fields {
+        Value::Object(fields) => fields,
+        value => Map::from_iter([("value".to_owned(), value)]),
+    };
+    if !log::log_enabled!(target: "pix::event", level) && level > Level::Warn {
+        return;
+    }
+    let text = fields
+        .iter()
+        .map(|(k, v)| format!(" {}={}", k, v))
+        .collect::<String>();
+    EVENT.with(|event| *event.borrow_mut() = Some((name, fields)));
+    log::log!(target: "pix::event", level, "{}{}", name, text);
+    EVENT.with(|event| event.borrow_mut().take());
+}
+
+fn json_line(
+    time: f64,
+    record: &Record,
+    event: Option<&(&'static str, Map<String, Value>)>,
+) -> String {
+    let mut line = Map::new();
+    line.insert("time".to_owned(), time.into());
+    line.insert("level".to_owned(), record.level().as_str().into());
+    line.insert("target".to_owned(), record.target().into());
+    line.insert("message".to_owned(), record.args().to_string().into());
+    if let Some((name, fields)) = event {
+        line.insert("event".to_owned(), (*name).into());
+        for (k, v) in fields {
+            line.insert(k.clone(), v.clone());
+        }
+    }
+    Value::Object(line).to_string()
+}
+
+// Records warnings and errors, and passes everything on to env_logger or the JSON sink.
+struct Logger {
+    inner: env_logger::Logger,
+
+    // JSON lines go here, text goes through env_logger.
+    json: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= Level::Warn {
+            push(format!(
+                "{:.1}s {} {}",
+                START.elapsed().as_secs_f64(),
+                record.level(),
+                record.args()
+            ));
+        }
+        let sink = match &self.json {
+            Some(sink) => sink,
+            None => return self.inner.log(record),
+        };
+        if !self.inner.matches(record) {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let line = EVENT.with(|event| json_line(time, record, event.borrow().as_ref()));
+        let _ = writeln!(sink.lock().unwrap(), "{}", line);
+    }
+
+    fn flush(&self) {
+        match &self.json {
+            Some(sink) => {
+                let _ = sink.lock().unwrap().flush();
+            }
+            None => self.inner.flush(),
+        }
+    }
+}
+
+// Set up logging to stderr or `file` as filtered by RUST_LOG, with warnings and errors recorded
+// even when RUST_LOG hides them.
+pub fn init(format: Format, file: Option<&Path>) {
+    lazy_static::initialize(&START);
+
+    let file = file.and_then(|path| {
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("unable to open log file {:?}: {}", path, e);
+                None
+            }
+        }
+    });
+
+    let mut builder = env_logger::Builder::from_default_env();
+    let json: Option<Box<dyn Write + Send>> = match (format, file) {
+        (Format::Text, Some(file)) => {
+            builder.target(env_logger::Target::Pipe(Box::new(file)));
+            None
+        }
+        (Format::Text, None) => None,
+        (Format::Json, Some(file)) => Some(Box::new(std::io::LineWriter::new(file))),
+        (Format::Json, None) => Some(Box::new(std::io::stderr())),
+    };
+    let inner = builder.build();
+    let max_level = inner.filter().max(LevelFilter::Warn);
+    let logger = Logger {
+        inner,
+        json: json.map(Mutex::new),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+fn push(entry: String) {
+    let mut entries = ENTRIES.lock().unwrap();
+    if entries.len() == MAX_ENTRIES {
+        entries.pop_front();
+    }
+    entries.push_back(entry);
+}
+
+fn len() -> usize {
+    ENTRIES.lock().unwrap().len()
+}
+
+// The recorded entries in a scrollable panel.
+#[derive(Debug, Default)]
+pub struct Panel {
+    // Index of the first entry shown.
+    scroll: usize,
+}
+
+impl Panel {
+    // Scrolled to the newest entries that fit in `rows` lines.
+    pub fn new(rows: usize) -> Self {
+        Self {
+            scroll: len().saturating_sub(rows.saturating_sub(1)),
+        }
+    }
+
+    pub fn scroll_by(&mut self, delta: isize) {
+        let max = len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max);
+    }
+
+    // The number of entries and the first and last shown in `rows` lines under a header,
+    // counted from 1, for the header.
+    pub fn range(&self, rows: usize) -> [usize; 3] {
+        let len = len();
+        [
+            len,
+            (self.scroll + 1).min(len),
+            (self.scroll + rows.saturating_sub(1)).min(len),
+        ]
+    }
+
+    // As many entries from the scroll position as fit in `rows` lines under a header.
+    pub fn lines(&self, rows: usize) -> Vec<String> {
+        let entries = ENTRIES.lock().unwrap();
+        entries
+            .iter()
+            .skip(self.scroll)
+            .take(rows.saturating_sub(1))
+            .cloned()
+            .collect()
+    }
+
+    // Every entry, one per line.
+    pub fn dump(&self) -> String {
+        let entries = ENTRIES.lock().unwrap();
+        entries.iter().map(|entry| format!("{}\n", entry)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panel() {
+        for i in 0..MAX_ENTRIES + 5 {
+            push(format!("entry {}", i));
+        }
+        assert_eq!(len(), MAX_ENTRIES);
+
+        let mut panel = Panel::new(3);
+        assert_eq!(
+            panel.lines(3),
+            [
+                format!("entry {}", MAX_ENTRIES + 3),
+                format!("entry {}", MAX_ENTRIES + 4)
+            ]
+        );
+        assert_eq!(panel.range(3), [MAX_ENTRIES, MAX_ENTRIES - 1, MAX_ENTRIES]);
+        panel.scroll_by(-(MAX_ENTRIES as isize));
+        assert_eq!(panel.lines(2), ["entry 5"]);
+        assert!(panel.dump().starts_with("entry 5\nentry 6\n"));
+    }
+
+    #[test]
+    fn json() {
+        let args = format_args!("scan images=3");
+        let record = Record::builder()
+            .args(args)
+            .level(Level::Info)
+            .target("pix::event")
+            .build();
+        let fields = Map::from_iter([("images".to_owned(), Value::from(3))]);
+        assert_eq!(
+            json_line(1.5, &record, Some(&("scan", fields))),
+            r#"{"event":"scan","images":3,"level":"INFO","message":"scan images=3","target":"pix::event","time":1.5}"#
+        );
+    }
+}
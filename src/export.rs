@@ -0,0 +1,291 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix export`, copies images to a folder for sharing, optionally without their metadata,
+// scaled down and watermarked.
+
+use crate::autorotate;
+use crate::thumbnailer::Thumbnailer;
+use crate::watermark::{self, Caption};
+use crate::{File, E, R};
+use ::image::imageops::FilterType;
+use ::image::{DynamicImage, ImageFormat, ImageOutputFormat, RgbaImage};
+use log::*;
+use rusttype::Font;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Quality of JPEGs encoded again.
+static JPEG_QUALITY: u8 = 90;
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    // Leave out EXIF, GPS, XMP, IPTC and comments.
+    pub strip_metadata: bool,
+
+    // Longest edge of the copies in pixels, smaller images are left as they are.
+    pub max_size: Option<u32>,
+
+    // Burned into the copies, which are then always encoded again.
+    pub watermark: Option<String>,
+    pub watermark_image: Option<PathBuf>,
+    pub caption: Option<Caption>,
+
+    // For the watermark and caption text.
+    pub font: Option<PathBuf>,
+}
+
+// The watermark and caption, loaded once for every image.
+#[derive(Default)]
+struct Stamp {
+    font: Option<Font<'static>>,
+    text: Option<String>,
+    logo: Option<RgbaImage>,
+    caption: Option<Caption>,
+}
+
+impl Stamp {
+    fn load(options: &Options) -> R<Self> {
+        let font = if options.watermark.is_some() || options.caption.is_some() {
+            let path = crate::overlay::font_path(options.font.clone())
+                .ok_or_else(|| E::CommandError("no font found, set one with --font".to_owned()))?;
+            let bytes = std::fs::read(&path).map_err(E::IoError)?;
+            let font = Font::try_from_vec(bytes)
+                .ok_or_else(|| E::CommandError(format!("unable to load font {:?}", path)))?;
+            Some(font)
+        } else {
+            None
+        };
+        let logo = match &options.watermark_image {
+            Some(path) => Some(::image::open(path).map_err(E::ImageError)?.to_rgba8()),
+            None => None,
+        };
+        Ok(Self {
+            font,
+            text: options.watermark.clone(),
+            logo,
+            caption: options.caption.clone(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_none() && self.logo.is_none() && self.caption.is_none()
+    }
+
+    fn apply(&self, image: DynamicImage, path: &str, bytes: &[u8]) -> DynamicImage {
+        if self.is_empty() {
+            return image;
+        }
+        let mut image = image.to_rgba8();
+        if let Some(logo) = &self.logo {
+            watermark::logo(&mut image, logo);
+        }
+        if let Some(font) = &self.font {
+            if let Some(text) = &self.text {
+                watermark::text(&mut image, font, text);
+            }
+            if let Some(text) = self.caption.as_ref().and_then(|c| c.text(path, bytes)) {
+                watermark::caption(&mut image, font, &text);
+            }
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+// A JPEG without the segments that carry metadata, or None if it isn't one. The JFIF header,
+// color profile and Adobe marker are kept since they change how the image looks.
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.get(..2) != Some(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut out = bytes[..2].to_vec();
+    let mut pos = 2;
+    loop {
+        let marker = match (bytes.get(pos), bytes.get(pos + 1)) {
+            (Some(0xff), Some(&marker)) => marker,
+            _ => return None,
+        };
+        // The start of scan is followed by the image data, copied as it is.
+        if marker == 0xda {
+            out.extend_from_slice(&bytes[pos..]);
+            return Some(out);
+        }
+        let len = bytes.get(pos + 2..pos + 4)?;
+        let end = pos + 2 + u16::from_be_bytes([len[0], len[1]]) as usize;
+        let segment = bytes.get(pos..end)?;
+        // APP1 is EXIF and XMP, APP13 IPTC, 0xfe comments.
+        let metadata = matches!(marker, 0xe1 | 0xe3..=0xed | 0xef | 0xfe);
+        if !metadata {
+            out.extend_from_slice(segment);
+        }
+        pos = end;
+    }
+}
+
+// The copy's file name in `dir`, numbered if the name is taken, e.g. by images of the same name
+// from different directories.
+fn dest(dir: &Path, path: &str, ext: Option<&str>) -> PathBuf {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .map_or("image".into(), |stem| stem.to_string_lossy());
+    let ext = match ext {
+        Some(ext) => Some(ext.into()),
+        None => path.extension().map(|ext| ext.to_string_lossy()),
+    };
+    let name = |n: usize| {
+        let mut name = stem.to_string();
+        if n > 0 {
+            name += &format!("-{}", n);
+        }
+        if let Some(ext) = &ext {
+            name += &format!(".{}", ext);
+        }
+        dir.join(name)
+    };
+    (0..)
+        .map(name)
+        .find(|dest| !dest.exists())
+        .expect("free name")
+}
+
+// Decode `path` upright, scaled to fit `max_size` and stamped, and encode it as PNG if it was
+// one, else as JPEG. Returns the bytes and their extension.
+fn encode(
+    path: &str,
+    bytes: &[u8],
+    max_size: Option<u32>,
+    stamp: &Stamp,
+) -> R<(Vec<u8>, &'static str)> {
+    let (image, _) = Thumbnailer::open(path)?;
+    let mut image = autorotate::apply(image, autorotate::orientation(bytes));
+    if let Some(max) = max_size {
+        if image.width() > max || image.height() > max {
+            image = image.resize(max, max, FilterType::Lanczos3);
+        }
+    }
+    let image = stamp.apply(image, path, bytes);
+
+    let mut out = std::io::Cursor::new(Vec::new());
+    if ImageFormat::from_path(path).ok() == Some(ImageFormat::Png) {
+        image
+            .write_to(&mut out, ImageOutputFormat::Png)
+            .map_err(E::ImageError)?;
+        Ok((out.into_inner(), "png"))
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut out, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .map_err(E::ImageError)?;
+        Ok((out.into_inner(), "jpg"))
+    }
+}
+
+fn export(dir: &Path, file: &File, options: &Options, stamp: &Stamp) -> R<PathBuf> {
+    let bytes = std::fs::read(&file.path).map_err(E::IoError)?;
+
+    let upright = autorotate::orientation(&bytes) == 1;
+    let copy = options.max_size.is_none() && stamp.is_empty();
+    let (bytes, ext) = match (copy, options.strip_metadata) {
+        (true, false) => (bytes, None),
+        // Removing the EXIF would lose the orientation too.
+        (true, true) if upright => match strip_jpeg(&bytes) {
+            Some(stripped) => (stripped, None),
+            None => {
+                let (bytes, ext) = encode(&file.path, &bytes, None, stamp)?;
+                (bytes, Some(ext))
+            }
+        },
+        _ => {
+            let (bytes, ext) = encode(&file.path, &bytes, options.max_size, stamp)?;
+            (bytes, Some(ext))
+        }
+    };
+
+    let dest = dest(dir, &file.path, ext);
+    std::fs::write(&dest, bytes).map_err(E::IoError)?;
+    Ok(dest)
+}
+
+// Copy `files` to `dir`, returns how many were copied.
+pub fn run(dir: &Path, files: Vec<Arc<File>>, options: &Options) -> usize {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        error!("create {:?}: {}", dir, e);
+        return 0;
+    }
+    let stamp = match Stamp::load(options) {
+        Ok(stamp) => stamp,
+        Err(e) => {
+            error!("export watermark: {}", e);
+            return 0;
+        }
+    };
+
+    let mut n = 0;
+    for file in files {
+        match export(dir, &file, options, &stamp) {
+            Ok(dest) => {
+                info!("Exported {:?} to {:?}", file.path, dest);
+                n += 1;
+            }
+            Err(e) => error!("export {:?}: {}", file.path, e),
+        }
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn stripped() {
+        let segment = |marker: u8, data: &[u8]| {
+            let mut segment = vec![0xff, marker];
+            segment.extend(((data.len() + 2) as u16).to_be_bytes());
+            segment.extend_from_slice(data);
+            segment
+        };
+        let jfif = segment(0xe0, b"JFIF\0");
+        let icc = segment(0xe2, b"ICC_PROFILE\0");
+        let scan = [0xff, 0xda, 0x00, 0x02, 0x12, 0x34, 0xff, 0xd9];
+
+        let mut bytes = vec![0xff, 0xd8];
+        bytes.extend(&jfif);
+        bytes.extend(segment(0xe1, b"Exif\0\0GPS"));
+        bytes.extend(&icc);
+        bytes.extend(segment(0xed, b"Photoshop 3.0\0"));
+        bytes.extend(segment(0xfe, b"a comment"));
+        bytes.extend(scan);
+
+        let mut expected = vec![0xff, 0xd8];
+        expected.extend(jfif);
+        expected.extend(icc);
+        expected.extend(scan);
+        assert_eq!(strip_jpeg(&bytes), Some(expected));
+
+        assert_eq!(strip_jpeg(b"\x89PNG"), None);
+        // Cut off before the image data.
+        assert_eq!(strip_jpeg(&bytes[..10]), None);
+    }
+
+    #[test]
+    fn numbered() {
+        let dir = TestDir::new("export");
+
+        assert_eq!(dest(&dir, "/a/b.jpeg", None), dir.join("b.jpeg"));
+        std::fs::write(dir.join("b.jpg"), b"").unwrap();
+        assert_eq!(dest(&dir, "/c/b.jpeg", Some("jpg")), dir.join("b-1.jpg"));
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Strip of neighboring thumbnails along the bottom of the single image view.
+
+use crate::vec::*;
+
+// Height of the strip and size of its squares in window pixels.
+static SIZE: f64 = 96.0;
+
+#[derive(Debug, Default)]
+pub struct Filmstrip {
+    // Scrolled squares from the shown image.
+    offset: isize,
+}
+
+impl Filmstrip {
+    pub fn scroll_by(&mut self, delta: isize) {
+        self.offset += delta;
+    }
+
+    pub fn contains(&self, [_, win_h]: Vector2<f64>, [_, y]: Vector2<f64>) -> bool {
+        y >= win_h - SIZE && y < win_h
+    }
+
+    pub fn rect(&self, [win_w, win_h]: Vector2<f64>) -> [f64; 4] {
+        [0.0, win_h - SIZE, win_w, SIZE]
+    }
+
+    // Image index and window rectangle of each square, centered on image `current` of `count`.
+    pub fn cells(
+        &self,
+        current: usize,
+        count: usize,
+        [win_w, win_h]: Vector2<f64>,
+    ) -> Vec<(usize, [f64; 4])> {
+        let n = (win_w / SIZE).floor() as isize;
+        let left = (win_w - n as f64 * SIZE) / 2.0;
+        let first = current as isize + self.offset - n / 2;
+
+        (0..n)
+            .filter_map(|j| {
+                let i = usize::try_from(first + j).ok().filter(|&i| i < count)?;
+                Some((i, [left + j as f64 * SIZE, win_h - SIZE, SIZE, SIZE]))
+            })
+            .collect()
+    }
+
+    // Index of the image under the mouse.
+    pub fn hit(
+        &self,
+        current: usize,
+        count: usize,
+        win_size: Vector2<f64>,
+        [x, y]: Vector2<f64>,
+    ) -> Option<usize> {
+        self.cells(current, count, win_size)
+            .into_iter()
+            .find(|(_, [cx, cy, w, h])| x >= *cx && x < cx + w && y >= *cy && y < cy + h)
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells() {
+        let mut strip = Filmstrip::default();
+        let win = [500.0, 400.0];
+
+        // Five squares fit, clipped at the first image.
+        let cells = strip.cells(1, 10, win);
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0], (0, [106.0, 304.0, 96.0, 96.0]));
+        assert_eq!(strip.hit(1, 10, win, [260.0, 350.0]), Some(1));
+        assert_eq!(strip.hit(1, 10, win, [260.0, 300.0]), None);
+
+        strip.scroll_by(8);
+        let indices: Vec<usize> = strip.cells(1, 10, win).iter().map(|(i, _)| *i).collect();
+        assert_eq!(indices, vec![7, 8, 9]);
+    }
+}
@@ -0,0 +1,605 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::quality::Flaw;
+use crate::scene::Tag;
+use crate::system::slashes;
+use crate::userdata::{Label, UserData};
+use crate::{File, Metadata};
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Path,
+    Modified,
+    Size,
+    Rating,
+    Label,
+    Sharpness,
+    Brightness,
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(Sort::Path),
+            "modified" => Ok(Sort::Modified),
+            "size" => Ok(Sort::Size),
+            "rating" => Ok(Sort::Rating),
+            "label" => Ok(Sort::Label),
+            "sharpness" => Ok(Sort::Sharpness),
+            "brightness" => Ok(Sort::Brightness),
+            _ => Err(format!("unknown sort key {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Screenshot,
+    Document,
+    Photo,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "screenshot" => Ok(Kind::Screenshot),
+            "document" => Ok(Kind::Document),
+            "photo" => Ok(Kind::Photo),
+            _ => Err(format!("unknown kind {:?}", s)),
+        }
+    }
+}
+
+// A filter/sort combination parsed from a space separated spec like
+// "ext:cr2,nef year:2023 path:holiday rating:4 label:red,green text:harbour quality:blurry
+// sort:-modified".
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filter {
+    // The spec this filter was parsed from, used when saving presets.
+    spec: String,
+
+    // Lowercase file extensions, empty matches everything.
+    extensions: Vec<String>,
+
+    // Year (UTC) the file was last modified.
+    year: Option<i64>,
+
+    // Substring of the file path.
+    path: Option<String>,
+
+    // Minimum star rating.
+    rating: Option<u8>,
+
+    // Color labels, empty matches everything.
+    labels: Vec<Label>,
+
+    // Lowercase words all found in the title, description or keywords.
+    text: Vec<String>,
+
+    // Lowercase keywords, empty matches everything.
+    keywords: Vec<String>,
+
+    // Flaws of which any matches, empty matches everything.
+    flaws: Vec<Flaw>,
+
+    // Kinds of which any matches, empty matches everything.
+    kinds: Vec<Kind>,
+
+    // Scene tags of which any matches, empty matches everything.
+    scenes: Vec<Tag>,
+
+    // Sort key and whether it's descending.
+    sort: Option<(Sort, bool)>,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut ret = Filter {
+            spec: spec.trim().to_owned(),
+            ..Default::default()
+        };
+
+        for term in spec.split_whitespace() {
+            let (k, v) = term
+                .split_once(':')
+                .ok_or_else(|| format!("expected key:value, got {:?}", term))?;
+
+            match k {
+                "ext" => {
+                    ret.extensions
+                        .extend(v.split(',').map(|ext| ext.to_lowercase()));
+                }
+                "year" => {
+                    ret.year = Some(v.parse().map_err(|_| format!("invalid year {:?}", v))?);
+                }
+                "path" => {
+                    ret.path = Some(v.to_owned());
+                }
+                "rating" => {
+                    ret.rating = Some(v.parse().map_err(|_| format!("invalid rating {:?}", v))?);
+                }
+                "label" => {
+                    for label in v.split(',') {
+                        ret.labels.push(label.parse()?);
+                    }
+                }
+                "text" => {
+                    ret.text.push(v.to_lowercase());
+                }
+                "keyword" => {
+                    ret.keywords
+                        .extend(v.split(',').map(|keyword| keyword.to_lowercase()));
+                }
+                "quality" => {
+                    for flaw in v.split(',') {
+                        ret.flaws.push(flaw.parse()?);
+                    }
+                }
+                "kind" => {
+                    for kind in v.split(',') {
+                        ret.kinds.push(kind.parse()?);
+                    }
+                }
+                "scene" => {
+                    for tag in v.split(',') {
+                        ret.scenes.push(tag.parse()?);
+                    }
+                }
+                "sort" => {
+                    ret.sort = Some(match v.strip_prefix('-') {
+                        Some(v) => (v.parse()?, true),
+                        None => (v.parse()?, false),
+                    });
+                }
+                _ => return Err(format!("unknown filter key {:?}", k)),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+// Days since the unix epoch to a (proleptic Gregorian) year, month and day.
+fn date_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+// A (proleptic Gregorian) year, month and day to days since the unix epoch.
+pub fn days_from_date(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+pub fn year_from_secs(secs: u64) -> i64 {
+    date_from_days(secs as i64 / 86_400).0
+}
+
+// E.g. 2023-04-01, in UTC.
+pub fn date_from_secs(secs: u64) -> String {
+    let (year, month, day) = date_from_days(secs as i64 / 86_400);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// EXIF capture time, local time read as UTC like the other dates pix shows.
+pub fn taken(data: &[u8]) -> Option<u64> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| match exif.get_field(tag, exif::In::PRIMARY)?.value {
+            exif::Value::Ascii(ref strings) => {
+                parse_taken(&String::from_utf8_lossy(strings.first()?))
+            }
+            _ => None,
+        })
+}
+
+// "2023:04:01 10:12:13" to seconds since the unix epoch.
+fn parse_taken(s: &str) -> Option<u64> {
+    let (date, time) = s.trim().split_once(' ')?;
+    let date: Vec<i64> = date
+        .split(':')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<i64> = time
+        .split(':')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    match (&date[..], &time[..]) {
+        (&[year, month, day], &[h, m, s]) if (1..=12).contains(&month) && day >= 1 => {
+            let secs = days_from_date(year, month, day) * 86_400 + h * 3600 + m * 60 + s;
+            u64::try_from(secs).ok()
+        }
+        _ => None,
+    }
+}
+
+impl Filter {
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    fn extension(path: &str) -> Option<String> {
+        std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+    }
+
+    // `metadata` is None for files not thumbnailed yet, which text and quality terms don't match.
+    pub fn matches(&self, file: &File, user_data: &UserData, metadata: Option<&Metadata>) -> bool {
+        if !self.extensions.is_empty() {
+            match Self::extension(&file.path) {
+                Some(ext) if self.extensions.contains(&ext) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(year) = self.year {
+            if year_from_secs(file.modified) != year {
+                return false;
+            }
+        }
+
+        if let Some(path) = &self.path {
+            if !slashes(&file.path).contains(slashes(path).as_ref()) {
+                return false;
+            }
+        }
+
+        if let Some(rating) = self.rating {
+            if user_data.rating < rating {
+                return false;
+            }
+        }
+
+        if !self.labels.is_empty() {
+            match user_data.label {
+                Some(label) if self.labels.contains(&label) => {}
+                _ => return false,
+            }
+        }
+
+        if !self.text.is_empty() || !self.keywords.is_empty() {
+            let captions = match metadata {
+                Some(metadata) => &metadata.captions,
+                None => return false,
+            };
+            if !self.text.iter().all(|text| captions.contains(text)) {
+                return false;
+            }
+            if !self.keywords.is_empty()
+                && !self
+                    .keywords
+                    .iter()
+                    .any(|keyword| captions.has_keyword(keyword))
+            {
+                return false;
+            }
+        }
+
+        if !self.flaws.is_empty() {
+            match metadata.and_then(|metadata| metadata.quality) {
+                Some(quality) if self.flaws.iter().any(|&flaw| quality.has(flaw)) => {}
+                _ => return false,
+            }
+        }
+
+        if !self.kinds.is_empty() {
+            let kind = match metadata {
+                Some(metadata) if metadata.screenshot => Kind::Screenshot,
+                Some(metadata) if metadata.scene.is_some_and(|scene| scene.document) => {
+                    Kind::Document
+                }
+                Some(_) => Kind::Photo,
+                None => return false,
+            };
+            if !self.kinds.contains(&kind) {
+                return false;
+            }
+        }
+
+        if !self.scenes.is_empty() {
+            match metadata.and_then(|metadata| metadata.scene) {
+                Some(scene) if self.scenes.iter().any(|&tag| scene.has(tag)) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    // `fields` gives the file, user data and metadata of an item.
+    pub fn apply<T>(
+        &self,
+        mut files: Vec<T>,
+        fields: impl Fn(&T) -> (&Arc<File>, &UserData, Option<&Metadata>),
+    ) -> Vec<T> {
+        files.retain(|item| {
+            let (file, user_data, metadata) = fields(item);
+            self.matches(file, user_data, metadata)
+        });
+
+        // Images without a quality, not thumbnailed yet or animated, sort after the others.
+        let quality = |item: &T| fields(item).2.and_then(|metadata| metadata.quality);
+
+        if let Some((sort, reverse)) = self.sort {
+            match sort {
+                Sort::Path => files.sort_by(|a, b| fields(a).0.path.cmp(&fields(b).0.path)),
+                Sort::Modified => files.sort_by_key(|item| fields(item).0.modified),
+                Sort::Size => files.sort_by_key(|item| fields(item).0.file_size),
+                Sort::Rating => files.sort_by_key(|item| fields(item).1.rating),
+                Sort::Label => files.sort_by_key(|item| fields(item).1.label),
+                Sort::Sharpness => {
+                    files.sort_by_key(|item| quality(item).map_or(u32::MAX, |q| q.sharpness))
+                }
+                Sort::Brightness => {
+                    files.sort_by_key(|item| quality(item).map_or(u8::MAX, |q| q.brightness))
+                }
+            }
+            if reverse {
+                files.reverse();
+            }
+        }
+
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::quality::Quality;
+    use crate::scene::Scene;
+
+    #[test]
+    fn taken_times() {
+        assert_eq!(parse_taken("1970:01:02 00:00:01"), Some(86_401));
+        assert_eq!(
+            date_from_secs(parse_taken("2024:02:29 23:59:59").unwrap()),
+            "2024-02-29"
+        );
+        assert_eq!(parse_taken("0000:00:00 00:00:00"), None);
+        assert_eq!(parse_taken("    :  :     :  :  "), None);
+    }
+
+    type Item = (Arc<File>, UserData, Metadata);
+
+    fn file(path: &str, modified: u64, file_size: u64) -> Item {
+        let file = Arc::new(File {
+            path: path.to_owned(),
+            modified,
+            file_size,
+        });
+        (file, UserData::default(), Metadata::default())
+    }
+
+    fn fields(item: &Item) -> (&Arc<File>, &UserData, Option<&Metadata>) {
+        (&item.0, &item.1, Some(&item.2))
+    }
+
+    #[test]
+    fn parse() {
+        let filter: Filter = "ext:CR2,nef year:2023 sort:-size".parse().unwrap();
+        assert_eq!(filter.extensions, vec!["cr2", "nef"]);
+        assert_eq!(filter.year, Some(2023));
+        assert_eq!(filter.sort, Some((Sort::Size, true)));
+        assert_eq!(filter.spec(), "ext:CR2,nef year:2023 sort:-size");
+
+        assert!("ext".parse::<Filter>().is_err());
+        assert!("color:red".parse::<Filter>().is_err());
+        assert!("sort:color".parse::<Filter>().is_err());
+        assert!("label:pink".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn years() {
+        assert_eq!(year_from_secs(0), 1970);
+        assert_eq!(year_from_secs(1_672_531_199), 2022);
+        assert_eq!(year_from_secs(1_672_531_200), 2023);
+        assert_eq!(date_from_secs(1_672_531_199), "2022-12-31");
+        assert_eq!(date_from_secs(1_709_208_000), "2024-02-29");
+
+        for days in [-800_000, -1, 0, 59, 19_782, 1_000_000] {
+            let (year, month, day) = date_from_days(days);
+            assert_eq!(days_from_date(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn apply() {
+        let files = vec![
+            file("/a.jpg", 1_672_531_200, 3),
+            file("/b.CR2", 1_672_531_200, 1),
+            file("/c.cr2", 0, 2),
+            file("/d.cr2", 1_672_531_201, 2),
+        ];
+
+        let filter: Filter = "ext:cr2 year:2023 sort:-size".parse().unwrap();
+        let paths: Vec<String> = filter
+            .apply(files, fields)
+            .iter()
+            .map(|(file, _, _)| file.path.clone())
+            .collect();
+        assert_eq!(paths, vec!["/d.cr2", "/b.CR2"]);
+    }
+
+    #[test]
+    fn apply_user_data() {
+        let mut files = vec![file("/a", 0, 0), file("/b", 0, 0), file("/c", 0, 0)];
+        files[0].1.rating = 5;
+        files[0].1.label = Some(Label::Red);
+        files[1].1.rating = 3;
+        files[1].1.label = Some(Label::Green);
+        files[2].1.rating = 4;
+
+        let filter: Filter = "rating:3 label:green,red sort:rating".parse().unwrap();
+        let paths: Vec<String> = filter
+            .apply(files, fields)
+            .iter()
+            .map(|(file, _, _)| file.path.clone())
+            .collect();
+        assert_eq!(paths, vec!["/b", "/a"]);
+    }
+
+    #[test]
+    fn apply_captions() {
+        let mut files = vec![file("/a", 0, 0), file("/b", 0, 0), file("/c", 0, 0)];
+        files[0].2.captions.title = Some("Harbour at dawn".to_owned());
+        files[0].2.captions.keywords = vec!["Boats".to_owned()];
+        files[1].2.captions.description = Some("The harbour".to_owned());
+        files[2].2.captions.keywords = vec!["boats".to_owned(), "fog".to_owned()];
+
+        let paths = |spec: &str, files: Vec<Item>| -> Vec<String> {
+            let filter: Filter = spec.parse().unwrap();
+            filter
+                .apply(files, fields)
+                .iter()
+                .map(|(file, _, _)| file.path.clone())
+                .collect()
+        };
+        assert_eq!(paths("text:HARBOUR", files.clone()), vec!["/a", "/b"]);
+        assert_eq!(paths("text:harbour text:dawn", files.clone()), vec!["/a"]);
+        assert_eq!(paths("keyword:boats", files.clone()), vec!["/a", "/c"]);
+        assert_eq!(paths("keyword:fog,none", files.clone()), vec!["/c"]);
+
+        // Files not thumbnailed yet have no captions.
+        let filter: Filter = "text:harbour".parse().unwrap();
+        let none = filter.apply(files, |(file, user_data, _)| (file, user_data, None));
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn apply_quality() {
+        let mut files = vec![
+            file("/a", 0, 0),
+            file("/b", 0, 0),
+            file("/c", 0, 0),
+            file("/d", 0, 0),
+        ];
+        let quality = |sharpness, brightness| {
+            Some(Quality {
+                sharpness,
+                brightness,
+            })
+        };
+        files[0].2.quality = quality(20, 120);
+        files[1].2.quality = quality(500, 10);
+        files[2].2.quality = quality(800, 130);
+
+        let paths = |spec: &str, files: Vec<Item>| -> Vec<String> {
+            let filter: Filter = spec.parse().unwrap();
+            filter
+                .apply(files, fields)
+                .iter()
+                .map(|(file, _, _)| file.path.clone())
+                .collect()
+        };
+        assert_eq!(paths("quality:blurry", files.clone()), vec!["/a"]);
+        assert_eq!(
+            paths("quality:blurry,dark", files.clone()),
+            vec!["/a", "/b"]
+        );
+        assert_eq!(
+            paths("sort:sharpness", files.clone()),
+            vec!["/a", "/b", "/c", "/d"]
+        );
+        assert_eq!(
+            paths("sort:brightness", files.clone()),
+            vec!["/b", "/a", "/c", "/d"]
+        );
+        assert!("quality:noisy".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn apply_kind() {
+        let mut files = vec![file("/a", 0, 0), file("/b", 0, 0), file("/c", 0, 0)];
+        files[1].2.screenshot = true;
+        files[2].2.scene = Some(Scene {
+            document: true,
+            ..Default::default()
+        });
+
+        let filter: Filter = "kind:screenshot".parse().unwrap();
+        let paths: Vec<String> = filter
+            .apply(files.clone(), fields)
+            .iter()
+            .map(|(file, _, _)| file.path.clone())
+            .collect();
+        assert_eq!(paths, vec!["/b"]);
+
+        // Files not thumbnailed yet are none of them.
+        let filter: Filter = "kind:photo,document".parse().unwrap();
+        let found = filter.apply(files, |(file, user_data, metadata)| {
+            (
+                file,
+                user_data,
+                Some(metadata).filter(|_| file.path != "/a"),
+            )
+        });
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.path, "/c");
+        assert!("kind:drawing".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn apply_scene() {
+        let mut files = vec![file("/a", 0, 0), file("/b", 0, 0), file("/c", 0, 0)];
+        files[0].2.scene = Some(Scene {
+            outdoor: true,
+            ..Default::default()
+        });
+        files[1].2.scene = Some(Scene {
+            night: true,
+            ..Default::default()
+        });
+
+        let paths = |spec: &str| -> Vec<String> {
+            let filter: Filter = spec.parse().unwrap();
+            filter
+                .apply(files.clone(), fields)
+                .iter()
+                .map(|(file, _, _)| file.path.clone())
+                .collect()
+        };
+        assert_eq!(paths("scene:outdoor"), vec!["/a"]);
+        assert_eq!(paths("scene:night"), vec!["/b"]);
+        assert_eq!(paths("scene:day,indoor"), vec!["/a", "/b"]);
+        assert!("scene:beach".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Quick hash of a file's size and its first and last bytes, to notice files edited in place
+// without their modification time changing.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Bytes hashed at each end of the file.
+static SAMPLE: u64 = 64 * 1024;
+
+fn hash(len: u64, head: &[u8], tail: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (len, head, tail).hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn of_bytes(bytes: &[u8]) -> u64 {
+    let len = bytes.len() as u64;
+    let head = &bytes[..u64::min(len, SAMPLE) as usize];
+    let tail = &bytes[len.saturating_sub(SAMPLE) as usize..];
+    hash(len, head, tail)
+}
+
+pub fn of_file(path: impl AsRef<Path>) -> std::io::Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut head = vec![0; u64::min(len, SAMPLE) as usize];
+    file.read_exact(&mut head)?;
+
+    let mut tail = vec![0; u64::min(len, SAMPLE) as usize];
+    file.seek(SeekFrom::Start(len - tail.len() as u64))?;
+    file.read_exact(&mut tail)?;
+
+    Ok(hash(len, &head, &tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn file_matches_bytes() {
+        let dir = TestDir::new("fingerprint");
+        let path = dir.join("file");
+        for len in [0, 10, SAMPLE as usize * 3] {
+            let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            std::fs::write(&path, &bytes).unwrap();
+            assert_eq!(of_file(&path).unwrap(), of_bytes(&bytes));
+        }
+
+        let mut bytes = vec![0u8; 100];
+        let before = of_bytes(&bytes);
+        bytes[50] = 1;
+        assert_ne!(of_bytes(&bytes), before);
+    }
+}
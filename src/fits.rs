@@ -0,0 +1,246 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Minimal FITS primary image decoder with a zscale display stretch.
+
+use crate::{E, R};
+use ::image::{DynamicImage, GrayImage};
+use std::collections::BTreeMap;
+
+static BLOCK: usize = 2880;
+static CARD: usize = 80;
+
+// Number of pixels sampled for the stretch.
+static ZSCALE_SAMPLES: usize = 1000;
+static ZSCALE_CONTRAST: f64 = 0.25;
+
+pub fn is_fits(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".fits") || path.ends_with(".fit") || path.ends_with(".fts")
+}
+
+fn err(msg: &str) -> E {
+    E::FitsError(msg.to_owned())
+}
+
+// Parse header cards up to END, returns the keywords and the data offset.
+fn header(data: &[u8]) -> R<(BTreeMap<String, String>, usize)> {
+    let mut keywords = BTreeMap::new();
+
+    for (i, card) in data.chunks(CARD).enumerate() {
+        let card = std::str::from_utf8(card).map_err(|_| err("non-ascii header"))?;
+        let key = card.get(..8).unwrap_or(card).trim();
+        if key == "END" {
+            let end = (i + 1) * CARD;
+            return Ok((keywords, end.div_ceil(BLOCK) * BLOCK));
+        }
+        if card.get(8..10) == Some("= ") {
+            let value = card[10..].split('/').next().unwrap_or("").trim();
+            keywords.insert(key.to_owned(), value.trim_matches('\'').trim().to_owned());
+        }
+    }
+
+    Err(err("missing END card"))
+}
+
+fn keyword<T: std::str::FromStr>(keywords: &BTreeMap<String, String>, key: &str) -> Option<T> {
+    keywords.get(key).and_then(|v| v.parse().ok())
+}
+
+fn pixels(data: &[u8], bitpix: i64, count: usize) -> R<Vec<f64>> {
+    if ![8, 16, 32, -32, -64].contains(&bitpix) {
+        return Err(err("unsupported BITPIX"));
+    }
+    let size = (bitpix.unsigned_abs() / 8) as usize;
+    let data = count
+        .checked_mul(size)
+        .and_then(|len| data.get(..len))
+        .ok_or_else(|| err("truncated data"))?;
+
+    let it = data.chunks_exact(size);
+    Ok(match bitpix {
+        8 => it.map(|b| b[0] as f64).collect(),
+        16 => it
+            .map(|b| i16::from_be_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        32 => it
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        -32 => it
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        _ => it
+            .map(|b| f64::from_be_bytes(b.try_into().unwrap()))
+            .collect(),
+    })
+}
+
+// Display range following IRAF's zscale: fit a line through the sorted samples near the median.
+pub fn zscale(values: &[f64]) -> (f64, f64) {
+    let step = std::cmp::max(1, values.len() / ZSCALE_SAMPLES);
+    let mut samples: Vec<f64> = values
+        .iter()
+        .step_by(step)
+        .copied()
+        .filter(|v| v.is_finite())
+        .collect();
+    if samples.is_empty() {
+        return (0.0, 1.0);
+    }
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let n = samples.len();
+    let (min, max) = (samples[0], samples[n - 1]);
+    let center = n / 2;
+    let median = samples[center];
+
+    // Least squares slope over the central half, the tails are mostly stars and hot pixels.
+    let (lo, hi) = (n / 4, std::cmp::max(n / 4 + 1, 3 * n / 4));
+    let xs = (lo..hi).map(|i| i as f64);
+    let k = (hi - lo) as f64;
+    let x_mean = xs.clone().sum::<f64>() / k;
+    let y_mean = samples[lo..hi].iter().sum::<f64>() / k;
+    let (mut num, mut den) = (0.0, 0.0);
+    for (x, y) in xs.zip(&samples[lo..hi]) {
+        num += (x - x_mean) * (y - y_mean);
+        den += (x - x_mean) * (x - x_mean);
+    }
+    let slope = if den > 0.0 { num / den } else { 0.0 } / ZSCALE_CONTRAST;
+
+    let z1 = f64::max(min, median - center as f64 * slope);
+    let z2 = f64::min(max, median + (n - 1 - center) as f64 * slope);
+    if z2 > z1 {
+        (z1, z2)
+    } else {
+        (min, f64::max(max, min + 1.0))
+    }
+}
+
+pub fn open(path: &str) -> R<DynamicImage> {
+    let data = std::fs::read(path).map_err(|e| err(&e.to_string()))?;
+    decode(&data)
+}
+
+// Width and height of the image in the primary HDU, from the header.
+pub fn dimensions(data: &[u8]) -> Option<[u32; 2]> {
+    let (keywords, _) = header(data).ok()?;
+    let w: u32 = keyword(&keywords, "NAXIS1")?;
+    let h: u32 = keyword(&keywords, "NAXIS2")?;
+    Some([w, h])
+}
+
+pub fn decode(data: &[u8]) -> R<DynamicImage> {
+    let (keywords, offset) = header(data)?;
+
+    let bitpix: i64 = keyword(&keywords, "BITPIX").ok_or_else(|| err("missing BITPIX"))?;
+    let naxis: u32 = keyword(&keywords, "NAXIS").unwrap_or(0);
+    let w: u32 = keyword(&keywords, "NAXIS1").unwrap_or(0);
+    let h: u32 = keyword(&keywords, "NAXIS2").unwrap_or(0);
+    if naxis < 2 || w == 0 || h == 0 {
+        return Err(err("no image in primary HDU"));
+    }
+
+    let bzero: f64 = keyword(&keywords, "BZERO").unwrap_or(0.0);
+    let bscale: f64 = keyword(&keywords, "BSCALE").unwrap_or(1.0);
+
+    // Only the first plane of data cubes.
+    let count = (w as usize) * (h as usize);
+    let data = data.get(offset..).ok_or_else(|| err("truncated data"))?;
+    let values: Vec<f64> = pixels(data, bitpix, count)?
+        .into_iter()
+        .map(|v| bzero + bscale * v)
+        .collect();
+
+    let (z1, z2) = zscale(&values);
+    let scale = 255.0 / (z2 - z1);
+
+    // FITS rows go bottom to top.
+    let image = GrayImage::from_fn(w, h, |x, y| {
+        let v = values[((h - 1 - y) * w + x) as usize];
+        let v = if v.is_finite() { (v - z1) * scale } else { 0.0 };
+        ::image::Luma([v.clamp(0.0, 255.0) as u8])
+    });
+
+    Ok(DynamicImage::ImageLuma8(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(s: &str) -> String {
+        format!("{:80}", s)
+    }
+
+    fn fits(w: usize, h: usize, values: &[i16]) -> Vec<u8> {
+        fits_bitpix(16, w, h, values)
+    }
+
+    fn fits_bitpix(bitpix: i64, w: usize, h: usize, values: &[i16]) -> Vec<u8> {
+        let mut data = String::new();
+        data += &card("SIMPLE  =                    T");
+        data += &card(&format!("BITPIX  = {:20}", bitpix));
+        data += &card("NAXIS   =                    2");
+        data += &card(&format!("NAXIS1  = {:20}", w));
+        data += &card(&format!("NAXIS2  = {:20}", h));
+        data += &card("BZERO   =                32768 / unsigned");
+        data += &card("END");
+        let mut data = data.into_bytes();
+        data.resize(BLOCK, b' ');
+        for v in values {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn decode_u16() {
+        let data = fits(2, 2, &[-32768, -32768, 32767, 32767]);
+        let image = decode(&data).unwrap().to_luma8();
+        assert_eq!(image.dimensions(), (2, 2));
+        // Bottom row first.
+        assert_eq!(image.get_pixel(0, 1).0, [0]);
+        assert_eq!(image.get_pixel(0, 0).0, [255]);
+    }
+
+    #[test]
+    fn zscale_ignores_outliers() {
+        let mut values: Vec<f64> = (0..1000).map(|i| 100.0 + (i % 10) as f64).collect();
+        values.push(1e6);
+        let (z1, z2) = zscale(&values);
+        assert!(z1 >= 100.0);
+        assert!(z2 < 1000.0);
+    }
+
+    #[test]
+    fn missing_end() {
+        assert!(decode(&[b' '; 2880]).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = fits(2, 2, &[0; 4]);
+        assert!(decode(&data[..BLOCK + 4]).is_err());
+        // The header block itself cut short.
+        assert!(decode(&data[..BLOCK - 80]).is_err());
+    }
+
+    #[test]
+    fn unsupported_bitpix() {
+        for bitpix in [0, 4, -4, 24] {
+            let data = fits_bitpix(bitpix, 2, 2, &[0; 4]);
+            assert!(decode(&data).is_err());
+        }
+    }
+}
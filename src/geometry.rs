@@ -0,0 +1,93 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// X11 style window geometry, `WxH` with an optional `+X+Y` position.
+
+use crate::vec::*;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub size: Vector2<u32>,
+    pub pos: Option<Vector2<i32>>,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            size: [800, 600],
+            pos: None,
+        }
+    }
+}
+
+// Split "+10-20" into "+10" and "-20".
+fn split_pos(s: &str) -> Option<(&str, &str)> {
+    let i = s[1..].find(['+', '-'])? + 1;
+    Some(s.split_at(i))
+}
+
+impl FromStr for Geometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid geometry {:?}, expected WxH or WxH+X+Y", s);
+
+        let (size, pos) = match s.find(['+', '-']) {
+            Some(i) => s.split_at(i),
+            None => (s, ""),
+        };
+
+        let (w, h) = size.split_once('x').ok_or_else(err)?;
+        let size = [w.parse().map_err(|_| err())?, h.parse().map_err(|_| err())?];
+        if size.contains(&0) {
+            return Err(err());
+        }
+
+        let pos = if pos.is_empty() {
+            None
+        } else {
+            let (x, y) = split_pos(pos).ok_or_else(err)?;
+            Some([x.parse().map_err(|_| err())?, y.parse().map_err(|_| err())?])
+        };
+
+        Ok(Self { size, pos })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "1024x768".parse(),
+            Ok(Geometry {
+                size: [1024, 768],
+                pos: None
+            })
+        );
+        assert_eq!(
+            "640x480+10-20".parse(),
+            Ok(Geometry {
+                size: [640, 480],
+                pos: Some([10, -20])
+            })
+        );
+        assert!("640x480+10".parse::<Geometry>().is_err());
+        assert!("0x480".parse::<Geometry>().is_err());
+        assert!("big".parse::<Geometry>().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// OpenGL compatibility for single board computers. The 2D renderer ships GLSL 1.20 and 1.50
+// shaders only, so GLES-only drivers can't draw. Boards like the Raspberry Pi also have a
+// desktop OpenGL 2.1 driver though, which the 1.20 shaders run on. Tiles larger than the
+// driver's textures are scaled down to fit and drawn at their original size.
+
+use log::*;
+use piston_window::{Api, GfxDevice, OpenGL};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Largest texture edge of the window's driver.
+static MAX_TEXTURE: AtomicU32 = AtomicU32::new(u32::MAX);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Version(pub OpenGL);
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid OpenGL version {:?}, expected e.g. 2.1 or 3.2", s);
+        let (major, minor) = s.split_once('.').ok_or_else(err)?;
+        let major = major.parse().map_err(|_| err())?;
+        let minor = minor.parse().map_err(|_| err())?;
+        OpenGL::from_api(Api::opengl(major, minor))
+            .map(Self)
+            .ok_or_else(err)
+    }
+}
+
+// ARM Linux boards may lack OpenGL 3.2 and then fall back to GLES, which can't draw. macOS needs
+// 3.2 for anything newer than 2.1, everything else has it.
+pub fn default_version() -> OpenGL {
+    if cfg!(all(
+        target_os = "linux",
+        any(target_arch = "arm", target_arch = "aarch64")
+    )) {
+        OpenGL::V2_1
+    } else {
+        OpenGL::V3_2
+    }
+}
+
+pub fn init(device: &mut GfxDevice) {
+    let mut max_texture = 0;
+    // Safety: a single integer is written.
+    unsafe {
+        device.with_gl(|gl| gl.GetIntegerv(gfx_gl::MAX_TEXTURE_SIZE, &mut max_texture));
+    }
+    let version = &device.get_info().version;
+    info!(
+        "OpenGL {}{}.{}, textures up to {}px",
+        if version.is_embedded { "ES " } else { "" },
+        version.major,
+        version.minor,
+        max_texture
+    );
+    if max_texture > 0 {
+        MAX_TEXTURE.store(max_texture as u32, Ordering::Relaxed);
+    }
+}
+
+pub fn max_texture() -> u32 {
+    MAX_TEXTURE.load(Ordering::Relaxed)
+}
+
+// Framebuffer blits, for resizing on the GPU.
+pub fn can_blit(device: &GfxDevice) -> bool {
+    let info = device.get_info();
+    info.is_version_or_extension_supported(3, 0, "GL_ARB_framebuffer_object")
+        || info.is_embedded_version_supported(3, 0)
+}
+
+// The size `size` is scaled down to so neither edge exceeds `max`, None when it fits.
+pub fn fit(size: [u32; 2], max: u32) -> Option<[u32; 2]> {
+    let long_edge = u32::max(size[0], size[1]);
+    if long_edge <= max {
+        return None;
+    }
+    let ratio = max as f64 / long_edge as f64;
+    Some(size.map(|v| u32::clamp((v as f64 * ratio).round() as u32, 1, max)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert!(matches!("2.1".parse(), Ok(Version(OpenGL::V2_1))));
+        assert!(matches!("3.2".parse(), Ok(Version(OpenGL::V3_2))));
+        assert!("2.5".parse::<Version>().is_err());
+        assert!("3".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn fits() {
+        assert_eq!(fit([600, 400], 2048), None);
+        assert_eq!(fit([600, 400], 600), None);
+        assert_eq!(fit([600, 400], 300), Some([300, 200]));
+        assert_eq!(fit([700, 1], 64), Some([64, 1]));
+    }
+}
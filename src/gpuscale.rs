@@ -0,0 +1,306 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Thumbnail downscaling on the GPU. The GL context belongs to the window's thread, so decoder
+// threads queue large images for it and wait. It uploads each one as a texture and blits it
+// down level by level with linear filtering, then reads the levels back. Every step at most
+// halves the image like the CPU path, so the filter never skips source pixels.
+
+use ::image::{ColorType, DynamicImage, RgbaImage};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use log::*;
+use piston_window::GfxDevice;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Smaller images resize faster on the CPU than the round trip to the window's thread.
+static MIN_SIZE: u32 = 2048;
+
+// How long a decoder waits for the GPU before resizing itself, e.g. while the window is busy.
+static TIMEOUT: Duration = Duration::from_secs(2);
+
+// Images resized per update, so drawing keeps up.
+static PER_UPDATE: usize = 2;
+
+// Requests waiting for the window's thread, each holds an RGBA copy of a large image. Decoders
+// resize on the CPU when it's full.
+static QUEUE_LEN: usize = 2 * PER_UPDATE;
+
+// Largest texture edge of the GPU serving requests, 0 without one.
+static MAX_SIZE: AtomicU32 = AtomicU32::new(0);
+
+static QUEUE: Mutex<Option<Sender<Request>>> = Mutex::new(None);
+
+struct Request {
+    image: RgbaImage,
+    sizes: Vec<[u32; 2]>,
+    levels: Sender<Option<Vec<RgbaImage>>>,
+    // When the decoder stops waiting.
+    deadline: Instant,
+}
+
+// The size of `[w, h]` scaled to fit in `bucket`, rounded like `DynamicImage::thumbnail`.
+fn fit([w, h]: [u32; 2], bucket: u32) -> [u32; 2] {
+    let ratio = f64::min(bucket as f64 / w as f64, bucket as f64 / h as f64);
+    let scale = |v: u32| u32::max(1, (v as f64 * ratio).round() as u32);
+    [scale(w), scale(h)]
+}
+
+// The sizes `Thumbnailer::make_pyramid` resizes an image of `size` to, largest first.
+fn sizes(size: [u32; 2], min_bucket: u32) -> Vec<[u32; 2]> {
+    let mut sizes = Vec::new();
+    let mut current = size;
+    let mut bucket = u32::max(size[0], size[1]).next_power_of_two() >> 1;
+    while min_bucket <= bucket {
+        if bucket < u32::max(current[0], current[1]).next_power_of_two() {
+            current = fit(current, bucket);
+            sizes.push(current);
+        }
+        bucket >>= 1;
+    }
+    sizes
+}
+
+// The smaller levels of `image` down to `min_bucket`, resized on the GPU. None when no GPU is
+// serving, the image is small, deeper than 8 bits or too large for a texture, the queue is full
+// or the GPU didn't answer in time, and the caller resizes on the CPU.
+pub fn levels(image: &DynamicImage, min_bucket: u32) -> Option<Vec<DynamicImage>> {
+    let max_size = MAX_SIZE.load(Ordering::Relaxed);
+    let long_edge = u32::max(image.width(), image.height());
+    if long_edge < MIN_SIZE || long_edge > max_size {
+        return None;
+    }
+    if !matches!(image.color(), ColorType::Rgb8 | ColorType::Rgba8) {
+        return None;
+    }
+
+    let queue = QUEUE.lock().unwrap().clone()?;
+    if queue.is_full() {
+        return None;
+    }
+
+    let sizes = sizes([image.width(), image.height()], min_bucket);
+    let (tx, rx) = bounded(1);
+    let request = Request {
+        image: image.to_rgba8(),
+        sizes,
+        levels: tx,
+        deadline: Instant::now() + TIMEOUT,
+    };
+    queue.try_send(request).ok()?;
+    let levels = rx.recv_timeout(TIMEOUT).ok()??;
+    Some(levels.into_iter().map(DynamicImage::ImageRgba8).collect())
+}
+
+// Resizes the images queued by decoder threads, on the thread owning the GL context. Requests
+// are dropped with it, and their decoders resize on the CPU. Requests their decoders gave up
+// on are dropped unresized.
+pub struct Server {
+    requests: Receiver<Request>,
+}
+
+impl Server {
+    pub fn start() -> Self {
+        let max_size = crate::glcompat::max_texture();
+        info!("Resizing thumbnails on the GPU, up to {}px", max_size);
+
+        let (tx, rx) = bounded(QUEUE_LEN);
+        *QUEUE.lock().unwrap() = Some(tx);
+        MAX_SIZE.store(max_size, Ordering::Relaxed);
+        Self { requests: rx }
+    }
+
+    pub fn serve(&self, device: &mut GfxDevice) {
+        let _s = crate::stats::ScopedDuration::new("gpu_resize");
+        let now = Instant::now();
+        let waited_for = self
+            .requests
+            .try_iter()
+            .filter(|request| now < request.deadline);
+        for request in waited_for.take(PER_UPDATE) {
+            let levels = resize(device, &request.image, &request.sizes);
+            if levels.is_none() {
+                warn!("GPU resize failed, resizing on the CPU");
+            }
+            // The decoder may have given up waiting since.
+            let _ = request.levels.send(levels);
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        MAX_SIZE.store(0, Ordering::Relaxed);
+        *QUEUE.lock().unwrap() = None;
+    }
+}
+
+// Blit `image` down through `sizes` and read each level back. The GL state gfx relies on is
+// restored afterwards.
+fn resize(device: &mut GfxDevice, image: &RgbaImage, sizes: &[[u32; 2]]) -> Option<Vec<RgbaImage>> {
+    let mut levels = Vec::with_capacity(sizes.len());
+
+    // Safety: uploads read exactly w * h tightly packed RGBA pixels of `image` and read backs
+    // write exactly w * h pixels into buffers of that size. The objects created are deleted.
+    unsafe {
+        device.with_gl(|gl| {
+            let get = |pname| {
+                let mut v = 0;
+                gl.GetIntegerv(pname, &mut v);
+                v
+            };
+            let texture_binding = get(gfx_gl::TEXTURE_BINDING_2D) as u32;
+            let read_binding = get(gfx_gl::READ_FRAMEBUFFER_BINDING) as u32;
+            let draw_binding = get(gfx_gl::DRAW_FRAMEBUFFER_BINDING) as u32;
+            let unpack = get(gfx_gl::UNPACK_ALIGNMENT);
+            let pack = get(gfx_gl::PACK_ALIGNMENT);
+            let scissor = gl.IsEnabled(gfx_gl::SCISSOR_TEST) == gfx_gl::TRUE;
+
+            gl.PixelStorei(gfx_gl::UNPACK_ALIGNMENT, 1);
+            gl.PixelStorei(gfx_gl::PACK_ALIGNMENT, 1);
+            gl.Disable(gfx_gl::SCISSOR_TEST);
+
+            let mut textures = vec![0; sizes.len() + 1];
+            gl.GenTextures(textures.len() as i32, textures.as_mut_ptr());
+            let mut framebuffers = [0; 2];
+            gl.GenFramebuffers(2, framebuffers.as_mut_ptr());
+            let [read, draw] = framebuffers;
+
+            let all = std::iter::once([image.width(), image.height()]).chain(sizes.iter().copied());
+            for (&texture, [w, h]) in textures.iter().zip(all) {
+                let pixels = if texture == textures[0] {
+                    image.as_ptr() as *const _
+                } else {
+                    std::ptr::null()
+                };
+                gl.BindTexture(gfx_gl::TEXTURE_2D, texture);
+                gl.TexParameteri(gfx_gl::TEXTURE_2D, gfx_gl::TEXTURE_MAX_LEVEL, 0);
+                gl.TexImage2D(
+                    gfx_gl::TEXTURE_2D,
+                    0,
+                    gfx_gl::RGBA8 as i32,
+                    w as i32,
+                    h as i32,
+                    0,
+                    gfx_gl::RGBA,
+                    gfx_gl::UNSIGNED_BYTE,
+                    pixels,
+                );
+            }
+
+            let mut prev = [image.width(), image.height()];
+            for (n, &[w, h]) in sizes.iter().enumerate() {
+                gl.BindFramebuffer(gfx_gl::READ_FRAMEBUFFER, read);
+                gl.FramebufferTexture2D(
+                    gfx_gl::READ_FRAMEBUFFER,
+                    gfx_gl::COLOR_ATTACHMENT0,
+                    gfx_gl::TEXTURE_2D,
+                    textures[n],
+                    0,
+                );
+                gl.BindFramebuffer(gfx_gl::DRAW_FRAMEBUFFER, draw);
+                gl.FramebufferTexture2D(
+                    gfx_gl::DRAW_FRAMEBUFFER,
+                    gfx_gl::COLOR_ATTACHMENT0,
+                    gfx_gl::TEXTURE_2D,
+                    textures[n + 1],
+                    0,
+                );
+                if gl.CheckFramebufferStatus(gfx_gl::READ_FRAMEBUFFER)
+                    != gfx_gl::FRAMEBUFFER_COMPLETE
+                    || gl.CheckFramebufferStatus(gfx_gl::DRAW_FRAMEBUFFER)
+                        != gfx_gl::FRAMEBUFFER_COMPLETE
+                {
+                    break;
+                }
+                gl.BlitFramebuffer(
+                    0,
+                    0,
+                    prev[0] as i32,
+                    prev[1] as i32,
+                    0,
+                    0,
+                    w as i32,
+                    h as i32,
+                    gfx_gl::COLOR_BUFFER_BIT,
+                    gfx_gl::LINEAR,
+                );
+
+                let mut pixels = vec![0u8; w as usize * h as usize * 4];
+                gl.BindFramebuffer(gfx_gl::READ_FRAMEBUFFER, draw);
+                gl.ReadPixels(
+                    0,
+                    0,
+                    w as i32,
+                    h as i32,
+                    gfx_gl::RGBA,
+                    gfx_gl::UNSIGNED_BYTE,
+                    pixels.as_mut_ptr() as *mut _,
+                );
+                levels.push(RgbaImage::from_raw(w, h, pixels).expect("level size"));
+                prev = [w, h];
+            }
+            let failed = gl.GetError() != gfx_gl::NO_ERROR;
+
+            gl.DeleteFramebuffers(2, framebuffers.as_ptr());
+            gl.DeleteTextures(textures.len() as i32, textures.as_ptr());
+            gl.BindTexture(gfx_gl::TEXTURE_2D, texture_binding);
+            gl.BindFramebuffer(gfx_gl::READ_FRAMEBUFFER, read_binding);
+            gl.BindFramebuffer(gfx_gl::DRAW_FRAMEBUFFER, draw_binding);
+            gl.PixelStorei(gfx_gl::UNPACK_ALIGNMENT, unpack);
+            gl.PixelStorei(gfx_gl::PACK_ALIGNMENT, pack);
+            if scissor {
+                gl.Enable(gfx_gl::SCISSOR_TEST);
+            }
+
+            if failed {
+                levels.clear();
+            }
+        });
+    }
+
+    Some(levels).filter(|levels| levels.len() == sizes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pyramid_sizes() {
+        assert_eq!(fit([3000, 2000], 2048), [2048, 1365]);
+        assert_eq!(fit([10, 4000], 8), [1, 8]);
+
+        // The same sizes as the CPU path, one per bucket.
+        let mut image = DynamicImage::new_rgb8(300, 200);
+        let mut cpu = Vec::new();
+        for bucket in [256, 128, 64, 32, 16, 8] {
+            image = image.thumbnail(bucket, bucket);
+            cpu.push([image.width(), image.height()]);
+        }
+        assert_eq!(sizes([300, 200], 8), cpu);
+        assert_eq!(sizes([3000, 2000], 8)[1], [1024, 683]);
+
+        // Images just over a bucket barely shrink at first.
+        assert_eq!(
+            super::sizes([2049, 100], 1024),
+            vec![[2048, 100], [1024, 50]]
+        );
+
+        // No GPU serving.
+        let image = DynamicImage::new_rgba8(4096, 4096);
+        assert!(levels(&image, 8).is_none());
+    }
+}
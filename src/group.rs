@@ -12,21 +12,42 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::adjust::Adjustments;
+use crate::cluster::Cluster;
 use crate::database::Database;
 use crate::image::Image;
+use crate::lowmem;
+use crate::proof::Proof;
+use crate::transform::Transform2D;
 use crate::vec::*;
-use crate::view::View;
+use crate::view::{Level, View};
+use crate::File;
 use crate::Stopwatch;
 use crate::TileRef;
 use crate::R;
 use crate::{Metadata, MetadataState};
 use log::*;
-use piston_window::{
-    color, rectangle, DrawState, G2d, G2dTexture, G2dTextureContext, Texture, TextureSettings,
-    Transformed,
-};
+use piston_window::{color, rectangle, DrawState, G2d, G2dTexture, G2dTextureContext, Rectangle};
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+
+// What the queues of a group were last built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Check {
+    on_screen: bool,
+    target_size: u32,
+    // Least and greatest `shift` of the images.
+    shifts: [usize; 2],
+}
+
+// How many sizes below the target an off screen image with `visible_ratio` loads, images
+// further from the window load smaller thumbnails.
+fn shift(ratio: f64) -> usize {
+    f64::max(0.0, ratio - 1.0).floor() as usize
+}
 
 #[derive(Debug)]
 pub struct Group {
@@ -35,6 +56,7 @@ pub struct Group {
     pub images: BTreeMap<Vector2<u32>, Image>,
     pub cache_todo: [VecDeque<Vector2<u32>>; 2],
     pub thumb_todo: [VecDeque<Vector2<u32>>; 2],
+    checked: Option<Check>,
 }
 
 impl Group {
@@ -45,6 +67,7 @@ impl Group {
             images: BTreeMap::new(),
             cache_todo: [VecDeque::new(), VecDeque::new()],
             thumb_todo: [VecDeque::new(), VecDeque::new()],
+            checked: None,
         }
     }
 
@@ -52,12 +75,37 @@ impl Group {
         self.images.insert(coords, image);
     }
 
+    // Columns and rows of the group that may be visible, empty if it is off screen.
+    fn visible(&self, view: &View) -> (Range<u32>, Range<u32>) {
+        let [min, max] = view.visible_coords();
+        let [extents_min, extents_max] = self.extents;
+        let range = |i: usize| {
+            let start = u32::max(min[i], extents_min[i]);
+            start..u32::max(start, u32::min(max[i], extents_max[i]))
+        };
+        (range(0), range(1))
+    }
+
+    // Images that may be visible, a column at a time.
+    fn visible_images<'a>(
+        &'a self,
+        view: &View,
+    ) -> impl Iterator<Item = (Vector2<u32>, &'a Image)> + 'a {
+        let (xs, ys) = self.visible(view);
+        xs.flat_map(move |x| {
+            self.images
+                .range([x, ys.start]..[x, ys.end])
+                .map(|(&coords, image)| (coords, image))
+        })
+    }
+
     #[allow(dead_code)]
     pub fn reset(&mut self) {
         for image in self.images.values_mut() {
             image.reset();
         }
         self.tiles.clear();
+        self.checked = None;
 
         for queue in &mut self.cache_todo {
             queue.clear();
@@ -69,6 +117,23 @@ impl Group {
     }
 
     pub fn recheck(&mut self, view: &View) {
+        let (xs, ys) = self.visible(view);
+        let on_screen = !xs.is_empty() && !ys.is_empty();
+
+        // Off screen groups keep their queues while every image still wants the same size, the
+        // order within them matters little. Only groups on a boundary between sizes and those
+        // on screen are sorted again as the view moves.
+        let [lo, hi] = view.visible_ratio_range(self.extents);
+        let check = Check {
+            on_screen,
+            target_size: view.target_size(),
+            shifts: [shift(lo), shift(hi)],
+        };
+        if !on_screen && check.shifts[0] == check.shifts[1] && self.checked == Some(check) {
+            return;
+        }
+        self.checked = Some(check);
+
         for queue in &mut self.thumb_todo {
             queue.clear();
         }
@@ -82,7 +147,7 @@ impl Group {
         mouse_dist.sort_by_key(|(&coords, _)| vec2_square_len(view.mouse_dist(coords)) as isize);
 
         for (&coords, image) in &mouse_dist {
-            let p = !view.is_visible(view.trans(coords)) as usize;
+            let p = !(on_screen && view.is_visible(view.trans(coords))) as usize;
 
             match image.metadata {
                 MetadataState::Some(_) => {
@@ -100,73 +165,102 @@ impl Group {
         &mut self,
         p: usize,
         view: &View,
+        single: Option<(Vector2<u32>, &Adjustments, Proof)>,
         db: &Database,
         texture_context: &mut G2dTextureContext,
         stopwatch: &Stopwatch,
     ) -> bool {
         let target_size = view.target_size();
 
-        let texture_settings = TextureSettings::new();
-
         while let Some(coords) = self.cache_todo[p].pop_front() {
+            let view_coords = view.trans(coords);
+
+            // Scrolled off screen since the queues were built, e.g. during a fast pan. Load what
+            // is on screen now first instead.
+            if p == 0 && !view.is_visible(view_coords) {
+                self.cache_todo[1].push_front(coords);
+                continue;
+            }
+
             let image = self.images.get_mut(&coords).unwrap();
 
             let metadata = image.get_metadata().expect("Image::get_metadata");
 
-            let view_coords = view.trans(coords);
-
             let shift = if p == 0 {
                 0
             } else {
-                let ratio = view.visible_ratio(view_coords);
-                f64::max(0.0, ratio - 1.0).floor() as usize
+                shift(view.visible_ratio(view_coords))
             };
 
-            let new_size = metadata.nearest(target_size >> shift);
+            // Visible images load the largest thumbnail once a grid square covers the window. In
+            // low memory mode off screen images keep only the smallest.
+            let new_size = if p == 0 && view.level() == Level::Full && !lowmem::enabled() {
+                metadata.thumbs(image.page).len() - 1
+            } else if p == 1 && lowmem::enabled() {
+                0
+            } else {
+                metadata.nearest(image.page, target_size >> shift)
+            };
 
             let current_size = image.size.unwrap_or(0);
 
-            // Progressive resizing.
+            // Progressive resizing, shrinking at once in low memory mode.
             let new_size = match new_size.cmp(&current_size) {
+                Ordering::Less if lowmem::enabled() => new_size,
                 Ordering::Less => current_size - 1,
-                Ordering::Equal => {
+                Ordering::Equal if image.size.is_some() => {
                     // Already loaded target size.
                     continue;
                 }
+                Ordering::Equal => current_size,
                 Ordering::Greater => current_size + 1,
             };
 
-            // Load new tiles.
-            for tile_ref in &metadata.thumbs[new_size].tile_refs {
-                // Already loaded.
-                if self.tiles.contains_key(tile_ref) {
-                    continue;
-                }
+            // Every frame of animations stays loaded, other images only load the current page.
+            let pages = if metadata.is_animated() {
+                0..metadata.page_count()
+            } else {
+                image.page..image.page + 1
+            };
 
-                if stopwatch.done() {
-                    self.cache_todo[p].push_front(coords);
-                    return false;
+            // Load new tiles.
+            for page in pages.clone() {
+                for tile_ref in &metadata.thumbs(page)[new_size].tile_refs {
+                    // Already loaded.
+                    if self.tiles.contains_key(tile_ref) {
+                        continue;
+                    }
+
+                    if stopwatch.done() {
+                        self.cache_todo[p].push_front(coords);
+                        return false;
+                    }
+
+                    // TODO: Would be great to move off thread.
+                    let image =
+                        crate::upload::tile(db, *tile_ref, texture_context, |image| match single {
+                            Some((single, adjustments, proof)) if single == coords => {
+                                if !adjustments.is_tone_default() {
+                                    adjustments.apply(image);
+                                }
+                                proof.apply(image);
+                            }
+                            _ => {}
+                        });
+
+                    self.tiles.insert(*tile_ref, image);
                 }
-
-                let data = db.get(*tile_ref).expect("db get").expect("missing tile");
-
-                let image = ::image::load_from_memory(&data).expect("load image");
-
-                // TODO: Would be great to move off thread.
-                let image =
-                    Texture::from_image(texture_context, &image.to_rgba8(), &texture_settings)
-                        .expect("texture");
-
-                self.tiles.insert(*tile_ref, image);
             }
 
             // Unload old tiles.
-            for (j, thumb) in metadata.thumbs.iter().enumerate() {
-                if j == new_size {
-                    continue;
-                }
-                for tile_ref in &thumb.tile_refs {
-                    self.tiles.remove(tile_ref);
+            for page in pages {
+                for (j, thumb) in metadata.thumbs(page).iter().enumerate() {
+                    if j == new_size {
+                        continue;
+                    }
+                    for tile_ref in &thumb.tile_refs {
+                        self.tiles.remove(tile_ref);
+                    }
                 }
             }
 
@@ -185,7 +279,7 @@ impl Group {
 
             if let Some(coords) = self.thumb_todo[p].pop_front() {
                 let image = self.images.get(&coords).unwrap();
-                if !thumbnailer.make_thumbs(image) {
+                if !image.is_missing() || !thumbnailer.make_thumbs(image.i, &image.file) {
                     return false;
                 }
             } else {
@@ -194,6 +288,94 @@ impl Group {
         }
     }
 
+    // Move to another page of a multi-page image, returns the new page and the page count.
+    pub fn step_page(&mut self, coords: Vector2<u32>, delta: isize) -> Option<(usize, usize)> {
+        let image = self.images.get_mut(&coords)?;
+        let metadata = image.get_metadata()?;
+
+        let count = metadata.page_count();
+        let page = (image.page as isize + delta).clamp(0, count as isize - 1) as usize;
+        if page == image.page {
+            return Some((page, count));
+        }
+
+        // All frames of animations are already loaded.
+        if metadata.is_animated() {
+            image.page = page;
+            return Some((page, count));
+        }
+
+        for thumb in metadata.thumbs(image.page) {
+            for tile_ref in &thumb.tile_refs {
+                self.tiles.remove(tile_ref);
+            }
+        }
+
+        image.page = page;
+        image.size = None;
+        self.cache_todo[0].push_front(coords);
+
+        Some((page, count))
+    }
+
+    pub fn animate(&mut self, view: &View, now: Instant) {
+        let (xs, ys) = self.visible(view);
+        for x in xs {
+            for (&coords, image) in self.images.range_mut([x, ys.start]..[x, ys.end]) {
+                if view.is_visible(view.trans(coords)) && image.animate(now) {
+                    crate::pacing::changed();
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, coords: Vector2<u32>) -> Option<&Image> {
+        self.images.get(&coords)
+    }
+
+    pub fn get_mut(&mut self, coords: Vector2<u32>) -> Option<&mut Image> {
+        self.images.get_mut(&coords)
+    }
+
+    // Upload the tiles of an image again, to apply changed adjustments.
+    pub fn reload(&mut self, coords: Vector2<u32>) {
+        let image = match self.images.get_mut(&coords) {
+            Some(image) => image,
+            None => return,
+        };
+
+        if let Some(metadata) = image.get_metadata() {
+            for tile_ref in metadata.tile_refs() {
+                self.tiles.remove(tile_ref);
+            }
+            image.reset();
+            self.cache_todo[0].push_front(coords);
+        }
+    }
+
+    // Thumbnail the image again after its file changed.
+    pub fn refresh(&mut self, coords: Vector2<u32>, file: Arc<File>) {
+        let image = match self.images.get_mut(&coords) {
+            Some(image) => image,
+            None => return,
+        };
+
+        // New tiles are written under the same ids.
+        if let Some(metadata) = image.get_metadata() {
+            for tile_ref in metadata.tile_refs() {
+                self.tiles.remove(tile_ref);
+            }
+        }
+
+        *image = Image::from(
+            image.i,
+            file,
+            MetadataState::Missing,
+            image.user_data.clone(),
+        );
+        self.thumb_todo[0].push_front(coords);
+    }
+
     pub fn update_metadata(&mut self, coords: Vector2<u32>, metadata_res: R<Metadata>) {
         let image = self.images.get_mut(&coords).unwrap();
         image.metadata = match metadata_res {
@@ -208,7 +390,7 @@ impl Group {
         };
     }
 
-    pub fn draw(&self, trans: [[f64; 3]; 2], view: &View, draw_state: &DrawState, g: &mut G2d) {
+    pub fn draw(&self, trans: Transform2D, view: &View, draw_state: &DrawState, g: &mut G2d) {
         //{
         //    let [min, max] = self.extents;
         //    let op_color = color::hex("FF0000");
@@ -221,24 +403,108 @@ impl Group {
         //    rectangle(op_color, [0.0, h, w, 1.0], trans, g);
         //}
 
-        let dot_color = color::hex("444444");
-        let mid_zoom = view.zoom * 0.5;
+        let adjustments = Adjustments::default();
+        let mut drawn = false;
+        for (coords, _) in self.visible_images(view) {
+            drawn |= self.draw_image(coords, trans, view, &adjustments, draw_state, g);
+        }
+        if drawn {
+            crate::stats::stage("startup_first_thumbs");
+        }
+    }
 
-        for (&coords, image) in &self.images {
-            let coords = view.trans(coords);
+    // Window positions of the visible stacked bursts and their number of frames.
+    pub fn stack_badges(&self, view: &View) -> Vec<(Vector2<f64>, usize)> {
+        self.visible_images(view)
+            .filter(|(_, image)| !image.stack.is_empty())
+            .map(|(coords, image)| (view.trans(coords), image.stack.len() + 1))
+            .filter(|(pos, _)| view.is_visible(*pos))
+            .collect()
+    }
 
-            if !view.is_visible(coords) {
+    // Fill each grid square with the color of its directory cluster.
+    pub fn draw_clusters(
+        &self,
+        trans: Transform2D,
+        view: &View,
+        clusters: &[Cluster],
+        g: &mut G2d,
+    ) {
+        for (coords, image) in self.visible_images(view) {
+            let [x, y] = view.trans(coords);
+            if !view.is_visible([x, y]) {
                 continue;
             }
+            if let Some(cluster) = Cluster::find(clusters, image.i) {
+                rectangle(
+                    cluster.color(),
+                    [x, y, view.zoom, view.zoom],
+                    trans.matrix(),
+                    g,
+                );
+            }
+        }
+    }
 
-            let trans = trans.trans(coords[0], coords[1]);
+    // Whether a thumbnail was drawn rather than a placeholder.
+    pub fn draw_image(
+        &self,
+        coords: Vector2<u32>,
+        trans: Transform2D,
+        view: &View,
+        adjustments: &Adjustments,
+        draw_state: &DrawState,
+        g: &mut G2d,
+    ) -> bool {
+        let image = match self.images.get(&coords) {
+            Some(image) => image,
+            None => return false,
+        };
 
-            if image.draw(trans, view, &self.tiles, draw_state, g) {
-                continue;
+        let coords = view.trans(coords);
+
+        if !view.is_visible(coords) {
+            return false;
+        }
+
+        let trans = trans.trans(coords[0], coords[1]);
+
+        let drawn = image.draw(trans, view.zoom, adjustments, &self.tiles, draw_state, g);
+        if !drawn {
+            if crate::mono::enabled() {
+                let inset = view.zoom * 0.1;
+                let size = view.zoom - 2.0 * inset;
+                Rectangle::new_border(crate::mono::PLACEHOLDER, 1.0).draw(
+                    [inset, inset, size, size],
+                    draw_state,
+                    trans.matrix(),
+                    g,
+                );
             } else {
-                rectangle(dot_color, [mid_zoom, mid_zoom, 1.0, 1.0], trans, g);
+                let dot_color = color::hex("444444");
+                let mid_zoom = view.zoom * 0.5;
+                rectangle(dot_color, [mid_zoom, mid_zoom, 1.0, 1.0], trans.matrix(), g);
             }
         }
+
+        image.draw_user_data(trans, view, g);
+        drawn
+    }
+
+    // Draw an image into a window rectangle with whatever tiles are loaded.
+    pub fn draw_thumb(
+        &self,
+        coords: Vector2<u32>,
+        [x, y, size, _]: [f64; 4],
+        trans: Transform2D,
+        draw_state: &DrawState,
+        g: &mut G2d,
+    ) {
+        if let Some(image) = self.images.get(&coords) {
+            let adjustments = Adjustments::default();
+            let trans = trans.trans(x, y);
+            image.draw(trans, size, &adjustments, &self.tiles, draw_state, g);
+        }
     }
 
     pub fn mouse_dist(&self, view: &View) -> usize {
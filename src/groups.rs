@@ -12,23 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::adjust::Adjustments;
+use crate::cluster::Cluster;
 use crate::database::Database;
 use crate::group::Group;
 use crate::image::Image;
+use crate::proof::Proof;
 use crate::thumbnailer::Thumbnailer;
+use crate::transform::Transform2D;
 use crate::vec::*;
-use crate::view::View;
-use crate::{Metadata, Stopwatch, R};
+use crate::view::{Level, View};
+use crate::{File, Metadata, Stopwatch, R};
 use piston_window::{DrawState, G2d, G2dTextureContext};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug, Default)]
 pub struct Groups {
     grid_size: Vector2<u32>,
     group_size: Vector2<u32>,
     groups: Vec<(Vector2<u32>, Group)>,
+
+    // Positions in `groups`, which are kept sorted by distance to the mouse cursor.
+    index: BTreeMap<Vector2<u32>, usize>,
+
+    clusters: Vec<Cluster>,
+    num_images: usize,
+
+    // Images waiting for thumbnails.
+    num_missing: usize,
 }
 
+// Clusters smaller than this many window pixels aren't labeled.
+static LABEL_AREA: f64 = 64.0 * 64.0;
+
+// Grid squares smaller than this many window pixels have no stack badges.
+static BADGE_ZOOM: f64 = 48.0;
+
 impl Groups {
     fn group_size_from_grid_size(grid_size: Vector2<u32>) -> Vector2<u32> {
         vec2_max(vec2_u32(vec2_log(vec2_f64(grid_size), 2.0)), [1, 1])
@@ -38,6 +59,9 @@ impl Groups {
         let mut ret = Groups {
             grid_size,
             group_size: Self::group_size_from_grid_size(grid_size),
+            clusters: Cluster::split(images.iter().map(|image| &*image.file)),
+            num_images: images.len(),
+            num_missing: images.iter().filter(|image| image.is_missing()).count(),
             ..Default::default()
         };
 
@@ -45,16 +69,34 @@ impl Groups {
         for image in images.into_iter() {
             ret.insert(&mut group_map, image);
         }
-        ret.groups.extend(group_map.into_iter());
+        ret.groups.extend(group_map);
+        ret.reindex();
 
         ret
     }
 
+    fn reindex(&mut self) {
+        self.index = self
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(i, (coords, _))| (*coords, i))
+            .collect();
+    }
+
     pub fn grid_size(&self) -> Vector2<u32> {
         self.grid_size
     }
 
-    fn image_coords(&self, i: usize) -> Vector2<u32> {
+    pub fn num_images(&self) -> usize {
+        self.num_images
+    }
+
+    pub fn num_missing(&self) -> usize {
+        self.num_missing
+    }
+
+    pub fn image_coords(&self, i: usize) -> Vector2<u32> {
         let w = self.grid_size[0] as usize;
         [(i % w) as u32, (i / w) as u32]
     }
@@ -75,17 +117,93 @@ impl Groups {
         group.insert(image_coords, image);
     }
 
+    // Remove all images, in grid order.
+    pub fn take_images(&mut self) -> Vec<Image> {
+        let groups = std::mem::take(self).groups;
+        let mut images: Vec<Image> = groups
+            .into_iter()
+            .flat_map(|(_, group)| group.images.into_values())
+            .collect();
+        images.sort_by_key(|image| image.i);
+        for image in &mut images {
+            image.reset();
+        }
+        images
+    }
+
+    // Mark the sharpest frame of each burst.
+    pub fn mark_picks(&mut self) {
+        let images = self
+            .groups
+            .iter()
+            .flat_map(|(_, group)| group.images.values());
+        let picks: BTreeSet<String> = crate::stack::picks(images)
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        for (_, group) in &mut self.groups {
+            for image in group.images.values_mut() {
+                image.pick = picks.contains(&image.file.path);
+                for frame in &mut image.stack {
+                    frame.pick = picks.contains(&frame.file.path);
+                }
+            }
+        }
+    }
+
     pub fn update_metadata(&mut self, i: usize, metadata_res: R<Metadata>) {
         let image_coords = self.image_coords(i);
-        let group_coords = self.group_coords(image_coords);
 
-        // This looks horrible and O(n), but it's likely O(1) for thumbnails close to the mouse
-        // cursor.
-        for (coords, group) in &mut self.groups {
-            if coords == &group_coords {
-                group.update_metadata(image_coords, metadata_res);
-                return;
-            }
+        if self.get(image_coords).is_some_and(Image::is_missing) {
+            self.num_missing -= 1;
+        }
+
+        if let Some(group) = self.group_mut(image_coords) {
+            group.update_metadata(image_coords, metadata_res);
+        }
+    }
+
+    fn group(&self, image_coords: Vector2<u32>) -> Option<&Group> {
+        let i = *self.index.get(&self.group_coords(image_coords))?;
+        Some(&self.groups[i].1)
+    }
+
+    fn group_mut(&mut self, image_coords: Vector2<u32>) -> Option<&mut Group> {
+        let i = *self.index.get(&self.group_coords(image_coords))?;
+        Some(&mut self.groups[i].1)
+    }
+
+    pub fn get(&self, image_coords: Vector2<u32>) -> Option<&Image> {
+        self.group(image_coords)?.get(image_coords)
+    }
+
+    pub fn get_mut(&mut self, image_coords: Vector2<u32>) -> Option<&mut Image> {
+        self.group_mut(image_coords)?.get_mut(image_coords)
+    }
+
+    pub fn step_page(
+        &mut self,
+        image_coords: Vector2<u32>,
+        delta: isize,
+    ) -> Option<(usize, usize)> {
+        self.group_mut(image_coords)?.step_page(image_coords, delta)
+    }
+
+    pub fn reload(&mut self, image_coords: Vector2<u32>) {
+        if let Some(group) = self.group_mut(image_coords) {
+            group.reload(image_coords);
+        }
+    }
+
+    pub fn refresh(&mut self, image_coords: Vector2<u32>, file: Arc<File>) {
+        if self
+            .get(image_coords)
+            .is_some_and(|image| !image.is_missing())
+        {
+            self.num_missing += 1;
+        }
+        if let Some(group) = self.group_mut(image_coords) {
+            group.refresh(image_coords, file);
         }
     }
 
@@ -103,7 +221,8 @@ impl Groups {
             }
         }
 
-        self.groups.extend(group_map.into_iter());
+        self.groups.extend(group_map);
+        self.reindex();
     }
 
     pub fn recheck(&mut self, view: &View) {
@@ -112,6 +231,13 @@ impl Groups {
         }
 
         self.groups.sort_by_key(|(_, g)| g.mouse_dist(view));
+        self.reindex();
+    }
+
+    pub fn animate(&mut self, view: &View, now: Instant) {
+        for (_, group) in &mut self.groups {
+            group.animate(view, now);
+        }
     }
 
     #[allow(dead_code)]
@@ -124,13 +250,15 @@ impl Groups {
     pub fn load_cache(
         &mut self,
         view: &View,
+        single: Option<(Vector2<u32>, &Adjustments, Proof)>,
         db: &Database,
         texture_context: &mut G2dTextureContext,
         stopwatch: &Stopwatch,
     ) {
+        let _s = crate::stats::ScopedDuration::new("load_cache");
         for p in 0..2 {
             for (_, group) in &mut self.groups {
-                if !group.load_cache(p, view, db, texture_context, stopwatch) {
+                if !group.load_cache(p, view, single, db, texture_context, stopwatch) {
                     return;
                 }
             }
@@ -147,9 +275,68 @@ impl Groups {
         }
     }
 
-    pub fn draw(&self, trans: [[f64; 3]; 2], view: &View, draw_state: &DrawState, g: &mut G2d) {
+    pub fn draw(&self, trans: Transform2D, view: &View, draw_state: &DrawState, g: &mut G2d) {
         for (_, group) in &self.groups {
-            group.draw(trans, view, draw_state, g);
+            if view.level() == Level::Clusters {
+                group.draw_clusters(trans, view, &self.clusters, g);
+            } else {
+                group.draw(trans, view, draw_state, g);
+            }
+        }
+    }
+
+    // Window positions and names of the clusters large enough to label.
+    pub fn cluster_labels(&self, view: &View) -> Vec<(Vector2<f64>, String)> {
+        if view.level() != Level::Clusters {
+            return Vec::new();
+        }
+
+        self.clusters
+            .iter()
+            .filter(|cluster| cluster.len as f64 * view.zoom * view.zoom >= LABEL_AREA)
+            .map(|cluster| (view.trans(self.image_coords(cluster.start)), cluster))
+            .filter(|(pos, _)| view.is_visible(*pos))
+            .map(|(pos, cluster)| (pos, cluster.label()))
+            .collect()
+    }
+
+    // Window positions of the visible stacked bursts and their number of frames.
+    pub fn stack_badges(&self, view: &View) -> Vec<(Vector2<f64>, usize)> {
+        if view.level() == Level::Clusters || view.zoom < BADGE_ZOOM {
+            return Vec::new();
+        }
+
+        self.groups
+            .iter()
+            .flat_map(|(_, group)| group.stack_badges(view))
+            .collect()
+    }
+
+    pub fn draw_thumb(
+        &self,
+        i: usize,
+        rect: [f64; 4],
+        trans: Transform2D,
+        draw_state: &DrawState,
+        g: &mut G2d,
+    ) {
+        let image_coords = self.image_coords(i);
+        if let Some(group) = self.group(image_coords) {
+            group.draw_thumb(image_coords, rect, trans, draw_state, g);
+        }
+    }
+
+    pub fn draw_single(
+        &self,
+        image_coords: Vector2<u32>,
+        trans: Transform2D,
+        view: &View,
+        adjustments: &Adjustments,
+        draw_state: &DrawState,
+        g: &mut G2d,
+    ) {
+        if let Some(group) = self.group(image_coords) {
+            group.draw_image(image_coords, trans, view, adjustments, draw_state, g);
         }
     }
 }
@@ -0,0 +1,125 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Composition and alignment lines drawn over the single image view.
+
+use piston_window::{line_from_to, Context, G2d};
+
+// Pixel grids finer than this many window pixels per cell aren't drawn.
+static MIN_CELL_PX: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Guide {
+    Thirds,
+    Golden,
+    Center,
+    PixelGrid,
+}
+
+impl Guide {
+    // Cycle through the guides and back to none.
+    pub fn next(guide: Option<Self>) -> Option<Self> {
+        match guide {
+            None => Some(Guide::Thirds),
+            Some(Guide::Thirds) => Some(Guide::Golden),
+            Some(Guide::Golden) => Some(Guide::Center),
+            Some(Guide::Center) => Some(Guide::PixelGrid),
+            Some(Guide::PixelGrid) => None,
+        }
+    }
+
+    // Fractions of the width and height to draw lines at.
+    fn fractions(self) -> &'static [f64] {
+        const PHI: f64 = 0.618_033_988_749_895;
+        match self {
+            Guide::Thirds => &[1.0 / 3.0, 2.0 / 3.0],
+            Guide::Golden => &[1.0 - PHI, PHI],
+            Guide::Center => &[0.5],
+            Guide::PixelGrid => &[],
+        }
+    }
+
+    // Lines in image pixels, `cell` is the pixel grid size.
+    pub fn lines(self, [w, h]: [u32; 2], cell: u32) -> Vec<([f64; 2], [f64; 2])> {
+        let (w, h) = (w as f64, h as f64);
+
+        let mut xs: Vec<f64> = self.fractions().iter().map(|f| f * w).collect();
+        let mut ys: Vec<f64> = self.fractions().iter().map(|f| f * h).collect();
+        if self == Guide::PixelGrid && cell > 0 {
+            let cell = cell as f64;
+            xs.extend((1..).map(|i| i as f64 * cell).take_while(|&x| x < w));
+            ys.extend((1..).map(|i| i as f64 * cell).take_while(|&y| y < h));
+        }
+
+        let vertical = xs.into_iter().map(|x| ([x, 0.0], [x, h]));
+        let horizontal = ys.into_iter().map(|y| ([0.0, y], [w, y]));
+        vertical.chain(horizontal).collect()
+    }
+
+    // `origin` and `scale` map image pixels to the window.
+    pub fn draw(
+        self,
+        img_size: [u32; 2],
+        cell: u32,
+        origin: [f64; 2],
+        scale: f64,
+        c: &Context,
+        g: &mut G2d,
+    ) {
+        if self == Guide::PixelGrid && (cell as f64) * scale < MIN_CELL_PX {
+            return;
+        }
+
+        let to_window = |[x, y]: [f64; 2]| [origin[0] + x * scale, origin[1] + y * scale];
+        for (from, to) in self.lines(img_size, cell) {
+            line_from_to(
+                [1.0, 1.0, 1.0, 0.6],
+                0.5,
+                to_window(from),
+                to_window(to),
+                c.transform,
+                g,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            Guide::Thirds.lines([300, 90], 0),
+            vec![
+                ([100.0, 0.0], [100.0, 90.0]),
+                ([200.0, 0.0], [200.0, 90.0]),
+                ([0.0, 30.0], [300.0, 30.0]),
+                ([0.0, 60.0], [300.0, 60.0]),
+            ]
+        );
+        assert_eq!(Guide::Center.lines([10, 10], 0).len(), 2);
+        assert_eq!(
+            Guide::PixelGrid.lines([20, 8], 8),
+            vec![([8.0, 0.0], [8.0, 8.0]), ([16.0, 0.0], [16.0, 8.0])]
+        );
+
+        let mut guide = None;
+        for _ in 0..5 {
+            guide = Guide::next(guide);
+        }
+        assert_eq!(guide, None);
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ::image::RgbaImage;
+use piston_window::{line_from_to, rectangle, Context, G2d, Transformed};
+
+static WIDTH: f64 = 256.0;
+static HEIGHT: f64 = 100.0;
+static MARGIN: f64 = 8.0;
+
+// Red, green, blue and luminance counts for each 8-bit value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub channels: [[u32; 256]; 4],
+}
+
+impl Histogram {
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let mut channels = [[0; 256]; 4];
+        for &::image::Rgba([r, g, b, _]) in image.pixels() {
+            channels[0][r as usize] += 1;
+            channels[1][g as usize] += 1;
+            channels[2][b as usize] += 1;
+            // Rec. 709 luma.
+            let y = (2126 * r as u32 + 7152 * g as u32 + 722 * b as u32) / 10000;
+            channels[3][y as usize] += 1;
+        }
+        Self { channels }
+    }
+
+    // Tallest bin, ignoring the clipped ends so they don't flatten everything else.
+    fn max(&self) -> u32 {
+        self.channels
+            .iter()
+            .flat_map(|channel| &channel[1..255])
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1)
+    }
+
+    // Draw in the bottom right corner of the window, `scale` times the size.
+    pub fn draw(&self, scale: f64, c: &Context, g: &mut G2d) {
+        let [w, h] = c.get_view_size();
+        let trans = c
+            .transform
+            .trans(w - (WIDTH + MARGIN) * scale, h - (HEIGHT + MARGIN) * scale)
+            .scale(scale, scale);
+
+        rectangle([0.0, 0.0, 0.0, 0.7], [0.0, 0.0, WIDTH, HEIGHT], trans, g);
+
+        let max = self.max() as f64;
+        let colors = [
+            [1.0, 0.2, 0.2, 0.8],
+            [0.2, 1.0, 0.2, 0.8],
+            [0.3, 0.3, 1.0, 0.8],
+            [1.0, 1.0, 1.0, 0.8],
+        ];
+        for (channel, color) in self.channels.iter().zip(colors) {
+            let y = |n: u32| HEIGHT - f64::min(n as f64 / max, 1.0) * HEIGHT;
+            for x in 1..256 {
+                let from = [(x - 1) as f64, y(channel[x - 1])];
+                let to = [x as f64, y(channel[x])];
+                line_from_to(color, 0.5, from, to, trans, g);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_image() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, ::image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, ::image::Rgba([100, 100, 100, 255]));
+
+        let histogram = Histogram::from_image(&image);
+        assert_eq!(histogram.channels[0][255], 1);
+        assert_eq!(histogram.channels[0][100], 1);
+        assert_eq!(histogram.channels[1][0], 1);
+        assert_eq!(histogram.channels[3][54], 1);
+        assert_eq!(histogram.channels[3][100], 1);
+        assert_eq!(histogram.max(), 1);
+    }
+}
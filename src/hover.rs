@@ -0,0 +1,166 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Full resolution crop of the grid thumbnail under the cursor, shown while a key is held. Only
+// the largest pyramid tiles around the cursor are loaded from the database.
+
+use crate::database::Database;
+use crate::groups::Groups;
+use crate::vec::*;
+use crate::view::View;
+use crate::{Stopwatch, Thumb, TileMap, TileRef};
+use piston_window::{
+    rectangle, Context, DrawState, G2d, G2dTexture, G2dTextureContext, Image, Rectangle,
+};
+
+// Loupe width and height in window pixels.
+static SIZE: f64 = 256.0;
+
+#[derive(Default)]
+pub struct Hover {
+    // Grid coordinates and page of the image the tiles belong to.
+    image: Option<(Vector2<u32>, usize)>,
+
+    // Largest pyramid level tiles and their rectangles in image pixels.
+    layout: Vec<(TileRef, [f64; 4])>,
+
+    // Cursor position in image pixels.
+    pos: Vector2<f64>,
+
+    tiles: TileMap<G2dTexture>,
+}
+
+// Tile refs of a thumb with their rectangles, tiles are stored row by row.
+pub fn layout(thumb: &Thumb) -> Vec<(TileRef, [f64; 4])> {
+    let tile_spec = thumb.tile_spec();
+    let xs: Vec<(u32, u32)> = tile_spec.x_ranges().collect();
+    let rects = tile_spec.y_ranges().flat_map(|(y, max_y)| {
+        xs.iter()
+            .map(move |&(x, max_x)| [x, y, max_x - x, max_y - y].map(|v| v as f64))
+    });
+    thumb.tile_refs.iter().copied().zip(rects).collect()
+}
+
+impl Hover {
+    // Track the image under the cursor and load the tiles under the loupe.
+    pub fn update(
+        &mut self,
+        view: &View,
+        groups: &Groups,
+        db: &Database,
+        texture_context: &mut G2dTextureContext,
+        stopwatch: &Stopwatch,
+    ) {
+        let found = view.mouse_coords().and_then(|coords| {
+            let image = groups.get(coords)?;
+            let thumb = image.get_metadata()?.thumbs(image.page).last()?;
+            Some(((coords, image.page), thumb))
+        });
+        let (image, thumb) = match found {
+            Some(found) => found,
+            None => {
+                *self = Self::default();
+                return;
+            }
+        };
+
+        if self.image != Some(image) {
+            *self = Self {
+                image: Some(image),
+                layout: layout(thumb),
+                ..Default::default()
+            };
+        }
+        self.pos = view.mouse_image_pos(image.0, thumb.img_size);
+
+        for (tile_ref, rect) in &self.layout {
+            if self.tiles.contains_key(tile_ref) || !self.is_under(*rect) {
+                continue;
+            }
+
+            if stopwatch.done() {
+                return;
+            }
+
+            let texture = crate::upload::tile(db, *tile_ref, texture_context, |_| {});
+            self.tiles.insert(*tile_ref, texture);
+        }
+    }
+
+    // Whether the tile at `rect` is at least partly under the loupe.
+    fn is_under(&self, rect: [f64; 4]) -> bool {
+        let half = SIZE / 2.0;
+        (0..2).all(|i| rect[i] < self.pos[i] + half && rect[i] + rect[i + 2] > self.pos[i] - half)
+    }
+
+    // Draw the tiles at one image pixel per window pixel, centered on the cursor.
+    pub fn draw(&self, mouse: Vector2<f64>, c: &Context, g: &mut G2d) {
+        if self.image.is_none() {
+            return;
+        }
+
+        let [x, y] = vec2_sub(mouse, [SIZE / 2.0, SIZE / 2.0]);
+        rectangle([0.0, 0.0, 0.0, 1.0], [x, y, SIZE, SIZE], c.transform, g);
+
+        // The scissor rectangle is in framebuffer pixels.
+        let ratio = crate::overlay::dpi(c);
+        let [sx, sy] = [x.max(0.0), y.max(0.0)];
+        let scissor = [sx, sy, SIZE + x - sx, SIZE + y - sy].map(|v| (v * ratio) as u32);
+        let draw_state = DrawState::default().scissor(scissor);
+
+        let offset = vec2_sub(mouse, self.pos);
+        let img = Image::new_color(crate::night::tint([1.0; 4]));
+        for (tile_ref, [x, y, w, h]) in &self.layout {
+            if let Some(texture) = self.tiles.get(tile_ref) {
+                let [tx, ty] = vec2_add(offset, [*x, *y]);
+                img.rect([tx, ty, *w, *h])
+                    .draw(texture, &draw_state, c.transform, g);
+            }
+        }
+
+        Rectangle::new_border([1.0, 1.0, 1.0, 1.0], 1.0).draw(
+            [x, y, SIZE, SIZE],
+            &c.draw_state,
+            c.transform,
+            g,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_rows() {
+        let thumb = Thumb {
+            img_size: [1200, 600],
+            tile_refs: (0..6).map(TileRef).collect(),
+        };
+        let spec = thumb.tile_spec();
+        let [w, h] = vec2_f64(spec.tile_size);
+        assert_eq!(spec.grid_size, [3, 2]);
+        assert_eq!(
+            layout(&thumb),
+            vec![
+                (TileRef(0), [0.0, 0.0, w, h]),
+                (TileRef(1), [w, 0.0, w, h]),
+                (TileRef(2), [2.0 * w, 0.0, w, h]),
+                (TileRef(3), [0.0, h, w, h]),
+                (TileRef(4), [w, h, w, h]),
+                (TileRef(5), [2.0 * w, h, w, h]),
+            ]
+        );
+    }
+}
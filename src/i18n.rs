@@ -0,0 +1,142 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Translations of on-screen text. The English text is the key, the locale files in locales/ are
+// compiled in with one `English = translation` pair per line. `{}` placeholders are filled in
+// order, `{0}`, `{1}`, ... by position for languages that need another order.
+
+use log::*;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+static LOCALES: &[(&str, &str)] = &[
+    ("de", include_str!("../locales/de.txt")),
+    ("fr", include_str!("../locales/fr.txt")),
+];
+
+static CATALOG: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+
+fn parse(text: &'static str) -> HashMap<&'static str, &'static str> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match line.split_once(" = ") {
+            Some(pair) => Some(pair),
+            None => {
+                error!("Malformed translation {:?}", line);
+                None
+            }
+        })
+        .collect()
+}
+
+// The language of a locale name like de_DE.UTF-8.
+fn language(locale: &str) -> &str {
+    locale.split(['_', '.', '@', '-']).next().unwrap_or(locale)
+}
+
+// Pick the translations of `locale`, or else of the locale the environment asks for.
+pub fn init(locale: Option<&str>) {
+    let env = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()));
+    let locale = match locale.map(str::to_owned).or(env) {
+        Some(locale) => locale,
+        None => return,
+    };
+
+    let language = language(&locale);
+    match LOCALES.iter().find(|(name, _)| *name == language) {
+        Some((_, text)) => {
+            info!("Locale: {}", language);
+            let _ = CATALOG.set(parse(text));
+        }
+        None if matches!(language, "en" | "C" | "POSIX") => {}
+        None => info!("No translations for {:?}, using English", locale),
+    }
+}
+
+// The translation of `msgid`, or `msgid` itself.
+pub fn tr(msgid: &str) -> &str {
+    CATALOG
+        .get()
+        .and_then(|catalog| catalog.get(msgid).copied())
+        .unwrap_or(msgid)
+}
+
+// The translation of `msgid` with its placeholders filled with `args`.
+pub fn trf(msgid: &str, args: &[&dyn Display]) -> String {
+    fill(tr(msgid), args)
+}
+
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let i = match &rest[1..end] {
+            "" => {
+                next += 1;
+                next - 1
+            }
+            n => match n.parse::<usize>() {
+                Ok(i) => i,
+                Err(_) => {
+                    out.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            },
+        };
+        if let Some(arg) = args.get(i) {
+            out.push_str(&arg.to_string());
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_placeholders() {
+        assert_eq!(fill("saved {}", &[&"a.png"]), "saved a.png");
+        assert_eq!(fill("{1} of {0}", &[&3, &1]), "1 of 3");
+        assert_eq!(fill("{} [{}/{}]", &[&"a", &1, &2]), "a [1/2]");
+        assert_eq!(fill("{x} {", &[]), "{x} {");
+        assert_eq!(language("de_DE.UTF-8"), "de");
+        assert_eq!(language("fr"), "fr");
+    }
+
+    #[test]
+    fn locales() {
+        let count = |s: &str| s.matches('{').count();
+        for (name, text) in LOCALES {
+            let catalog = parse(text);
+            assert!(!catalog.is_empty(), "{}", name);
+            for (msgid, msgstr) in catalog {
+                assert_eq!(count(msgid), count(msgstr), "{}: {}", name, msgid);
+            }
+        }
+    }
+}
@@ -12,11 +12,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::adjust::Adjustments;
+use crate::transform::Transform2D;
+use crate::userdata::{Label, UserData};
+use crate::vec::*;
 use crate::view::View;
-use crate::{File, Metadata, MetadataState, TileRef};
-use piston_window::{DrawState, G2d, G2dTexture};
+use crate::{File, Metadata, MetadataState, Thumb, TileRef};
+use piston_window::{color, rectangle, types::Color, DrawState, G2d, G2dTexture};
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Frame of the sharpest image of a burst.
+static PICK: &str = "4CAF50";
+
+fn label_color(label: Label) -> Color {
+    match label {
+        Label::Red => color::hex("E53935"),
+        Label::Yellow => color::hex("FDD835"),
+        Label::Green => color::hex("43A047"),
+        Label::Blue => color::hex("1E88E5"),
+        Label::Purple => color::hex("8E24AA"),
+    }
+}
 
 #[derive(Debug)]
 pub struct Image {
@@ -24,15 +42,39 @@ pub struct Image {
     pub file: Arc<File>,
     pub metadata: MetadataState,
     pub size: Option<usize>,
+    pub page: usize,
+    pub user_data: UserData,
+
+    // When to show the next frame of an animated image.
+    pub next_frame: Option<Instant>,
+
+    // Frame drawn around the grid square, e.g. for differences in `pix diff`.
+    pub highlight: Option<[f32; 4]>,
+
+    // The other frames of a stacked burst this is the first of.
+    pub stack: Vec<Image>,
+
+    // Number of the burst this is a frame of, while bursts are stacked.
+    pub burst: Option<usize>,
+
+    // The sharpest frame of its burst.
+    pub pick: bool,
 }
 
 impl Image {
-    pub fn from(i: usize, file: Arc<File>, metadata: MetadataState) -> Self {
+    pub fn from(i: usize, file: Arc<File>, metadata: MetadataState, user_data: UserData) -> Self {
         Image {
             i,
             file,
             metadata,
             size: None,
+            page: 0,
+            user_data,
+            next_frame: None,
+            highlight: None,
+            stack: Vec::new(),
+            burst: None,
+            pick: false,
         }
     }
 
@@ -44,6 +86,32 @@ impl Image {
         self.size = None;
     }
 
+    // Advance animated images, returns true when the frame changed.
+    pub fn animate(&mut self, now: Instant) -> bool {
+        let delays = match &self.metadata {
+            MetadataState::Some(metadata) if metadata.is_animated() => &metadata.delays,
+            _ => return false,
+        };
+
+        // Start once loaded.
+        if self.size.is_none() {
+            return false;
+        }
+
+        match self.next_frame {
+            Some(next_frame) if now < next_frame => false,
+            Some(_) => {
+                self.page = (self.page + 1) % delays.len();
+                self.next_frame = Some(now + Duration::from_millis(delays[self.page] as u64));
+                true
+            }
+            None => {
+                self.next_frame = Some(now + Duration::from_millis(delays[self.page] as u64));
+                false
+            }
+        }
+    }
+
     pub fn get_metadata(&self) -> Option<&Metadata> {
         match &self.metadata {
             MetadataState::Some(metadata) => Some(metadata),
@@ -53,19 +121,102 @@ impl Image {
 
     pub fn draw(
         &self,
-        trans: [[f64; 3]; 2],
-        view: &View,
+        trans: Transform2D,
+        zoom: f64,
+        adjustments: &Adjustments,
         tiles: &BTreeMap<TileRef, G2dTexture>,
         draw_state: &DrawState,
         g: &mut G2d,
     ) -> bool {
         if let Some(n) = self.size {
             let metadata = self.get_metadata().expect("Image::get_metadata");
-            let thumb = &metadata.thumbs[n];
-            thumb.draw(trans, view, tiles, draw_state, g);
+            let thumb = &metadata.thumbs(self.page)[n];
+            draw_thumb(thumb, trans, zoom, adjustments, tiles, draw_state, g);
             true
         } else {
             false
         }
     }
+
+    // Draw the color label chip and rating pips over the grid square.
+    pub fn draw_user_data(&self, trans: Transform2D, view: &View, g: &mut G2d) {
+        let chip = f64::max(4.0, view.zoom * 0.08);
+
+        if let Some(label) = self.user_data.label {
+            rectangle(
+                label_color(label),
+                [0.0, 0.0, chip, chip],
+                trans.matrix(),
+                g,
+            );
+        }
+
+        let pick = self.pick.then(|| color::hex(PICK));
+        if let Some(highlight) = self.highlight.or(pick) {
+            let w = f64::max(1.0, view.zoom * 0.03);
+            let z = view.zoom;
+            for rect in [
+                [0.0, 0.0, z, w],
+                [0.0, z - w, z, w],
+                [0.0, 0.0, w, z],
+                [z - w, 0.0, w, z],
+            ] {
+                rectangle(highlight, rect, trans.matrix(), g);
+            }
+        }
+
+        let pip_color = color::hex("FFC107");
+        let pip = chip * 0.5;
+        for n in 0..self.user_data.rating {
+            let x = (n as f64) * pip * 1.5;
+            rectangle(pip_color, [x, view.zoom - pip, pip, pip], trans.matrix(), g);
+        }
+    }
+}
+
+// Draw a thumbnail into a `zoom` window pixels wide square.
+fn draw_thumb(
+    thumb: &Thumb,
+    trans: Transform2D,
+    zoom: f64,
+    adjustments: &Adjustments,
+    tiles: &BTreeMap<TileRef, G2dTexture>,
+    draw_state: &DrawState,
+    g: &mut G2d,
+) {
+    let img = piston_window::image::Image::new();
+    let img = if adjustments.is_default() && !crate::night::enabled() {
+        img
+    } else {
+        img.color(crate::night::tint(adjustments.color()))
+    };
+
+    let max_dimension = thumb.max_dimension() as f64;
+
+    let trans = trans.zoom(zoom / max_dimension);
+
+    // Center the image within the grid square.
+    let [x_offset, y_offset] = {
+        let img_size = vec2_f64(thumb.img_size);
+        let gaps = vec2_sub([max_dimension, max_dimension], img_size);
+        vec2_scale(gaps, 0.5)
+    };
+
+    let tile_spec = thumb.tile_spec();
+
+    let mut it = thumb.tile_refs.iter();
+    for (y, max_y) in tile_spec.y_ranges() {
+        for (x, max_x) in tile_spec.x_ranges() {
+            let tile_ref = it.next().unwrap();
+            if let Some(texture) = tiles.get(tile_ref) {
+                let rect = [
+                    x_offset + x as f64,
+                    y_offset + y as f64,
+                    (max_x - x) as f64,
+                    (max_y - y) as f64,
+                ];
+                img.rect(rect).draw(texture, draw_state, trans.matrix(), g);
+            }
+        }
+    }
 }
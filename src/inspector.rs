@@ -0,0 +1,411 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Every EXIF, IPTC and XMP field of a file, listed in a scrollable panel.
+
+use crossbeam_channel::{bounded, Receiver};
+use log::*;
+use std::io::Read;
+
+// Metadata is read from at most this much of the start of a file, where formats keep it, so
+// opening the inspector on a video or a huge scan doesn't read all of it.
+static MAX_READ: u64 = 16 << 20;
+
+pub type Field = (String, String);
+
+fn exif_fields(data: &[u8], fields: &mut Vec<Field>) {
+    let exif = match exif::Reader::new().read_from_container(&mut std::io::Cursor::new(data)) {
+        Ok(exif) => exif,
+        Err(e) => {
+            debug!("no exif: {:?}", e);
+            return;
+        }
+    };
+
+    for field in exif.fields() {
+        let name = if field.ifd_num == exif::In::PRIMARY {
+            format!("EXIF {}", field.tag)
+        } else {
+            format!("EXIF {} ({})", field.tag, field.ifd_num)
+        };
+        let value = field.display_value().with_unit(&exif).to_string();
+        fields.push((name, value));
+    }
+}
+
+fn iptc_name(dataset: u8) -> Option<&'static str> {
+    Some(match dataset {
+        5 => "ObjectName",
+        15 => "Category",
+        25 => "Keywords",
+        40 => "SpecialInstructions",
+        55 => "DateCreated",
+        60 => "TimeCreated",
+        80 => "By-line",
+        85 => "By-lineTitle",
+        90 => "City",
+        92 => "Sub-location",
+        95 => "Province-State",
+        101 => "Country",
+        103 => "OriginalTransmissionReference",
+        105 => "Headline",
+        110 => "Credit",
+        115 => "Source",
+        116 => "CopyrightNotice",
+        118 => "Contact",
+        120 => "Caption-Abstract",
+        122 => "Writer-Editor",
+        _ => return None,
+    })
+}
+
+// IPTC-IIM datasets: 0x1C, record, dataset, big endian u16 length, value.
+fn iim_fields(mut data: &[u8], fields: &mut Vec<Field>) {
+    while data.len() >= 5 && data[0] == 0x1C {
+        let (record, dataset) = (data[1], data[2]);
+        let len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        // Extended lengths are only used for large binary values.
+        if len & 0x8000 != 0 || data.len() < 5 + len {
+            break;
+        }
+        let value = &data[5..5 + len];
+        data = &data[5 + len..];
+
+        // Skip the envelope record and the version dataset.
+        if record != 2 || dataset == 0 {
+            continue;
+        }
+        let name = match iptc_name(dataset) {
+            Some(name) => format!("IPTC {}", name),
+            None => format!("IPTC 2:{}", dataset),
+        };
+        fields.push((name, String::from_utf8_lossy(value).into_owned()));
+    }
+}
+
+// Photoshop image resources, IPTC is resource 0x0404.
+fn photoshop_fields(mut data: &[u8], fields: &mut Vec<Field>) {
+    while data.len() >= 12 && &data[..4] == b"8BIM" {
+        let id = u16::from_be_bytes([data[4], data[5]]);
+        // Pascal string name, padded to an even length.
+        let name_len = (1 + data[6] as usize + 1) & !1;
+        let rest = match data.get(6 + name_len..) {
+            Some(rest) if rest.len() >= 4 => rest,
+            _ => return,
+        };
+        let size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let value = match rest.get(4..4 + size) {
+            Some(value) => value,
+            None => return,
+        };
+        if id == 0x0404 {
+            iim_fields(value, fields);
+        }
+        data = rest.get(4 + ((size + 1) & !1)..).unwrap_or(&[]);
+    }
+}
+
+// IPTC is stored in the Photoshop APP13 segment of JPEG files.
+fn iptc_fields(data: &[u8], fields: &mut Vec<Field>) {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return;
+    }
+
+    let mut i = 2;
+    while i + 4 <= data.len() && data[i] == 0xFF {
+        let marker = data[i + 1];
+        // Start of scan, no more metadata segments.
+        if marker == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        let segment = match data.get(i + 4..i + 2 + len) {
+            Some(segment) => segment,
+            None => break,
+        };
+        if marker == 0xED {
+            if let Some(resources) = segment.strip_prefix(b"Photoshop 3.0\0") {
+                photoshop_fields(resources, fields);
+            }
+        }
+        i += 2 + len;
+    }
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Whether an xml name is a property rather than structure or a namespace declaration.
+fn is_property(name: &str) -> bool {
+    name.contains(':')
+        && !["rdf:", "x:", "xml:", "xmlns:"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+// Properties written as attributes, `ns:Name="value"`.
+fn xmp_attributes(tag: &str, fields: &mut Vec<Field>) {
+    let mut rest = tag;
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].rsplit(char::is_whitespace).next().unwrap_or("");
+        let quoted = &rest[eq + 1..];
+        let quote = match quoted.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return,
+        };
+        let end = match quoted[1..].find(quote) {
+            Some(end) => end,
+            None => return,
+        };
+        if is_property(name) {
+            fields.push((format!("XMP {}", name), unescape(&quoted[1..1 + end])));
+        }
+        rest = &quoted[1 + end + 1..];
+    }
+}
+
+// Properties written as elements, with rdf:Bag/Seq/Alt items joined.
+fn xmp_fields(xml: &str, fields: &mut Vec<Field>) {
+    let mut stack: Vec<&str> = Vec::new();
+    // The property the last text was added to, for joining list items.
+    let mut last: Option<usize> = None;
+
+    for part in xml.split('<').skip(1) {
+        let (tag, text) = part.split_once('>').unwrap_or((part, ""));
+
+        if let Some(name) = tag.strip_prefix('/') {
+            if let Some(i) = stack.iter().rposition(|open| *open == name.trim()) {
+                stack.truncate(i);
+            }
+        } else if !tag.starts_with('?') && !tag.starts_with('!') {
+            let name = tag.split_whitespace().next().unwrap_or("");
+            xmp_attributes(tag, fields);
+            if !tag.ends_with('/') {
+                stack.push(name);
+            }
+        }
+
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let property = match stack.iter().rev().find(|name| is_property(name)) {
+            Some(property) => format!("XMP {}", property),
+            None => continue,
+        };
+        let text = unescape(text);
+        match last {
+            Some(i) if fields[i].0 == property => {
+                fields[i].1.push_str(", ");
+                fields[i].1.push_str(&text);
+            }
+            _ => {
+                last = Some(fields.len());
+                fields.push((property, text));
+            }
+        }
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+// XMP packets are plain text embedded anywhere in the file.
+fn xmp_packet(data: &[u8]) -> Option<&[u8]> {
+    let start = find(data, b"<x:xmpmeta")?;
+    let end = b"</x:xmpmeta>";
+    let len = find(&data[start..], end)?;
+    Some(&data[start..start + len + end.len()])
+}
+
+pub fn read(path: &str) -> Vec<Field> {
+    let mut data = Vec::new();
+    let read =
+        std::fs::File::open(path).and_then(|file| file.take(MAX_READ).read_to_end(&mut data));
+    match read {
+        Ok(_) => {}
+        Err(e) => {
+            error!("unable to read {:?}: {:?}", path, e);
+            return Vec::new();
+        }
+    }
+
+    let mut fields = Vec::new();
+    exif_fields(&data, &mut fields);
+    iptc_fields(&data, &mut fields);
+    if let Some(packet) = xmp_packet(&data) {
+        xmp_fields(&String::from_utf8_lossy(packet), &mut fields);
+    }
+    fields
+}
+
+pub struct Inspector {
+    path: String,
+    fields: Vec<Field>,
+
+    // Fields being read on a thread.
+    pending: Option<Receiver<Vec<Field>>>,
+
+    // Index of the first field shown.
+    scroll: usize,
+}
+
+impl Inspector {
+    pub fn new(path: &str) -> Self {
+        let (tx, rx) = bounded(1);
+        let owned = path.to_owned();
+        std::thread::spawn(move || {
+            let _ = tx.send(read(&owned));
+        });
+        Self {
+            path: path.to_owned(),
+            fields: Vec::new(),
+            pending: Some(rx),
+            scroll: 0,
+        }
+    }
+
+    // Take the fields once read.
+    pub fn poll(&mut self) {
+        if let Some(fields) = self.pending.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.fields = fields;
+            self.pending = None;
+            crate::pacing::changed();
+        }
+    }
+
+    pub fn scroll_by(&mut self, delta: isize) {
+        let max = self.fields.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max);
+    }
+
+    // Header and as many fields from the scroll position as fit in `rows` lines.
+    pub fn lines(&self, rows: usize) -> Vec<String> {
+        if self.pending.is_some() {
+            return vec![crate::i18n::trf("{} (reading)", &[&self.path])];
+        }
+        let mut lines = vec![crate::i18n::trf(
+            "{} ({} fields, {}-{}, Ctrl+C to copy)",
+            &[
+                &self.path,
+                &self.fields.len(),
+                &(self.scroll + 1).min(self.fields.len()),
+                &(self.scroll + rows.saturating_sub(1)).min(self.fields.len()),
+            ],
+        )];
+        lines.extend(
+            self.fields
+                .iter()
+                .skip(self.scroll)
+                .take(rows.saturating_sub(1))
+                .map(|(name, value)| format!("{}: {}", name, value)),
+        );
+        lines
+    }
+
+    // Every field, one per line.
+    pub fn dump(&self) -> String {
+        let mut dump = format!("{}\n", self.path);
+        for (name, value) in &self.fields {
+            dump.push_str(&format!("{}: {}\n", name, value));
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iptc() {
+        let mut iim = vec![0x1C, 2, 0, 0, 2, 0, 4];
+        iim.extend([0x1C, 2, 25, 0, 3]);
+        iim.extend(b"cat");
+        iim.extend([0x1C, 2, 200, 0, 1, b'x']);
+
+        // Resource with an empty name, padded to an even length.
+        let mut app13 = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+        app13.extend((iim.len() as u32).to_be_bytes());
+        app13.extend(&iim);
+        app13.push(0);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xED];
+        jpeg.extend((app13.len() as u16 + 2).to_be_bytes());
+        jpeg.extend(&app13);
+        jpeg.extend([0xFF, 0xDA, 0, 2]);
+
+        let mut fields = Vec::new();
+        iptc_fields(&jpeg, &mut fields);
+        assert_eq!(
+            fields,
+            vec![
+                ("IPTC Keywords".to_owned(), "cat".to_owned()),
+                ("IPTC 2:200".to_owned(), "x".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn xmp() {
+        let data = br#"junk<?xpacket begin=""?><x:xmpmeta xmlns:x="adobe:ns:meta/">
+            <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+            <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+                xmp:Rating="4" xmp:CreatorTool="A &amp; B">
+            <dc:subject><rdf:Bag><rdf:li>cat</rdf:li><rdf:li>dog</rdf:li></rdf:Bag></dc:subject>
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Pets</rdf:li></rdf:Alt></dc:title>
+            </rdf:Description></rdf:RDF></x:xmpmeta><?xpacket end="w"?>"#;
+
+        let mut fields = Vec::new();
+        xmp_fields(
+            &String::from_utf8_lossy(xmp_packet(data).unwrap()),
+            &mut fields,
+        );
+        let field = |name: &str, value: &str| (name.to_owned(), value.to_owned());
+        assert_eq!(
+            fields,
+            vec![
+                field("XMP xmp:Rating", "4"),
+                field("XMP xmp:CreatorTool", "A & B"),
+                field("XMP dc:subject", "cat, dog"),
+                field("XMP dc:title", "Pets"),
+            ]
+        );
+    }
+
+    #[test]
+    fn scroll() {
+        let mut inspector = Inspector {
+            path: "/a.jpg".to_owned(),
+            fields: (0..10).map(|i| (i.to_string(), i.to_string())).collect(),
+            pending: None,
+            scroll: 0,
+        };
+        inspector.scroll_by(-3);
+        assert_eq!(inspector.scroll, 0);
+        inspector.scroll_by(8);
+        assert_eq!(
+            inspector.lines(4),
+            vec!["/a.jpg (10 fields, 9-10, Ctrl+C to copy)", "8: 8", "9: 9",]
+        );
+        inspector.scroll_by(20);
+        assert_eq!(inspector.scroll, 9);
+    }
+}
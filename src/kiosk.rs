@@ -0,0 +1,177 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Unattended shuffled slideshow for photo frames and lobby displays. When weighted by rating,
+// each round of the shuffle shows an image once more per star, so the best shots come up more
+// often without the unrated ones disappearing.
+
+use std::time::{Duration, Instant};
+
+// How often the directories are searched again for added and removed images.
+static RELOAD_INTERVAL: Duration = Duration::from_secs(600);
+
+pub struct Kiosk {
+    interval: Duration,
+
+    // Times each image is shown per round.
+    weights: Vec<usize>,
+    weighted: bool,
+
+    // Shuffled image indices and the position of the next slide.
+    order: Vec<usize>,
+    pos: usize,
+    seed: u64,
+
+    next_slide: Instant,
+    next_reload: Instant,
+}
+
+// xorshift64*, good enough to shuffle slides.
+fn next_random(seed: &mut u64) -> u64 {
+    *seed ^= *seed >> 12;
+    *seed ^= *seed << 25;
+    *seed ^= *seed >> 27;
+    seed.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+// Image `i` appears `weights[i]` times, never twice in a row if it can be helped.
+fn shuffle(weights: &[usize], seed: &mut u64) -> Vec<usize> {
+    let mut order: Vec<usize> = weights
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &weight)| std::iter::repeat_n(i, weight))
+        .collect();
+    let n = order.len();
+    for i in (1..n).rev() {
+        let j = (next_random(seed) % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    for i in 1..n {
+        if order[i] == order[i - 1] {
+            if let Some(j) = (i + 1..n).find(|&j| order[j] != order[i]) {
+                order.swap(i, j);
+            }
+        }
+    }
+    order
+}
+
+impl Kiosk {
+    // `weighted` shows higher rated images more often.
+    pub fn new(interval: Duration, weighted: bool) -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let now = Instant::now();
+        Self {
+            interval,
+            weights: Vec::new(),
+            weighted,
+            order: Vec::new(),
+            pos: 0,
+            // Zero would stay zero.
+            seed: seed | 1,
+            next_slide: now,
+            next_reload: now + RELOAD_INTERVAL,
+        }
+    }
+
+    // Start over with a new shuffle of the images with star `ratings`, the first slide is shown
+    // right away.
+    pub fn restart(&mut self, ratings: &[u8]) {
+        self.weights = ratings
+            .iter()
+            .map(|&rating| match self.weighted {
+                true => 1 + rating as usize,
+                false => 1,
+            })
+            .collect();
+        self.order = shuffle(&self.weights, &mut self.seed);
+        self.pos = 0;
+        self.next_slide = Instant::now();
+    }
+
+    // The next image to show once the current one has been up long enough. Images `showable`
+    // rejects are skipped.
+    pub fn next_slide(&mut self, now: Instant, showable: impl Fn(usize) -> bool) -> Option<usize> {
+        if now < self.next_slide {
+            return None;
+        }
+        self.next_slide = now + self.interval;
+        self.skip(showable)
+    }
+
+    // Move on to the next slide in the shuffle.
+    pub fn skip(&mut self, showable: impl Fn(usize) -> bool) -> Option<usize> {
+        let n = self.order.len();
+        for _ in 0..n {
+            if self.pos == n {
+                // Every image was shown, shuffle again.
+                self.order = shuffle(&self.weights, &mut self.seed);
+                self.pos = 0;
+            }
+            let i = self.order[self.pos];
+            self.pos += 1;
+            if showable(i) {
+                self.next_slide = Instant::now() + self.interval;
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    // Whether it's time to search the directories again.
+    pub fn reload_due(&mut self, now: Instant) -> bool {
+        if now < self.next_reload {
+            return false;
+        }
+        self.next_reload = now + RELOAD_INTERVAL;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shuffled() {
+        let mut seed = 1;
+        let mut order = shuffle(&[1; 10], &mut seed);
+        assert_ne!(order, (0..10).collect::<Vec<usize>>());
+        order.sort();
+        assert_eq!(order, (0..10).collect::<Vec<usize>>());
+
+        let order = shuffle(&[1, 3, 2], &mut seed);
+        assert_eq!(order.iter().filter(|&&i| i == 1).count(), 3);
+        assert_eq!(order.iter().filter(|&&i| i == 2).count(), 2);
+    }
+
+    #[test]
+    fn skip() {
+        let mut kiosk = Kiosk::new(Duration::from_secs(10), false);
+        kiosk.restart(&[0, 5, 0, 0]);
+
+        // Every image once per round, skipping the ones that can't be shown.
+        let now = Instant::now();
+        let mut shown: Vec<usize> = (0..3).map(|_| kiosk.skip(|i| i != 2).unwrap()).collect();
+        shown.sort();
+        assert_eq!(shown, vec![0, 1, 3]);
+        assert_eq!(kiosk.next_slide(now, |_| true), None);
+
+        kiosk.restart(&[]);
+        assert_eq!(kiosk.skip(|_| true), None);
+    }
+}
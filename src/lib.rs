@@ -0,0 +1,511 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The thumbnail cache machinery of the pix image viewer, for other tools to reuse: scanning
+//! directories for images, the pyramid of thumbnail tiles the `Thumbnailer` makes of each image,
+//! the `TileRef` keys the tiles are stored under, and the `Database` keeping tiles, metadata,
+//! ratings and notes.
+//!
+//! ```no_run
+//! use pix_core::database::Database;
+//!
+//! let db = Database::open(&pix_core::system::db_path()).unwrap();
+//! for file in pix_core::find_images(vec![".".into()]) {
+//!     if let Ok(Some(metadata)) = db.get_metadata(&file) {
+//!         println!("{} {:?}", file.path, metadata.img_size());
+//!     }
+//! }
+//! ```
+
+pub mod adjust;
+mod animation;
+pub mod archive;
+mod budget;
+pub mod captions;
+pub mod checksum;
+pub mod concurrency;
+pub mod crypt;
+pub mod database;
+pub mod depth;
+#[cfg(feature = "dicom")]
+mod dicom;
+pub mod errlog;
+pub mod filter;
+pub mod fingerprint;
+#[cfg(feature = "fits")]
+mod fits;
+mod psd;
+pub mod quality;
+pub mod scan;
+pub mod scene;
+mod screenshot;
+pub mod stats;
+pub mod system;
+#[cfg(test)]
+mod testdir;
+mod throttle;
+pub mod thumbnailer;
+pub mod tonemap;
+pub mod userdata;
+pub mod vec;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+use vec::*;
+
+/// Errors of every pix operation.
+#[derive(Debug, Error)]
+pub enum E {
+    #[error("database error: {0:?}")]
+    DatabaseError(sled::Error),
+
+    #[error("decode error {0:?}")]
+    DecodeError(bincode::Error),
+
+    #[error("encode error {0:?}")]
+    EncodeError(bincode::Error),
+
+    #[error("missing data for key {0:?}")]
+    MissingData(String),
+
+    #[error("image error: {0:?}")]
+    ImageError(::image::ImageError),
+
+    #[error("archive error: {0:?}")]
+    ArchiveError(zip::result::ZipError),
+
+    #[error("tiff error: {0:?}")]
+    TiffError(tiff::TiffError),
+
+    #[error("psd error: {0}")]
+    PsdError(String),
+
+    #[cfg(feature = "dicom")]
+    #[error("dicom error: {0}")]
+    DicomError(String),
+
+    #[cfg(feature = "fits")]
+    #[error("fits error: {0}")]
+    FitsError(String),
+
+    #[error("command error: {0}")]
+    CommandError(String),
+
+    // The decoder crashed, the file isn't tried again.
+    #[error("quarantined: {0}")]
+    Quarantined(String),
+
+    // The decoder took longer than `--decode-timeout`, slow files are tried again like other
+    // failures.
+    #[error("timed out: {0}")]
+    TimedOut(String),
+
+    #[error("io error: {0}")]
+    IoError(std::io::Error),
+
+    #[error("crypt error: {0}")]
+    CryptError(String),
+}
+
+pub type R<T> = std::result::Result<T, E>;
+
+/// A power of two size, stored as its exponent.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Pow2(pub u8);
+
+impl Pow2 {
+    /// Panics unless `i` is a power of two.
+    pub fn from(i: u32) -> Self {
+        assert!(i.is_power_of_two());
+        Pow2((32 - i.leading_zeros() - 1) as u8)
+    }
+
+    pub fn u32(&self) -> u32 {
+        1 << self.0
+    }
+}
+
+#[test]
+fn size_conversions() {
+    assert_eq!(Pow2::from(128), Pow2(7));
+    assert_eq!(Pow2(7).u32(), 128);
+}
+
+/// Key of one thumbnail tile: the thumbnail size in the top byte, the 40 bit index of the image
+/// in the middle and the tile's chunk of the thumbnail in the low 16 bits. Big endian bytes sort
+/// tiles by size, then image.
+#[derive(
+    Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Default,
+)]
+pub struct TileRef(pub u64);
+
+impl TileRef {
+    /// Indices wrap at 2^40.
+    pub fn new(size: Pow2, index: u64, chunk: u16) -> Self {
+        Self((chunk as u64) | ((index % (1u64 << 40)) << 16) | ((size.0 as u64) << 56))
+    }
+
+    pub fn index(&self) -> u64 {
+        (self.0 & 0x00FF_FFFF_FFFF_0000u64) >> 16
+    }
+
+    /// The same size and chunk of another image.
+    pub fn with_index(self, index: u64) -> Self {
+        Self((self.0 & 0xFF00_0000_0000_FFFFu64) | ((index % (1u64 << 40)) << 16))
+    }
+
+    /// The size, index and chunk.
+    pub fn deconstruct(&self) -> (Pow2, u64, u16) {
+        let size = ((self.0 & 0xFF00_0000_0000_0000u64) >> 56) as u8;
+        let index = (self.0 & 0x00FF_FFFF_FFFF_0000u64) >> 16;
+        let chunk = (self.0 & 0x0000_0000_0000_FFFFu64) as u16;
+        (Pow2(size), index, chunk)
+    }
+}
+
+#[test]
+fn tile_ref_test() {
+    assert_eq!(
+        TileRef::new(Pow2(0xFFu8), 0u64, 0u16),
+        TileRef(0xFF00_0000_0000_0000u64)
+    );
+    assert_eq!(
+        TileRef::new(Pow2(0xFFu8), 0u64, 0u16).deconstruct(),
+        (Pow2(0xFFu8), 0u64, 0u16)
+    );
+    assert_eq!(
+        TileRef::new(Pow2(0xFFu8), 0u64, 0u16).0.to_be_bytes(),
+        [0xFF, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    assert_eq!(
+        TileRef::new(Pow2(0u8), 0x00FF_FFFF_FFFFu64, 0u16),
+        TileRef(0x00FF_FFFF_FFFF_0000_u64)
+    );
+    assert_eq!(
+        TileRef::new(Pow2(0u8), 0x00FF_FFFF_FFFFu64, 0u16).deconstruct(),
+        (Pow2(0u8), 0x00FF_FFFF_FFFFu64, 0u16)
+    );
+
+    assert_eq!(
+        TileRef::new(Pow2(0u8), 0u64, 0xFFFFu16),
+        TileRef(0x0000_0000_0000_FFFFu64)
+    );
+    assert_eq!(
+        TileRef::new(Pow2(0u8), 0u64, 0xFFFFu16).deconstruct(),
+        (Pow2(0u8), 0u64, 0xFFFFu16)
+    );
+
+    let tile_ref = TileRef::new(Pow2(7u8), 42u64, 3u16);
+    assert_eq!(tile_ref.index(), 42);
+    assert_eq!(
+        tile_ref.with_index(0x00FF_FFFF_FFFFu64).deconstruct(),
+        (Pow2(7u8), 0x00FF_FFFF_FFFFu64, 3u16)
+    );
+}
+
+/// One level of an image's thumbnail pyramid, split into tiles in row order.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Thumb {
+    pub img_size: [u32; 2],
+    pub tile_refs: Vec<TileRef>,
+}
+
+/// What's known about an image once thumbnailed: its thumbnail pyramid, smallest first, and
+/// what was read from the file on the way.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Metadata {
+    pub thumbs: Vec<Thumb>,
+
+    /// Thumbs for the pages after the first of multi-page documents.
+    pub pages: Vec<Vec<Thumb>>,
+
+    /// Format specific details shown in the info overlay.
+    pub info: Info,
+
+    /// Frame delays in milliseconds of animated images, empty for still images.
+    pub delays: Vec<u32>,
+
+    /// Size of the source when the thumbnails were made from a downsampled decode.
+    pub full_size: Option<[u32; 2]>,
+
+    /// Of the file contents the thumbnails were made from, to check the file is unchanged.
+    pub fingerprint: Option<u64>,
+
+    /// Title, description and keywords, for filters.
+    pub captions: captions::Captions,
+
+    /// Sharpness and brightness of still images, to pick the sharpest frame of a burst and
+    /// filter out the failed shots.
+    pub quality: Option<quality::Quality>,
+
+    /// Whether the image looks like a screen capture rather than a photo.
+    pub screenshot: bool,
+
+    /// Guessed scene type of still images, for filters.
+    pub scene: Option<scene::Scene>,
+
+    /// EXIF capture time in seconds since the unix epoch.
+    pub taken: Option<u64>,
+}
+
+/// Named values, in display order.
+pub type Info = Vec<(String, String)>;
+
+impl Metadata {
+    pub fn page_count(&self) -> usize {
+        1 + self.pages.len()
+    }
+
+    pub fn is_animated(&self) -> bool {
+        !self.delays.is_empty()
+    }
+
+    /// Tiles of every page and size.
+    pub fn tile_refs(&self) -> impl Iterator<Item = &TileRef> {
+        std::iter::once(&self.thumbs)
+            .chain(&self.pages)
+            .flatten()
+            .flat_map(|thumb| &thumb.tile_refs)
+    }
+
+    /// The pyramid of a page, 0 is the first.
+    pub fn thumbs(&self, page: usize) -> &[Thumb] {
+        if page == 0 {
+            &self.thumbs
+        } else {
+            &self.pages[page - 1]
+        }
+    }
+
+    /// Full size of the first page.
+    pub fn img_size(&self) -> [u32; 2] {
+        self.full_size.unwrap_or_else(|| {
+            self.thumbs
+                .last()
+                .map(|thumb| thumb.img_size)
+                .unwrap_or([0, 0])
+        })
+    }
+
+    /// Index of the thumbnail of `page` closest in size to `target_size` pixels.
+    pub fn nearest(&self, page: usize, target_size: u32) -> usize {
+        let mut found = None;
+
+        let ts_zeros = target_size.leading_zeros() as i16;
+
+        for (i, thumb) in self.thumbs(page).iter().enumerate() {
+            let size = thumb.size();
+            let size_zeros = size.leading_zeros() as i16;
+            let dist = (ts_zeros - size_zeros).abs();
+            if let Some((found_dist, found_i)) = found.take() {
+                if dist < found_dist {
+                    found = Some((dist, i));
+                } else {
+                    found = Some((found_dist, found_i));
+                }
+            } else {
+                found = Some((dist, i));
+            }
+        }
+
+        let (_, i) = found.unwrap();
+        i
+    }
+}
+
+/// How a thumbnail is cut into tiles.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TileSpec {
+    pub img_size: [u32; 2],
+
+    /// Grid width and height (in number of tiles).
+    pub grid_size: [u32; 2],
+
+    /// Tile width and height in pixels.
+    pub tile_size: [u32; 2],
+}
+
+impl TileSpec {
+    fn ranges(img_size: u32, grid_size: u32, tile_size: u32) -> impl Iterator<Item = (u32, u32)> {
+        (0..grid_size).map(move |i| {
+            let min = i * tile_size;
+            let max = std::cmp::min(img_size, min + tile_size);
+            (min, max)
+        })
+    }
+
+    /// Pixel ranges of the tile columns.
+    pub fn x_ranges(&self) -> impl Iterator<Item = (u32, u32)> {
+        Self::ranges(self.img_size[0], self.grid_size[0], self.tile_size[0])
+    }
+
+    /// Pixel ranges of the tile rows.
+    pub fn y_ranges(&self) -> impl Iterator<Item = (u32, u32)> {
+        Self::ranges(self.img_size[1], self.grid_size[1], self.tile_size[1])
+    }
+}
+
+impl Thumb {
+    pub fn max_dimension(&self) -> u32 {
+        let [w, h] = self.img_size;
+        std::cmp::max(w, h)
+    }
+
+    /// The larger side rounded up to a power of two.
+    pub fn size(&self) -> u32 {
+        self.max_dimension().next_power_of_two()
+    }
+
+    /// Tiles are about 128 pixels times log8 of the side.
+    pub fn tile_spec(&self) -> TileSpec {
+        let img_size = vec2_f64(self.img_size);
+        let tile_size = vec2_scale(vec2_log(img_size, 8.0), 128.0);
+        let grid_size = vec2_ceil(vec2_div(img_size, tile_size));
+        let tile_size = vec2_ceil(vec2_div(img_size, grid_size));
+        TileSpec {
+            img_size: self.img_size,
+            grid_size: vec2_u32(grid_size),
+            tile_size: vec2_u32(tile_size),
+        }
+    }
+}
+
+/// Metadata of an image being loaded.
+// Every image has one, boxing the metadata would only add an allocation.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Eq, PartialEq)]
+pub enum MetadataState {
+    Missing,
+    Some(Metadata),
+    Errored,
+}
+
+/// Per tile values, e.g. textures.
+pub type TileMap<T> = BTreeMap<TileRef, T>;
+
+/// An image file as found, its database keys are made from all three.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct File {
+    /// Canonical path.
+    pub path: String,
+
+    /// Modification time in seconds since the unix epoch.
+    pub modified: u64,
+
+    pub file_size: u64,
+}
+
+impl File {
+    pub fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+        metadata
+            .modified()
+            .expect("metadata modified")
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .expect("duration since unix epoch")
+            .as_secs()
+    }
+
+    /// Look up a file again after it was written.
+    pub fn stat(path: &str) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            modified: Self::modified_secs(&metadata),
+            file_size: metadata.len(),
+        })
+    }
+}
+
+/// Pix's annotation sidecars are written next to images as `IMAGE.pix.json`.
+pub static SIDECAR_SUFFIX: &str = ".pix.json";
+
+/// Every file under `dirs`, sorted by path, skipping pix's cache directories and sidecars. Files
+/// are only recognised as images or not when thumbnailing.
+pub fn find_images(dirs: Vec<PathBuf>) -> Vec<Arc<File>> {
+    let mut ret = Vec::new();
+
+    for dir in dirs {
+        let entries = walkdir::WalkDir::new(&dir)
+            .into_iter()
+            .filter_entry(|entry| !system::is_cache_dir(entry.file_name()));
+        for entry in entries {
+            let i = ret.len();
+            if i > 0 && i % 1000 == 0 {
+                info!("Found {} images...", i);
+            }
+
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    error!("Walkdir error: {:?}", e);
+                    continue;
+                }
+            };
+
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    error!("Metadata lookup error: {:?}: {:?}", entry, e);
+                    continue;
+                }
+            };
+
+            if metadata.is_dir() {
+                info!("Searching in {:?}", entry.path());
+                continue;
+            }
+
+            if entry.path().to_string_lossy().ends_with(SIDECAR_SUFFIX) {
+                continue;
+            }
+
+            let file_size = metadata.len();
+
+            let modified = File::modified_secs(&metadata);
+
+            let path = entry.path();
+
+            let path = match path.canonicalize() {
+                Ok(path) => path,
+                Err(e) => {
+                    error!("unable to canonicalize: {:?} {:?}", path, e);
+                    continue;
+                }
+            };
+
+            let path = if let Some(path) = path.to_str() {
+                path.to_owned()
+            } else {
+                error!("Skipping non-utf8 path: {:?}", path);
+                continue;
+            };
+
+            let file = File {
+                path,
+                modified,
+                file_size,
+            };
+
+            ret.push(Arc::new(file));
+        }
+    }
+
+    ret.sort();
+    ret
+}
@@ -0,0 +1,114 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Magnified pixels around the cursor, with the value of the one under it.
+
+use ::image::RgbaImage;
+use piston_window::{rectangle, Context, G2d, Rectangle};
+
+// Pixels on each side of the center pixel.
+static RADIUS: i64 = 5;
+static PIXEL_SIZE: f64 = 10.0;
+static OFFSET: f64 = 20.0;
+
+pub fn describe(image: &RgbaImage, [x, y]: [u32; 2]) -> String {
+    let ::image::Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+    format!(
+        "{}, {}  rgba({}, {}, {}, {})  #{:02X}{:02X}{:02X}{:02X}",
+        x, y, r, g, b, a, r, g, b, a
+    )
+}
+
+// Width and height in window units at UI scale `scale`.
+pub fn size(scale: f64) -> f64 {
+    (2 * RADIUS + 1) as f64 * PIXEL_SIZE * scale
+}
+
+// Top left corner of the loupe, below and right of the cursor unless that's off screen.
+pub fn pos(c: &Context, [x, y]: [f64; 2], scale: f64) -> [f64; 2] {
+    let [w, h] = c.get_view_size();
+    let size = size(scale);
+    let x = if x + OFFSET + size > w {
+        x - OFFSET - size
+    } else {
+        x + OFFSET
+    };
+    let y = if y + OFFSET + size > h {
+        y - OFFSET - size
+    } else {
+        y + OFFSET
+    };
+    [x, y]
+}
+
+pub fn draw(
+    image: &RgbaImage,
+    [px, py]: [u32; 2],
+    [x, y]: [f64; 2],
+    scale: f64,
+    c: &Context,
+    g: &mut G2d,
+) {
+    let pixel_size = PIXEL_SIZE * scale;
+
+    // Gray behind transparent pixels and past the image edges.
+    rectangle(
+        [0.5, 0.5, 0.5, 1.0],
+        [x, y, size(scale), size(scale)],
+        c.transform,
+        g,
+    );
+
+    for dy in -RADIUS..=RADIUS {
+        for dx in -RADIUS..=RADIUS {
+            let (ix, iy) = (px as i64 + dx, py as i64 + dy);
+            if ix < 0 || iy < 0 || ix >= image.width() as i64 || iy >= image.height() as i64 {
+                continue;
+            }
+            let ::image::Rgba(rgba) = *image.get_pixel(ix as u32, iy as u32);
+            let color = rgba.map(|v| v as f32 / 255.0);
+            let rect = [
+                x + (dx + RADIUS) as f64 * pixel_size,
+                y + (dy + RADIUS) as f64 * pixel_size,
+                pixel_size,
+                pixel_size,
+            ];
+            rectangle(color, rect, c.transform, g);
+        }
+    }
+
+    // Outline the center pixel.
+    let center = RADIUS as f64 * pixel_size;
+    Rectangle::new_border([1.0, 1.0, 1.0, 1.0], scale).draw(
+        [x + center, y + center, pixel_size, pixel_size],
+        &c.draw_state,
+        c.transform,
+        g,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_pixel() {
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(1, 0, ::image::Rgba([255, 128, 0, 10]));
+        assert_eq!(
+            describe(&image, [1, 0]),
+            "1, 0  rgba(255, 128, 0, 10)  #FF80000A"
+        );
+    }
+}
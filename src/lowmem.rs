@@ -0,0 +1,67 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Low memory mode for Raspberry Pi class machines and old laptops. Only images on screen keep
+// textures larger than the smallest thumbnail, and never larger than drawn. New thumbnails are
+// decoded smaller and the single image view doesn't decode neighbors ahead of time.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Long edge above which JPEGs are decoded downsampled.
+static MAX_DECODE: u32 = 2048;
+
+// MB the images being thumbnailed may take together.
+static THUMB_MEMORY: u64 = 256;
+
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// `--max-decode` in the current mode, 0 decodes in full.
+pub fn max_decode(requested: u32) -> u32 {
+    cap_decode(requested, enabled())
+}
+
+fn cap_decode(requested: u32, enabled: bool) -> u32 {
+    match requested {
+        _ if !enabled => requested,
+        0 => MAX_DECODE,
+        _ => u32::min(requested, MAX_DECODE),
+    }
+}
+
+// `--thumb-memory` in the current mode.
+pub fn thumb_memory(requested: Option<u64>) -> Option<u64> {
+    requested.or(enabled().then_some(THUMB_MEMORY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_caps() {
+        assert_eq!(cap_decode(8192, false), 8192);
+        assert_eq!(cap_decode(0, false), 0);
+        assert_eq!(cap_decode(8192, true), MAX_DECODE);
+        assert_eq!(cap_decode(0, true), MAX_DECODE);
+        assert_eq!(cap_decode(1024, true), 1024);
+    }
+}
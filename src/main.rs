@@ -12,277 +12,226 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-mod database;
+mod access;
+mod annotate;
+mod autorotate;
+mod bench;
+mod cache;
+mod cluster;
+mod compare;
+mod crop;
+mod decoded;
+mod dupes;
+mod export;
+mod filmstrip;
+mod geometry;
+mod glcompat;
+mod gpuscale;
 mod group;
 mod groups;
+mod guides;
+mod histogram;
+mod hover;
+mod i18n;
 mod image;
-mod thumbnailer;
-mod vec;
+mod inspector;
+mod kiosk;
+mod loupe;
+mod lowmem;
+mod measure;
+mod meta;
+mod mono;
+mod night;
+mod notes;
+mod overlay;
+mod pacing;
+mod palette;
+mod pause;
+mod power;
+mod preview;
+mod proof;
+mod shutdown;
+mod snapshot;
+mod stack;
+#[cfg(test)]
+mod testdir;
+mod timeline;
+mod title;
+mod transform;
+mod upload;
+#[cfg(feature = "video")]
+mod video;
 mod view;
+mod watermark;
 
 use crate::groups::Groups;
 use boolinator::Boolinator;
 use log::*;
 use piston_window::*;
+use pix_core::{
+    adjust, archive, checksum, concurrency, crypt, database, depth, errlog, filter, fingerprint,
+    scan, stats, system, thumbnailer, tonemap, userdata, vec,
+};
+use pix_core::{
+    find_images, File, Metadata, MetadataState, Thumb, TileMap, TileRef, E, R, SIDECAR_SUFFIX,
+};
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
-use thiserror::Error;
 use thumbnailer::Thumbnailer;
+use transform::Transform2D;
+use userdata::{Label, UserData};
 use vec::*;
 
-#[derive(Debug, Error)]
-pub enum E {
-    #[error("database error: {:?}", 0)]
-    DatabaseError(sled::Error),
+struct App {
+    db: Arc<database::Database>,
 
-    #[error("decode error {:?}", 0)]
-    DecodeError(bincode::Error),
+    groups: groups::Groups,
 
-    #[error("encode error {:?}", 0)]
-    EncodeError(bincode::Error),
+    thumbnailer: Thumbnailer,
 
-    #[error("missing data for key {:?}", 0)]
-    MissingData(String),
+    // Graphics state
+    window: PistonWindow,
+    texture_context: G2dTextureContext,
+    overlay: overlay::Overlay,
 
-    #[error("image error: {:?}", 0)]
-    ImageError(::image::ImageError),
-}
+    // Movement state & modes.
+    view: view::View,
+    panning: bool,
+    zooming: Option<f64>,
+    cursor_captured: bool,
 
-type R<T> = std::result::Result<T, E>;
+    // Mouse distance calculations are relative to this point.
+    focus: Option<Vector2<f64>>,
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
-struct Pow2(u8);
+    shift_held: bool,
+    ctrl_held: bool,
 
-impl Pow2 {
-    fn from(i: u32) -> Self {
-        assert!(i.is_power_of_two());
-        Pow2((32 - i.leading_zeros() - 1) as u8)
-    }
+    // Single image view, the grid coordinates of the shown image and the grid view to return to.
+    single: Option<(Vector2<u32>, view::View)>,
 
-    #[allow(unused)]
-    fn u32(&self) -> u32 {
-        1 << self.0
-    }
-}
+    // How single images are sized to the window.
+    fit: view::Fit,
 
-#[test]
-fn size_conversions() {
-    assert_eq!(Pow2::from(128), Pow2(7));
-    assert_eq!(Pow2(7).u32(), 128);
-}
+    // Zoom and pan of each image shown in single image view this session.
+    placements: BTreeMap<String, view::Placement>,
 
-#[derive(
-    Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Default,
-)]
-pub struct TileRef(u64);
+    // Capture times along the bottom of the grid.
+    timeline: Option<timeline::Timeline>,
 
-impl TileRef {
-    fn new(size: Pow2, index: u64, chunk: u16) -> Self {
-        Self((chunk as u64) | ((index % (1u64 << 40)) << 16) | ((size.0 as u64) << 56))
-    }
+    // Neighbors along the bottom of the single image view.
+    filmstrip: Option<filmstrip::Filmstrip>,
 
-    #[cfg(test)]
-    fn deconstruct(&self) -> (Pow2, u64, u16) {
-        let size = ((self.0 & 0xFF00_0000_0000_0000u64) >> 56) as u8;
-        let index = (self.0 & 0x00FF_FFFF_FFFF_0000u64) >> 16;
-        let chunk = (self.0 & 0x0000_0000_0000_FFFFu64) as u16;
-        (Pow2(size), index, chunk)
-    }
-}
+    // Applied in single image view.
+    adjustments: adjust::Adjustments,
 
-#[test]
-fn tile_ref_test() {
-    assert_eq!(
-        TileRef::new(Pow2(0xFFu8), 0u64, 0u16),
-        TileRef(0xFF00_0000_0000_0000u64)
-    );
-    assert_eq!(
-        TileRef::new(Pow2(0xFFu8), 0u64, 0u16).deconstruct(),
-        (Pow2(0xFFu8), 0u64, 0u16)
-    );
-    assert_eq!(
-        TileRef::new(Pow2(0xFFu8), 0u64, 0u16).0.to_be_bytes(),
-        [0xFF, 0, 0, 0, 0, 0, 0, 0]
-    );
+    // Channel view or soft proof of single images.
+    proof: proof::Proof,
 
-    assert_eq!(
-        TileRef::new(Pow2(0u8), 0x00FF_FFFF_FFFFu64, 0u16),
-        TileRef(0x00FF_FFFF_FFFF_0000_u64)
-    );
-    assert_eq!(
-        TileRef::new(Pow2(0u8), 0x00FF_FFFF_FFFFu64, 0u16).deconstruct(),
-        (Pow2(0u8), 0x00FF_FFFF_FFFFu64, 0u16)
-    );
+    // Full resolution pixels of the single image.
+    decoded: decoded::Loader,
 
-    assert_eq!(
-        TileRef::new(Pow2(0u8), 0u64, 0xFFFFu16),
-        TileRef(0x0000_0000_0000_FFFFu64)
-    );
-    assert_eq!(
-        TileRef::new(Pow2(0u8), 0u64, 0xFFFFu16).deconstruct(),
-        (Pow2(0u8), 0u64, 0xFFFFu16)
-    )
-}
+    // Image being decoded on a thread to be copied to the clipboard.
+    copying: Option<crossbeam_channel::Receiver<::image::RgbaImage>>,
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
-struct Thumb {
-    img_size: [u32; 2],
-    tile_refs: Vec<TileRef>,
-}
+    show_histogram: bool,
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
-pub struct Metadata {
-    thumbs: Vec<Thumb>,
-}
+    // Magnifier and value of the pixel under the cursor.
+    show_loupe: bool,
 
-impl Metadata {
-    fn nearest(&self, target_size: u32) -> usize {
-        let mut found = None;
+    win_size: Vector2<u32>,
 
-        let ts_zeros = target_size.leading_zeros() as i16;
+    // Split pane layout with a preview right of the grid.
+    preview: Option<preview::Preview>,
 
-        for (i, thumb) in self.thumbs.iter().enumerate() {
-            let size = thumb.size();
-            let size_zeros = size.leading_zeros() as i16;
-            let dist = (ts_zeros - size_zeros).abs();
-            if let Some((found_dist, found_i)) = found.take() {
-                if dist < found_dist {
-                    found = Some((dist, i));
-                } else {
-                    found = Some((found_dist, found_i));
-                }
-            } else {
-                found = Some((dist, i));
-            }
-        }
+    // Full resolution crop of the grid image under the cursor, while Space is held.
+    hover: Option<hover::Hover>,
 
-        let (_, i) = found.unwrap();
-        i
-    }
-}
+    // Loop of the video under the cursor.
+    #[cfg(feature = "video")]
+    video: video::Preview,
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TileSpec {
-    img_size: [u32; 2],
+    // Crop mode, the left mouse button drags a rectangle instead of zooming.
+    crop: Option<crop::Crop>,
 
-    // Grid width and height (in number of tiles).
-    grid_size: [u32; 2],
+    guide: Option<guides::Guide>,
 
-    // Tile width and height in pixels.
-    tile_size: [u32; 2],
-}
+    // Measure mode, like crop mode the left mouse button drags instead of zooming.
+    measure: Option<measure::Measure>,
 
-impl TileSpec {
-    fn ranges(img_size: u32, grid_size: u32, tile_size: u32) -> impl Iterator<Item = (u32, u32)> {
-        (0..grid_size).map(move |i| {
-            let min = i * tile_size;
-            let max = std::cmp::min(img_size, min + tile_size);
-            (min, max)
-        })
-    }
+    // Annotations of the single image, and the tool the left mouse button draws with.
+    annotations: annotate::Layer,
+    annotate: Option<annotate::Tool>,
 
-    fn x_ranges(&self) -> impl Iterator<Item = (u32, u32)> {
-        Self::ranges(self.img_size[0], self.grid_size[0], self.tile_size[0])
-    }
+    // Cell size of the pixel grid guide in image pixels.
+    pixel_grid: u32,
 
-    fn y_ranges(&self) -> impl Iterator<Item = (u32, u32)> {
-        Self::ranges(self.img_size[1], self.grid_size[1], self.tile_size[1])
-    }
-}
+    show_info: bool,
 
-impl Thumb {
-    fn max_dimension(&self) -> u32 {
-        let [w, h] = self.img_size;
-        std::cmp::max(w, h)
-    }
+    // Metadata inspector panel, shown instead of the info overlay.
+    inspector: Option<inspector::Inspector>,
 
-    fn size(&self) -> u32 {
-        self.max_dimension().next_power_of_two()
-    }
+    // Recorded warnings and errors, shown like the inspector.
+    log_panel: Option<errlog::Panel>,
 
-    fn tile_spec(&self) -> TileSpec {
-        let img_size = vec2_f64(self.img_size);
-        let tile_size = vec2_scale(vec2_log(img_size, 8.0), 128.0);
-        let grid_size = vec2_ceil(vec2_div(img_size, tile_size));
-        let tile_size = vec2_ceil(vec2_div(img_size, grid_size));
-        TileSpec {
-            img_size: self.img_size,
-            grid_size: vec2_u32(grid_size),
-            tile_size: vec2_u32(tile_size),
-        }
-    }
+    clipboard: system::Clipboard,
 
-    fn draw(
-        &self,
-        trans: [[f64; 3]; 2],
-        view: &view::View,
-        tiles: &BTreeMap<TileRef, G2dTexture>,
-        draw_state: &DrawState,
-        g: &mut G2d,
-    ) -> bool {
-        let img = piston_window::image::Image::new();
+    // Save the next rendered frame to a PNG file.
+    snapshot: bool,
 
-        let max_dimension = self.max_dimension() as f64;
+    // Database cache counters below the other overlay lines.
+    show_stats: bool,
 
-        let trans = trans.zoom(view.zoom / max_dimension);
+    // Unattended slideshow, most input is ignored.
+    kiosk: Option<kiosk::Kiosk>,
 
-        // Center the image within the grid square.
-        let [x_offset, y_offset] = {
-            let img_size = vec2_f64(self.img_size);
-            let gaps = vec2_sub([max_dimension, max_dimension], img_size);
-            vec2_scale(gaps, 0.5)
-        };
+    // Searches the directories again on F5 and kiosk reloads.
+    scan: Option<scan::Scan>,
 
-        let tile_spec = self.tile_spec();
+    // Nothing is written next to the originals: no saved crops or annotation sidecars.
+    read_only_files: bool,
 
-        let mut it = self.tile_refs.iter();
-        for (y, _) in tile_spec.y_ranges() {
-            for (x, _) in tile_spec.x_ranges() {
-                let tile_ref = it.next().unwrap();
-                if let Some(texture) = tiles.get(tile_ref) {
-                    let trans = trans.trans(x_offset + x as f64, y_offset + y as f64);
-                    img.draw(texture, draw_state, trans, g);
-                }
-            }
-        }
+    // Sets of identical files in `pix dupes`.
+    dupes: Option<dupes::Dupes>,
 
-        true
-    }
-}
+    // First frames of the bursts expanded while bursts are stacked.
+    stacks: Option<BTreeSet<String>>,
 
-#[derive(Debug, Eq, PartialEq)]
-pub enum MetadataState {
-    Missing,
-    Some(Metadata),
-    Errored,
-}
+    // Resizes large thumbnails with `--gpu-resize`.
+    gpu: Option<gpuscale::Server>,
 
-pub type TileMap<T> = BTreeMap<TileRef, T>;
+    pacing: pacing::Pacing,
 
-struct App {
-    db: Arc<database::Database>,
+    // Pauses decoders while frames come late during input.
+    governor: concurrency::Governor,
 
-    groups: groups::Groups,
+    // Thumbnailing paused by the user.
+    pause: pause::Pause,
 
-    thumbnailer: Thumbnailer,
+    // Grid locked to this many columns, scrolled vertically.
+    columns: Option<u32>,
 
-    // Graphics state
-    window_settings: WindowSettings,
-    window: PistonWindow,
-    texture_context: G2dTextureContext,
+    // Stepping past the last image shows the first, the grid tiles when panned past its edges.
+    wrap: bool,
 
-    // Movement state & modes.
-    view: view::View,
-    panning: bool,
-    zooming: Option<f64>,
-    cursor_captured: bool,
+    // Single images of `pix diff` are shown next to the rest of their set.
+    compare: bool,
 
-    // Mouse distance calculations are relative to this point.
-    focus: Option<Vector2<f64>>,
+    // Note being typed.
+    note: Option<notes::Editor>,
 
-    shift_held: bool,
+    // Command palette, keys go to it while open.
+    palette: Option<palette::Palette>,
+
+    // Less background work on battery.
+    power: power::Power,
+
+    // Print the startup stages with `--profile-startup` once thumbnails show.
+    profile_startup: bool,
+
+    title: title::Title,
+
+    access: access::Access,
 }
 
 pub struct Stopwatch {
@@ -308,29 +257,48 @@ impl App {
         images: Vec<image::Image>,
         db: Arc<database::Database>,
         thumbnailer: Thumbnailer,
+        font: Option<PathBuf>,
+        pixel_grid: u32,
+        window_settings: WindowSettings,
+        kiosk: Option<kiosk::Kiosk>,
     ) -> Self {
-        let view = view::View::new(images.len());
+        let num_images = images.len();
+        let view = view::View::new(num_images);
+        let ratings: Vec<u8> = images.iter().map(|image| image.user_data.rating).collect();
 
         let groups = Groups::from(images, vec2_u32(view.grid_size));
 
-        let window_settings = WindowSettings::new("pix", [800.0, 600.0])
-            .exit_on_esc(true)
-            .fullscreen(false);
-
         let mut window: PistonWindow = window_settings.build().expect("window build");
+        stats::stage("startup_window");
+        glcompat::init(&mut window.device);
+        let draw_size = window.draw_size();
+
+        // Nobody is meant to use the mouse on a photo frame.
+        window.set_capture_cursor(kiosk.is_some());
+
+        let (rgba, size) = title::icon();
+        match winit::window::Icon::from_rgba(rgba, size, size) {
+            Ok(icon) => window.window.window.set_window_icon(Some(icon)),
+            Err(e) => error!("window icon: {}", e),
+        }
 
         let texture_context = window.create_texture_context();
 
-        Self {
+        let overlay = overlay::Overlay::new(&mut window, font);
+
+        let governor_threads = thumbnailer.threads();
+        let governor = concurrency::Governor::new(governor_threads, 60);
+
+        let mut app = Self {
             db,
 
             groups,
 
             thumbnailer,
 
-            window_settings,
             window,
             texture_context,
+            overlay,
 
             view,
             panning: false,
@@ -338,21 +306,173 @@ impl App {
             cursor_captured: false,
 
             shift_held: false,
+            ctrl_held: false,
 
             focus: None,
+
+            single: None,
+
+            fit: view::Fit::default(),
+
+            placements: BTreeMap::new(),
+
+            timeline: None,
+            filmstrip: None,
+
+            adjustments: adjust::Adjustments::default(),
+            proof: proof::Proof::Off,
+
+            decoded: decoded::Loader::default(),
+
+            copying: None,
+
+            show_histogram: false,
+
+            show_loupe: false,
+
+            win_size: [800, 600],
+
+            preview: None,
+
+            hover: None,
+
+            #[cfg(feature = "video")]
+            video: Default::default(),
+
+            crop: None,
+
+            guide: None,
+
+            measure: None,
+
+            annotations: annotate::Layer::default(),
+            annotate: None,
+
+            pixel_grid,
+
+            show_info: false,
+
+            inspector: None,
+            log_panel: None,
+
+            clipboard: system::Clipboard::new(),
+
+            snapshot: false,
+
+            show_stats: false,
+
+            kiosk: kiosk.map(|mut kiosk| {
+                kiosk.restart(&ratings);
+                kiosk
+            }),
+
+            scan: None,
+
+            read_only_files: false,
+
+            pause: pause::Pause::default(),
+
+            columns: None,
+
+            wrap: false,
+
+            compare: false,
+
+            note: None,
+            palette: None,
+
+            power: power::Power::new(
+                power::Budget {
+                    threads: governor_threads,
+                    fps: 60,
+                    neighbors: 2,
+                },
+                None,
+            ),
+
+            dupes: None,
+
+            stacks: None,
+            gpu: None,
+            pacing: pacing::Pacing::new(false),
+            governor,
+
+            profile_startup: false,
+
+            title: title::Title::default(),
+
+            access: access::Access::default(),
+        };
+        app.resize([draw_size.width as u32, draw_size.height as u32]);
+        app
+    }
+
+    fn coords_of(&self, path: &Path) -> Option<Vector2<u32>> {
+        (0..self.groups.num_images())
+            .map(|i| self.groups.image_coords(i))
+            .find(|&coords| {
+                self.groups
+                    .get(coords)
+                    .is_some_and(|image| Path::new(&image.file.path) == path)
+            })
+    }
+
+    // Open the grid at `zoom` window pixels per square and centered on the image at `path`.
+    fn start_at(&mut self, zoom: Option<f64>, path: Option<&Path>) {
+        let coords = path.and_then(|path| self.coords_of(&path.canonicalize().ok()?));
+        if let (Some(path), None) = (path, coords) {
+            error!("Start image {:?} not found", path);
+        }
+
+        if zoom.is_none() && coords.is_none() {
+            return;
         }
+        let center = coords.map(|coords| vec2_add(vec2_f64(coords), [0.5, 0.5]));
+        self.view.look_at(zoom.unwrap_or(self.view.zoom), center);
+        self.force_refocus();
     }
 
     fn update(&mut self, args: UpdateArgs) {
+        let _s = stats::ScopedDuration::new("update");
+
+        if shutdown::requested() {
+            self.window.set_should_close(true);
+            return;
+        }
         let stopwatch = Stopwatch::from_millis(10);
 
         let grid_size = vec2_u32(self.view.grid_size);
         if grid_size != self.groups.grid_size() {
             self.groups.regroup(grid_size);
+            pacing::changed();
         }
 
+        let now = std::time::Instant::now();
         if let Some(z) = self.zooming {
             self.zoom(z.mul_add(args.dt, 1.0));
+            pacing::changed();
+            self.governor.input(now);
+        }
+        if self
+            .timeline
+            .as_ref()
+            .is_some_and(|timeline| timeline.rebuild_due(now))
+        {
+            let dragging = self.timeline.as_ref().is_some_and(|t| t.dragging);
+            let mut timeline = self.build_timeline();
+            timeline.dragging = dragging;
+            self.timeline = Some(timeline);
+            pacing::changed();
+        }
+
+        if let Some(budget) = self.power.update(now) {
+            self.window.set_max_fps(budget.fps);
+            if let Some(n) = self.governor.set_budget(budget.threads, budget.fps) {
+                self.thumbnailer.set_active(n);
+            }
+        }
+        if let Some(n) = self.governor.step(now) {
+            self.thumbnailer.set_active(n);
         }
 
         if self.focus.is_none() {
@@ -360,125 +480,1804 @@ impl App {
             self.focus = Some(self.view.mouse_dist([0, 0]));
         }
 
+        if let Some(gpu) = &self.gpu {
+            gpu.serve(&mut self.window.device);
+        }
+
         self.recv_thumbs();
 
-        self.groups.make_thumbs(&mut self.thumbnailer);
+        self.recv_scan();
+        if let Some(inspector) = &mut self.inspector {
+            inspector.poll();
+        }
+
+        self.decoded.recv();
+        self.recv_copy();
+
+        self.update_kiosk();
+
+        self.update_title();
+
+        if !self.access.reduced_motion {
+            self.groups.animate(&self.view, std::time::Instant::now());
+        }
+
+        if self.access.announce {
+            let path = self.focused().map(|image| image.file.path.clone());
+            self.access.announce(path.as_deref());
+        }
+
+        if let Some(paused) = self.pause.update(std::time::Instant::now()) {
+            self.pause_message(paused);
+        }
+        if !self.pause.is_paused() {
+            self.groups.make_thumbs(&mut self.thumbnailer);
+        }
+
+        if let Some(preview) = &mut self.preview {
+            preview.update(
+                &self.view,
+                &self.groups,
+                &self.db,
+                &mut self.texture_context,
+                &stopwatch,
+                self.win_size,
+            );
+        }
+
+        // Before the grid so the tiles under the cursor load first.
+        if let Some(hover) = &mut self.hover {
+            hover.update(
+                &self.view,
+                &self.groups,
+                &self.db,
+                &mut self.texture_context,
+                &stopwatch,
+            );
+        }
+
+        #[cfg(feature = "video")]
+        match self.single {
+            Some(_) => self.video = Default::default(),
+            None => self
+                .video
+                .update(&self.view, &self.groups, &mut self.texture_context, now),
+        }
 
-        self.groups
-            .load_cache(&self.view, &*self.db, &mut self.texture_context, &stopwatch);
+        let single = self
+            .single
+            .as_ref()
+            .map(|(coords, _)| (*coords, &self.adjustments, self.proof));
+        self.groups.load_cache(
+            &self.view,
+            single,
+            &self.db,
+            &mut self.texture_context,
+            &stopwatch,
+        );
     }
 
-    pub fn recv_thumbs(&mut self) {
-        for (i, metadata_res) in self.thumbnailer.recv() {
-            self.groups.update_metadata(i, metadata_res);
+    // The focused image and thumbnailing progress, unless a message or mode is shown.
+    fn update_title(&mut self) {
+        let focused = self
+            .focused()
+            .map(|image| (image.file.path.as_str(), image.i));
+        let status = title::status(
+            focused,
+            self.groups.num_images(),
+            self.groups.num_missing(),
+            self.pause.is_paused(),
+        );
+        if let Some(title) = self.title.update(std::time::Instant::now(), status) {
+            self.window.set_title(title);
         }
     }
 
-    fn resize(&mut self, win_size: Vector2<u32>) {
-        self.view.resize_to(win_size);
-        self.focus = None;
+    // Advance the slideshow and pick up added and removed images.
+    fn update_kiosk(&mut self) {
+        let mut kiosk = match self.kiosk.take() {
+            Some(kiosk) => kiosk,
+            None => return,
+        };
+
+        let now = std::time::Instant::now();
+        if kiosk.reload_due(now) {
+            self.rescan();
+        }
+
+        if let Some(i) = kiosk.next_slide(now, |i| self.is_slide(i)) {
+            self.show_slide(i);
+        }
+
+        self.kiosk = Some(kiosk);
     }
 
-    fn force_refocus(&mut self) {
-        self.focus = None;
+    // Star ratings by image index.
+    fn ratings(&self) -> Vec<u8> {
+        (0..self.groups.num_images())
+            .map(|i| match self.groups.get(self.groups.image_coords(i)) {
+                Some(image) => image.user_data.rating,
+                None => 0,
+            })
+            .collect()
     }
 
-    fn maybe_refocus(&mut self) {
-        if let Some(old) = self.focus {
-            let new = self.view.mouse_dist([0, 0]);
-            let delta = vec2_sub(new, old);
-            if vec2_square_len(delta) > 500.0 {
-                self.force_refocus();
+    // Whether image `i` can be shown in the slideshow, broken images and books are skipped.
+    fn is_slide(&self, i: usize) -> bool {
+        match self.groups.get(self.groups.image_coords(i)) {
+            Some(image) => {
+                !matches!(image.metadata, MetadataState::Errored)
+                    && !archive::is_book(&image.file.path)
             }
+            None => false,
         }
     }
 
-    fn mouse_move(&mut self, loc: Vector2<f64>) {
-        self.view.mouse_to(loc);
-        self.maybe_refocus();
+    fn show_slide(&mut self, i: usize) {
+        if self.single.is_some() {
+            self.jump_single(i);
+        } else {
+            let coords = self.groups.image_coords(i);
+            self.enter_single(coords, self.view.clone());
+            self.force_refocus();
+        }
     }
 
-    fn mouse_zoom(&mut self, v: f64) {
-        for _ in 0..(v as isize) {
-            self.zoom(1.0 + self.zoom_increment());
-        }
-        for _ in (v as isize)..0 {
-            self.zoom(1.0 - self.zoom_increment());
-        }
+    // Lay the grid out in sets of `n` images.
+    fn show_sets(&mut self, n: u32) {
+        self.view = view::View::sets(self.groups.num_images(), n);
+        self.view.set_columns(self.columns);
+        self.view.set_wrap(self.wrap);
+        self.resize(self.win_size);
     }
 
-    fn mouse_pan(&mut self, delta: Vector2<f64>) {
-        if self.panning {
-            if self.cursor_captured {
-                self.view.center_mouse();
-            }
-            self.trans(vec2_scale(delta, 4.0));
+    // Open the first image of the next or previous set of duplicates.
+    fn step_dupes(&mut self, delta: isize) {
+        let i = self.focused().map(|image| image.i);
+        if let Some(i) = self.dupes.as_ref().and_then(|dupes| dupes.step(i, delta)) {
+            self.show_slide(i);
         }
     }
 
-    fn shift_increment(&self) -> f64 {
-        if self.shift_held {
-            // snap to zoom
-            if self.view.zoom > 100.0 {
-                self.view.zoom
-            } else {
-                100.0
+    // Trash every file of the focused image's set of duplicates but the focused one.
+    fn keep_dupe(&mut self) {
+        if self.read_only_files {
+            self.title
+                .message(i18n::tr("read-only files, nothing trashed").to_owned());
+            return;
+        }
+        let keep = match self.focused() {
+            Some(image) => image.i,
+            None => return,
+        };
+        let (n, range) = match self.dupes.as_ref().and_then(|dupes| dupes.set_of(keep)) {
+            Some(set) => set,
+            None => return,
+        };
+
+        let others: Vec<String> = range
+            .filter(|&i| i != keep)
+            .filter_map(|i| self.groups.get(self.groups.image_coords(i)))
+            .map(|image| image.file.path.clone())
+            .collect();
+        let dupes = self.dupes.as_mut().expect("dupes");
+        let mut trashed = 0;
+        for path in others {
+            match system::trash(&path) {
+                Ok(()) => {
+                    dupes.remove(&path);
+                    trashed += 1;
+                }
+                Err(e) => error!("unable to trash {:?}: {}", path, e),
             }
-        } else {
-            20.0
+        }
+        info!("Trashed {} duplicates", trashed);
+        self.title.message(i18n::trf(
+            "trashed {}, {} sets of duplicates left",
+            &[&trashed, &dupes.len()],
+        ));
+
+        let images = dupes.images(&self.db);
+        if images.is_empty() {
+            return;
+        }
+        // The same set if some files are left, else the next one.
+        let next = dupes.start(n);
+        self.replace_images(images);
+        self.show_sets(2);
+        if let Some(i) = next {
+            self.show_slide(i);
         }
     }
 
-    fn zoom_increment(&self) -> f64 {
-        if self.shift_held {
-            0.5
-        } else {
-            0.1
+    fn dupe_lines(&self) -> Vec<String> {
+        let (dupes, focused) = match (&self.dupes, self.focused()) {
+            (Some(dupes), Some(image)) => (dupes, image.i),
+            _ => return Vec::new(),
+        };
+        let (n, range) = match dupes.set_of(focused) {
+            Some(set) => set,
+            None => return Vec::new(),
+        };
+        let mut lines = vec![i18n::trf(
+            "Duplicates {}/{}, K: keep this, trash the others",
+            &[&(n + 1), &dupes.len()],
+        )];
+        lines.extend(
+            range
+                .filter_map(|i| self.groups.get(self.groups.image_coords(i)))
+                .map(|image| dupes::line(image, image.i == focused)),
+        );
+        lines
+    }
+
+    fn toggle_stacks(&mut self) {
+        self.stacks = match self.stacks {
+            Some(_) => None,
+            None => Some(BTreeSet::new()),
+        };
+        self.restack();
+        self.title.message(
+            i18n::tr(match self.stacks {
+                Some(_) => "bursts stacked",
+                None => "bursts expanded",
+            })
+            .to_owned(),
+        );
+    }
+
+    // The stacked burst under the cursor in the grid.
+    fn stack_under_mouse(&self) -> Option<&image::Image> {
+        if self.stacks.is_none()
+            || self.single.is_some()
+            || self.view.level() == view::Level::Clusters
+        {
+            return None;
         }
+        let image = self.groups.get(self.view.mouse_coords()?)?;
+        (!image.stack.is_empty()).as_some(image)
     }
 
-    fn trans(&mut self, trans: Vector2<f64>) {
-        self.view.trans_by(trans);
-        self.maybe_refocus();
+    fn expand_stack(&mut self) {
+        let path = match self.stack_under_mouse() {
+            Some(image) => image.file.path.clone(),
+            None => return,
+        };
+        if let Some(expanded) = &mut self.stacks {
+            expanded.insert(path);
+        }
+        self.restack();
     }
 
-    fn zoom(&mut self, ratio: f64) {
-        self.view.zoom_by(ratio);
-        self.maybe_refocus();
+    fn toggle_night(&mut self) {
+        let on = !night::enabled();
+        night::set(on);
+        if let Err(e) = self.db.set_setting(night::SETTING, &on.to_string()) {
+            error!("unable to save night mode: {:?}", e);
+        }
+        self.title.message(
+            i18n::tr(if on {
+                "night mode on"
+            } else {
+                "night mode off"
+            })
+            .to_owned(),
+        );
     }
 
-    fn reset(&mut self) {
-        self.view.reset();
-        self.force_refocus();
+    // Pause or resume thumbnailing, `snooze` pauses it for a while.
+    fn toggle_pause(&mut self, snooze: bool) {
+        let now = std::time::Instant::now();
+        if snooze {
+            self.pause.snooze(now);
+            let minutes = pause::SNOOZE.as_secs() / 60;
+            self.title.message(i18n::trf(
+                "thumbnailing snoozed for {} minutes",
+                &[&minutes],
+            ));
+        } else {
+            let paused = !self.pause.is_paused();
+            self.pause.set(paused);
+            self.pause_message(paused);
+        }
     }
 
-    fn button(&mut self, b: ButtonArgs) {
-        match (b.state, b.button) {
-            (ButtonState::Press, Button::Keyboard(Key::Z)) => {
-                self.reset();
-            }
+    fn pause_message(&mut self, paused: bool) {
+        self.title.message(
+            i18n::tr(if paused {
+                "thumbnailing paused"
+            } else {
+                "thumbnailing resumed"
+            })
+            .to_owned(),
+        );
+    }
 
-            //(ButtonState::Press, Button::Keyboard(Key::F)) => {
-            //    // TODO: this crashes with: thread 'main' panicked at 'Creating EventLoop multiple
-            //    // times is not supported.',
-            //    self.window.set_should_close(true);
-            //    self.window_settings.set_fullscreen(true);
-            //    self.window = self.window_settings.build().unwrap();
+    fn rescan(&mut self) {
+        if let Some(scan) = &mut self.scan {
+            scan.start(&self.db);
+        }
+    }
 
-            //    self.groups.reset();
-            //    self.focus = None;
-            //    self.panning = false;
-            //    self.cursor_captured = false;
-            //    self.zooming = None;
-            //}
-            (ButtonState::Press, Button::Keyboard(Key::T)) => {
-                self.cursor_captured = !self.cursor_captured;
-                self.window.set_capture_cursor(self.cursor_captured);
-                self.panning = self.cursor_captured;
-                self.view.center_mouse();
+    // Add the files and directories of pasted paths or file:// uris to the grid, from the
+    // clipboard or else the primary selection.
+    fn paste(&mut self) {
+        let scan = match &mut self.scan {
+            Some(scan) if !scan.is_running() => scan,
+            Some(_) => {
+                self.title
+                    .message(i18n::tr("still searching, paste again later").to_owned());
+                return;
             }
+            None => return,
+        };
 
-            (ButtonState::Press, Button::Keyboard(Key::Up)) => {
+        let mut paths = self
+            .clipboard
+            .get_text()
+            .map(|text| system::parse_paths(&text))
+            .unwrap_or_default();
+        if paths.is_empty() {
+            paths = self
+                .clipboard
+                .get_primary()
+                .map(|text| system::parse_paths(&text))
+                .unwrap_or_default();
+        }
+        paths.retain(|path| match std::fs::metadata(path) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Skipping pasted path {:?}: {}", path, e);
+                false
+            }
+        });
+        if paths.is_empty() {
+            self.title.message(i18n::tr("no paths to paste").to_owned());
+            return;
+        }
+
+        let n = scan.add(paths);
+        info!("Pasted {} new paths", n);
+        self.title
+            .message(i18n::trf("adding {} pasted paths", &[&n]));
+        scan.start(&self.db);
+    }
+
+    // Show the images found by a search started with `rescan`.
+    fn recv_scan(&mut self) {
+        let images = match self.scan.as_mut().and_then(|scan| scan.poll()) {
+            Some(found) => images_of(found),
+            None => return,
+        };
+
+        let old = (0..self.groups.num_images())
+            .filter_map(|i| self.groups.get(self.groups.image_coords(i)))
+            .flat_map(|image| std::iter::once(image).chain(&image.stack))
+            .map(|image| &*image.file);
+        let diff = scan::Diff::new(old, images.iter().map(|image| &*image.file));
+        errlog::event(
+            Level::Info,
+            "rescan",
+            serde_json::json!({"added": diff.added, "removed": diff.removed, "changed": diff.changed}),
+        );
+
+        if images.is_empty() {
+            warn!("Rescan found no files, keeping the old ones");
+            return;
+        }
+        if diff.is_empty() {
+            return;
+        }
+
+        if let Some(mut kiosk) = self.kiosk.take() {
+            self.replace_images(images);
+            kiosk.restart(&self.ratings());
+            self.kiosk = Some(kiosk);
+        } else {
+            self.update_images(images);
+            self.title.message(i18n::trf(
+                "{} added, {} removed, {} changed",
+                &[&diff.added, &diff.removed, &diff.changed],
+            ));
+        }
+    }
+
+    // Like `replace_images` but the grid stays zoomed and panned to the same images, and the
+    // single image stays open.
+    fn update_images(&mut self, images: Vec<image::Image>) {
+        self.keep_view(|app| app.replace_images(images));
+    }
+
+    // Stack or expand bursts again, keeping the view.
+    fn restack(&mut self) {
+        self.keep_view(|app| {
+            let images = app.groups.take_images();
+            app.replace_images(images);
+        });
+    }
+
+    // Replace the images with `replace`, like `update_images`.
+    fn keep_view(&mut self, replace: impl FnOnce(&mut Self)) {
+        let single = self.focused().filter(|_| self.single.is_some());
+        let single = single.map(|image| image.file.path.clone());
+        if let Some(grid_view) = self.leave_single() {
+            self.view = grid_view;
+        }
+
+        let old = self.view.clone();
+        let anchor = old.mouse_coords().and_then(|coords| {
+            let image = self.groups.get(coords)?;
+            Some((image.file.path.clone(), old.trans(coords)))
+        });
+
+        replace(self);
+        self.view.mouse_to(old.mouse());
+
+        if !old.is_auto() {
+            match anchor.and_then(|(path, pos)| Some((self.coords_of(Path::new(&path))?, pos))) {
+                Some((coords, pos)) => self.view.pin(old.zoom, coords, pos),
+                None => self.view.look_at(old.zoom, None),
+            }
+        }
+
+        if let Some(coords) = single.and_then(|path| self.coords_of(Path::new(&path))) {
+            self.enter_single(coords, self.view.clone());
+        }
+        self.force_refocus();
+    }
+
+    // Start over with a new set of images, back in the grid.
+    fn replace_images(&mut self, images: Vec<image::Image>) {
+        let images = match &self.stacks {
+            Some(expanded) => stack::collapse(images, expanded),
+            None => stack::expand_all(images),
+        };
+        if let Some(grid_view) = self.leave_single() {
+            self.view = grid_view;
+        }
+        self.decoded.clear();
+        self.hover = None;
+        self.thumbnailer.rebase(self.db.reserve(images.len()));
+        self.view = view::View::new(images.len());
+        self.view.set_columns(self.columns);
+        self.view.set_wrap(self.wrap);
+        self.groups = Groups::from(images, vec2_u32(self.view.grid_size));
+        self.groups.mark_picks();
+        self.resize(self.win_size);
+        if let Some(timeline) = &mut self.timeline {
+            timeline.invalidate();
+        }
+    }
+
+    pub fn recv_thumbs(&mut self) {
+        let mut bursts = false;
+        for (i, metadata_res) in self.thumbnailer.recv() {
+            self.groups.update_metadata(i, metadata_res);
+            let coords = self.groups.image_coords(i);
+            bursts |= self
+                .groups
+                .get(coords)
+                .is_some_and(|image| image.burst.is_some());
+            if let Some(timeline) = &mut self.timeline {
+                timeline.invalidate();
+            }
+            pacing::changed();
+        }
+        if bursts {
+            self.groups.mark_picks();
+        }
+    }
+
+    fn resize(&mut self, win_size: Vector2<u32>) {
+        self.win_size = win_size;
+        let win_size = match self.preview {
+            Some(_) => [preview::grid_width(win_size[0]), win_size[1]],
+            None => win_size,
+        };
+        pacing::changed();
+        self.view.resize_to(win_size);
+        if self.compare && self.single.is_some() {
+            self.view.resize_to(self.cell_size());
+        }
+        if let Some((coords, grid_view)) = &mut self.single {
+            grid_view.resize_to(win_size);
+            let coords = *coords;
+            self.fit_single(coords);
+        }
+        self.focus = None;
+    }
+
+    fn force_refocus(&mut self) {
+        self.focus = None;
+        pacing::changed();
+    }
+
+    fn maybe_refocus(&mut self) {
+        if let Some(old) = self.focus {
+            let new = self.view.mouse_dist([0, 0]);
+            let delta = vec2_sub(new, old);
+            if vec2_square_len(delta) > 500.0 {
+                self.force_refocus();
+            }
+        }
+    }
+
+    fn mouse_move(&mut self, loc: Vector2<f64>) {
+        // Compared images share the mouse position within their cells.
+        let [mx, my] = loc;
+        let cell = self
+            .cells()
+            .into_iter()
+            .find(|&(_, [x, y, w, h])| mx >= x && mx < x + w && my >= y && my < y + h);
+        let loc = match cell {
+            Some((_, [x, y, _, _])) => [mx - x, my - y],
+            None => loc,
+        };
+        self.view.mouse_to(loc);
+        if self.timeline.as_ref().is_some_and(|t| t.dragging) {
+            self.timeline_jump();
+        }
+        self.maybe_refocus();
+
+        if let Some((coords, img_size)) = self.single_img_size() {
+            let pos = self.view.mouse_image_pos(coords, img_size);
+            if let Some(crop) = &mut self.crop {
+                crop.drag(pos);
+            }
+            if let Some(measure) = &mut self.measure {
+                measure.drag(pos);
+            }
+            if self.annotate.is_some() {
+                self.annotations.drag(pos);
+            }
+        }
+    }
+
+    // Lock the grid to `columns`, or unlock it with None.
+    fn set_columns(&mut self, columns: Option<u32>) {
+        self.columns = columns;
+        match &mut self.single {
+            Some((_, grid_view)) => grid_view.set_columns(columns),
+            None => self.view.set_columns(columns),
+        }
+        self.resize(self.win_size);
+        self.force_refocus();
+    }
+
+    fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+        match &mut self.single {
+            Some((_, grid_view)) => grid_view.set_wrap(wrap),
+            None => self.view.set_wrap(wrap),
+        }
+    }
+
+    fn mouse_zoom(&mut self, v: f64) {
+        // A locked grid scrolls instead.
+        if self.view.is_locked() {
+            self.trans([0.0, v * self.view.zoom / 2.0]);
+            return;
+        }
+        for _ in 0..(v as isize) {
+            self.zoom(1.0 + self.zoom_increment());
+        }
+        for _ in (v as isize)..0 {
+            self.zoom(1.0 - self.zoom_increment());
+        }
+    }
+
+    fn mouse_pan(&mut self, delta: Vector2<f64>) {
+        if self.panning {
+            if self.cursor_captured {
+                self.view.center_mouse();
+            }
+            self.trans(vec2_scale(delta, 4.0));
+        }
+    }
+
+    fn shift_increment(&self) -> f64 {
+        if self.shift_held {
+            // snap to zoom
+            if self.view.zoom > 100.0 {
+                self.view.zoom
+            } else {
+                100.0
+            }
+        } else {
+            20.0
+        }
+    }
+
+    fn zoom_increment(&self) -> f64 {
+        if self.shift_held {
+            0.5
+        } else {
+            0.1
+        }
+    }
+
+    fn trans(&mut self, trans: Vector2<f64>) {
+        self.view.trans_by(trans);
+        self.maybe_refocus();
+    }
+
+    fn zoom(&mut self, ratio: f64) {
+        let level = self.view.level();
+        self.view.zoom_by(ratio);
+        if self.view.level() != level {
+            // Different thumbnail sizes are wanted.
+            self.force_refocus();
+        } else {
+            self.maybe_refocus();
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some((coords, _)) = self.single {
+            self.fit_single(coords);
+        } else {
+            self.view.reset();
+        }
+        self.force_refocus();
+    }
+
+    fn toggle_split(&mut self) {
+        self.preview = match self.preview {
+            Some(_) => None,
+            None => Some(preview::Preview::default()),
+        };
+        self.resize(self.win_size);
+    }
+
+    fn toggle_single(&mut self) {
+        if let Some(grid_view) = self.leave_single() {
+            self.view = grid_view;
+            self.decoded.clear();
+        } else if let Some(coords) = self.view.mouse_coords() {
+            if let Some(image) = self.groups.get(coords) {
+                // Ebooks and comics are only browsable by cover, read them elsewhere.
+                if archive::is_book(&image.file.path) {
+                    system::open(&image.file.path);
+                    return;
+                }
+
+                // The single image view takes the whole window.
+                if self.preview.take().is_some() {
+                    self.resize(self.win_size);
+                }
+                self.enter_single(coords, self.view.clone());
+            }
+        }
+        self.force_refocus();
+    }
+
+    // Show the image at `coords` in single image view, returning to `grid_view` afterwards.
+    fn enter_single(&mut self, coords: Vector2<u32>, grid_view: view::View) {
+        let image = match self.groups.get(coords) {
+            Some(image) => image,
+            None => return,
+        };
+
+        let placement = self.placements.get(&image.file.path).copied();
+        self.hover = None;
+        self.decoded.load(&image.file.path);
+        self.annotations = annotate::Layer::load(&image.file.path);
+        self.adjustments = self.db.get_adjustments(&image.file).unwrap_or_else(|e| {
+            error!("error loading adjustments for {:?}: {:?}", image.file, e);
+            adjust::Adjustments::default()
+        });
+        if !self.adjustments.is_tone_default() || self.proof != proof::Proof::Off {
+            self.groups.reload(coords);
+        }
+        self.single = Some((coords, grid_view));
+        // The image is zoomed and panned freely, the grid keeps its lock and wrapping.
+        self.view.set_columns(None);
+        self.view.set_wrap(false);
+        if self.compare {
+            self.view.set_synced(Some(coords));
+            self.view.resize_to(self.cell_size());
+        }
+        self.view.orientation = view::Orientation::default();
+        match placement {
+            Some(placement) => self.view.place(coords, placement),
+            None => self.fit_single(coords),
+        }
+        self.preload_neighbors();
+    }
+
+    // Decode the images two steps either side of the single image so stepping to them is instant,
+    // one step on battery.
+    fn preload_neighbors(&mut self) {
+        let i = match self.single_index() {
+            Some(i) if !lowmem::enabled() => i,
+            _ => return,
+        };
+
+        let reach = self.power.budget().neighbors;
+        let neighbors: Vec<Arc<File>> = (i.saturating_sub(reach)..=i + reach)
+            .filter(|&n| n != i && n < self.groups.num_images())
+            .filter_map(|n| self.groups.get(self.groups.image_coords(n)))
+            .filter(|image| !archive::is_book(&image.file.path))
+            .map(|image| image.file.clone())
+            .collect();
+        let paths: Vec<&str> = neighbors.iter().map(|file| file.path.as_str()).collect();
+        self.decoded.preload(&paths);
+    }
+
+    // Size the single image to the window, images not thumbnailed yet fit their grid square.
+    fn fit_single(&mut self, coords: Vector2<u32>) {
+        let metadata = self
+            .groups
+            .get(coords)
+            .and_then(|image| image.get_metadata());
+        match metadata {
+            Some(metadata) => {
+                let img_size = self.view.orientation.size(metadata.img_size());
+                self.view.fit_to(coords, img_size, self.fit)
+            }
+            None => self.view.zoom_to(coords),
+        }
+    }
+
+    // Rotate or flip the single image view.
+    fn orient(&mut self, f: impl FnOnce(&mut view::Orientation)) {
+        if let Some((coords, _)) = self.single {
+            f(&mut self.view.orientation);
+            self.fit_single(coords);
+        }
+    }
+
+    fn cycle_fit(&mut self) {
+        self.fit = self.fit.next();
+        if let Some((coords, _)) = self.single {
+            self.fit_single(coords);
+            self.force_refocus();
+        }
+        self.title
+            .message(i18n::trf("display mode: {}", &[&i18n::tr(self.fit.name())]));
+    }
+
+    // Drop the state of the single image view, returns the grid view to go back to.
+    fn leave_single(&mut self) -> Option<view::View> {
+        let (coords, grid_view) = self.single.take()?;
+        if let Some(image) = self.groups.get(coords) {
+            let placement = self.view.placement(coords);
+            self.placements.insert(image.file.path.clone(), placement);
+        }
+        if !self.adjustments.is_tone_default() || self.proof != proof::Proof::Off {
+            self.groups.reload(coords);
+        }
+        self.adjustments = adjust::Adjustments::default();
+        self.crop = None;
+        self.guide = None;
+        self.measure = None;
+        self.annotations.commit();
+        self.annotations = annotate::Layer::default();
+        self.annotate = None;
+        self.title.set_mode(None);
+        Some(grid_view)
+    }
+
+    // Window cells of the single image, the whole window, or in `pix diff` of its set: side by
+    // side, two by two for four images. Empty in the grid.
+    fn cells(&self) -> Vec<(Vector2<u32>, [f64; 4])> {
+        let set = match &self.single {
+            Some((coords, _)) if self.compare => self.view.set_of(*coords),
+            Some((coords, _)) => vec![*coords],
+            None => return Vec::new(),
+        };
+        let columns = if set.len() == 4 { 2 } else { set.len() };
+        let rows = set.len().div_ceil(columns);
+        let [w, h] = vec2_f64(self.win_size);
+        let [cell_w, cell_h] = [w / columns as f64, h / rows as f64];
+        set.into_iter()
+            .enumerate()
+            .map(|(k, coords)| {
+                let [x, y] = [(k % columns) as f64, (k / columns) as f64];
+                (coords, [x * cell_w, y * cell_h, cell_w, cell_h])
+            })
+            .collect()
+    }
+
+    fn cell_size(&self) -> Vector2<u32> {
+        match self.cells().first() {
+            Some(&(_, [_, _, w, h])) => vec2_u32([w, h]),
+            None => self.win_size,
+        }
+    }
+
+    // Window position of the cell of the single image.
+    fn cell_origin(&self) -> Vector2<f64> {
+        let single = self.single.as_ref().map(|(coords, _)| *coords);
+        self.cells()
+            .into_iter()
+            .find(|&(coords, _)| Some(coords) == single)
+            .map_or([0.0, 0.0], |(_, [x, y, _, _])| [x, y])
+    }
+
+    // Index of the single image in the grid.
+    fn single_index(&self) -> Option<usize> {
+        let (coords, _) = self.single.as_ref()?;
+        self.groups.get(*coords).map(|image| image.i)
+    }
+
+    // Show image `i` instead of the current single image.
+    fn jump_single(&mut self, i: usize) {
+        let coords = self.groups.image_coords(i);
+        match self.groups.get(coords) {
+            Some(image) if archive::is_book(&image.file.path) => {
+                system::open(&image.file.path);
+                return;
+            }
+            Some(_) => {}
+            None => return,
+        }
+
+        if let Some(grid_view) = self.leave_single() {
+            self.enter_single(coords, grid_view);
+            if let Some(filmstrip) = &mut self.filmstrip {
+                *filmstrip = filmstrip::Filmstrip::default();
+            }
+            self.force_refocus();
+        }
+    }
+
+    // Capture times of the images, modification times for images without one.
+    fn build_timeline(&self) -> timeline::Timeline {
+        timeline::Timeline::new((0..self.groups.num_images()).filter_map(|i| {
+            let image = self.groups.get(self.groups.image_coords(i))?;
+            let taken = image.get_metadata().and_then(|metadata| metadata.taken);
+            Some((taken.unwrap_or(image.file.modified), i))
+        }))
+    }
+
+    fn toggle_timeline(&mut self) {
+        self.timeline = match self.timeline {
+            Some(_) => None,
+            None => Some(self.build_timeline()),
+        };
+    }
+
+    fn over_timeline(&self) -> bool {
+        match (&self.timeline, &self.single) {
+            (Some(timeline), None) => timeline.contains(self.view.win_size(), self.view.mouse()),
+            _ => false,
+        }
+    }
+
+    // Center the grid on the image taken closest to the time under the mouse.
+    fn timeline_jump(&mut self) {
+        let i = self.timeline.as_ref().and_then(|timeline| {
+            let [x, _] = self.view.mouse();
+            timeline.nearest(timeline.time_at(self.view.win_size()[0], x)?)
+        });
+        if let Some(i) = i {
+            let coords = self.groups.image_coords(i);
+            let center = vec2_add(vec2_f64(coords), [0.5, 0.5]);
+            self.view.look_at(self.view.zoom, Some(center));
+            self.force_refocus();
+        }
+    }
+
+    fn timeline_mouse(&mut self, state: ButtonState) {
+        let dragging = state == ButtonState::Press;
+        if let Some(timeline) = &mut self.timeline {
+            timeline.dragging = dragging;
+        }
+        if dragging {
+            self.timeline_jump();
+        }
+    }
+
+    fn over_filmstrip(&self) -> bool {
+        match (&self.filmstrip, &self.single) {
+            (Some(filmstrip), Some(_)) => {
+                filmstrip.contains(vec2_f64(self.win_size), self.view.mouse())
+            }
+            _ => false,
+        }
+    }
+
+    // Index of the filmstrip square under the mouse.
+    fn filmstrip_hit(&self) -> Option<usize> {
+        let filmstrip = self.filmstrip.as_ref()?;
+        let current = self.single_index()?;
+        filmstrip.hit(
+            current,
+            self.groups.num_images(),
+            vec2_f64(self.win_size),
+            self.view.mouse(),
+        )
+    }
+
+    // Grid coordinates and full size of the single image, once thumbnailed.
+    fn single_img_size(&self) -> Option<(Vector2<u32>, [u32; 2])> {
+        let (coords, _) = self.single.as_ref()?;
+        let metadata = self.groups.get(*coords)?.get_metadata()?;
+        Some((*coords, metadata.img_size()))
+    }
+
+    fn crop_title(&mut self) {
+        let crop = match &self.crop {
+            Some(crop) => crop,
+            None => {
+                self.title.set_mode(None);
+                return;
+            }
+        };
+
+        let size = self
+            .single_img_size()
+            .and_then(|(_, img_size)| crop.rect(img_size))
+            .map(|[_, _, w, h]| format!(" {} x {}", w, h))
+            .unwrap_or_default();
+        self.title.set_mode(Some(i18n::trf(
+            "crop ({}){}, A: aspect, S: save copy, Shift+S: overwrite",
+            &[&i18n::tr(crop.aspect.name()), &size],
+        )));
+    }
+
+    fn toggle_crop(&mut self) {
+        if self.single.is_some() {
+            self.crop = match self.crop {
+                Some(_) => None,
+                None => Some(crop::Crop::default()),
+            };
+            self.measure = None;
+            self.annotate = None;
+            self.crop_title();
+        }
+    }
+
+    fn crop_mouse(&mut self, state: ButtonState) {
+        let (coords, img_size) = match self.single_img_size() {
+            Some(single) => single,
+            None => return,
+        };
+
+        if let Some(crop) = &mut self.crop {
+            if state == ButtonState::Press {
+                crop.begin(self.view.mouse_image_pos(coords, img_size));
+            } else {
+                crop.dragging = false;
+            }
+        }
+        self.crop_title();
+    }
+
+    fn cycle_measure(&mut self) {
+        let coords = match &self.single {
+            Some((coords, _)) => *coords,
+            None => return,
+        };
+
+        let shape = measure::Shape::next(self.measure.as_ref().map(|measure| measure.shape));
+        self.measure = match (shape, self.measure.take()) {
+            (Some(shape), Some(mut measure)) => {
+                measure.shape = shape;
+                Some(measure)
+            }
+            (Some(shape), None) => {
+                let path = &self.groups.get(coords).expect("single image").file.path;
+                Some(measure::Measure::new(shape, measure::dpi(path)))
+            }
+            (None, _) => None,
+        };
+
+        if self.measure.is_some() {
+            self.crop = None;
+            self.annotate = None;
+        }
+        self.title.set_mode(shape.map(|shape| {
+            let name = format!("{:?}", shape).to_lowercase();
+            i18n::trf("measure {}", &[&i18n::tr(&name)])
+        }));
+    }
+
+    fn measure_mouse(&mut self, state: ButtonState) {
+        let (coords, img_size) = match self.single_img_size() {
+            Some(single) => single,
+            None => return,
+        };
+
+        if let Some(measure) = &mut self.measure {
+            if state == ButtonState::Press {
+                measure.begin(self.view.mouse_image_pos(coords, img_size));
+            } else {
+                measure.dragging = false;
+            }
+        }
+    }
+
+    fn cycle_annotate(&mut self) {
+        if self.single.is_none() {
+            return;
+        }
+
+        if self.read_only_files {
+            self.title
+                .message(i18n::tr("read-only files, annotations are off").to_owned());
+            return;
+        }
+
+        self.annotations.commit();
+        self.annotate = annotate::Tool::next(self.annotate);
+        if self.annotate.is_some() {
+            self.crop = None;
+            self.measure = None;
+        }
+        self.title.set_mode(self.annotate.map(|tool| {
+            let name = format!("{:?}", tool).to_lowercase();
+            i18n::trf("annotate {}, Ctrl+Z: undo", &[&i18n::tr(&name)])
+        }));
+    }
+
+    fn annotate_mouse(&mut self, state: ButtonState) {
+        let (coords, img_size) = match self.single_img_size() {
+            Some(single) => single,
+            None => return,
+        };
+
+        if let Some(tool) = self.annotate {
+            if state == ButtonState::Press {
+                let pos = self.view.mouse_image_pos(coords, img_size);
+                self.annotations.begin(tool, pos);
+            } else {
+                self.annotations.end();
+            }
+        }
+    }
+
+    // Only skipping slides, nothing that writes files or the database.
+    fn kiosk_button(&mut self, b: ButtonArgs) {
+        if let (ButtonState::Press, Button::Keyboard(Key::Right | Key::Space)) = (b.state, b.button)
+        {
+            let mut kiosk = self.kiosk.take().expect("kiosk");
+            if let Some(i) = kiosk.skip(|i| self.is_slide(i)) {
+                self.show_slide(i);
+            }
+            self.kiosk = Some(kiosk);
+        }
+    }
+
+    // Edit the note of the image under the cursor, or discard the edit.
+    fn edit_note(&mut self) {
+        if self.note.take().is_some() {
+            return;
+        }
+        if let Some(image) = self.focused() {
+            let note = self.db.get_note(&image.file).unwrap_or_else(|e| {
+                error!("error loading the note of {:?}: {:?}", image.file, e);
+                None
+            });
+            self.note = Some(notes::Editor::new(
+                Arc::clone(&image.file),
+                note.unwrap_or_default(),
+            ));
+        }
+    }
+
+    // Keys go to the note being typed instead of the usual actions.
+    fn note_key(&mut self, key: Key) {
+        match key {
+            Key::Return => self.save_note(),
+            Key::Backspace => {
+                if let Some(note) = &mut self.note {
+                    note.backspace();
+                }
+            }
+            Key::E if self.ctrl_held => self.edit_note(),
+            _ => {}
+        }
+    }
+
+    // Open the command palette with the saved filter presets and some toggles, or close it.
+    fn toggle_palette(&mut self) {
+        if self.palette.take().is_some() {
+            return;
+        }
+        let mut actions = Vec::new();
+        let mut can_save = false;
+        if let Some(scan) = &self.scan {
+            match self.db.presets() {
+                Ok(presets) => actions.extend(
+                    presets
+                        .into_iter()
+                        .map(|(name, spec)| palette::Action::Preset { name, spec }),
+                ),
+                Err(e) => error!("error loading presets: {:?}", e),
+            }
+            if scan.filter().is_some() {
+                actions.push(palette::Action::ShowAll);
+                can_save = true;
+            }
+            actions.push(palette::Action::Rescan);
+        }
+        actions.extend([
+            palette::Action::NightMode,
+            palette::Action::Fullscreen,
+            palette::Action::Timeline,
+            palette::Action::Log,
+        ]);
+        self.palette = Some(palette::Palette::new(actions, can_save));
+    }
+
+    fn palette_key(&mut self, key: Key) {
+        let palette = match &mut self.palette {
+            Some(palette) => palette,
+            None => return,
+        };
+        match key {
+            Key::Return => {
+                if let Some(action) = self.palette.take().and_then(|palette| palette.take()) {
+                    self.run_action(action);
+                }
+            }
+            Key::Backspace => palette.backspace(),
+            Key::Up => palette.step(-1),
+            Key::Down => palette.step(1),
+            Key::F1 => self.toggle_palette(),
+            _ => {}
+        }
+    }
+
+    fn run_action(&mut self, action: palette::Action) {
+        match action {
+            palette::Action::Preset { name, spec } => match spec.parse() {
+                Ok(filter) => {
+                    self.set_filter(Some(filter));
+                    self.title.message(i18n::trf("filter {}", &[&name]));
+                }
+                Err(e) => error!("bad spec of preset {:?}: {}", name, e),
+            },
+            palette::Action::SavePreset(name) => {
+                let spec = match self.scan.as_ref().and_then(|scan| scan.filter()) {
+                    Some(filter) => filter.spec().to_owned(),
+                    None => return,
+                };
+                match self.db.set_preset(&name, &spec) {
+                    Ok(()) => self.title.message(i18n::trf("saved preset {}", &[&name])),
+                    Err(e) => error!("error saving preset {:?}: {:?}", name, e),
+                }
+            }
+            palette::Action::ShowAll => {
+                self.set_filter(None);
+                self.title
+                    .message(i18n::tr("showing all images").to_owned());
+            }
+            palette::Action::Rescan => {
+                self.title.message(i18n::tr("rescanning").to_owned());
+                self.rescan();
+            }
+            palette::Action::NightMode => self.toggle_night(),
+            palette::Action::Fullscreen => self.toggle_fullscreen(),
+            palette::Action::Timeline => self.toggle_timeline(),
+            palette::Action::Log => self.toggle_log(),
+        }
+    }
+
+    // Search again showing only the images matching `filter`.
+    fn set_filter(&mut self, filter: Option<filter::Filter>) {
+        match &mut self.scan {
+            Some(scan) if !scan.is_running() => {
+                scan.set_filter(filter);
+                scan.start(&self.db);
+            }
+            Some(_) => self
+                .title
+                .message(i18n::tr("still searching, try again later").to_owned()),
+            None => {}
+        }
+    }
+
+    fn save_note(&mut self) {
+        let note = match self.note.take() {
+            Some(note) => note,
+            None => return,
+        };
+        let text = note.text().trim();
+        match self.db.set_note(&note.file, text) {
+            Ok(()) if text.is_empty() => self.title.message(i18n::tr("note removed").to_owned()),
+            Ok(()) => self.title.message(i18n::tr("note saved").to_owned()),
+            Err(e) => error!("error saving the note of {:?}: {:?}", note.file, e),
+        }
+    }
+
+    fn type_key(&mut self, key: Key) {
+        match key {
+            Key::Return => self.annotations.commit(),
+            Key::Backspace => self.annotations.backspace(),
+            _ => {}
+        }
+    }
+
+    // Save the crop as a copy, or with Shift over the original.
+    fn save_crop(&mut self) {
+        let (coords, img_size) = match self.single_img_size() {
+            Some(single) => single,
+            None => return,
+        };
+
+        if self.read_only_files {
+            self.title
+                .message(i18n::tr("read-only files, crop not saved").to_owned());
+            return;
+        }
+
+        let rect = match self.crop.as_ref().and_then(|crop| crop.rect(img_size)) {
+            Some(rect) => rect,
+            None => return,
+        };
+
+        let path = self
+            .groups
+            .get(coords)
+            .expect("single image")
+            .file
+            .path
+            .clone();
+        let overwrite = self.shift_held;
+
+        let dest = match crop::save(&path, rect, overwrite) {
+            Ok(dest) => dest,
+            Err(e) => {
+                error!("unable to save crop of {:?}: {:?}", path, e);
+                return;
+            }
+        };
+
+        info!("Saved crop to {:?}", dest);
+        self.title.message(i18n::trf("saved {}", &[&dest]));
+
+        if overwrite {
+            match File::stat(&dest) {
+                Ok(file) => self.groups.refresh(coords, Arc::new(file)),
+                Err(e) => error!("unable to stat {:?}: {:?}", dest, e),
+            }
+            self.decoded.reload(&dest);
+            if let Some(crop) = &mut self.crop {
+                crop.clear();
+            }
+        }
+    }
+
+    // Thumbnail the focused image, or every image, again even if the file looks unchanged.
+    fn rethumb(&mut self, all: bool) {
+        let indices = if all {
+            0..self.groups.num_images()
+        } else {
+            match self.focused() {
+                Some(image) => image.i..image.i + 1,
+                None => return,
+            }
+        };
+
+        for i in indices {
+            let coords = self.groups.image_coords(i);
+            let file = match self.groups.get(coords) {
+                Some(image) => Arc::clone(&image.file),
+                None => continue,
+            };
+            if self.single_index() == Some(i) {
+                self.decoded.reload(&file.path);
+            }
+            self.groups.refresh(coords, file);
+        }
+    }
+
+    // Show the single image, and the next ones, as grayscale, a channel or a print proof.
+    fn set_proof(&mut self, proof: proof::Proof) {
+        let coords = match self.single {
+            Some((coords, _)) => coords,
+            None => return,
+        };
+        self.proof = proof;
+        self.groups.reload(coords);
+        self.title.message(i18n::tr(proof.name()).to_owned());
+    }
+
+    // Change the adjustments of the single image and remember them for next time.
+    fn adjust<F: FnOnce(&mut adjust::Adjustments)>(&mut self, f: F) {
+        let coords = match self.single {
+            Some((coords, _)) => coords,
+            None => return,
+        };
+
+        let old = self.adjustments.clone();
+        f(&mut self.adjustments);
+        self.adjustments.clamp();
+
+        // Exposure is applied when drawing, the rest when uploading tiles.
+        if old.tone() != self.adjustments.tone() {
+            self.groups.reload(coords);
+        }
+
+        self.title.message(self.adjustments.describe(i18n::tr));
+
+        let file = &self.groups.get(coords).expect("single image").file;
+        if let Err(e) = self.db.set_adjustments(file, &self.adjustments) {
+            error!("error saving adjustments for: {:?}: {:?}", file, e);
+        }
+    }
+
+    // The single image, or the image under the mouse cursor.
+    fn focused(&self) -> Option<&image::Image> {
+        let coords = match self.single.as_ref() {
+            Some((coords, _)) => *coords,
+            None => self.view.mouse_coords()?,
+        };
+        self.groups.get(coords)
+    }
+
+    fn info_lines(&self) -> Vec<String> {
+        let image = match self.focused() {
+            Some(image) => image,
+            None => return Vec::new(),
+        };
+        let mut lines = Self::image_lines(image);
+        match self.db.get_note(&image.file) {
+            Ok(Some(note)) => lines.push(i18n::trf("Note: {}", &[&note])),
+            Ok(None) => {}
+            Err(e) => error!("error loading the note of {:?}: {:?}", image.file, e),
+        }
+        lines
+    }
+
+    // Many metadata misses with stale entries mean something keeps changing the files' mtimes.
+    fn stats_lines() -> Vec<String> {
+        let ratio = |hits: u64, misses: u64| {
+            let total = hits + misses;
+            if total == 0 {
+                0.0
+            } else {
+                100.0 * hits as f64 / total as f64
+            }
+        };
+        let [meta_hits, meta_misses] = [
+            stats::counter("metadata_hits"),
+            stats::counter("metadata_misses"),
+        ];
+        let [tile_hits, tile_misses] = [stats::counter("tile_hits"), stats::counter("tile_misses")];
+        vec![
+            i18n::trf(
+                "Metadata: {} cached, {} missing ({}% hits), {} stale",
+                &[
+                    &meta_hits,
+                    &meta_misses,
+                    &format!("{:.0}", ratio(meta_hits, meta_misses)),
+                    &stats::counter("metadata_stale"),
+                ],
+            ),
+            i18n::trf(
+                "Tiles: {} loaded, {} missing ({}% hits)",
+                &[
+                    &tile_hits,
+                    &tile_misses,
+                    &format!("{:.0}", ratio(tile_hits, tile_misses)),
+                ],
+            ),
+            i18n::trf("Thumbnails made: {}", &[&stats::counter("thumbnails_made")]),
+        ]
+    }
+
+    fn image_lines(image: &image::Image) -> Vec<String> {
+        let mut lines = vec![
+            image.file.path.clone(),
+            i18n::trf("{} KiB", &[&(image.file.file_size / 1024)]),
+        ];
+
+        if let Some(metadata) = image.get_metadata() {
+            let [w, h] = metadata.img_size();
+            lines.push(format!("{} x {}", w, h));
+            if metadata.is_animated() {
+                lines.push(i18n::trf("{} frames", &[&metadata.page_count()]));
+            } else if metadata.page_count() > 1 {
+                lines.push(i18n::trf(
+                    "Page {} of {}",
+                    &[&(image.page + 1), &metadata.page_count()],
+                ));
+            }
+            for (name, value) in &metadata.info {
+                lines.push(format!("{}: {}", name, value));
+            }
+            if metadata.screenshot {
+                lines.push(i18n::tr("Screenshot").to_owned());
+            }
+        }
+
+        if image.user_data.rating > 0 {
+            lines.push(i18n::trf("Rating: {}", &[&image.user_data.rating]));
+        }
+        if let Some(label) = image.user_data.label {
+            let name = format!("{:?}", label);
+            lines.push(i18n::trf("Label: {}", &[&i18n::tr(&name)]));
+        }
+
+        lines
+    }
+
+    fn step_page(&mut self, delta: isize) {
+        if let Some((coords, _)) = self.single {
+            if let Some((page, count)) = self.groups.step_page(coords, delta) {
+                let path = &self.groups.get_mut(coords).expect("single image").file.path;
+                self.title
+                    .message(i18n::trf("{} [page {}/{}]", &[path, &(page + 1), &count]));
+            }
+        }
+    }
+
+    fn toggle_inspector(&mut self) {
+        if self.inspector.take().is_some() {
+            return;
+        }
+
+        if let Some(image) = self.focused() {
+            self.inspector = Some(inspector::Inspector::new(&image.file.path));
+            self.log_panel = None;
+        }
+    }
+
+    fn toggle_log(&mut self) {
+        if self.log_panel.take().is_some() {
+            return;
+        }
+        self.inspector = None;
+        self.log_panel = Some(errlog::Panel::new(self.panel_rows()));
+    }
+
+    // Scroll the inspector or log panel, returns false when neither is shown.
+    fn scroll_panel(&mut self, delta: isize) -> bool {
+        match (&mut self.inspector, &mut self.log_panel) {
+            (Some(inspector), _) => inspector.scroll_by(delta),
+            (None, Some(panel)) => panel.scroll_by(delta),
+            (None, None) => return false,
+        }
+        true
+    }
+
+    // Copy the focused image's path, or with Shift its pixels.
+    fn copy(&mut self) {
+        let path = match self.focused() {
+            Some(image) => image.file.path.clone(),
+            None => return,
+        };
+
+        if !self.shift_held {
+            self.clipboard.set_text(path);
+            return;
+        }
+
+        // Already decoded for the single image view, else decoded on a thread as originals can
+        // take seconds.
+        if let Some(decoded) = self.decoded.get_path(&path) {
+            self.clipboard.set_image(decoded.image.clone());
+            return;
+        }
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        std::thread::spawn(move || match Thumbnailer::open(&path) {
+            Ok((image, _)) => {
+                let _ = tx.send(image.into_rgba8());
+            }
+            Err(e) => error!("unable to copy {:?}: {:?}", path, e),
+        });
+        self.copying = Some(rx);
+    }
+
+    fn recv_copy(&mut self) {
+        let rx = match &self.copying {
+            Some(rx) => rx,
+            None => return,
+        };
+        match rx.try_recv() {
+            Ok(image) => self.clipboard.set_image(image),
+            Err(crossbeam_channel::TryRecvError::Empty) => return,
+            Err(crossbeam_channel::TryRecvError::Disconnected) => {}
+        }
+        self.copying = None;
+    }
+
+    // Number of inspector or log lines that fit in the window.
+    fn panel_rows(&self) -> usize {
+        self.overlay.rows(self.window.size().height)
+    }
+
+    // Edit the user data of the image under the mouse cursor.
+    fn edit_user_data<F: FnOnce(&mut UserData)>(&mut self, f: F) {
+        let coords = match self.view.mouse_coords() {
+            Some(coords) => coords,
+            None => return,
+        };
+
+        if let Some(image) = self.groups.get_mut(coords) {
+            f(&mut image.user_data);
+            if let Err(e) = self.db.set_user_data(&image.file, &image.user_data) {
+                error!("error saving user data for: {:?}: {:?}", image.file, e);
+            }
+        }
+    }
+
+    fn button(&mut self, b: ButtonArgs) {
+        if self.kiosk.is_some() {
+            self.kiosk_button(b);
+            return;
+        }
+
+        if let (ButtonState::Press, Button::Keyboard(key)) = (b.state, b.button) {
+            let modifier = matches!(key, Key::LShift | Key::RShift | Key::LCtrl | Key::RCtrl);
+            if self.palette.is_some() && !modifier {
+                self.palette_key(key);
+                return;
+            }
+            if self.note.is_some() && !modifier {
+                self.note_key(key);
+                return;
+            }
+            if self.annotations.is_typing() && !modifier {
+                self.type_key(key);
+                return;
+            }
+        }
+
+        match (b.state, b.button) {
+            (ButtonState::Press, Button::Keyboard(Key::Z)) if self.ctrl_held => {
+                self.annotations.undo();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Z)) => {
+                self.reset();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Q)) => {
+                let turns = if self.shift_held { -1 } else { 1 };
+                self.orient(|orientation| orientation.rotate(turns));
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::X)) => {
+                self.orient(|orientation| orientation.flip_h());
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Y)) => {
+                self.orient(|orientation| orientation.flip_v());
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F)) => {
+                self.cycle_fit();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Return)) => {
+                self.toggle_single();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::LeftBracket)) => {
+                self.step_page(-1);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::RightBracket)) => {
+                self.step_page(1);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::E)) if self.ctrl_held => {
+                self.edit_note();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::G)) if self.ctrl_held => {
+                self.set_proof(self.proof.next_channel());
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::K)) if self.ctrl_held => {
+                self.set_proof(self.proof.toggle_print());
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::E)) => {
+                let sign = if self.shift_held { -1.0 } else { 1.0 };
+                self.adjust(|adjustments| adjustments.exposure += sign / 3.0);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::B)) => {
+                let sign = if self.shift_held { -1.0 } else { 1.0 };
+                self.adjust(|adjustments| adjustments.brightness += sign * 0.05);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::O)) => {
+                let sign = if self.shift_held { -1.0 } else { 1.0 };
+                self.adjust(|adjustments| adjustments.contrast += sign * 0.1);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::G)) => {
+                let sign = if self.shift_held { -1.0 } else { 1.0 };
+                self.adjust(|adjustments| adjustments.gamma += sign * 0.1);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::U)) => {
+                let sign = if self.shift_held { -1.0 } else { 1.0 };
+                self.adjust(|adjustments| adjustments.saturation += sign * 0.1);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Backspace)) => {
+                self.adjust(|adjustments| *adjustments = Default::default());
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::I)) => {
+                self.show_info = !self.show_info;
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::R)) => {
+                if let Some(image) = self.focused() {
+                    system::reveal(&image.file.path);
+                }
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::H)) => {
+                self.show_histogram = !self.show_histogram;
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::L)) if self.single.is_some() => {
+                self.guide = guides::Guide::next(self.guide);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::L)) => {
+                self.toggle_log();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::N)) if self.ctrl_held => {
+                self.toggle_night();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::N)) if self.dupes.is_some() => {
+                self.step_dupes(if self.shift_held { -1 } else { 1 });
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::K)) if self.dupes.is_some() => {
+                self.keep_dupe();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::W)) => {
+                self.cycle_annotate();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::D)) => {
+                self.cycle_measure();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::P)) if self.ctrl_held => {
+                self.toggle_pause(self.shift_held);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::P)) => {
+                self.show_loupe = !self.show_loupe;
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::V)) if self.ctrl_held => {
+                self.paste();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::V)) => {
+                self.filmstrip = match self.filmstrip {
+                    Some(_) => None,
+                    None => Some(filmstrip::Filmstrip::default()),
+                };
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F1)) => {
+                self.toggle_palette();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F3)) => {
+                self.show_stats = !self.show_stats;
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F5)) if self.shift_held => {
+                self.rethumb(self.ctrl_held);
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F5)) => {
+                self.title.message(i18n::tr("rescanning").to_owned());
+                self.rescan();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F11)) => {
+                self.toggle_fullscreen();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::F12)) => {
+                self.snapshot = true;
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Tab)) if self.single.is_none() => {
+                self.toggle_split();
+            }
+
+            // Only searched directories, not diffs and duplicates.
+            (ButtonState::Press, Button::Keyboard(Key::J))
+                if self.single.is_none() && self.scan.is_some() =>
+            {
+                self.toggle_stacks();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Space)) if self.single.is_none() => {
+                self.hover.get_or_insert_with(Default::default);
+            }
+
+            (ButtonState::Release, Button::Keyboard(Key::Space)) => {
+                self.hover = None;
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::M)) => {
+                self.toggle_inspector();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::C)) if self.ctrl_held => {
+                match (&self.inspector, &self.log_panel) {
+                    (Some(inspector), _) => self.clipboard.set_text(inspector.dump()),
+                    (None, Some(panel)) => self.clipboard.set_text(panel.dump()),
+                    (None, None) => self.copy(),
+                }
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::C)) => {
+                self.toggle_crop();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::A)) => {
+                if let Some(crop) = &mut self.crop {
+                    crop.aspect = crop.aspect.next();
+                    self.crop_title();
+                }
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::S)) => {
+                self.save_crop();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::T))
+                if self.ctrl_held && self.single.is_none() =>
+            {
+                self.toggle_timeline();
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::T)) => {
+                self.cursor_captured = !self.cursor_captured;
+                self.window.set_capture_cursor(self.cursor_captured);
+                self.panning = self.cursor_captured;
+                self.view.center_mouse();
+            }
+
+            (
+                ButtonState::Press,
+                Button::Keyboard(key @ (Key::D1 | Key::D2 | Key::D3 | Key::D4 | Key::D5)),
+            ) => {
+                let rating = (key as u32 - Key::D0 as u32) as u8;
+                self.edit_user_data(|user_data| user_data.toggle_rating(rating));
+            }
+
+            (
+                ButtonState::Press,
+                Button::Keyboard(key @ (Key::D6 | Key::D7 | Key::D8 | Key::D9 | Key::D0)),
+            ) => {
+                let label = match key {
+                    Key::D6 => Label::Red,
+                    Key::D7 => Label::Yellow,
+                    Key::D8 => Label::Green,
+                    Key::D9 => Label::Blue,
+                    _ => Label::Purple,
+                };
+                self.edit_user_data(|user_data| user_data.toggle_label(label));
+            }
+
+            (ButtonState::Press, Button::Keyboard(key @ (Key::Up | Key::Down)))
+                if self.inspector.is_some() || self.log_panel.is_some() =>
+            {
+                self.scroll_panel(if key == Key::Up { -1 } else { 1 });
+            }
+
+            (ButtonState::Press, Button::Keyboard(key @ (Key::PageUp | Key::PageDown)))
+                if self.inspector.is_some() || self.log_panel.is_some() =>
+            {
+                let rows = self.panel_rows() as isize - 1;
+                self.scroll_panel(if key == Key::PageUp { -rows } else { rows });
+            }
+
+            (ButtonState::Press, Button::Keyboard(Key::Up)) => {
                 self.trans([0.0, self.shift_increment()]);
             }
 
@@ -486,6 +2285,25 @@ impl App {
                 self.trans([0.0, -self.shift_increment()]);
             }
 
+            (ButtonState::Press, Button::Keyboard(key @ (Key::Left | Key::Right)))
+                if self.single.is_some() =>
+            {
+                if let Some(i) = self.single_index() {
+                    let n = self.groups.num_images();
+                    // Compared sets step a set at a time.
+                    let step = self.cells().len().max(1);
+                    let i = match key {
+                        Key::Left if self.wrap => Some((i + n - step) % n),
+                        Key::Left => i.checked_sub(step),
+                        _ if self.wrap => Some((i + step) % n),
+                        _ => Some(i + step).filter(|&i| i < n),
+                    };
+                    if let Some(i) = i {
+                        self.jump_single(i);
+                    }
+                }
+            }
+
             (ButtonState::Press, Button::Keyboard(Key::Left)) => {
                 self.trans([self.shift_increment(), 0.0]);
             }
@@ -494,6 +2312,13 @@ impl App {
                 self.trans([-self.shift_increment(), 0.0]);
             }
 
+            (ButtonState::Press, Button::Keyboard(key @ (Key::PageUp | Key::PageDown)))
+                if self.view.is_locked() =>
+            {
+                let page = self.view.win_size()[1] - self.view.zoom;
+                self.trans([0.0, if key == Key::PageUp { page } else { -page }]);
+            }
+
             (ButtonState::Press, Button::Keyboard(Key::PageUp)) => {
                 self.view.center_mouse();
                 self.zoom(1.0 - self.zoom_increment());
@@ -508,10 +2333,44 @@ impl App {
                 self.shift_held = state == ButtonState::Press;
             }
 
+            (state, Button::Keyboard(Key::LCtrl)) | (state, Button::Keyboard(Key::RCtrl)) => {
+                self.ctrl_held = state == ButtonState::Press;
+            }
+
             (state, Button::Mouse(MouseButton::Middle)) => {
                 self.panning = state == ButtonState::Press;
             }
 
+            (state, Button::Mouse(MouseButton::Left))
+                if self.over_timeline() || self.timeline.as_ref().is_some_and(|t| t.dragging) =>
+            {
+                self.timeline_mouse(state);
+            }
+
+            (ButtonState::Press, Button::Mouse(MouseButton::Left))
+                if self.stack_under_mouse().is_some() =>
+            {
+                self.expand_stack();
+            }
+
+            (ButtonState::Press, Button::Mouse(MouseButton::Left)) if self.over_filmstrip() => {
+                if let Some(i) = self.filmstrip_hit() {
+                    self.jump_single(i);
+                }
+            }
+
+            (state, Button::Mouse(MouseButton::Left)) if self.crop.is_some() => {
+                self.crop_mouse(state);
+            }
+
+            (state, Button::Mouse(MouseButton::Left)) if self.measure.is_some() => {
+                self.measure_mouse(state);
+            }
+
+            (state, Button::Mouse(MouseButton::Left)) if self.annotate.is_some() => {
+                self.annotate_mouse(state);
+            }
+
             (state, Button::Mouse(MouseButton::Left)) => {
                 self.zooming = (state == ButtonState::Press).as_some(5.0);
             }
@@ -524,8 +2383,23 @@ impl App {
         }
     }
 
-    fn draw_2d(e: &Event, c: Context, g: &mut G2d, view: &view::View, groups: &Groups) {
-        clear([0.0, 0.0, 0.0, 1.0], g);
+    fn draw_2d(
+        e: &Event,
+        c: Context,
+        g: &mut G2d,
+        view: &view::View,
+        groups: &Groups,
+        cells: &[(Vector2<u32>, [f64; 4])],
+        adjustments: &adjust::Adjustments,
+    ) {
+        clear(
+            if mono::enabled() {
+                mono::BACKGROUND
+            } else {
+                [0.0, 0.0, 0.0, 1.0]
+            },
+            g,
+        );
 
         let args = e.render_args().expect("render args");
         let draw_state = DrawState::default().scissor([0, 0, args.draw_size[0], args.draw_size[1]]);
@@ -534,148 +2408,833 @@ impl App {
         let _missing_color = color::hex("888888");
         let _op_color = color::hex("222222");
 
-        groups.draw(c.transform, view, &draw_state, g);
-    }
-
-    fn run(&mut self) {
-        loop {
-            if let Some(e) = self.window.next() {
-                e.update(|args| {
-                    self.update(*args);
-                });
-
-                e.resize(|args| {
-                    self.resize(args.draw_size);
-                });
-
-                e.mouse_scroll(|[_, v]| {
-                    self.mouse_zoom(v);
-                });
-
-                e.mouse_cursor(|loc| {
-                    self.mouse_move(loc);
-                });
-
-                e.mouse_relative(|delta| {
-                    self.mouse_pan(delta);
-                });
-
-                e.button(|b| self.button(b));
-
-                // borrowck
-                let v = &self.view;
-                let groups = &self.groups;
-                self.window.draw_2d(&e, |c, g, _device| {
-                    Self::draw_2d(&e, c, g, v, groups);
-                });
-            } else {
-                break;
+        if !cells.is_empty() {
+            // The single image, or each image of its compared set in a cell over the same square.
+            for &(coords, [x, y, w, h]) in cells {
+                let transform = view
+                    .orientation
+                    .transform(view.center(coords))
+                    .then(Transform2D::translate([x, y]))
+                    .then(c.transform.into());
+                let draw_state = draw_state.scissor([x, y, w, h].map(|v| v as u32));
+                groups.draw_single(coords, transform, view, adjustments, &draw_state, g);
+            }
+        } else {
+            for tile in view.tiles() {
+                groups.draw(c.transform.into(), &tile, &draw_state, g);
             }
         }
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
-pub struct File {
-    path: String,
-    modified: u64,
-    file_size: u64,
-}
+    // Borderless on the current monitor, which every platform supports without switching the
+    // video mode. The window keeps its event loop, unlike building it again.
+    fn toggle_fullscreen(&mut self) {
+        let window = &self.window.window.window;
+        let fullscreen = window.fullscreen().is_none();
+        window.set_fullscreen(fullscreen.then_some(winit::window::Fullscreen::Borderless(None)));
+    }
 
-fn find_images(dirs: Vec<PathBuf>) -> Vec<Arc<File>> {
-    let mut ret = Vec::new();
+    // Save what's open and finish the database writes before exiting.
+    fn shutdown(&mut self) {
+        info!("Shutting down");
+        if let Some(grid_view) = self.leave_single() {
+            self.view = grid_view;
+        }
+        // Closed before any thumbnail showed.
+        if self.profile_startup {
+            self.profile_startup = false;
+            stats::print_startup();
+        }
+        // Decoders waiting for the GPU resize on the CPU instead.
+        self.gpu = None;
+        self.thumbnailer.shutdown();
+        if let Err(e) = self.db.flush() {
+            error!("flushing the database: {:?}", e);
+        }
+    }
 
-    for dir in dirs {
-        for entry in walkdir::WalkDir::new(&dir) {
-            let i = ret.len();
-            if i > 0 && i % 1000 == 0 {
-                info!("Found {} images...", i);
+    fn run(&mut self) {
+        while let Some(e) = self.window.next() {
+            // Input and window events may change anything on screen.
+            if e.update_args().is_none()
+                && e.render_args().is_none()
+                && e.after_render_args().is_none()
+                && e.idle_args().is_none()
+            {
+                pacing::changed();
+                self.governor.input(std::time::Instant::now());
             }
 
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(e) => {
-                    error!("Walkdir error: {:?}", e);
-                    continue;
-                }
-            };
+            e.update(|args| {
+                self.update(*args);
+            });
 
-            let metadata = match entry.metadata() {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    error!("Metadata lookup error: {:?}: {:?}", entry, e);
-                    continue;
-                }
-            };
+            e.resize(|args| {
+                self.resize(args.draw_size);
+            });
 
-            if metadata.is_dir() {
-                info!("Searching in {:?}", entry.path());
-                continue;
-            }
+            e.mouse_scroll(|[_, v]| {
+                if self.scroll_panel(-3 * v.signum() as isize) {
+                    return;
+                }
+                let over_filmstrip = self.over_filmstrip();
+                match &mut self.filmstrip {
+                    Some(filmstrip) if over_filmstrip => filmstrip.scroll_by(-v.signum() as isize),
+                    _ => self.mouse_zoom(v),
+                }
+            });
 
-            let file_size = metadata.len();
+            e.mouse_cursor(|loc| {
+                self.mouse_move(loc);
+            });
 
-            let modified: u64 = metadata
-                .modified()
-                .expect("metadata modified")
-                .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                .expect("duration since unix epoch")
-                .as_secs();
+            e.mouse_relative(|delta| {
+                self.mouse_pan(delta);
+            });
 
-            let path = entry.path();
+            e.button(|b| self.button(b));
 
-            let path = match path.canonicalize() {
-                Ok(path) => path,
-                Err(e) => {
-                    error!("unable to canonicalize: {:?} {:?}", path, e);
-                    continue;
+            e.text(|text| {
+                if let Some(palette) = &mut self.palette {
+                    palette.type_text(text);
+                } else if let Some(note) = &mut self.note {
+                    // Shortcuts arrive as text on some platforms.
+                    if !self.ctrl_held {
+                        note.type_text(text);
+                    }
+                } else if self.annotations.is_typing() {
+                    self.annotations.type_text(text);
                 }
-            };
+            });
 
-            let path = if let Some(path) = path.to_str() {
-                path.to_owned()
-            } else {
-                error!("Skipping non-utf8 path: {:?}", path);
+            // Nothing changed since the last frame, which stays on screen.
+            let now = std::time::Instant::now();
+            if e.render_args().is_none() || !(self.pacing.due(now) || self.show_stats) {
                 continue;
-            };
+            }
 
-            let file = File {
-                path,
-                modified,
-                file_size,
+            // borrowck
+            let v = &self.view;
+            let groups = &self.groups;
+            let single = self.single.as_ref().map(|(coords, _)| *coords);
+            let cells = self.cells();
+            let origin = self.cell_origin();
+            let adjustments = &self.adjustments;
+            let mut info_lines = if let Some(palette) = &self.palette {
+                palette.lines(self.panel_rows())
+            } else if let Some(note) = &self.note {
+                note.lines()
+            } else if let Some(inspector) = &self.inspector {
+                inspector.lines(self.panel_rows())
+            } else if let Some(panel) = &self.log_panel {
+                let rows = self.panel_rows();
+                let [count, first, last] = panel.range(rows);
+                let mut lines = vec![i18n::trf(
+                    "Log ({} warnings and errors, {}-{}, Ctrl+C to copy)",
+                    &[&count, &first, &last],
+                )];
+                lines.extend(panel.lines(rows));
+                lines
+            } else if self.show_info {
+                self.info_lines()
+            } else if self.dupes.is_some() {
+                self.dupe_lines()
+            } else {
+                Vec::new()
+            };
+            if self.show_stats {
+                info_lines.extend(Self::stats_lines());
+            }
+            let decoded = single.and(self.decoded.get());
+            let histogram = decoded
+                .filter(|_| self.show_histogram)
+                .map(|decoded| &decoded.histogram);
+            let loupe = match (single, decoded) {
+                (Some(coords), Some(decoded)) if self.show_loupe => {
+                    let img_size = [decoded.image.width(), decoded.image.height()];
+                    v.mouse_pixel(coords, img_size)
+                        .map(|pixel| (&decoded.image, pixel, vec2_add(v.mouse(), origin)))
+                }
+                _ => None,
+            };
+            let guide = match (self.guide, self.single_img_size()) {
+                (Some(guide), Some((coords, img_size))) => {
+                    Some((guide, img_size, v.image_trans(coords, img_size)))
+                }
+                _ => None,
+            };
+            let pixel_grid = self.pixel_grid;
+            let annotations = self
+                .single_img_size()
+                .map(|(coords, img_size)| (&self.annotations, v.image_trans(coords, img_size)));
+            let measure = match (&self.measure, self.single_img_size()) {
+                (Some(measure), Some((coords, img_size))) => {
+                    Some((measure, v.image_trans(coords, img_size)))
+                }
+                _ => None,
+            };
+            let crop = match (&self.crop, self.single_img_size()) {
+                (Some(crop), Some((coords, img_size))) => {
+                    Some((crop, img_size, v.image_trans(coords, img_size)))
+                }
+                _ => None,
+            };
+            let cluster_labels = match single {
+                Some(_) => Vec::new(),
+                None => groups.cluster_labels(v),
             };
+            let stack_badges = match (single, &self.stacks) {
+                (None, Some(_)) => groups.stack_badges(v),
+                _ => Vec::new(),
+            };
+            let preview = self.preview.as_ref().map(|preview| {
+                let lines = preview
+                    .coords()
+                    .and_then(|coords| groups.get(coords))
+                    .map(Self::image_lines)
+                    .unwrap_or_default();
+                (preview, lines)
+            });
+            let filmstrip = match (&self.filmstrip, self.single_index()) {
+                (Some(filmstrip), Some(current)) => {
+                    let win_size = vec2_f64(self.win_size);
+                    let cells = filmstrip.cells(current, groups.num_images(), win_size);
+                    Some((filmstrip.rect(win_size), cells, current))
+                }
+                _ => None,
+            };
+            let timeline = match (&self.timeline, single) {
+                (Some(timeline), None) => {
+                    let win_size = v.win_size();
+                    let label = match self.over_timeline() {
+                        true => timeline
+                            .time_at(win_size[0], v.mouse()[0])
+                            .map(filter::date_from_secs),
+                        false => timeline.describe(),
+                    };
+                    Some((timeline, win_size, label))
+                }
+                _ => None,
+            };
+            let hover = self.hover.as_ref().map(|hover| (hover, v.mouse()));
+            #[cfg(feature = "video")]
+            let video = &self.video;
+            let outline = match v.mouse_coords() {
+                Some(coords) if self.access.outline && single.is_none() => {
+                    groups.get(coords).map(|_| (v.trans(coords), v.zoom))
+                }
+                _ => None,
+            };
+            // Tools over the single image turn with it, in its cell when compared.
+            let oriented = single.map(|coords| {
+                v.orientation
+                    .transform(v.center(coords))
+                    .then(Transform2D::translate(origin))
+            });
+            let overlay = &mut self.overlay;
+            self.window.draw_2d(&e, |c, g, device| {
+                let _s = stats::ScopedDuration::new("draw");
+                Self::draw_2d(&e, c, g, v, groups, &cells, adjustments);
+                #[cfg(feature = "video")]
+                video.draw(v, std::time::Instant::now(), &c, g);
+                if let Some((pos, size)) = outline {
+                    access::draw_outline(pos, size, overlay.scale(), &c, g);
+                }
+                let ci = match oriented {
+                    Some(transform) => Context {
+                        transform: transform.then(c.transform.into()).matrix(),
+                        ..c
+                    },
+                    None => c,
+                };
+                let to_window = |pos| match oriented {
+                    Some(transform) => transform.apply(pos),
+                    None => pos,
+                };
+                for (pos, label) in cluster_labels {
+                    overlay.draw_lines_at(&[label], pos, &c, g);
+                }
+                for ([x, y], frames) in stack_badges {
+                    let inset = v.zoom * 0.1;
+                    overlay.draw_lines_at(&[frames.to_string()], [x + inset, y + inset], &c, g);
+                }
+                if let Some((timeline, win_size, label)) = timeline {
+                    timeline.draw(win_size, &c, g);
+                    let [x, y, _, _] = timeline.rect(win_size);
+                    overlay.draw_lines_at(&Vec::from_iter(label), [x, y], &c, g);
+                }
+                if let Some((preview, lines)) = preview {
+                    let pos = preview.draw(&c, g);
+                    overlay.draw_lines_at(&lines, pos, &c, g);
+                }
+                if let Some((rect, cells, current)) = filmstrip {
+                    rectangle([0.0, 0.0, 0.0, 0.8], rect, c.transform, g);
+                    for (i, cell) in cells {
+                        groups.draw_thumb(i, cell, c.transform.into(), &c.draw_state, g);
+                        if i == current {
+                            Rectangle::new_border([1.0, 1.0, 1.0, 1.0], 1.0).draw(
+                                cell,
+                                &c.draw_state,
+                                c.transform,
+                                g,
+                            );
+                        }
+                    }
+                }
+                if let Some(histogram) = histogram {
+                    histogram.draw(overlay.scale(), &c, g);
+                }
+                if let Some((guide, img_size, (origin, scale))) = guide {
+                    guide.draw(img_size, pixel_grid, origin, scale, &ci, g);
+                }
+                if let Some((crop, img_size, (origin, scale))) = crop {
+                    crop.draw(img_size, origin, scale, &ci, g);
+                }
+                if let Some((annotations, (origin, scale))) = annotations {
+                    for (pos, note) in annotations.draw(origin, scale, &ci, g) {
+                        overlay.draw_lines_at(&[note], to_window(pos), &c, g);
+                    }
+                }
+                if let Some((measure, (origin, scale))) = measure {
+                    measure.draw(origin, scale, &ci, g);
+                    if let Some(text) = measure.describe() {
+                        let [x, y] = to_window(measure.end_pos(origin, scale));
+                        overlay.draw_lines_at(&[text], [x + 12.0, y + 12.0], &c, g);
+                    }
+                }
+                if let Some((image, pixel, mouse)) = loupe {
+                    let scale = overlay.scale();
+                    let pos = loupe::pos(&c, mouse, scale);
+                    loupe::draw(image, pixel, pos, scale, &c, g);
+                    let lines = [loupe::describe(image, pixel)];
+                    let below = [pos[0], pos[1] + loupe::size(scale)];
+                    overlay.draw_lines_at(&lines, below, &c, g);
+                }
+                if let Some((hover, mouse)) = hover {
+                    hover.draw(mouse, &c, g);
+                }
+                overlay.draw_lines(&info_lines, &c, g);
+                overlay.flush(device);
+            });
+
+            // The frame is flushed but not yet swapped.
+            if let Some(args) = e.render_args().filter(|_| self.snapshot) {
+                self.snapshot = false;
+                let image = snapshot::capture(&mut self.window.device, args.draw_size);
+                match snapshot::save(&image) {
+                    Ok(path) => self.title.message(i18n::trf("saved {}", &[&path])),
+                    Err(e) => error!("snapshot: {:?}", e),
+                }
+            }
+            Window::swap_buffers(&mut self.window);
+            self.governor.frame(now);
 
-            ret.push(Arc::new(file));
+            stats::stage("startup_first_frame");
+            if self.profile_startup && stats::stage_done("startup_first_thumbs") {
+                self.profile_startup = false;
+                stats::print_startup();
+            }
         }
     }
+}
+
+// Find the images under `paths` with their user data and metadata, in grid order. With
+// `verify_content` thumbnails of files whose contents changed are made again.
+fn load_images(
+    db: &database::Database,
+    paths: Vec<PathBuf>,
+    filter: Option<&filter::Filter>,
+    verify_content: bool,
+) -> Vec<image::Image> {
+    images_of(scan::load(db, find_images(paths), filter, verify_content))
+}
 
-    ret.sort();
-    ret
+// The images of files found by a scan.
+fn images_of(found: Vec<scan::Found>) -> Vec<image::Image> {
+    found
+        .into_par_iter()
+        .enumerate()
+        .map(|(i, (file, user_data, metadata))| image::Image::from(i, file, metadata, user_data))
+        .collect()
 }
 
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Time scanning, reading, decoding, resizing and encoding, and database writes over a
+    /// sample of images, then exit. Thumbnails go to a throwaway database.
+    Bench {
+        dir: PathBuf,
+
+        /// Number of images to thumbnail.
+        #[arg(long, value_name = "COUNT", default_value_t = 100)]
+        sample: usize,
+    },
+
+    /// Show the images of two to four directory trees side by side, lined up by relative path,
+    /// with changed files framed orange and files missing on a side red. Single images are shown
+    /// next to the others of their row at the same zoom and pan.
+    Diff {
+        #[arg(num_args = 2..=4, required = true)]
+        dirs: Vec<PathBuf>,
+
+        /// Line files up by their contents instead, e.g. after renaming. Rows at different paths
+        /// are framed orange.
+        #[arg(long)]
+        by_content: bool,
+    },
+
+    /// Show only images with identical contents, set after set, to keep one of each and move
+    /// the others to the trash.
+    Dupes {
+        #[arg(default_value = ".")]
+        dirs: Vec<PathBuf>,
+    },
+
+    /// Turn images upright the way their EXIF orientation says and reset it, for software that
+    /// ignores it, then thumbnail them again. JPEGs are turned losslessly with jpegtran.
+    Autorotate {
+        #[arg(default_value = ".")]
+        dirs: Vec<PathBuf>,
+
+        /// Only turn matching images, e.g. "label:red" for the ones marked while culling.
+        #[arg(long, value_name = "SPEC")]
+        filter: Option<filter::Filter>,
+
+        /// List the images that would be turned and their orientation without changing them.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Drop the few edge pixels of JPEGs whose size doesn't fit jpegtran's 8 or 16 pixel
+        /// blocks, which can't be turned losslessly otherwise.
+        #[arg(long)]
+        trim: bool,
+
+        /// Encode PNGs and single-page TIFFs again, dropping their ICC profile and metadata.
+        /// Multi-page TIFFs are never turned.
+        #[arg(long)]
+        reencode: bool,
+    },
+
+    /// Copy images to OUT for sharing, e.g. the picks of a culling session with --filter.
+    Export {
+        out: PathBuf,
+
+        #[arg(default_value = ".")]
+        dirs: Vec<PathBuf>,
+
+        /// Only copy matching images, e.g. "rating:4".
+        #[arg(long, value_name = "SPEC")]
+        filter: Option<filter::Filter>,
+
+        /// Leave out EXIF including GPS positions, XMP, IPTC and comments. JPEGs are copied
+        /// without their metadata segments, other formats are encoded again.
+        #[arg(long)]
+        strip_metadata: bool,
+
+        /// Scale images down to fit this many pixels on their long edge, which also strips the
+        /// metadata.
+        #[arg(long, value_name = "PIXELS")]
+        max_size: Option<u32>,
+
+        /// Burn translucent text across the middle of the images, e.g. "PROOF".
+        #[arg(long, value_name = "TEXT")]
+        watermark: Option<String>,
+
+        /// Burn a translucent logo across the middle of the images, e.g. a PNG with
+        /// transparency.
+        #[arg(long, value_name = "PATH")]
+        watermark_image: Option<PathBuf>,
+
+        /// Burn a caption along the bottom of the images: "name" for the file name or an EXIF
+        /// field, e.g. ImageDescription or Artist.
+        #[arg(long, value_name = "SOURCE")]
+        caption: Option<watermark::Caption>,
+    },
+
+    /// Hash every image under the directories and compare to the hashes stored by earlier runs
+    /// or --checksum. Prints files whose contents changed although their modification time and
+    /// size didn't, and exits with status 1 if there are any.
+    Verify {
+        #[arg(default_value = ".")]
+        dirs: Vec<PathBuf>,
+    },
+
+    /// Move the thumbnails of a directory between databases.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Back up ratings, labels, notes and adjustments to a JSON file, or restore them, e.g. on
+    /// another machine.
+    Meta {
+        #[command(subcommand)]
+        command: MetaCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MetaCommand {
+    /// Write the ratings, labels, notes and adjustments of the images under DIR to a JSON file.
+    Export {
+        json: PathBuf,
+
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+
+    /// Apply a JSON file written by export to the images now under DIR, found by their path
+    /// relative to DIR.
+    Import {
+        json: PathBuf,
+
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Find images by their contents instead, e.g. after renaming. Every copy is changed.
+        #[arg(long)]
+        by_content: bool,
+
+        /// Combine with what's here instead of replacing it, e.g. another curator's export. Each
+        /// field is taken from the side that changed it last, removals included, and fields both
+        /// sides set differently are printed as conflicts.
+        #[arg(long)]
+        merge: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Write the thumbnails of the images under DIR to a tar file.
+    Export {
+        tar: PathBuf,
+
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+
+    /// Read thumbnails written by export for the images now under DIR.
+    Import {
+        tar: PathBuf,
+
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+}
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Set number of background thumbnailer threads.
     #[arg(long, value_name = "COUNT")]
     threads: Option<usize>,
 
-    /// Set database path.
+    /// Stack bursts of sequentially named images taken within a second of each other into one
+    /// grid square with a count badge, click a stack to expand it. J toggles this.
+    #[arg(long)]
+    stack_bursts: bool,
+
+    /// Keep every thumbnailer thread working while panning and zooming, instead of pausing
+    /// some when frames come late.
+    #[arg(long)]
+    fixed_threads: bool,
+
+    /// Limit how fast thumbnailing reads source files, in MB/s, to keep the disk responsive.
+    #[arg(long, value_name = "MB_PER_SEC", value_parser = positive_rate)]
+    io_limit: Option<f64>,
+
+    /// Read source files for thumbnailing at idle disk priority, so they only get the disk when
+    /// nothing else uses it. Linux only.
+    #[arg(long)]
+    io_nice: bool,
+
+    /// Seconds before a hanging decoder is abandoned and the file counted as failed, converters
+    /// and multi-page formats get four times as long. 0 waits forever.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    decode_timeout: u64,
+
+    /// JPEGs larger than this many pixels on the long edge are decoded at 1/2, 1/4 or 1/8
+    /// scale, keeping at least this size for the largest thumbnail. 0 always decodes in full.
+    /// Only affects new thumbnails.
+    #[arg(long, value_name = "PIXELS", default_value_t = 8192)]
+    max_decode: u32,
+
+    /// Memory in MB the images being thumbnailed may take together, large images wait for
+    /// others to finish instead of decoding at the same time.
+    #[arg(long, value_name = "MB")]
+    thumb_memory: Option<u64>,
+
+    /// Resize large thumbnails on the GPU, which is faster for huge images on most systems.
+    /// Only affects new thumbnails.
+    #[arg(long)]
+    gpu_resize: bool,
+
+    /// Tone mapping for HDR images: clamp, reinhard or aces. Only affects new thumbnails.
+    #[arg(long, value_name = "OPERATOR", default_value = "reinhard")]
+    tone_map: tonemap::ToneMap,
+
+    /// Reduce 16-bit images with: truncate, dither or scale (stretch to the used range). Only
+    /// affects new thumbnails.
+    #[arg(long, value_name = "MODE", default_value = "dither")]
+    depth: depth::Depth,
+
+    /// Font for text overlays and export watermarks, defaults to a common system font.
+    #[arg(long, value_name = "PATH", global = true)]
+    font: Option<PathBuf>,
+
+    /// Frame the image under the cursor with a thick yellow on black outline.
+    #[arg(long)]
+    focus_outline: bool,
+
+    /// Keep animated images on their first frame.
+    #[arg(long)]
+    reduced_motion: bool,
+
+    /// Print the path of each image the cursor moves onto to stdout, for screen readers.
+    #[arg(long)]
+    announce: bool,
+
+    /// Show thumbnails in dithered grayscale on white, with framed placeholders and still
+    /// animations, for e-ink monitors and very low-power displays.
+    #[arg(long)]
+    monochrome: bool,
+
+    /// Keep little in memory for Raspberry Pi class machines and old laptops: only images on
+    /// screen keep large textures, new thumbnails decode smaller and the single image view
+    /// doesn't decode neighbors ahead of time.
+    #[arg(long)]
+    low_memory: bool,
+
+    /// Log as plain text or as JSON lines with separate fields, e.g. for log aggregation.
+    #[arg(long, value_name = "FORMAT", default_value = "text", global = true)]
+    log_format: errlog::Format,
+
+    /// Append logs to this file instead of stderr.
+    #[arg(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Language of on-screen text, e.g. de or fr, defaults to LC_ALL, LC_MESSAGES or LANG.
+    #[arg(long, value_name = "LOCALE")]
+    locale: Option<String>,
+
+    /// Size of text, the histogram and the loupe relative to the monitor scaling, e.g. 1.5 for
+    /// projectors.
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    ui_scale: f64,
+
+    /// Cell size in image pixels of the pixel grid guide.
+    #[arg(long, value_name = "PIXELS", default_value_t = 16)]
+    pixel_grid: u32,
+
+    /// Write latency percentiles of the run as JSON to this file on exit.
     #[arg(long, value_name = "PATH")]
+    stats_json: Option<PathBuf>,
+
+    /// Print how long each startup stage took once the first thumbnails show.
+    #[arg(long)]
+    profile_startup: bool,
+
+    /// Set database path.
+    #[arg(long, value_name = "PATH", global = true)]
     db_path: Option<PathBuf>,
 
+    /// Keep the database in .pix-cache next to the executable, or in the first opened directory
+    /// when the opened paths aren't inside the executable's directory. Files are keyed by their
+    /// path relative to it, so a removable drive can carry pix, its cache and the photos.
+    #[arg(long, global = true)]
+    portable: bool,
+
+    /// Keep the database in a hidden .pix directory in the opened directory, or in the directory
+    /// holding all opened paths, so copies of it carry their thumbnails along. Deleting .pix
+    /// removes the cache.
+    #[arg(long, global = true, conflicts_with = "portable")]
+    local_cache: bool,
+
+    /// Don't write to the database, thumbnails made this run are kept until exit only.
+    #[arg(long)]
+    db_readonly: bool,
+
+    /// Encrypt thumbnails and metadata in the database with a passphrase from
+    /// $PIX_CACHE_PASSPHRASE or --cache-key-command.
+    #[arg(long)]
+    encrypt_cache: bool,
+
+    /// Shell command printing the cache passphrase, e.g. a keyring lookup.
+    #[arg(long, value_name = "COMMAND", requires = "encrypt_cache")]
+    cache_key_command: Option<String>,
+
+    /// Only show matching images, e.g. "ext:cr2,nef year:2023 path:holiday sort:-modified".
+    #[arg(long, value_name = "SPEC")]
+    filter: Option<filter::Filter>,
+
+    /// Hash the start and end of every file to thumbnail files edited in place again, even when
+    /// their modification time didn't change.
+    #[arg(long)]
+    verify_content: bool,
+
+    /// Never write next to the originals: crops can't be saved and annotations are off, e.g. for
+    /// handing the viewer to clients or browsing evidence and archive volumes. Also refuses
+    /// `pix autorotate` unless it's a dry run.
+    #[arg(long, global = true)]
+    read_only_files: bool,
+
+    /// Start with thumbnailing paused, Ctrl+P or SIGUSR2 resumes it.
+    #[arg(long)]
+    paused: bool,
+
+    /// Store the SHA-256 of files as they are thumbnailed, for `pix verify`.
+    #[arg(long)]
+    checksum: bool,
+
+    /// Only show the files that failed `pix verify`.
+    #[arg(long)]
+    bit_rot: bool,
+
+    /// Thumbnail files again that failed too often before or were quarantined.
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// List the files that are no longer thumbnailed with their errors and exit.
+    #[arg(long)]
+    list_failed: bool,
+
+    /// Use a filter previously saved with --save-preset.
+    #[arg(long, value_name = "NAME", conflicts_with = "filter")]
+    preset: Option<String>,
+
+    /// Save the --filter spec under a name.
+    #[arg(long, value_name = "NAME", requires = "filter")]
+    save_preset: Option<String>,
+
+    /// List saved filter presets and exit.
+    #[arg(long)]
+    list_presets: bool,
+
+    /// Open the window fullscreen.
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Sync frames to the monitor refresh.
+    #[arg(long)]
+    vsync: bool,
+
+    /// OpenGL version to ask the driver for, e.g. 2.1 for drivers without 3.2. Defaults to 2.1
+    /// on ARM Linux boards and 3.2 elsewhere.
+    #[arg(long, value_name = "VERSION")]
+    opengl: Option<glcompat::Version>,
+
+    /// Draw every frame, also when nothing on screen changed, e.g. to measure drawing.
+    #[arg(long)]
+    every_frame: bool,
+
+    /// Upper bound on frames drawn per second, lower to save battery.
+    #[arg(long, value_name = "FPS", default_value_t = 60)]
+    max_fps: u64,
+
+    /// Decoders thumbnailing on battery, by default half of `--threads`.
+    #[arg(long, value_name = "N")]
+    battery_threads: Option<usize>,
+
+    /// Upper bound on frames drawn per second on battery.
+    #[arg(long, value_name = "FPS", default_value_t = 30)]
+    battery_fps: u64,
+
+    /// Keep working at full speed on battery.
+    #[arg(long, conflicts_with_all = ["battery_threads", "battery_fps"])]
+    ignore_battery: bool,
+
+    /// Updates per second, each loads thumbnails for up to 10ms and advances animations.
+    #[arg(long, value_name = "UPS", default_value_t = 120)]
+    ups: u64,
+
+    /// Window size and optional position, e.g. 1280x720 or 1280x720+0+0.
+    #[arg(long, value_name = "WxH+X+Y")]
+    geometry: Option<geometry::Geometry>,
+
+    /// Initial grid zoom in window pixels per thumbnail, by default all images fit the window.
+    #[arg(long, value_name = "PIXELS")]
+    start_zoom: Option<f64>,
+
+    /// Center the grid on this image.
+    #[arg(long, value_name = "PATH")]
+    start_at: Option<PathBuf>,
+
+    /// Run a fullscreen shuffled slideshow for photo frames and lobby displays. The cursor is
+    /// hidden, input other than Right/Space (next slide) and Esc is ignored and the directories
+    /// are searched again every 10 minutes.
+    #[arg(long)]
+    kiosk: bool,
+
+    /// Seconds each slide is shown in kiosk mode.
+    #[arg(long, value_name = "SECS", default_value_t = 10, requires = "kiosk")]
+    slide_secs: u64,
+
+    /// Show higher rated images more often in kiosk mode, once more per star each round.
+    #[arg(long, requires = "kiosk")]
+    weight_ratings: bool,
+
+    /// Lock the grid to N columns filling the window width, scrolled only up and down like a
+    /// photo browser.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    columns: Option<u32>,
+
+    /// Step from the last image to the first and back, and repeat the grid when panning past
+    /// its edges.
+    #[arg(long, conflicts_with = "columns")]
+    wrap: bool,
+
     /// Images or directories to open.
     #[arg(value_name = "PATH", default_value = ".")]
     paths: Vec<PathBuf>,
 }
 
+// Finite and above zero, a rate of zero would stall thumbnailing.
+fn positive_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        Ok(_) => Err(String::from("must be a number above 0")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 fn main() {
-    env_logger::init();
+    stats::start();
 
     let args = Args::parse();
 
+    errlog::init(args.log_format, args.log_file.as_deref());
+
+    i18n::init(args.locale.as_deref());
+
+    mono::init(args.monochrome);
+
+    lowmem::init(args.low_memory);
+
+    let stats_json = args.stats_json.clone();
+
+    let settings = thumbnailer::Settings {
+        tone_map: args.tone_map,
+        depth: args.depth,
+        io_limit: args.io_limit.map(|mb| (mb * 1_000_000.0) as u64),
+        io_nice: args.io_nice,
+        max_decode: lowmem::max_decode(args.max_decode),
+        decode_timeout: std::time::Duration::from_secs(args.decode_timeout),
+        memory_limit: lowmem::thumb_memory(args.thumb_memory).map(|mb| mb * 1_000_000),
+        checksum: args.checksum,
+        scaler: Some(gpuscale::levels),
+    };
+
+    if let Some(Command::Bench { dir, sample }) = args.command {
+        bench::run(dir, sample, settings);
+        return;
+    }
+
     let thumbnailer_threads: usize = if let Some(threads) = args.threads {
         threads
     } else {
@@ -683,47 +3242,342 @@ fn main() {
     };
     info!("Thumbnailer threads {}", thumbnailer_threads);
 
-    let db_path: PathBuf = if let Some(db_path) = args.db_path {
-        db_path
+    // Portable and local caches key files relative to the directory holding them.
+    let cache_root = if args.portable {
+        Some((system::portable_root(&args.paths), system::CACHE_DIR))
+    } else if args.local_cache {
+        Some((system::local_root(&args.paths), system::LOCAL_CACHE_DIR))
     } else {
-        let mut db_path = dirs_next::cache_dir().expect("cache dir");
-        db_path.push("pix/thumbs.db");
-        db_path
+        None
+    };
+    let db_path: PathBuf = match (args.db_path, &cache_root) {
+        (Some(db_path), _) => db_path,
+        (None, Some((root, dir))) => root.join(dir).join("thumbs.db"),
+        (None, None) => system::db_path(),
     };
     info!("Database path: {:?}", db_path);
 
-    info!("Paths: {:?}", args.paths);
-    let files = find_images(args.paths);
-    if files.is_empty() {
-        error!("No files found, exiting.");
+    let db = if args.db_readonly {
+        database::Database::open_readonly(&db_path)
+    } else {
+        database::Database::open(&db_path)
+    };
+    let mut db = db.expect("db open");
+
+    if args.encrypt_cache {
+        let passphrase = crypt::passphrase(args.cache_key_command.as_deref()).unwrap_or_else(|e| {
+            error!("No cache passphrase: {}", e);
+            std::process::exit(1);
+        });
+        if let Err(e) = db.encrypt(&passphrase) {
+            error!("Unable to decrypt the cache: {}", e);
+            std::process::exit(1);
+        }
+    } else if db.is_encrypted().expect("db is encrypted") {
+        error!("The cache is encrypted, pass --encrypt-cache.");
         std::process::exit(1);
+    }
+
+    if let Some((root, _)) = cache_root {
+        db.set_root(root);
+    }
+
+    let db = Arc::new(db);
+
+    night::set(db.get_setting(night::SETTING).ok().flatten().as_deref() == Some("true"));
+
+    if let Some(Command::Verify { dirs }) = args.command {
+        let mismatched = checksum::verify(&db, dirs);
+        for file in &mismatched {
+            println!("{}", file.path);
+        }
+        std::process::exit(if mismatched.is_empty() { 0 } else { 1 });
+    }
+
+    if let Some(Command::Autorotate {
+        dirs,
+        filter,
+        dry_run,
+        trim,
+        reencode,
+    }) = args.command
+    {
+        if args.read_only_files && !dry_run {
+            error!("Autorotate rewrites the originals, drop --read-only-files or pass --dry-run.");
+            std::process::exit(1);
+        }
+        let files = load_images(&db, dirs, filter.as_ref(), false)
+            .into_iter()
+            .map(|image| image.file)
+            .collect();
+        let options = autorotate::Options {
+            dry_run,
+            trim,
+            reencode,
+        };
+        let n = autorotate::run(&db, files, &options, settings);
+        if !dry_run {
+            println!("Turned {} images upright", n);
+        }
+        db.flush().expect("db flush");
+        return;
+    }
+
+    if let Some(Command::Export {
+        out,
+        dirs,
+        filter,
+        strip_metadata,
+        max_size,
+        watermark,
+        watermark_image,
+        caption,
+    }) = args.command
+    {
+        let files = load_images(&db, dirs, filter.as_ref(), false)
+            .into_iter()
+            .map(|image| image.file)
+            .collect();
+        let options = export::Options {
+            strip_metadata,
+            max_size,
+            watermark,
+            watermark_image,
+            caption,
+            font: args.font,
+        };
+        let n = export::run(&out, files, &options);
+        println!("Exported {} images to {:?}", n, out);
+        return;
+    }
+
+    if let Some(Command::Cache { command }) = args.command {
+        match command {
+            CacheCommand::Export { tar, dir } => {
+                let n = cache::export(&db, &tar, &dir).expect("cache export");
+                println!("Exported thumbnails of {} images to {:?}", n, tar);
+            }
+            CacheCommand::Import { tar, dir } => {
+                let n = cache::import(&db, &tar, &dir).expect("cache import");
+                println!("Imported thumbnails of {} images from {:?}", n, tar);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Meta { command }) = args.command {
+        match command {
+            MetaCommand::Export { json, dir } => {
+                let n = meta::export(&db, &json, &dir).expect("meta export");
+                println!(
+                    "Exported ratings, labels, notes and adjustments of {} images to {:?}",
+                    n, json
+                );
+            }
+            MetaCommand::Import {
+                json,
+                dir,
+                by_content,
+                merge,
+            } => {
+                let imported =
+                    meta::import(&db, &json, &dir, by_content, merge).expect("meta import");
+                for conflict in &imported.conflicts {
+                    println!("{}", conflict);
+                }
+                println!(
+                    "Imported ratings, labels, notes and adjustments of {} images from {:?}",
+                    imported.changed, json
+                );
+                if merge {
+                    println!("{} conflicts", imported.conflicts.len());
+                }
+            }
+        }
+        return;
+    }
+
+    if args.list_presets {
+        for (name, spec) in db.presets().expect("db presets") {
+            println!("{}: {}", name, spec);
+        }
+        return;
+    }
+
+    let filter: Option<filter::Filter> = if let Some(name) = &args.preset {
+        match db.get_preset(name).expect("db get preset") {
+            Some(spec) => Some(spec.parse().expect("preset spec")),
+            None => {
+                error!("No preset named {:?}, exiting.", name);
+                std::process::exit(1);
+            }
+        }
     } else {
-        info!("Found {} files", files.len());
+        args.filter
+    };
+
+    if let (Some(name), Some(filter)) = (&args.save_preset, &filter) {
+        db.set_preset(name, filter.spec()).expect("db set preset");
+        info!("Saved preset {:?}: {}", name, filter.spec());
     }
 
-    let db = Arc::new(database::Database::open(&db_path).expect("db open"));
+    if args.list_failed {
+        for (path, failure) in db.failures().expect("db failures") {
+            if failure.is_permanent() {
+                println!("{}: {}", path, failure.error);
+            }
+        }
+        return;
+    }
 
-    let images: Vec<image::Image> = {
-        files
-            .into_par_iter()
-            .enumerate()
-            .map(|(i, file)| {
-                let metadata = match db.get_metadata(&file) {
-                    Ok(Some(metadata)) => MetadataState::Some(metadata),
-                    Ok(None) => MetadataState::Missing,
-                    Err(e) => {
-                        error!("error loading metadata for: {:?}: {:?}", file, e);
-                        MetadataState::Errored
-                    }
-                };
-                image::Image::from(i, file, metadata)
-            })
-            .collect()
+    if args.retry_failed {
+        let n = db.clear_failures().expect("db clear failures");
+        info!("Retrying {} failed files", n);
+    }
+
+    info!("Paths: {:?}", args.paths);
+    let scan_start = std::time::Instant::now();
+    // Images per row of `pix diff` and `pix dupes`.
+    let sets = match &args.command {
+        Some(Command::Diff { dirs, .. }) => dirs.len() as u32,
+        Some(Command::Dupes { .. }) => 2,
+        _ => 0,
+    };
+    let compare = matches!(args.command, Some(Command::Diff { .. }));
+    let mut dupes = None;
+    let (images, scan) = match args.command {
+        Some(Command::Diff { dirs, by_content }) => (compare::load(&db, &dirs, by_content), None),
+        Some(Command::Dupes { dirs }) => {
+            let found = dupes::Dupes::new(dirs);
+            let images = found.images(&db);
+            dupes = Some(found);
+            (images, None)
+        }
+        _ => {
+            let paths = if args.bit_rot {
+                let paths = db.mismatched_checksums().expect("db mismatched checksums");
+                paths.into_iter().map(PathBuf::from).collect()
+            } else {
+                args.paths.clone()
+            };
+            let mut scan = scan::Scan::new(paths, filter, args.verify_content);
+            (images_of(scan.load(&db)), Some(scan))
+        }
     };
+    stats::stage("startup_metadata");
+    if images.is_empty() {
+        error!("No files found, exiting.");
+        std::process::exit(1);
+    } else {
+        errlog::event(
+            Level::Info,
+            "scan",
+            serde_json::json!({
+                "images": images.len(),
+                "ms": scan_start.elapsed().as_millis() as u64,
+            }),
+        );
+    }
 
     let uid_base = db.reserve(images.len());
 
-    let thumbnailer = Thumbnailer::new(Arc::clone(&db), uid_base, thumbnailer_threads);
+    let thumbnailer = Thumbnailer::new(Arc::clone(&db), uid_base, thumbnailer_threads, settings);
+
+    let geometry = args.geometry.unwrap_or_default();
+    let window_settings = WindowSettings::new("pix", geometry.size)
+        .exit_on_esc(true)
+        .fullscreen(args.fullscreen || args.kiosk)
+        .vsync(args.vsync)
+        .graphics_api(
+            args.opengl
+                .map(|version| version.0)
+                .unwrap_or_else(glcompat::default_version),
+        );
+
+    // Diffs and duplicates are shown in pairs and sets, never stacked.
+    let stacks = (args.stack_bursts && sets == 0).then(BTreeSet::new);
+    let images = match &stacks {
+        Some(expanded) => stack::collapse(images, expanded),
+        None => images,
+    };
 
-    App::new(images, Arc::clone(&db), thumbnailer).run();
+    let mut app = App::new(
+        images,
+        Arc::clone(&db),
+        thumbnailer,
+        args.font,
+        args.pixel_grid,
+        window_settings,
+        args.kiosk.then(|| {
+            kiosk::Kiosk::new(
+                std::time::Duration::from_secs(args.slide_secs),
+                args.weight_ratings,
+            )
+        }),
+    );
+    app.scan = scan;
+    app.stacks = stacks;
+    app.groups.mark_picks();
+    app.read_only_files = args.read_only_files;
+    app.pause = pause::Pause::new(args.paused);
+    app.set_columns(args.columns);
+    app.set_wrap(args.wrap);
+    app.dupes = dupes;
+    if args.gpu_resize && glcompat::can_blit(&app.window.device) {
+        app.gpu = Some(gpuscale::Server::start());
+    } else if args.gpu_resize {
+        warn!("The OpenGL driver can't resize thumbnails, resizing on the CPU");
+    }
+    app.access = access::Access::new(
+        args.focus_outline,
+        args.reduced_motion || args.monochrome,
+        args.announce,
+    );
+    app.overlay.set_scale(if args.ui_scale.is_finite() {
+        args.ui_scale.clamp(0.25, 8.0)
+    } else {
+        1.0
+    });
+    if sets > 0 {
+        app.show_sets(sets);
+    }
+    app.compare = compare;
+    if let Some(pos) = geometry.pos {
+        app.window.set_position(pos);
+    }
+    // Loading and animation run at the update rate, independent of how often frames are drawn.
+    app.window.set_ups(args.ups);
+    app.window.set_max_fps(args.max_fps);
+    // Buffers are swapped only after frames that were drawn.
+    app.window.set_swap_buffers(false);
+    app.pacing.always = args.every_frame;
+    app.governor = concurrency::Governor::new(thumbnailer_threads, args.max_fps);
+    app.power = power::Power::new(
+        power::Budget {
+            threads: thumbnailer_threads,
+            fps: args.max_fps,
+            neighbors: 2,
+        },
+        (!args.ignore_battery).then(|| power::Budget {
+            threads: args
+                .battery_threads
+                .unwrap_or(usize::max(1, thumbnailer_threads / 2)),
+            fps: u64::min(args.battery_fps, args.max_fps),
+            neighbors: 1,
+        }),
+    );
+    app.governor.adaptive = !args.fixed_threads;
+    app.profile_startup = args.profile_startup;
+    app.start_at(args.start_zoom, args.start_at.as_deref());
+    shutdown::install();
+    pause::install();
+    app.run();
+    app.shutdown();
+
+    stats::dump();
+    if let Some(path) = stats_json {
+        if let Err(e) = stats::write_json(&path) {
+            error!("writing stats to {:?}: {}", path, e);
+        }
+    }
 }
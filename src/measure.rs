@@ -0,0 +1,183 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Distances and sizes dragged over the single image view, in pixels and physical units.
+
+use log::*;
+use piston_window::{line_from_to, Context, G2d, Rectangle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Line,
+    Rect,
+}
+
+impl Shape {
+    // Cycle through the shapes and back to off.
+    pub fn next(shape: Option<Self>) -> Option<Self> {
+        match shape {
+            None => Some(Shape::Line),
+            Some(Shape::Line) => Some(Shape::Rect),
+            Some(Shape::Rect) => None,
+        }
+    }
+}
+
+// Horizontal and vertical pixels per inch from EXIF.
+pub fn dpi(path: &str) -> Option<[f64; 2]> {
+    let file = std::fs::File::open(path).ok()?;
+    let exif = match exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file)) {
+        Ok(exif) => exif,
+        Err(e) => {
+            debug!("no exif: {:?}", e);
+            return None;
+        }
+    };
+
+    let rational = |tag| match exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Rational(ref v) if !v.is_empty() && v[0].denom != 0 => Some(v[0].to_f64()),
+        _ => None,
+    };
+    let per_inch = match exif
+        .get_field(exif::Tag::ResolutionUnit, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+    {
+        Some(3) => 2.54,
+        // Inches is the default.
+        Some(2) | None => 1.0,
+        Some(_) => return None,
+    };
+
+    let x = rational(exif::Tag::XResolution)?;
+    let y = rational(exif::Tag::YResolution).unwrap_or(x);
+    (x > 0.0 && y > 0.0).then_some([x * per_inch, y * per_inch])
+}
+
+#[derive(Debug)]
+pub struct Measure {
+    pub shape: Shape,
+
+    // Pixels per inch of the image, if known.
+    dpi: Option<[f64; 2]>,
+
+    // Image pixel positions the drag started and ended at.
+    start: Option<[f64; 2]>,
+    end: [f64; 2],
+
+    pub dragging: bool,
+}
+
+impl Measure {
+    pub fn new(shape: Shape, dpi: Option<[f64; 2]>) -> Self {
+        Self {
+            shape,
+            dpi,
+            start: None,
+            end: [0.0, 0.0],
+            dragging: false,
+        }
+    }
+
+    pub fn begin(&mut self, pos: [f64; 2]) {
+        self.start = Some(pos);
+        self.end = pos;
+        self.dragging = true;
+    }
+
+    pub fn drag(&mut self, pos: [f64; 2]) {
+        if self.dragging {
+            self.end = pos;
+        }
+    }
+
+    fn size(&self) -> Option<[f64; 2]> {
+        let [sx, sy] = self.start?;
+        Some([(self.end[0] - sx).abs(), (self.end[1] - sy).abs()])
+    }
+
+    pub fn describe(&self) -> Option<String> {
+        let [w, h] = self.size()?;
+        let mm = self.dpi.map(|[x, y]| [w / x * 25.4, h / y * 25.4]);
+
+        Some(match self.shape {
+            Shape::Line => {
+                let px = w.hypot(h);
+                match mm {
+                    Some([w, h]) => format!("{:.1} px, {:.1} mm", px, w.hypot(h)),
+                    None => format!("{:.1} px", px),
+                }
+            }
+            Shape::Rect => match mm {
+                Some([mm_w, mm_h]) => {
+                    format!("{:.0} x {:.0} px, {:.1} x {:.1} mm", w, h, mm_w, mm_h)
+                }
+                None => format!("{:.0} x {:.0} px", w, h),
+            },
+        })
+    }
+
+    // Window position of the end of the drag, `origin` and `scale` map image pixels to the window.
+    pub fn end_pos(&self, origin: [f64; 2], scale: f64) -> [f64; 2] {
+        [
+            origin[0] + self.end[0] * scale,
+            origin[1] + self.end[1] * scale,
+        ]
+    }
+
+    pub fn draw(&self, origin: [f64; 2], scale: f64, c: &Context, g: &mut G2d) {
+        let start = match self.start {
+            Some([x, y]) => [origin[0] + x * scale, origin[1] + y * scale],
+            None => return,
+        };
+        let end = self.end_pos(origin, scale);
+        let color = [1.0, 0.9, 0.2, 1.0];
+
+        match self.shape {
+            Shape::Line => line_from_to(color, 1.0, start, end, c.transform, g),
+            Shape::Rect => {
+                let rect = [
+                    f64::min(start[0], end[0]),
+                    f64::min(start[1], end[1]),
+                    (end[0] - start[0]).abs(),
+                    (end[1] - start[1]).abs(),
+                ];
+                Rectangle::new_border(color, 1.0).draw(rect, &c.draw_state, c.transform, g);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe() {
+        let mut measure = Measure::new(Shape::Line, None);
+        assert_eq!(measure.describe(), None);
+
+        measure.begin([10.0, 10.0]);
+        measure.drag([40.0, 50.0]);
+        assert_eq!(measure.describe().unwrap(), "50.0 px");
+
+        measure.shape = Shape::Rect;
+        assert_eq!(measure.describe().unwrap(), "30 x 40 px");
+
+        measure.dpi = Some([254.0, 254.0]);
+        assert_eq!(measure.describe().unwrap(), "30 x 40 px, 3.0 x 4.0 mm");
+
+        measure.shape = Shape::Line;
+        assert_eq!(measure.describe().unwrap(), "50.0 px, 5.0 mm");
+    }
+}
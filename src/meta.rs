@@ -0,0 +1,528 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// `pix meta export/import`, round-trips what was added to images by hand, ratings, labels, notes
+// and adjustments, through a JSON file, for backups and to carry a culling session to another
+// machine. Entries hold the path relative to the exported directory and a quick hash of the
+// contents, so images are found again by path, or by contents after renaming.
+//
+// Importing either restores the exported state or merges it with what's here, for two people
+// curating the same archive. Merging takes each field from the side that changed it last, so
+// whatever only one side added is kept and removals carry over. When both sides set a field
+// differently the conflict is reported so it can be checked.
+
+use crate::adjust::Adjustments;
+use crate::database::{Database, Edited};
+use crate::userdata::{Label, UserData};
+use crate::{fingerprint, File, E, R};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+// One per image with anything added.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    path: String,
+    hash: Option<String>,
+
+    #[serde(default)]
+    rating: u8,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<Label>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adjustments: Option<Adjustments>,
+
+    // When each field was last changed, removals included.
+    #[serde(
+        default,
+        deserialize_with = "edited",
+        skip_serializing_if = "Edited::is_empty"
+    )]
+    edited: Edited,
+}
+
+// Exports made before times were kept per field hold one time for all of them.
+fn edited<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Edited, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Times {
+        All(u64),
+        Fields(Edited),
+    }
+    Ok(match Times::deserialize(d)? {
+        Times::All(time) => Edited::all(Some(time)),
+        Times::Fields(edited) => edited,
+    })
+}
+
+// A field both sides set differently.
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub path: String,
+    pub field: &'static str,
+    pub here: String,
+    pub there: String,
+    pub kept_there: bool,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} {} here, {} there, kept {}",
+            self.path,
+            self.field,
+            self.here,
+            self.there,
+            if self.kept_there { "there" } else { "here" }
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Imported {
+    // Images changed.
+    pub changed: usize,
+
+    pub conflicts: Vec<Conflict>,
+}
+
+// Merges the fields of one image.
+struct Merge<'a> {
+    path: &'a str,
+    conflicts: &'a mut Vec<Conflict>,
+}
+
+impl Merge<'_> {
+    // The value changed last and its time. Times are None for fields never changed since they
+    // were kept, which lose to any timed change.
+    fn pick<T: PartialEq + Default>(
+        &mut self,
+        field: &'static str,
+        (here, here_edited): (T, Option<u64>),
+        (there, there_edited): (T, Option<u64>),
+        show: impl Fn(&T) -> String,
+    ) -> (T, Option<u64>) {
+        if here == there {
+            return (here, here_edited.max(there_edited));
+        }
+        let there_newer = there_edited > here_edited;
+        // A value set on one side only, or removed on one, is no conflict.
+        if here != T::default() && there != T::default() {
+            self.conflicts.push(Conflict {
+                path: self.path.to_owned(),
+                field,
+                here: show(&here),
+                there: show(&there),
+                kept_there: there_newer,
+            });
+        }
+        if there_newer {
+            (there, there_edited)
+        } else {
+            (here, here_edited)
+        }
+    }
+}
+
+fn json_error(e: serde_json::Error) -> E {
+    E::IoError(e.into())
+}
+
+fn hash(file: &File) -> Option<String> {
+    fingerprint::of_file(&file.path)
+        .ok()
+        .map(|hash| format!("{:016x}", hash))
+}
+
+// Images under `root` with their paths relative to it, with forward slashes so an export reads
+// back on every platform.
+fn files(root: &Path) -> Vec<(String, Arc<File>)> {
+    crate::find_images(vec![root.to_owned()])
+        .into_iter()
+        .map(|file| {
+            let rel = Path::new(&file.path)
+                .strip_prefix(root)
+                .expect("found under root")
+                .to_string_lossy();
+            let rel = crate::system::slashes(&rel).into_owned();
+            (rel, file)
+        })
+        .collect()
+}
+
+// Write what was added to the images under `dir` to `json`, returns how many images had any.
+pub fn export(db: &Database, json: &Path, dir: &Path) -> R<usize> {
+    let root = dir.canonicalize().map_err(E::IoError)?;
+
+    let mut entries = Vec::new();
+    for (rel, file) in files(&root) {
+        let user_data = db.get_user_data(&file)?;
+        let note = db.get_note(&file)?;
+        let adjustments = Some(db.get_adjustments(&file)?).filter(|a| !a.is_default());
+        let edited = db.get_edited(&file)?;
+        // Images with everything removed are kept for the times of the removals.
+        if user_data.is_empty() && note.is_none() && adjustments.is_none() && edited.is_empty() {
+            continue;
+        }
+        entries.push(Entry {
+            path: rel,
+            hash: hash(&file),
+            rating: user_data.rating,
+            label: user_data.label,
+            note,
+            adjustments,
+            edited,
+        });
+    }
+
+    let encoded = serde_json::to_vec_pretty(&entries).map_err(json_error)?;
+    std::fs::write(json, encoded).map_err(E::IoError)?;
+    Ok(entries.len())
+}
+
+// Apply the entries of `json` to the images now under `dir`, found by their relative path or with
+// `by_content` by their contents, every copy alike. Images take the exported state, e.g. an entry
+// without a note removes the note, or with `merge` are combined with it.
+pub fn import(
+    db: &Database,
+    json: &Path,
+    dir: &Path,
+    by_content: bool,
+    merge: bool,
+) -> R<Imported> {
+    let root = dir.canonicalize().map_err(E::IoError)?;
+    let data = std::fs::read(json).map_err(E::IoError)?;
+    let entries: Vec<Entry> = serde_json::from_slice(&data).map_err(json_error)?;
+
+    let mut found: BTreeMap<String, Vec<(String, Arc<File>)>> = BTreeMap::new();
+    for (rel, file) in files(&root) {
+        let key = if by_content {
+            hash(&file)
+        } else {
+            Some(rel.clone())
+        };
+        if let Some(key) = key {
+            found.entry(key).or_default().push((rel, file));
+        }
+    }
+
+    let mut imported = Imported::default();
+    for entry in entries {
+        let key = if by_content {
+            entry.hash.as_ref()
+        } else {
+            Some(&entry.path)
+        };
+        let Some(files) = key.and_then(|key| found.get(key)) else {
+            debug!("No image for {:?}", entry.path);
+            continue;
+        };
+        for (rel, file) in files {
+            let changed = if merge {
+                merge_entry(db, &entry, rel, file, &mut imported.conflicts)?
+            } else {
+                restore(db, &entry, file)?;
+                true
+            };
+            if changed {
+                imported.changed += 1;
+            }
+        }
+    }
+
+    db.flush()?;
+    Ok(imported)
+}
+
+fn restore(db: &Database, entry: &Entry, file: &File) -> R<()> {
+    let user_data = UserData {
+        rating: entry.rating.min(crate::userdata::MAX_RATING),
+        label: entry.label,
+    };
+    db.set_user_data(file, &user_data)?;
+    db.set_note(file, entry.note.as_deref().unwrap_or_default())?;
+    db.set_adjustments(file, &entry.adjustments.clone().unwrap_or_default())?;
+    db.set_edited(file, &entry.edited)
+}
+
+// Returns whether anything here changed.
+fn merge_entry(
+    db: &Database,
+    entry: &Entry,
+    path: &str,
+    file: &File,
+    conflicts: &mut Vec<Conflict>,
+) -> R<bool> {
+    let user_data = db.get_user_data(file)?;
+    let note = db.get_note(file)?;
+    let adjustments = db.get_adjustments(file)?;
+    let edited = db.get_edited(file)?;
+
+    let mut merge = Merge { path, conflicts };
+    let (rating, rating_edited) = merge.pick(
+        "rating",
+        (user_data.rating, edited.rating),
+        (
+            entry.rating.min(crate::userdata::MAX_RATING),
+            entry.edited.rating,
+        ),
+        |rating| rating.to_string(),
+    );
+    let (label, label_edited) = merge.pick(
+        "label",
+        (user_data.label, edited.label),
+        (entry.label, entry.edited.label),
+        |label| label.map_or_else(String::new, |label| format!("{:?}", label).to_lowercase()),
+    );
+    let (merged_note, note_edited) = merge.pick(
+        "note",
+        (note.clone(), edited.note),
+        (entry.note.clone(), entry.edited.note),
+        |note| format!("{:?}", note.as_deref().unwrap_or_default()),
+    );
+    let (merged_adjustments, adjustments_edited) = merge.pick(
+        "adjustments",
+        (adjustments.clone(), edited.adjustments),
+        (
+            entry.adjustments.clone().unwrap_or_default(),
+            entry.edited.adjustments,
+        ),
+        |adjustments| adjustments.describe(crate::i18n::tr),
+    );
+    let merged_user_data = UserData { rating, label };
+    let merged_edited = Edited {
+        rating: rating_edited,
+        label: label_edited,
+        note: note_edited,
+        adjustments: adjustments_edited,
+    };
+
+    let changed =
+        merged_user_data != user_data || merged_note != note || merged_adjustments != adjustments;
+    if changed {
+        db.set_user_data(file, &merged_user_data)?;
+        db.set_note(file, merged_note.as_deref().unwrap_or_default())?;
+        db.set_adjustments(file, &merged_adjustments)?;
+    }
+    // The setters time their changes now, the times of the side they came from are kept.
+    if changed || merged_edited != edited {
+        db.set_edited(file, &merged_edited)?;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    // A directory of two fake images, a in a subdirectory and b.
+    fn images(name: &str) -> TestDir {
+        let dir = TestDir::new(name);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/a.png"), b"not really").unwrap();
+        std::fs::write(dir.join("b.png"), b"not at all").unwrap();
+        dir
+    }
+
+    fn find(dir: &Path, name: &str) -> Arc<File> {
+        crate::find_images(vec![dir.to_owned()])
+            .into_iter()
+            .find(|f| f.path.ends_with(name))
+            .unwrap()
+    }
+
+    #[test]
+    fn export_import() {
+        let dir = images("meta");
+        let json = dir.join("meta.json");
+
+        let a = find(&dir, "a.png");
+        let from = Database::temporary().unwrap();
+        let user_data = UserData {
+            rating: 4,
+            label: Some(Label::Green),
+        };
+        from.set_user_data(&a, &user_data).unwrap();
+        from.set_note(&a, "keeper").unwrap();
+        assert_eq!(export(&from, &json, &dir).unwrap(), 1);
+
+        let to = Database::temporary().unwrap();
+        assert_eq!(import(&to, &json, &dir, false, false).unwrap().changed, 1);
+        assert_eq!(to.get_user_data(&a).unwrap(), user_data);
+        assert_eq!(to.get_note(&a).unwrap().as_deref(), Some("keeper"));
+        assert_eq!(to.get_edited(&a).unwrap(), from.get_edited(&a).unwrap());
+
+        // Found by contents after renaming.
+        std::fs::rename(dir.join("sub/a.png"), dir.join("renamed.png")).unwrap();
+        let to = Database::temporary().unwrap();
+        assert_eq!(import(&to, &json, &dir, false, false).unwrap().changed, 0);
+        assert_eq!(import(&to, &json, &dir, true, false).unwrap().changed, 1);
+        let renamed = find(&dir, "renamed.png");
+        assert_eq!(to.get_user_data(&renamed).unwrap(), user_data);
+    }
+
+    #[test]
+    fn merge() {
+        let dir = images("merge");
+        let json = dir.join("meta.json");
+        let a = find(&dir, "a.png");
+        let b = find(&dir, "b.png");
+
+        // There a was rated and labeled later, b was rated earlier.
+        let there = Database::temporary().unwrap();
+        there
+            .set_user_data(
+                &a,
+                &UserData {
+                    rating: 4,
+                    label: Some(Label::Green),
+                },
+            )
+            .unwrap();
+        there
+            .set_edited(
+                &a,
+                &Edited {
+                    rating: Some(200),
+                    label: Some(200),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        there
+            .set_user_data(
+                &b,
+                &UserData {
+                    rating: 1,
+                    label: None,
+                },
+            )
+            .unwrap();
+        there
+            .set_edited(
+                &b,
+                &Edited {
+                    rating: Some(100),
+                    label: Some(100),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        export(&there, &json, &dir).unwrap();
+
+        let here = Database::temporary().unwrap();
+        here.set_user_data(
+            &a,
+            &UserData {
+                rating: 2,
+                label: None,
+            },
+        )
+        .unwrap();
+        here.set_note(&a, "mine").unwrap();
+        here.set_edited(
+            &a,
+            &Edited {
+                rating: Some(150),
+                label: Some(150),
+                note: Some(150),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        here.set_user_data(
+            &b,
+            &UserData {
+                rating: 5,
+                label: None,
+            },
+        )
+        .unwrap();
+        here.set_edited(
+            &b,
+            &Edited {
+                rating: Some(150),
+                label: Some(150),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let imported = import(&here, &json, &dir, false, true).unwrap();
+        assert_eq!(imported.changed, 1);
+        assert_eq!(
+            imported
+                .conflicts
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "b.png: rating 5 here, 1 there, kept here",
+                "sub/a.png: rating 2 here, 4 there, kept there",
+            ]
+        );
+
+        // What only one side set is kept.
+        assert_eq!(
+            here.get_user_data(&a).unwrap(),
+            UserData {
+                rating: 4,
+                label: Some(Label::Green),
+            }
+        );
+        assert_eq!(here.get_note(&a).unwrap().as_deref(), Some("mine"));
+        assert_eq!(
+            here.get_edited(&a).unwrap(),
+            Edited {
+                rating: Some(200),
+                label: Some(200),
+                note: Some(150),
+                adjustments: None,
+            }
+        );
+        assert_eq!(here.get_user_data(&b).unwrap().rating, 5);
+
+        // Nothing new the second time.
+        assert_eq!(import(&here, &json, &dir, false, true).unwrap().changed, 0);
+
+        // Removing the rating of b there later removes it here too.
+        there.set_user_data(&b, &UserData::default()).unwrap();
+        there
+            .set_edited(
+                &b,
+                &Edited {
+                    rating: Some(300),
+                    label: Some(100),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        export(&there, &json, &dir).unwrap();
+        let imported = import(&here, &json, &dir, false, true).unwrap();
+        assert_eq!(imported.changed, 1);
+        assert!(imported.conflicts.is_empty());
+        assert_eq!(here.get_user_data(&b).unwrap(), UserData::default());
+        assert_eq!(here.get_edited(&b).unwrap().rating, Some(300));
+    }
+}
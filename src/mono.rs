@@ -0,0 +1,84 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Dithered grayscale on white for e-ink monitors and very low-power displays.
+
+use ::image::RgbaImage;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Gray levels of common e-ink panels.
+static LEVELS: u32 = 16;
+
+static BAYER: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+pub static BACKGROUND: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Frames images that have no thumbnail yet.
+pub static PLACEHOLDER: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Reduce a tile to `LEVELS` grays with ordered dithering. Tiles start at multiples of the
+// pattern size, so neighboring tiles line up without seams.
+pub fn dither(image: &mut RgbaImage) {
+    let step = 255 / (LEVELS - 1);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let [r, g, b, _] = pixel.0;
+        // Rec. 601 luma in 0..=255.
+        let luma = (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000;
+        let threshold = (BAYER[y as usize % 4][x as usize % 4] * 2 + 1) * step / 32;
+        let level = u32::min(LEVELS - 1, (luma + step - 1 - threshold) / step);
+        let v = (level * step) as u8;
+        pixel.0 = [v, v, v, pixel.0[3]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::Rgba;
+
+    #[test]
+    fn grays() {
+        let mut image = RgbaImage::from_fn(4, 4, |x, _| match x {
+            0 => Rgba([0, 0, 0, 255]),
+            1 => Rgba([255, 255, 255, 128]),
+            2 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([128, 128, 128, 255]),
+        });
+        dither(&mut image);
+
+        for y in 0..4 {
+            assert_eq!(image.get_pixel(0, y), &Rgba([0, 0, 0, 255]));
+            assert_eq!(image.get_pixel(1, y), &Rgba([255, 255, 255, 128]));
+            let [r, g, b, _] = image.get_pixel(2, y).0;
+            assert!(r == g && g == b && r % 17 == 0);
+        }
+
+        // A gray between two levels dithers to both, averaging close to it.
+        let mean: u32 = (0..4).map(|y| image.get_pixel(3, y)[0] as u32).sum::<u32>() / 4;
+        let values: std::collections::BTreeSet<u8> =
+            (0..4).map(|y| image.get_pixel(3, y)[0]).collect();
+        assert_eq!(values.len(), 2);
+        assert!((120..=136).contains(&mean), "{}", mean);
+    }
+}
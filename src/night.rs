@@ -0,0 +1,68 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Night mode, dimmed and warm colors for looking at photos in the dark. Applied when drawing, so
+// toggling it doesn't load any tiles again.
+
+use piston_window::types::Color;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Whites are capped to this brightness and tinted towards candle light.
+static BRIGHTNESS: f32 = 0.6;
+static WARM: [f32; 3] = [1.0, 0.8, 0.55];
+
+// Database setting remembering the mode between runs.
+pub static SETTING: &str = "night";
+
+pub fn set(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn warm(color: Color) -> Color {
+    let [r, g, b, a] = color;
+    [
+        r * BRIGHTNESS * WARM[0],
+        g * BRIGHTNESS * WARM[1],
+        b * BRIGHTNESS * WARM[2],
+        a,
+    ]
+}
+
+// `color` as drawn in the current mode.
+pub fn tint(color: Color) -> Color {
+    if enabled() {
+        warm(color)
+    } else {
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warm_white() {
+        let [r, g, b, a] = warm([1.0, 1.0, 1.0, 0.5]);
+        assert!(r <= BRIGHTNESS && g < r && b < g);
+        assert_eq!(a, 0.5);
+        assert_eq!(warm([0.0, 0.0, 0.0, 1.0]), [0.0, 0.0, 0.0, 1.0]);
+    }
+}
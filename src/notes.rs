@@ -0,0 +1,75 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Short free-text notes on images, e.g. captions or review comments. They're kept in the database
+// by path like ratings, never in the files, and edited on a line of the info overlay.
+
+use crate::File;
+use std::sync::Arc;
+
+// Longest note in characters.
+pub static MAX_LEN: usize = 500;
+
+#[derive(Debug)]
+pub struct Editor {
+    pub file: Arc<File>,
+    text: String,
+}
+
+impl Editor {
+    pub fn new(file: Arc<File>, text: String) -> Self {
+        Self { file, text }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        // Enter and Backspace also arrive as text on some platforms.
+        let room = MAX_LEN.saturating_sub(self.text.chars().count());
+        self.text
+            .extend(text.chars().filter(|c| !c.is_control()).take(room));
+    }
+
+    pub fn backspace(&mut self) {
+        self.text.pop();
+    }
+
+    // The note being typed under the path of its image, with a cursor.
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            self.file.path.clone(),
+            crate::i18n::trf("Note: {}", &[&format!("{}_", self.text)]),
+            crate::i18n::tr("Enter saves, Ctrl+E discards").to_owned(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing() {
+        let mut editor = Editor::new(Arc::new(File::default()), "ok".to_owned());
+        editor.type_text("\u{5} fine\r");
+        assert_eq!(editor.text(), "ok fine");
+        editor.backspace();
+        assert_eq!(editor.text(), "ok fin");
+
+        editor.type_text(&"x".repeat(MAX_LEN));
+        assert_eq!(editor.text().chars().count(), MAX_LEN);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Text drawn over the grid. Sizes are in window units times the UI scale, glyphs are rendered
+// at the resolution of the monitor the window is on.
+
+use log::*;
+use piston_window::*;
+use std::path::{Path, PathBuf};
+
+// Tried in order when --font isn't set.
+static FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+static FONT_SIZE: u32 = 14;
+static LINE_HEIGHT: f64 = 18.0;
+static MARGIN: f64 = 8.0;
+
+pub struct Overlay {
+    glyphs: Option<Glyphs>,
+
+    // User factor on top of the monitor scaling, e.g. for projectors.
+    scale: f64,
+}
+
+// Framebuffer pixels per window unit, 2 on most 4K panels.
+pub fn dpi(c: &Context) -> f64 {
+    c.viewport
+        .map(|v| v.draw_size[0] as f64 / v.window_size[0])
+        .filter(|dpi| dpi.is_finite() && *dpi > 0.0)
+        .unwrap_or(1.0)
+}
+
+// The --font path or else the first system font found.
+pub fn font_path(font: Option<PathBuf>) -> Option<PathBuf> {
+    font.or_else(|| {
+        FONT_PATHS
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .map(Path::to_path_buf)
+    })
+}
+
+impl Overlay {
+    pub fn new(window: &mut PistonWindow, font: Option<PathBuf>) -> Self {
+        let glyphs = match font_path(font) {
+            Some(path) => match window.load_font(&path) {
+                Ok(glyphs) => Some(glyphs),
+                Err(e) => {
+                    error!("unable to load font {:?}: {:?}", path, e);
+                    None
+                }
+            },
+            None => {
+                warn!("No font found, text overlays disabled. Set one with --font.");
+                None
+            }
+        };
+
+        Self { glyphs, scale: 1.0 }
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    // Number of lines that fit in a window of the given height.
+    pub fn rows(&self, height: f64) -> usize {
+        ((height - 2.0 * MARGIN * self.scale) / (LINE_HEIGHT * self.scale)).max(1.0) as usize
+    }
+
+    // Draw lines of text in a box anchored to the top left corner.
+    pub fn draw_lines(&mut self, lines: &[String], c: &Context, g: &mut G2d) {
+        self.draw_lines_at(lines, [0.0, 0.0], c, g);
+    }
+
+    // Draw lines of text in a box with its top left corner at `pos`.
+    pub fn draw_lines_at(&mut self, lines: &[String], pos: [f64; 2], c: &Context, g: &mut G2d) {
+        let glyphs = match &mut self.glyphs {
+            Some(glyphs) => glyphs,
+            None => return,
+        };
+
+        if lines.is_empty() {
+            return;
+        }
+
+        // Rendered in framebuffer pixels and scaled back down to window units.
+        let dpi = dpi(c);
+        let font_size = ((FONT_SIZE as f64 * self.scale * dpi).round() as u32).max(1);
+        let [line_height, margin] = [LINE_HEIGHT, MARGIN].map(|v| v * self.scale);
+
+        let width = lines
+            .iter()
+            .map(|line| glyphs.width(font_size, line).unwrap_or(0.0) / dpi)
+            .fold(0.0, f64::max);
+        let height = lines.len() as f64 * line_height;
+        let transform = c.transform.trans(pos[0], pos[1]);
+
+        // Solid on the white background of monochrome mode.
+        let alpha = if crate::mono::enabled() { 1.0 } else { 0.7 };
+        rectangle(
+            [0.0, 0.0, 0.0, alpha],
+            [0.0, 0.0, width + 2.0 * margin, height + 2.0 * margin],
+            transform,
+            g,
+        );
+
+        let text = Text::new_color(crate::night::tint([1.0, 1.0, 1.0, 1.0]), font_size);
+        for (i, line) in lines.iter().enumerate() {
+            let y = margin + (i + 1) as f64 * line_height - 4.0 * self.scale;
+            let trans = transform.trans(margin, y).scale(1.0 / dpi, 1.0 / dpi);
+            if let Err(e) = text.draw(line, glyphs, &c.draw_state, trans, g) {
+                error!("text draw: {:?}", e);
+            }
+        }
+    }
+
+    pub fn flush(&mut self, device: &mut GfxDevice) {
+        if let Some(glyphs) = &mut self.glyphs {
+            glyphs.factory.encoder.flush(device);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Frame pacing. A frame is only drawn when something on screen may have changed: input, the
+// view moving, tiles uploaded, animation frames and results arriving from background threads.
+// Anything changing the picture calls `changed`, so a static grid costs no drawing at all.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static CHANGES: AtomicU64 = AtomicU64::new(0);
+
+// Frames are drawn at least this often, for changes nothing reports, e.g. the window being
+// uncovered or new lines in the log panel.
+static IDLE: Duration = Duration::from_secs(1);
+
+// Something on screen may have changed since the last frame.
+pub fn changed() {
+    CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub struct Pacing {
+    // Draw every frame, for benchmarks and the cache counters.
+    pub always: bool,
+    seen: u64,
+    last_draw: Option<Instant>,
+}
+
+impl Pacing {
+    pub fn new(always: bool) -> Self {
+        Self {
+            always,
+            seen: 0,
+            last_draw: None,
+        }
+    }
+
+    // Whether the frame at `now` needs drawing, it is then taken as drawn.
+    pub fn due(&mut self, now: Instant) -> bool {
+        self.due_after(CHANGES.load(Ordering::Relaxed), now)
+    }
+
+    fn due_after(&mut self, changes: u64, now: Instant) -> bool {
+        let due = self.always
+            || changes != self.seen
+            || self.last_draw.is_none_or(|last| now >= last + IDLE);
+        if due {
+            self.seen = changes;
+            self.last_draw = Some(now);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_identical() {
+        let mut pacing = Pacing::new(false);
+        let now = Instant::now();
+        let at = |ms| now + Duration::from_millis(ms);
+        assert!(pacing.due_after(0, at(0)));
+        assert!(!pacing.due_after(0, at(16)));
+
+        // Something changed.
+        assert!(pacing.due_after(1, at(32)));
+        assert!(!pacing.due_after(1, at(48)));
+
+        // Drawn again when idle.
+        assert!(pacing.due_after(1, at(1100)));
+
+        pacing.always = true;
+        assert!(pacing.due_after(1, at(1116)));
+    }
+}
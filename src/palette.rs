@@ -0,0 +1,158 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Command palette: a list of actions narrowed down by typing, run with Enter. It's where saved
+// filter presets are recalled, and where the current filter is saved under the typed name.
+
+use crate::i18n;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Preset { name: String, spec: String },
+    SavePreset(String),
+    ShowAll,
+    Rescan,
+    NightMode,
+    Fullscreen,
+    Timeline,
+    Log,
+}
+
+impl Action {
+    pub fn label(&self) -> String {
+        match self {
+            Action::Preset { name, spec } => i18n::trf("Filter: {} ({})", &[name, spec]),
+            Action::SavePreset(name) => i18n::trf("Save the filter as \"{}\"", &[name]),
+            Action::ShowAll => i18n::tr("Show all images").to_owned(),
+            Action::Rescan => i18n::tr("Search again").to_owned(),
+            Action::NightMode => i18n::tr("Toggle night mode").to_owned(),
+            Action::Fullscreen => i18n::tr("Toggle fullscreen").to_owned(),
+            Action::Timeline => i18n::tr("Toggle the timeline").to_owned(),
+            Action::Log => i18n::tr("Toggle the log").to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Palette {
+    actions: Vec<Action>,
+
+    // Offer saving the current filter under the typed name.
+    can_save: bool,
+
+    query: String,
+    selected: usize,
+}
+
+impl Palette {
+    pub fn new(actions: Vec<Action>, can_save: bool) -> Self {
+        Self {
+            actions,
+            can_save,
+            query: String::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        self.query.extend(text.chars().filter(|c| !c.is_control()));
+        self.selected = 0;
+    }
+
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+
+    // Actions whose label contains the query, ignoring case.
+    fn matches(&self) -> Vec<Action> {
+        let query = self.query.trim().to_lowercase();
+        let mut matches: Vec<Action> = self
+            .actions
+            .iter()
+            .filter(|action| action.label().to_lowercase().contains(&query))
+            .cloned()
+            .collect();
+        if self.can_save && !query.is_empty() {
+            matches.push(Action::SavePreset(self.query.trim().to_owned()));
+        }
+        matches
+    }
+
+    pub fn step(&mut self, n: isize) {
+        let len = self.matches().len();
+        if len > 0 {
+            self.selected = self.selected.saturating_add_signed(n).min(len - 1);
+        }
+    }
+
+    // The selected action, to run.
+    pub fn take(self) -> Option<Action> {
+        self.matches().into_iter().nth(self.selected)
+    }
+
+    // The query with a cursor, then at most `rows` actions around the selected one.
+    pub fn lines(&self, rows: usize) -> Vec<String> {
+        let matches = self.matches();
+        let rows = rows.saturating_sub(2).max(1);
+        let first = self.selected.saturating_sub(rows - 1);
+        let mut lines = vec![i18n::trf("Command: {}", &[&format!("{}_", self.query)])];
+        lines.extend(
+            matches
+                .iter()
+                .enumerate()
+                .skip(first)
+                .take(rows)
+                .map(|(i, action)| {
+                    let marker = if i == self.selected { ">" } else { " " };
+                    format!("{} {}", marker, action.label())
+                }),
+        );
+        if matches.is_empty() {
+            lines.push(i18n::tr("no matching commands").to_owned());
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrows() {
+        let raws = Action::Preset {
+            name: "raws".to_owned(),
+            spec: "ext:cr2".to_owned(),
+        };
+        let mut palette = Palette::new(vec![raws.clone(), Action::ShowAll, Action::Rescan], true);
+        assert_eq!(palette.matches().len(), 3);
+
+        palette.type_text("RAW");
+        assert_eq!(
+            palette.matches(),
+            [raws.clone(), Action::SavePreset("RAW".to_owned())]
+        );
+        palette.step(5);
+        assert_eq!(palette.selected, 1);
+        palette.step(-1);
+        assert_eq!(palette.take(), Some(raws));
+
+        let mut palette = Palette::new(vec![Action::ShowAll], false);
+        palette.type_text("nothing\r");
+        assert!(palette.matches().is_empty());
+        palette.step(1);
+        assert_eq!(palette.take(), None);
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Pausing thumbnailing, e.g. on battery or while sharing the screen. No new jobs are queued while
+// paused, the few in flight still finish. A snooze resumes by itself after a while. On Unix
+// SIGUSR1 pauses and SIGUSR2 resumes, for power and screen sharing hooks to send.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+pub static SNOOZE: Duration = Duration::from_secs(30 * 60);
+
+// What the last signal asked for.
+static SIGNALED: AtomicU8 = AtomicU8::new(NONE);
+const NONE: u8 = 0;
+const PAUSE: u8 = 1;
+const RESUME: u8 = 2;
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    let request = if signal == libc::SIGUSR1 {
+        PAUSE
+    } else {
+        RESUME
+    };
+    SIGNALED.store(request, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub fn install() {
+    for signal in [libc::SIGUSR1, libc::SIGUSR2] {
+        // Safe as the handler only touches an atomic.
+        unsafe { libc::signal(signal, handle as *const () as libc::sighandler_t) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+#[derive(Debug, Default)]
+pub struct Pause {
+    paused: bool,
+
+    // When a snooze ends.
+    until: Option<Instant>,
+}
+
+impl Pause {
+    pub fn new(paused: bool) -> Self {
+        Self {
+            paused,
+            until: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set(&mut self, paused: bool) {
+        self.paused = paused;
+        self.until = None;
+    }
+
+    pub fn snooze(&mut self, now: Instant) {
+        self.paused = true;
+        self.until = Some(now + SNOOZE);
+    }
+
+    // Apply signals and the end of a snooze, returns the new state when it changed.
+    pub fn update(&mut self, now: Instant) -> Option<bool> {
+        let was = self.paused;
+        match SIGNALED.swap(NONE, Ordering::SeqCst) {
+            PAUSE => self.set(true),
+            RESUME => self.set(false),
+            _ => {}
+        }
+        if self.until.is_some_and(|until| now >= until) {
+            self.set(false);
+        }
+        (self.paused != was).then_some(self.paused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snooze() {
+        let now = Instant::now();
+        let mut pause = Pause::new(false);
+        pause.snooze(now);
+        assert!(pause.is_paused());
+        assert_eq!(pause.update(now + SNOOZE / 2), None);
+        assert_eq!(pause.update(now + SNOOZE), Some(false));
+        assert!(!pause.is_paused());
+
+        // Pausing again cancels the snooze.
+        pause.snooze(now);
+        pause.set(true);
+        assert_eq!(pause.update(now + SNOOZE), None);
+        assert!(pause.is_paused());
+    }
+}
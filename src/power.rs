@@ -0,0 +1,204 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Battery awareness for laptops. The power source is checked every so often, and on battery
+// fewer decoders thumbnail, fewer frames are drawn and the single image view decodes fewer
+// neighbors ahead of time.
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::*;
+use std::time::{Duration, Instant};
+
+// How often the power source is checked.
+static INTERVAL: Duration = Duration::from_secs(30);
+
+// Decoders, frame rate cap and neighbors decoded either side of the single image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub threads: usize,
+    pub fps: u64,
+    pub neighbors: usize,
+}
+
+pub struct Power {
+    plugged: Budget,
+    battery: Budget,
+
+    // None when battery awareness is off.
+    next_check: Option<Instant>,
+    on_battery: bool,
+
+    // Checks run on their own thread, e.g. pmset takes a while to start.
+    checked_tx: Sender<Option<bool>>,
+    checked_rx: Receiver<Option<bool>>,
+}
+
+impl Power {
+    // `battery` is None to ignore the power source.
+    pub fn new(plugged: Budget, battery: Option<Budget>) -> Self {
+        let (checked_tx, checked_rx) = unbounded();
+        Self {
+            plugged,
+            battery: battery.unwrap_or(plugged),
+            next_check: battery.map(|_| Instant::now()),
+            on_battery: false,
+            checked_tx,
+            checked_rx,
+        }
+    }
+
+    pub fn budget(&self) -> Budget {
+        match self.on_battery {
+            true => self.battery,
+            false => self.plugged,
+        }
+    }
+
+    // Start a check of the power source when it's time, returns the new budget once a check
+    // finds it switched.
+    pub fn update(&mut self, now: Instant) -> Option<Budget> {
+        if matches!(self.next_check, Some(next) if now >= next) {
+            self.next_check = Some(now + INTERVAL);
+            let tx = self.checked_tx.clone();
+            std::thread::spawn(move || {
+                let _ = tx.send(on_battery());
+            });
+        }
+
+        let on_battery = self.checked_rx.try_iter().last()?.unwrap_or(false);
+        if on_battery == self.on_battery {
+            return None;
+        }
+        info!(
+            "Running on {}",
+            if on_battery { "battery" } else { "AC power" }
+        );
+        self.on_battery = on_battery;
+        Some(self.budget())
+    }
+}
+
+// Power supply class and the value that tells whether it powers the machine: `online` of mains
+// and USB supplies, `status` of batteries.
+#[cfg_attr(any(target_os = "macos", target_os = "windows"), allow(dead_code))]
+fn from_supplies<'a>(supplies: impl IntoIterator<Item = (&'a str, &'a str)>) -> Option<bool> {
+    let mut ret = None;
+    for (class, value) in supplies {
+        match class {
+            "Mains" | "USB" if value == "1" => return Some(false),
+            "Battery" => ret = Some(ret.unwrap_or(false) || value == "Discharging"),
+            _ => {}
+        }
+    }
+    ret
+}
+
+// Whether the machine runs on battery, None when it can't be told, e.g. desktops.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn on_battery() -> Option<bool> {
+    let read = |path: std::path::PathBuf| {
+        std::fs::read_to_string(path)
+            .map(|s| s.trim().to_owned())
+            .unwrap_or_default()
+    };
+    let supplies: Vec<(String, String)> = std::fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .flatten()
+        .map(|entry| {
+            let dir = entry.path();
+            let class = read(dir.join("type"));
+            let value = match class.as_str() {
+                "Battery" => read(dir.join("status")),
+                _ => read(dir.join("online")),
+            };
+            (class, value)
+        })
+        .collect();
+    from_supplies(supplies.iter().map(|(c, v)| (c.as_str(), v.as_str())))
+}
+
+#[cfg(target_os = "macos")]
+fn on_battery() -> Option<bool> {
+    let output = std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let first = output.lines().next()?;
+    Some(first.contains("'Battery Power'"))
+}
+
+#[cfg(target_os = "windows")]
+fn on_battery() -> Option<bool> {
+    // Filled in by Windows, only the AC line status is read.
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct SystemPowerStatus {
+        ac_line_status: u8,
+        battery_flag: u8,
+        battery_life_percent: u8,
+        system_status_flag: u8,
+        battery_life_time: u32,
+        battery_full_life_time: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetSystemPowerStatus(status: *mut SystemPowerStatus) -> i32;
+    }
+
+    let mut status = SystemPowerStatus::default();
+    // Safe as the struct matches SYSTEM_POWER_STATUS and outlives the call.
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    match status.ac_line_status {
+        0 => Some(true),
+        1 => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supplies() {
+        assert_eq!(from_supplies([("Battery", "Discharging")]), Some(true));
+        assert_eq!(
+            from_supplies([("Battery", "Charging"), ("Mains", "1")]),
+            Some(false)
+        );
+        assert_eq!(
+            from_supplies([("Mains", "0"), ("Battery", "Full")]),
+            Some(false)
+        );
+        assert_eq!(from_supplies([("UPS", "1")]), None);
+        assert_eq!(from_supplies([]), None);
+    }
+
+    #[test]
+    fn ignored() {
+        let budget = Budget {
+            threads: 4,
+            fps: 60,
+            neighbors: 2,
+        };
+        let mut power = Power::new(budget, None);
+        assert_eq!(power.update(Instant::now() + INTERVAL), None);
+        assert_eq!(power.budget(), budget);
+    }
+}
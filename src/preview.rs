@@ -0,0 +1,131 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Pane to the right of the grid with a large preview of the last image under the cursor.
+
+use crate::database::Database;
+use crate::groups::Groups;
+use crate::hover::layout;
+use crate::vec::*;
+use crate::view::View;
+use crate::{Stopwatch, TileMap, TileRef};
+use piston_window::{rectangle, Context, G2d, G2dTexture, G2dTextureContext, Image, Transformed};
+
+// Fraction of the window width taken by the pane.
+static WIDTH: f64 = 0.4;
+
+// Fraction of the pane height taken by the image, the info lines go below.
+static IMAGE_HEIGHT: f64 = 0.75;
+
+#[derive(Default)]
+pub struct Preview {
+    // Grid coordinates, page and pyramid level of the previewed image.
+    image: Option<(Vector2<u32>, usize, usize)>,
+
+    img_size: Vector2<u32>,
+    layout: Vec<(TileRef, [f64; 4])>,
+    tiles: TileMap<G2dTexture>,
+}
+
+// Width of the grid left of the pane.
+pub fn grid_width(win_width: u32) -> u32 {
+    (win_width as f64 * (1.0 - WIDTH)) as u32
+}
+
+impl Preview {
+    pub fn coords(&self) -> Option<Vector2<u32>> {
+        self.image.map(|(coords, _, _)| coords)
+    }
+
+    // Follow the cursor over the grid and load the thumbnail nearest to the pane size.
+    pub fn update(
+        &mut self,
+        view: &View,
+        groups: &Groups,
+        db: &Database,
+        texture_context: &mut G2dTextureContext,
+        stopwatch: &Stopwatch,
+        win_size: Vector2<u32>,
+    ) {
+        // Keep the last image while the cursor is over the pane.
+        let coords = match view.mouse_coords().or(self.coords()) {
+            Some(coords) => coords,
+            None => return,
+        };
+        let (image, metadata) = match groups.get(coords) {
+            Some(image) => match image.get_metadata() {
+                Some(metadata) => (image, metadata),
+                None => return,
+            },
+            None => return,
+        };
+
+        let [w, h] = win_size;
+        let pane_size = u32::max(w - grid_width(w), (h as f64 * IMAGE_HEIGHT) as u32);
+        let size = metadata.nearest(image.page, pane_size.next_power_of_two());
+
+        if self.image != Some((coords, image.page, size)) {
+            let thumb = &metadata.thumbs(image.page)[size];
+            *self = Self {
+                image: Some((coords, image.page, size)),
+                img_size: thumb.img_size,
+                layout: layout(thumb),
+                ..Default::default()
+            };
+        }
+
+        for (tile_ref, _) in &self.layout {
+            if self.tiles.contains_key(tile_ref) {
+                continue;
+            }
+
+            if stopwatch.done() {
+                return;
+            }
+
+            let texture = crate::upload::tile(db, *tile_ref, texture_context, |_| {});
+            self.tiles.insert(*tile_ref, texture);
+        }
+    }
+
+    // Draw the pane, returns the window position for the info lines.
+    pub fn draw(&self, c: &Context, g: &mut G2d) -> Vector2<f64> {
+        let [win_w, win_h] = c.get_view_size();
+        let x = grid_width(win_w as u32) as f64;
+        let [w, h] = [win_w - x, win_h * IMAGE_HEIGHT];
+
+        rectangle(
+            [0.05, 0.05, 0.05, 1.0],
+            [x, 0.0, win_w - x, win_h],
+            c.transform,
+            g,
+        );
+
+        let [img_w, img_h] = vec2_f64(self.img_size);
+        if img_w > 0.0 && img_h > 0.0 {
+            let scale = f64::min(w / img_w, h / img_h);
+            let origin = [x + (w - img_w * scale) / 2.0, (h - img_h * scale) / 2.0];
+            let trans = c.transform.trans(origin[0], origin[1]).zoom(scale);
+            for (tile_ref, rect) in &self.layout {
+                if let Some(texture) = self.tiles.get(tile_ref) {
+                    Image::new()
+                        .rect(*rect)
+                        .draw(texture, &c.draw_state, trans, g);
+                }
+            }
+        }
+
+        [x + 8.0, h + 8.0]
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Print preparation checks for the single image: grayscale, a single channel shown as grayscale,
+// or a rough soft proof of how the image prints. The proof goes through a naive CMYK conversion
+// with a total ink limit, squeezes colors too saturated for ink, and maps white and black to
+// paper and ink. It's no substitute for ICC profiles, it shows which colors will dull and how much
+// contrast the paper loses.
+
+use ::image::RgbaImage;
+
+// Paper white and the darkest black ink lays down, in 0 to 1 RGB.
+static PAPER: [f32; 3] = [0.96, 0.95, 0.91];
+static INK: [f32; 3] = [0.12, 0.11, 0.12];
+
+// Most ink all four plates may lay down together, 3 is 300%.
+static INK_LIMIT: f32 = 3.0;
+
+// Chroma beyond this is squeezed, ink reaches less saturated colors than screens.
+static CHROMA: f32 = 0.6;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Proof {
+    #[default]
+    Off,
+    Gray,
+    Red,
+    Green,
+    Blue,
+    Print,
+}
+
+impl Proof {
+    // Next of grayscale and the channels, the soft proof is toggled separately.
+    pub fn next_channel(self) -> Self {
+        match self {
+            Proof::Gray => Proof::Red,
+            Proof::Red => Proof::Green,
+            Proof::Green => Proof::Blue,
+            Proof::Blue => Proof::Off,
+            Proof::Off | Proof::Print => Proof::Gray,
+        }
+    }
+
+    pub fn toggle_print(self) -> Self {
+        match self {
+            Proof::Print => Proof::Off,
+            _ => Proof::Print,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Proof::Off => "full color",
+            Proof::Gray => "grayscale",
+            Proof::Red => "red channel",
+            Proof::Green => "green channel",
+            Proof::Blue => "blue channel",
+            Proof::Print => "print proof",
+        }
+    }
+
+    pub fn apply(self, image: &mut RgbaImage) {
+        if self == Proof::Off {
+            return;
+        }
+        for ::image::Rgba(p) in image.pixels_mut() {
+            let rgb = [p[0], p[1], p[2]].map(|v| v as f32 / 255.0);
+            let rgb = match self {
+                Proof::Off => rgb,
+                Proof::Gray => [luma(rgb); 3],
+                Proof::Red => [rgb[0]; 3],
+                Proof::Green => [rgb[1]; 3],
+                Proof::Blue => [rgb[2]; 3],
+                Proof::Print => print(rgb),
+            };
+            for (v, c) in p.iter_mut().zip(rgb) {
+                *v = (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+fn luma([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn print(rgb: [f32; 3]) -> [f32; 3] {
+    // Squeeze the chroma, keeping luma.
+    let y = luma(rgb);
+    let max = rgb.into_iter().fold(0.0, f32::max);
+    let min = rgb.into_iter().fold(1.0, f32::min);
+    let chroma = max - min;
+    let rgb = if chroma > CHROMA {
+        let squeezed = CHROMA + (chroma - CHROMA) * 0.3;
+        rgb.map(|v| y + (v - y) * squeezed / chroma)
+    } else {
+        rgb
+    };
+
+    // Naive CMYK with the black taken from the common part of the inks.
+    let [c, m, yellow] = rgb.map(|v| 1.0 - v.clamp(0.0, 1.0));
+    let k = c.min(m).min(yellow);
+    let mut cmy = [c, m, yellow].map(|v| if k < 1.0 { (v - k) / (1.0 - k) } else { 0.0 });
+    let total = cmy.iter().sum::<f32>() + k;
+    if total > INK_LIMIT {
+        let scale = (INK_LIMIT - k) / (total - k);
+        cmy = cmy.map(|v| v * scale);
+    }
+
+    // Back to RGB, on paper.
+    let [r, g, b] = cmy.map(|v| (1.0 - v) * (1.0 - k));
+    [0, 1, 2].map(|i| INK[i] + (PAPER[i] - INK[i]) * [r, g, b][i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(proof: Proof, rgba: [u8; 4]) -> [u8; 4] {
+        let mut image = RgbaImage::from_pixel(1, 1, ::image::Rgba(rgba));
+        proof.apply(&mut image);
+        image.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn proofs() {
+        let orange = [250, 120, 20, 200];
+        assert_eq!(apply(Proof::Off, orange), orange);
+        assert_eq!(apply(Proof::Gray, orange), [140, 140, 140, 200]);
+        assert_eq!(apply(Proof::Red, orange), [250, 250, 250, 200]);
+        assert_eq!(apply(Proof::Blue, orange), [20, 20, 20, 200]);
+
+        // Paper isn't as white, ink isn't as black.
+        assert_eq!(
+            apply(Proof::Print, [255, 255, 255, 255]),
+            [245, 242, 232, 255]
+        );
+        assert_eq!(apply(Proof::Print, [0, 0, 0, 255]), [31, 28, 31, 255]);
+
+        // Saturated colors dull.
+        let [r, g, b, _] = apply(Proof::Print, [0, 0, 255, 255]);
+        assert!(r > 20 && g > 20 && b < 245);
+
+        let mut proof = Proof::Print;
+        for expected in [
+            Proof::Gray,
+            Proof::Red,
+            Proof::Green,
+            Proof::Blue,
+            Proof::Off,
+        ] {
+            proof = proof.next_channel();
+            assert_eq!(proof, expected);
+        }
+        assert_eq!(proof.toggle_print().toggle_print(), Proof::Off);
+    }
+}
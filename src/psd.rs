@@ -0,0 +1,290 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Decode the merged composite stored at the end of Photoshop PSD/PSB files, layers are ignored.
+
+use crate::{E, R};
+use ::image::{DynamicImage, ImageBuffer, Luma, Rgba};
+
+static MODE_GRAYSCALE: u16 = 1;
+static MODE_RGB: u16 = 3;
+
+// A two byte PackBits run decodes to at most 128 bytes.
+static MAX_RLE_EXPANSION: usize = 64;
+
+pub fn is_psd(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".psd") || path.ends_with(".psb")
+}
+
+fn err(msg: &str) -> E {
+    E::PsdError(msg.to_owned())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> R<&'a [u8]> {
+        let b = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| err("truncated file"))?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn u16(&mut self) -> R<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> R<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> R<u64> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn skip_section(&mut self, long: bool) -> R<()> {
+        let len = if long {
+            usize::try_from(self.u64()?).map_err(|_| err("truncated file"))?
+        } else {
+            self.u32()? as usize
+        };
+        self.bytes(len)?;
+        Ok(())
+    }
+}
+
+// Decode a PackBits compressed row.
+fn unpack_bits(mut src: &[u8], len: usize) -> R<Vec<u8>> {
+    let mut dst = Vec::with_capacity(len);
+    while dst.len() < len {
+        let (&n, rest) = src.split_first().ok_or_else(|| err("truncated rle data"))?;
+        let n = n as i8;
+        if n >= 0 {
+            let count = n as usize + 1;
+            let literal = rest.get(..count).ok_or_else(|| err("truncated rle data"))?;
+            dst.extend_from_slice(literal);
+            src = &rest[count..];
+        } else if n != -128 {
+            let count = (1 - n as isize) as usize;
+            let (&v, rest) = rest
+                .split_first()
+                .ok_or_else(|| err("truncated rle data"))?;
+            dst.resize(dst.len() + count, v);
+            src = rest;
+        } else {
+            src = rest;
+        }
+    }
+    dst.truncate(len);
+    Ok(dst)
+}
+
+// Width and height from the file header.
+pub fn dimensions(data: &[u8]) -> Option<[u32; 2]> {
+    let mut r = Reader { data, pos: 0 };
+    if r.bytes(4).ok()? != b"8BPS" {
+        return None;
+    }
+    // Version, reserved bytes and channels.
+    r.bytes(10).ok()?;
+    let h = r.u32().ok()?;
+    let w = r.u32().ok()?;
+    Some([w, h])
+}
+
+pub fn open(path: &str) -> R<DynamicImage> {
+    let data = std::fs::read(path).map_err(|e| err(&e.to_string()))?;
+    decode(&data)
+}
+
+pub fn decode(data: &[u8]) -> R<DynamicImage> {
+    let mut r = Reader { data, pos: 0 };
+
+    if r.bytes(4)? != b"8BPS" {
+        return Err(err("missing 8BPS signature"));
+    }
+    let psb = match r.u16()? {
+        1 => false,
+        2 => true,
+        _ => return Err(err("unknown version")),
+    };
+    r.bytes(6)?;
+    let channels = r.u16()? as usize;
+    let h = r.u32()?;
+    let w = r.u32()?;
+    let depth = r.u16()?;
+    let mode = r.u16()?;
+
+    if depth != 8 && depth != 16 {
+        return Err(err("only 8 and 16-bit images are supported"));
+    }
+    let color_channels = match mode {
+        m if m == MODE_RGB => 3,
+        m if m == MODE_GRAYSCALE => 1,
+        _ => return Err(err("only RGB and grayscale images are supported")),
+    };
+    if channels < color_channels {
+        return Err(err("missing color channels"));
+    }
+
+    // Color mode data, image resources, then layer and mask info.
+    r.skip_section(false)?;
+    r.skip_section(false)?;
+    r.skip_section(psb)?;
+
+    let too_large = || err("image too large");
+    let row_len = (w as usize)
+        .checked_mul((depth / 8) as usize)
+        .ok_or_else(too_large)?;
+    let rows = (h as usize).checked_mul(channels).ok_or_else(too_large)?;
+    let plane_len = row_len.checked_mul(h as usize).ok_or_else(too_large)?;
+    let planes_len = row_len.checked_mul(rows).ok_or_else(too_large)?;
+
+    let planes: Vec<u8> = match r.u16()? {
+        0 => r.bytes(planes_len)?.to_vec(),
+        1 => {
+            let len_size = if psb { 4 } else { 2 };
+            if rows > r.remaining() / len_size {
+                return Err(err("truncated file"));
+            }
+            let mut lens = Vec::with_capacity(rows);
+            for _ in 0..rows {
+                lens.push(if psb {
+                    r.u32()? as usize
+                } else {
+                    r.u16()? as usize
+                });
+            }
+            // The header's dimensions are untrusted, only reserve what the data could expand to.
+            let mut planes = Vec::with_capacity(std::cmp::min(
+                planes_len,
+                r.remaining().saturating_mul(MAX_RLE_EXPANSION),
+            ));
+            for len in lens {
+                planes.extend(unpack_bits(r.bytes(len)?, row_len)?);
+            }
+            planes
+        }
+        _ => return Err(err("unsupported compression")),
+    };
+
+    let sample = |c: usize, x: u32, y: u32| -> u16 {
+        let i = c * plane_len + y as usize * row_len;
+        if depth == 16 {
+            let i = i + 2 * x as usize;
+            u16::from_be_bytes([planes[i], planes[i + 1]])
+        } else {
+            planes[i + x as usize] as u16 * 257
+        }
+    };
+
+    // The first extra channel is transparency.
+    let alpha = channels > color_channels;
+
+    Ok(if color_channels == 1 && !alpha {
+        DynamicImage::ImageLuma16(ImageBuffer::from_fn(w, h, |x, y| Luma([sample(0, x, y)])))
+    } else {
+        DynamicImage::ImageRgba16(ImageBuffer::from_fn(w, h, |x, y| {
+            let c = |i: usize| sample(std::cmp::min(i, color_channels - 1), x, y);
+            let a = if alpha {
+                sample(color_channels, x, y)
+            } else {
+                u16::MAX
+            };
+            Rgba([c(0), c(1), c(2), a])
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn psd(compression: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"8BPS");
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&[0; 6]);
+        out.extend_from_slice(&3u16.to_be_bytes());
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend_from_slice(&2u32.to_be_bytes());
+        out.extend_from_slice(&8u16.to_be_bytes());
+        out.extend_from_slice(&MODE_RGB.to_be_bytes());
+        out.extend_from_slice(&[0; 12]);
+        out.extend_from_slice(&compression.to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn raw() {
+        let image = decode(&psd(0, &[255, 0, 0, 255, 0, 0])).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn rle() {
+        // Row byte counts, then a repeat run, a literal run and a repeat run of zeros.
+        let data = [0, 2, 0, 3, 0, 2, 0xFF, 10, 1, 20, 30, 0xFF, 0];
+        let image = decode(&psd(1, &data)).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [10, 20, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [10, 30, 0, 255]);
+    }
+
+    #[test]
+    fn huge_section() {
+        let mut data = vec![0; 2];
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        let mut reader = Reader {
+            data: &data,
+            pos: 2,
+        };
+        assert!(reader.skip_section(true).is_err());
+    }
+
+    #[test]
+    fn huge_dimensions() {
+        let mut data = psd(1, &[0, 2, 0xFF, 0]);
+        // 300000x300000 pixels, 16-bit, many channels.
+        data[12..14].copy_from_slice(&56u16.to_be_bytes());
+        data[14..18].copy_from_slice(&300_000u32.to_be_bytes());
+        data[18..22].copy_from_slice(&300_000u32.to_be_bytes());
+        data[22..24].copy_from_slice(&16u16.to_be_bytes());
+        assert!(decode(&data).is_err());
+    }
+
+    #[test]
+    fn unpack() {
+        assert_eq!(
+            unpack_bits(&[0xFE, 7, 1, 1, 2], 5).unwrap(),
+            vec![7, 7, 7, 1, 2]
+        );
+        assert!(unpack_bits(&[3, 1], 4).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Sharpness and brightness of photos, to pick the keeper of a burst and find the shots to delete.
+// Sharpness is the variance of the Laplacian of the luma, sampled on a grid at full resolution
+// where missed focus and motion blur show, rather than over a thumbnail where every frame looks
+// sharp.
+
+use ::image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Samples along each edge.
+static SAMPLES: u32 = 512;
+
+// Sharper images score higher, the usual cutoff for the variance of the Laplacian.
+static BLURRY: u32 = 100;
+
+// Mean luma of underexposed shots.
+static DARK: u8 = 40;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Quality {
+    pub sharpness: u32,
+
+    // Mean luma.
+    pub brightness: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flaw {
+    Blurry,
+    Dark,
+}
+
+impl FromStr for Flaw {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blurry" => Ok(Flaw::Blurry),
+            "dark" => Ok(Flaw::Dark),
+            _ => Err(format!("unknown quality {:?}", s)),
+        }
+    }
+}
+
+impl Quality {
+    pub fn has(&self, flaw: Flaw) -> bool {
+        match flaw {
+            Flaw::Blurry => self.sharpness < BLURRY,
+            Flaw::Dark => self.brightness < DARK,
+        }
+    }
+}
+
+pub fn measure(image: &DynamicImage) -> Quality {
+    let _s = crate::stats::ScopedDuration::new("quality");
+    let (w, h) = image.dimensions();
+    if w < 3 || h < 3 {
+        return Quality {
+            sharpness: 0,
+            brightness: 0,
+        };
+    }
+
+    let luma = |x, y| {
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+    };
+    let step = |size: u32| u32::max(1, (size - 2) / SAMPLES) as usize;
+
+    let (mut n, mut sum, mut sum_sq, mut sum_luma) = (0.0, 0.0, 0.0, 0.0);
+    for y in (1..h - 1).step_by(step(h)) {
+        for x in (1..w - 1).step_by(step(w)) {
+            let center = luma(x, y);
+            let laplacian =
+                4.0 * center - luma(x - 1, y) - luma(x + 1, y) - luma(x, y - 1) - luma(x, y + 1);
+            n += 1.0;
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+            sum_luma += center;
+        }
+    }
+    let mean = sum / n;
+    Quality {
+        sharpness: (sum_sq / n - mean * mean).round() as u32,
+        brightness: (sum_luma / n).round() as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{GrayImage, Luma};
+
+    #[test]
+    fn sharper_scores_higher() {
+        let stripes = GrayImage::from_fn(64, 64, |x, _| Luma([if x % 4 < 2 { 0 } else { 255 }]));
+        let sharp = DynamicImage::ImageLuma8(stripes);
+        let blurred = sharp.blur(2.0);
+        let flat = DynamicImage::ImageLuma8(GrayImage::from_pixel(64, 64, Luma([20])));
+
+        let [sharp, blurred, flat] = [sharp, blurred, flat].map(|image| measure(&image));
+        assert!(sharp.sharpness > blurred.sharpness);
+        assert!(blurred.sharpness > flat.sharpness);
+        assert_eq!(flat.sharpness, 0);
+        assert_eq!(measure(&DynamicImage::new_luma8(2, 2)).sharpness, 0);
+
+        assert!(!sharp.has(Flaw::Blurry) && !sharp.has(Flaw::Dark));
+        assert!(flat.has(Flaw::Blurry) && flat.has(Flaw::Dark));
+        assert_eq!(flat.brightness, 20);
+    }
+}
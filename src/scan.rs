@@ -0,0 +1,280 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Searching the opened directories again in the background, for F5 and kiosk reloads. The
+// files found are kept in the database, so the next start shows them right away and searches
+// again in the background.
+
+use crate::database::Database;
+use crate::filter::Filter;
+use crate::fingerprint;
+use crate::userdata::UserData;
+use crate::{File, Metadata, MetadataState};
+use crossbeam_channel::{bounded, Receiver, TryRecvError};
+use log::*;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A file found with its user data and metadata.
+pub type Found = (Arc<File>, UserData, MetadataState);
+
+pub struct Scan {
+    paths: Vec<PathBuf>,
+    filter: Option<Filter>,
+    verify_content: bool,
+
+    // Files found by a search in progress.
+    pending: Option<Receiver<Vec<Found>>>,
+}
+
+// How a new search differs from the files shown.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+impl Diff {
+    pub fn new<'a>(
+        old: impl Iterator<Item = &'a File>,
+        new: impl Iterator<Item = &'a File>,
+    ) -> Self {
+        let mut old: BTreeMap<&str, &File> = old.map(|file| (file.path.as_str(), file)).collect();
+
+        let mut diff = Self::default();
+        for file in new {
+            match old.remove(file.path.as_str()) {
+                Some(old) if old != file => diff.changed += 1,
+                Some(_) => {}
+                None => diff.added += 1,
+            }
+        }
+        diff.removed = old.len();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Scan {
+    pub fn new(paths: Vec<PathBuf>, filter: Option<Filter>, verify_content: bool) -> Self {
+        Self {
+            paths,
+            filter,
+            verify_content,
+            pending: None,
+        }
+    }
+
+    // The files found by the last search at startup, searching again in the background. The
+    // first time the search runs in the foreground.
+    pub fn load(&mut self, db: &Arc<Database>) -> Vec<Found> {
+        let files = match db.get_scan(&key(&self.paths)) {
+            Ok(Some(files)) if !files.is_empty() => {
+                info!("Showing {} files of the last search", files.len());
+                self.start(db);
+                files.into_iter().map(Arc::new).collect()
+            }
+            Ok(_) => find(db, &self.paths),
+            Err(e) => {
+                error!("error loading the last search: {:?}", e);
+                find(db, &self.paths)
+            }
+        };
+        crate::stats::stage("startup_scan");
+        load(db, files, self.filter.as_ref(), self.verify_content)
+    }
+
+    pub fn filter(&self) -> Option<&Filter> {
+        self.filter.as_ref()
+    }
+
+    // Filter the images of the next search with `filter`.
+    pub fn set_filter(&mut self, filter: Option<Filter>) {
+        self.filter = filter;
+    }
+
+    // Search `paths` too from the next search on, returns how many weren't searched before.
+    pub fn add(&mut self, paths: Vec<PathBuf>) -> usize {
+        let n = self.paths.len();
+        for path in paths {
+            if !self.paths.contains(&path) {
+                self.paths.push(path);
+            }
+        }
+        self.paths.len() - n
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Search again in the background unless a search is in progress.
+    pub fn start(&mut self, db: &Arc<Database>) {
+        if self.is_running() {
+            return;
+        }
+
+        let (tx, rx) = bounded(1);
+        let db = Arc::clone(db);
+        let paths = self.paths.clone();
+        let filter = self.filter.clone();
+        let verify_content = self.verify_content;
+        std::thread::spawn(move || {
+            let files = find(&db, &paths);
+            let found = load(&db, files, filter.as_ref(), verify_content);
+            let _ = tx.send(found);
+        });
+        self.pending = Some(rx);
+    }
+
+    // The files found once the search finished.
+    pub fn poll(&mut self) -> Option<Vec<Found>> {
+        let rx = self.pending.as_ref()?;
+        match rx.try_recv() {
+            Ok(found) => {
+                self.pending = None;
+                Some(found)
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.pending = None;
+                None
+            }
+        }
+    }
+}
+
+// The same directories opened from elsewhere share the last search.
+fn key(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+        .collect()
+}
+
+// Search `paths` and keep the files found for the next start.
+fn find(db: &Database, paths: &[PathBuf]) -> Vec<Arc<File>> {
+    let files = crate::find_images(paths.to_vec());
+    if let Err(e) = db.set_scan(&key(paths), &files) {
+        error!("error saving the search: {:?}", e);
+    }
+    files
+}
+
+// `files` with their user data and metadata, in grid order. With `verify_content` thumbnails of
+// files whose contents changed are made again.
+pub fn load(
+    db: &Database,
+    files: Vec<Arc<File>>,
+    filter: Option<&Filter>,
+    verify_content: bool,
+) -> Vec<Found> {
+    // Metadata is loaded before filtering, it has the captions and quality terms match.
+    let files: Vec<Found> = files
+        .into_par_iter()
+        .map(|file| {
+            let user_data = db.get_user_data(&file).unwrap_or_else(|e| {
+                error!("error loading user data for: {:?}: {:?}", file, e);
+                UserData::default()
+            });
+            let metadata = load_metadata(db, &file, verify_content);
+            (file, user_data, metadata)
+        })
+        .collect();
+    match filter {
+        Some(filter) => {
+            info!("Filter: {}", filter.spec());
+            filter.apply(files, |(file, user_data, metadata)| {
+                let metadata = match metadata {
+                    MetadataState::Some(metadata) => Some(metadata),
+                    _ => None,
+                };
+                (file, user_data, metadata)
+            })
+        }
+        None => files,
+    }
+}
+
+pub fn load_metadata(db: &Database, file: &File, verify_content: bool) -> MetadataState {
+    match db.get_metadata(file) {
+        Ok(Some(metadata)) if verify_content && !is_unchanged(file, &metadata) => {
+            info!("Contents of {:?} changed", file.path);
+            MetadataState::Missing
+        }
+        Ok(Some(metadata)) => MetadataState::Some(metadata),
+        Ok(None) => match db.get_failure(file) {
+            Ok(Some(failure)) if failure.is_permanent() => MetadataState::Errored,
+            _ => MetadataState::Missing,
+        },
+        Err(e) => {
+            error!("error loading metadata for: {:?}: {:?}", file, e);
+            MetadataState::Errored
+        }
+    }
+}
+
+// Whether the file still has the contents its thumbnails were made from.
+fn is_unchanged(file: &File, metadata: &Metadata) -> bool {
+    match (metadata.fingerprint, fingerprint::of_file(&file.path)) {
+        (Some(old), Ok(new)) => old == new,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, modified: u64) -> File {
+        File {
+            path: path.to_owned(),
+            modified,
+            file_size: 1,
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let old = [file("/a", 1), file("/b", 1), file("/c", 1)];
+        let new = [file("/a", 1), file("/b", 2), file("/d", 1)];
+        assert_eq!(
+            Diff::new(old.iter(), new.iter()),
+            Diff {
+                added: 1,
+                removed: 1,
+                changed: 1,
+            }
+        );
+        assert!(Diff::new(old.iter(), old.iter()).is_empty());
+    }
+
+    #[test]
+    fn add() {
+        let mut scan = Scan::new(vec![PathBuf::from("/a")], None, false);
+        let paths = vec![
+            PathBuf::from("/a"),
+            PathBuf::from("/b"),
+            PathBuf::from("/b"),
+        ];
+        assert_eq!(scan.add(paths), 1);
+        assert_eq!(scan.paths, vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Rough scene types guessed from the colors of a small copy of each image, for filters. Night
+// shots are mostly dark, outdoor ones have blue sky at the top or foliage at the bottom, and
+// documents are mostly paper white with a little dark, unsaturated ink. These are heuristics, they
+// sort a folder roughly, not reliably.
+
+use ::image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Long edge of the copy the colors are counted on.
+static SIZE: u32 = 64;
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Scene {
+    pub night: bool,
+    pub outdoor: bool,
+    pub document: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Day,
+    Night,
+    Indoor,
+    Outdoor,
+}
+
+impl FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Tag::Day),
+            "night" => Ok(Tag::Night),
+            "indoor" => Ok(Tag::Indoor),
+            "outdoor" => Ok(Tag::Outdoor),
+            _ => Err(format!("unknown scene {:?}", s)),
+        }
+    }
+}
+
+impl Scene {
+    // Documents are neither day nor night, indoor nor outdoor.
+    pub fn has(&self, tag: Tag) -> bool {
+        if self.document {
+            return false;
+        }
+        match tag {
+            Tag::Day => !self.night,
+            Tag::Night => self.night,
+            Tag::Indoor => !self.outdoor,
+            Tag::Outdoor => self.outdoor,
+        }
+    }
+}
+
+pub fn classify(image: &DynamicImage) -> Scene {
+    let _s = crate::stats::ScopedDuration::new("classify_scene");
+    let small = image.thumbnail(SIZE, SIZE).into_rgb8();
+    let h = small.height();
+
+    let (mut n, mut dark, mut gray, mut white, mut ink) = (0.0, 0, 0, 0, 0);
+    let (mut sky, mut foliage) = (0, 0);
+    for (_, y, pixel) in small.enumerate_pixels() {
+        let [r, g, b] = pixel.0.map(|c| c as i32);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let luma = (299 * r + 587 * g + 114 * b) / 1000;
+        let unsaturated = max - min < 24;
+
+        n += 1.0;
+        dark += (luma < 50) as u32;
+        gray += unsaturated as u32;
+        white += (unsaturated && luma > 190) as u32;
+        // Text shrunk to a few pixels is gray rather than black.
+        ink += (unsaturated && luma < 160) as u32;
+        if y < h / 3 {
+            sky += (b > r + 20 && b >= g && luma > 90) as u32;
+        } else if y >= h * 2 / 3 {
+            foliage += (g > r + 10 && g > b + 10) as u32;
+        }
+    }
+    let share = |count: u32| count as f64 / n;
+    let third = 1.0 / 3.0;
+
+    Scene {
+        night: share(dark) > 0.6,
+        outdoor: share(sky) > 0.4 * third || share(foliage) > 0.5 * third,
+        document: share(gray) > 0.9 && share(white) > 0.5 && share(ink) > 0.01,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{Rgb, RgbImage};
+
+    #[test]
+    fn scenes() {
+        let classify = |f: fn(u32, u32) -> [u8; 3]| {
+            classify(&DynamicImage::ImageRgb8(RgbImage::from_fn(
+                90,
+                60,
+                |x, y| Rgb(f(x, y)),
+            )))
+        };
+
+        let beach = classify(|_, y| {
+            if y < 30 {
+                [90, 150, 230]
+            } else {
+                [220, 200, 150]
+            }
+        });
+        assert!(beach.has(Tag::Day) && beach.has(Tag::Outdoor));
+
+        let room = classify(|_, _| [180, 140, 110]);
+        assert!(room.has(Tag::Day) && room.has(Tag::Indoor));
+
+        let street = classify(|x, _| {
+            if x % 10 == 0 {
+                [250, 200, 80]
+            } else {
+                [15, 15, 25]
+            }
+        });
+        assert!(street.has(Tag::Night) && !street.has(Tag::Day));
+
+        let page = classify(|x, y| match (x % 6, y % 4) {
+            (0..=3, 0) => [30, 30, 30],
+            _ => [245, 245, 240],
+        });
+        assert!(page.document);
+        assert!(!page.has(Tag::Day) && !page.has(Tag::Indoor));
+        assert!(!beach.document && !room.document && !street.document);
+    }
+}
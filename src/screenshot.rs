@@ -0,0 +1,123 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Screen captures, told apart from photos while thumbnailing so folders like Downloads can be
+// split with `kind:screenshot` and `kind:photo`. A file is a screenshot when its name says so in
+// one of the usual languages, or when it's a PNG without EXIF the size of a common display.
+
+use std::io::Cursor;
+
+// Lowercase file name parts of the capture tools of the major systems.
+static NAMES: &[&str] = &[
+    "screenshot",
+    "screen shot",
+    "screen_shot",
+    "bildschirmfoto",
+    "capture d’écran",
+    "capture d'écran",
+    "captura de pantalla",
+    "schermafbeelding",
+    "istantanea",
+    "cleanshot",
+];
+
+// Sizes of common monitors and phones in landscape, portrait ones are matched too.
+static DISPLAYS: &[[u32; 2]] = &[
+    [1280, 720],
+    [1280, 800],
+    [1280, 1024],
+    [1366, 768],
+    [1440, 900],
+    [1536, 864],
+    [1600, 900],
+    [1680, 1050],
+    [1920, 1080],
+    [1920, 1200],
+    [2560, 1080],
+    [2560, 1440],
+    [2560, 1600],
+    [2880, 1800],
+    [3024, 1964],
+    [3440, 1440],
+    [3456, 2234],
+    [3840, 2160],
+    [5120, 2880],
+    [1334, 750],
+    [1792, 828],
+    [2340, 1080],
+    [2400, 1080],
+    [2436, 1125],
+    [2532, 1170],
+    [2556, 1179],
+    [2688, 1242],
+    [2778, 1284],
+    [2796, 1290],
+    [3200, 1440],
+];
+
+fn named(path: &str) -> bool {
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    NAMES.iter().any(|part| name.contains(part))
+}
+
+fn display_sized([w, h]: [u32; 2]) -> bool {
+    DISPLAYS.contains(&[w, h]) || DISPLAYS.contains(&[h, w])
+}
+
+// `bytes` are the file contents, without them only the name is checked. `size` is the full size
+// of the image.
+pub fn detect(path: &str, bytes: Option<&[u8]>, size: [u32; 2]) -> bool {
+    if named(path) {
+        return true;
+    }
+    let bytes = match bytes {
+        Some(bytes) if path.to_lowercase().ends_with(".png") => bytes,
+        _ => return false,
+    };
+    let has_exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .is_ok();
+    !has_exif && display_sized(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screenshots() {
+        assert!(detect(
+            "/d/Screenshot 2023-04-01 at 10.12.13.png",
+            None,
+            [10, 10]
+        ));
+        assert!(detect(
+            "/d/Bildschirmfoto vom 2023-04-01.jpg",
+            None,
+            [10, 10]
+        ));
+        assert!(detect("/d/Screenshot_20230401-101213.jpg", None, [10, 10]));
+
+        let png = b"\x89PNG\r\n\x1a\n";
+        assert!(detect("/d/image.PNG", Some(png), [1920, 1080]));
+        assert!(detect("/d/image.png", Some(png), [1170, 2532]));
+        assert!(!detect("/d/image.png", Some(png), [1000, 1000]));
+        assert!(!detect("/d/image.png", None, [1920, 1080]));
+        assert!(!detect("/d/image.jpg", Some(png), [1920, 1080]));
+        assert!(!detect("/screenshots/beach.jpg", None, [4000, 3000]));
+    }
+}
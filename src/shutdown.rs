@@ -0,0 +1,43 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// SIGINT and SIGTERM close the window like the user would, so pending writes are finished and
+// flushed. A second signal exits right away.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle(_: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        // Only async-signal-safe calls in here.
+        unsafe { libc::_exit(130) };
+    }
+}
+
+#[cfg(unix)]
+pub fn install() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // Safe as the handler only touches an atomic and exits.
+        unsafe { libc::signal(signal, handle as *const () as libc::sighandler_t) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
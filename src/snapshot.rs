@@ -0,0 +1,89 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// PNG snapshots of the window contents, read back from the frame just drawn.
+
+use crate::vec::*;
+use crate::{E, R};
+use ::image::RgbaImage;
+use piston_window::GfxDevice;
+use std::path::{Path, PathBuf};
+
+// Read the back buffer, call after drawing and before the buffers are swapped.
+pub fn capture(device: &mut GfxDevice, [w, h]: Vector2<u32>) -> RgbaImage {
+    let mut pixels = vec![0u8; w as usize * h as usize * 4];
+
+    // Safety: the buffer holds exactly w * h tightly packed RGBA pixels.
+    unsafe {
+        device.with_gl(|gl| {
+            gl.PixelStorei(gfx_gl::PACK_ALIGNMENT, 1);
+            gl.ReadPixels(
+                0,
+                0,
+                w as i32,
+                h as i32,
+                gfx_gl::RGBA,
+                gfx_gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut _,
+            );
+        });
+    }
+
+    // OpenGL rows start at the bottom.
+    let mut image = RgbaImage::from_raw(w, h, pixels).expect("snapshot size");
+    ::image::imageops::flip_vertical_in_place(&mut image);
+    image
+}
+
+// A snapshot file name in `dir` that doesn't exist yet.
+fn path_in(dir: &Path) -> PathBuf {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut n = 1;
+    loop {
+        let name = if n == 1 {
+            format!("pix-{}.png", secs)
+        } else {
+            format!("pix-{}-{}.png", secs, n)
+        };
+        let path = dir.join(name);
+        if !path.exists() {
+            return path;
+        }
+        n += 1;
+    }
+}
+
+// Save to the working directory, returns the path written.
+pub fn save(image: &RgbaImage) -> R<String> {
+    let dir = std::env::current_dir().map_err(|e| E::ImageError(e.into()))?;
+    let path = path_in(&dir);
+    image.save(&path).map_err(E::ImageError)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let path = path_in(Path::new("/nonexistent"));
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("pix-") && name.ends_with(".png"));
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Bursts of continuous shots, runs of images with sequential file names taken within a second
+// of each other. A stacked burst takes a single grid square, its first frame with a count
+// badge, and the other frames wait in the first one's `stack` until it is expanded. The sharpest
+// frame of each burst is marked as the likely keeper.
+
+use crate::image::Image;
+use crate::File;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Frames further apart than this many seconds start a new burst.
+static GAP: u64 = 1;
+
+// Shorter runs are left alone.
+static MIN_LEN: usize = 3;
+
+// The path up to the frame number, the number and the extension, e.g. "/a/IMG_", 1234 and "JPG".
+fn sequence(path: &str) -> Option<(&str, u64, &str)> {
+    let (stem, ext) = path.rsplit_once('.')?;
+    let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = stem[prefix.len()..].parse().ok()?;
+    Some((prefix, number, ext))
+}
+
+// Whether `next` is the frame after `prev` in a burst.
+fn continues(prev: &File, next: &File) -> bool {
+    match (sequence(&prev.path), sequence(&next.path)) {
+        (Some((prefix, n, ext)), Some((next_prefix, m, next_ext))) => {
+            prefix == next_prefix
+                && ext.eq_ignore_ascii_case(next_ext)
+                && m == n + 1
+                && next.modified.abs_diff(prev.modified) <= GAP
+        }
+        _ => false,
+    }
+}
+
+// Every image in its own square again, and in no burst.
+pub fn expand_all(images: Vec<Image>) -> Vec<Image> {
+    let mut ret = Vec::with_capacity(images.len());
+    for mut image in images {
+        let stack = std::mem::take(&mut image.stack);
+        ret.push(image);
+        ret.extend(stack);
+    }
+    for image in &mut ret {
+        image.burst = None;
+        image.pick = false;
+    }
+    renumber(ret)
+}
+
+// Stack the bursts in grid order, except those whose first frame is in `expanded`.
+pub fn collapse(images: Vec<Image>, expanded: &BTreeSet<String>) -> Vec<Image> {
+    let images = expand_all(images);
+
+    // Lengths of the runs of continuing frames.
+    let mut runs: Vec<usize> = Vec::new();
+    for (i, image) in images.iter().enumerate() {
+        match runs.last_mut() {
+            Some(len) if continues(&images[i - 1].file, &image.file) => *len += 1,
+            _ => runs.push(1),
+        }
+    }
+
+    let mut ret = Vec::with_capacity(images.len());
+    let mut images = images.into_iter();
+    for (burst, len) in runs.into_iter().enumerate() {
+        let mut run: Vec<Image> = images.by_ref().take(len).collect();
+        if len < MIN_LEN {
+            ret.extend(run);
+            continue;
+        }
+        for image in &mut run {
+            image.burst = Some(burst);
+        }
+        if expanded.contains(&run[0].file.path) {
+            ret.extend(run);
+        } else {
+            let mut top = run.remove(0);
+            top.stack = run;
+            ret.push(top);
+        }
+    }
+    renumber(ret)
+}
+
+// Paths of the sharpest frame of each burst. Frames without a score yet are never picked.
+pub fn picks<'a>(images: impl IntoIterator<Item = &'a Image>) -> BTreeSet<&'a str> {
+    let frames = images
+        .into_iter()
+        .flat_map(|image| std::iter::once(image).chain(&image.stack));
+
+    let mut best: BTreeMap<usize, (u32, &str)> = BTreeMap::new();
+    for frame in frames {
+        let score = frame
+            .get_metadata()
+            .and_then(|metadata| metadata.quality.map(|q| q.sharpness));
+        if let (Some(burst), Some(score)) = (frame.burst, score) {
+            let entry = best.entry(burst).or_insert((score, &frame.file.path));
+            if score > entry.0 {
+                *entry = (score, &frame.file.path);
+            }
+        }
+    }
+    best.into_values().map(|(_, path)| path).collect()
+}
+
+fn renumber(mut images: Vec<Image>) -> Vec<Image> {
+    for (i, image) in images.iter_mut().enumerate() {
+        image.i = i;
+    }
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::userdata::UserData;
+    use crate::{Metadata, MetadataState};
+    use pix_core::quality::Quality;
+    use std::sync::Arc;
+
+    fn image(path: &str, modified: u64) -> Image {
+        let file = Arc::new(File {
+            path: path.to_owned(),
+            modified,
+            file_size: 0,
+        });
+        Image::from(0, file, MetadataState::Missing, UserData::default())
+    }
+
+    fn layout(images: &[Image]) -> Vec<(usize, usize)> {
+        images
+            .iter()
+            .map(|image| (image.i, image.stack.len() + 1))
+            .collect()
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(sequence("/a/IMG_0099.JPG"), Some(("/a/IMG_", 99, "JPG")));
+        assert_eq!(sequence("/a/DSC1.nef"), Some(("/a/DSC", 1, "nef")));
+        assert_eq!(sequence("/a/cover.jpg"), None);
+        assert_eq!(sequence("/a/0012"), None);
+    }
+
+    #[test]
+    fn bursts() {
+        let images = vec![
+            image("/a/IMG_0098.JPG", 90),
+            image("/a/IMG_0099.JPG", 100),
+            image("/a/IMG_0100.JPG", 100),
+            image("/a/IMG_0101.JPG", 101),
+            image("/a/IMG_0102.JPG", 101),
+            // Too late.
+            image("/a/IMG_0103.JPG", 105),
+            // A pair isn't stacked.
+            image("/a/IMG_0104.JPG", 105),
+            // Another directory.
+            image("/b/IMG_0105.JPG", 105),
+            image("/b/IMG_0106.JPG", 105),
+            image("/b/IMG_0107.JPG", 106),
+        ];
+
+        let stacked = collapse(images, &BTreeSet::new());
+        assert_eq!(
+            layout(&stacked),
+            vec![(0, 1), (1, 4), (2, 1), (3, 1), (4, 3)]
+        );
+        assert_eq!(stacked[1].stack[2].file.path, "/a/IMG_0102.JPG");
+
+        let expanded = BTreeSet::from(["/a/IMG_0099.JPG".to_owned()]);
+        let stacked = collapse(stacked, &expanded);
+        assert_eq!(
+            layout(&stacked)[1..6],
+            [(1, 1), (2, 1), (3, 1), (4, 1), (5, 1)]
+        );
+        assert_eq!(layout(&stacked)[7], (7, 3));
+
+        let flat = expand_all(stacked);
+        assert_eq!(flat.len(), 10);
+        assert_eq!(flat[9].i, 9);
+        assert_eq!(flat[9].file.path, "/b/IMG_0107.JPG");
+        assert!(flat.iter().all(|image| image.burst.is_none()));
+    }
+
+    #[test]
+    fn sharpest() {
+        let scored = |path, sharpness: Option<u32>| {
+            let mut image = image(path, 0);
+            image.metadata = MetadataState::Some(Metadata {
+                quality: sharpness.map(|sharpness| Quality {
+                    sharpness,
+                    brightness: 128,
+                }),
+                ..Default::default()
+            });
+            image
+        };
+        let images = vec![
+            scored("/a/1.jpg", Some(10)),
+            scored("/a/2.jpg", Some(30)),
+            scored("/a/3.jpg", None),
+            scored("/a/5.jpg", Some(50)),
+            scored("/a/6.jpg", None),
+            image("/a/7.jpg", 0),
+        ];
+        let stacked = collapse(images, &BTreeSet::new());
+        assert_eq!(layout(&stacked), vec![(0, 3), (1, 3)]);
+        assert_eq!(picks(&stacked), BTreeSet::from(["/a/2.jpg", "/a/5.jpg"]));
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Latencies of named scopes and event counters over the whole run, logged on exit. Startup is
+// broken down into stages, each recorded as the time since the previous one finished.
+
+use lazy_static::lazy_static;
+use log::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref SCOPES: Mutex<BTreeMap<&'static str, Vec<Duration>>> = Mutex::default();
+    static ref COUNTERS: Mutex<BTreeMap<&'static str, u64>> = Mutex::default();
+    static ref START: Instant = Instant::now();
+    // Time since START each startup stage finished at, in order.
+    static ref STAGES: Mutex<Vec<(&'static str, Duration)>> = Mutex::default();
+}
+
+// Records the time until dropped under `name`.
+pub struct ScopedDuration {
+    name: &'static str,
+    start: Instant,
+}
+
+impl ScopedDuration {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ScopedDuration {
+    fn drop(&mut self) {
+        record(self.name, self.start.elapsed());
+    }
+}
+
+pub fn record(name: &'static str, duration: Duration) {
+    SCOPES
+        .lock()
+        .unwrap()
+        .entry(name)
+        .or_default()
+        .push(duration);
+}
+
+pub fn count(name: &'static str) {
+    *COUNTERS.lock().unwrap().entry(name).or_default() += 1;
+}
+
+pub fn counter(name: &'static str) -> u64 {
+    COUNTERS.lock().unwrap().get(name).copied().unwrap_or(0)
+}
+
+// Startup stages are timed from here.
+pub fn start() {
+    lazy_static::initialize(&START);
+}
+
+// The startup stage `name` finished, only the first time counts.
+pub fn stage(name: &'static str) {
+    let elapsed = START.elapsed();
+    let mut stages = STAGES.lock().unwrap();
+    if stages.iter().any(|(n, _)| *n == name) {
+        return;
+    }
+    let prev = stages.last().map(|(_, t)| *t).unwrap_or_default();
+    stages.push((name, elapsed));
+    drop(stages);
+    record(name, elapsed.saturating_sub(prev));
+}
+
+pub fn stage_done(name: &'static str) -> bool {
+    STAGES.lock().unwrap().iter().any(|(n, _)| *n == name)
+}
+
+fn breakdown(stages: &[(&'static str, Duration)]) -> Vec<String> {
+    let mut prev = Duration::ZERO;
+    stages
+        .iter()
+        .map(|(name, t)| {
+            let line = format!(
+                "{:<24}{:>10.1}ms{:>10.1}ms",
+                name,
+                ms(t.saturating_sub(prev)),
+                ms(*t)
+            );
+            prev = *t;
+            line
+        })
+        .collect()
+}
+
+// Each finished startup stage with its own time and the time since start.
+pub fn print_startup() {
+    println!("{:<24}{:>12}{:>12}", "stage", "took", "since start");
+    for line in breakdown(&STAGES.lock().unwrap()) {
+        println!("{}", line);
+    }
+}
+
+// Milliseconds, for the JSON output.
+#[derive(Debug, Serialize, PartialEq)]
+struct Summary {
+    count: usize,
+    total_ms: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Nearest rank percentile of sorted samples.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn summarize(samples: &[Duration]) -> Summary {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    Summary {
+        count: sorted.len(),
+        total_ms: ms(sorted.iter().sum()),
+        p50_ms: ms(percentile(&sorted, 50.0)),
+        p95_ms: ms(percentile(&sorted, 95.0)),
+        p99_ms: ms(percentile(&sorted, 99.0)),
+        max_ms: ms(*sorted.last().expect("samples")),
+    }
+}
+
+fn summaries() -> BTreeMap<&'static str, Summary> {
+    SCOPES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, samples)| !samples.is_empty())
+        .map(|(name, samples)| (*name, summarize(samples)))
+        .collect()
+}
+
+pub fn dump() {
+    for (name, s) in summaries() {
+        info!(
+            "{}: count {} total {:.1}ms p50 {:.3}ms p95 {:.3}ms p99 {:.3}ms max {:.3}ms",
+            name, s.count, s.total_ms, s.p50_ms, s.p95_ms, s.p99_ms, s.max_ms
+        );
+    }
+    for (name, n) in COUNTERS.lock().unwrap().iter() {
+        info!("{}: {}", name, n);
+    }
+}
+
+#[derive(Serialize)]
+struct Json {
+    scopes: BTreeMap<&'static str, Summary>,
+    counters: BTreeMap<&'static str, u64>,
+}
+
+// Scope summaries and counters keyed by name.
+pub fn write_json(path: &Path) -> std::io::Result<()> {
+    let json = Json {
+        scopes: summaries(),
+        counters: COUNTERS.lock().unwrap().clone(),
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        assert_eq!(
+            summarize(&samples),
+            Summary {
+                count: 100,
+                total_ms: 5050.0,
+                p50_ms: 50.0,
+                p95_ms: 95.0,
+                p99_ms: 99.0,
+                max_ms: 100.0,
+            }
+        );
+
+        count("test_counter");
+        count("test_counter");
+        assert_eq!(counter("test_counter"), 2);
+        assert_eq!(counter("test_missing"), 0);
+
+        let one = [Duration::from_millis(3)];
+        assert_eq!(percentile(&one, 50.0), one[0]);
+        assert_eq!(percentile(&one, 99.0), one[0]);
+    }
+
+    #[test]
+    fn startup_breakdown() {
+        let stages = [
+            ("startup_scan", Duration::from_millis(20)),
+            ("startup_window", Duration::from_millis(50)),
+        ];
+        assert_eq!(
+            breakdown(&stages),
+            vec![
+                format!("{:<24}{:>10.1}ms{:>10.1}ms", "startup_scan", 20.0, 20.0),
+                format!("{:<24}{:>10.1}ms{:>10.1}ms", "startup_window", 30.0, 50.0),
+            ]
+        );
+    }
+}
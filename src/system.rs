@@ -0,0 +1,479 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Hand files over to the rest of the desktop.
+
+use log::*;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Portable caches live in this directory, which searches skip.
+pub static CACHE_DIR: &str = ".pix-cache";
+
+// Caches stored with the photos with `--local-cache`, also skipped.
+pub static LOCAL_CACHE_DIR: &str = ".pix";
+
+pub fn is_cache_dir(name: &std::ffi::OsStr) -> bool {
+    name == CACHE_DIR || name == LOCAL_CACHE_DIR
+}
+
+// The default database, in the platform's cache directory: ~/.cache on Linux, ~/Library/Caches
+// on macOS and %LOCALAPPDATA% on Windows.
+pub fn db_path() -> PathBuf {
+    let dir = dirs_next::cache_dir().unwrap_or_else(|| {
+        warn!("No cache directory, keeping the database in the temporary directory");
+        std::env::temp_dir()
+    });
+    dir.join("pix").join("thumbs.db")
+}
+
+// The directory a portable cache lives in and paths are kept relative to: the executable's
+// directory when every opened path is inside it, e.g. pix on the drive with the photos, otherwise
+// the first opened directory.
+pub fn portable_root(paths: &[PathBuf]) -> PathBuf {
+    let exe_dir = std::env::current_exe()
+        .and_then(|exe| exe.canonicalize())
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    let paths: Vec<PathBuf> = paths.iter().filter_map(|p| p.canonicalize().ok()).collect();
+    pick_root(exe_dir, &paths)
+}
+
+fn pick_root(exe_dir: Option<PathBuf>, paths: &[PathBuf]) -> PathBuf {
+    match (exe_dir, paths.first()) {
+        (Some(dir), Some(_)) if paths.iter().all(|path| path.starts_with(&dir)) => dir,
+        (_, Some(first)) if first.is_dir() => first.clone(),
+        (_, Some(first)) => first.parent().unwrap_or(first).to_path_buf(),
+        (_, None) => PathBuf::from("."),
+    }
+}
+
+// The directory a local cache lives in: the opened directory, or the deepest directory holding
+// all opened paths, e.g. ~/photos for ~/photos/2022 and ~/photos/2023. Paths with nothing but the
+// filesystem root in common use the first one.
+pub fn local_root(paths: &[PathBuf]) -> PathBuf {
+    let dirs: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|p| p.canonicalize().ok())
+        .map(|p| match p.is_dir() {
+            true => p,
+            false => p.parent().map_or(p.clone(), Path::to_path_buf),
+        })
+        .collect();
+    match common_dir(&dirs) {
+        Some(dir) => dir,
+        None => {
+            let first = dirs.first().cloned().unwrap_or_else(|| PathBuf::from("."));
+            warn!("Opened paths share no directory, caching in {:?}", first);
+            first
+        }
+    }
+}
+
+fn common_dir(dirs: &[PathBuf]) -> Option<PathBuf> {
+    let first = dirs.first()?;
+    first
+        .ancestors()
+        .find(|dir| dirs.iter().all(|d| d.starts_with(dir)))
+        .filter(|dir| dir.parent().is_some())
+        .map(Path::to_path_buf)
+}
+
+// `path` with forward slashes, so paths typed with either separator match on Windows.
+pub fn slashes(path: &str) -> Cow<'_, str> {
+    to_slashes(path, std::path::MAIN_SEPARATOR)
+}
+
+fn to_slashes(path: &str, separator: char) -> Cow<'_, str> {
+    if separator == '/' {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(path.replace(separator, "/"))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn open_command(path: &str) -> Command {
+    let mut command = Command::new("open");
+    command.arg(path);
+    command
+}
+
+// Explorer opens files with their default application. Unlike `cmd /C start` it doesn't treat
+// & or ^ in file names as shell syntax.
+#[cfg(target_os = "windows")]
+fn open_command(path: &str) -> Command {
+    let mut command = Command::new("explorer");
+    command.arg(path.replace('/', "\\"));
+    command
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn open_command(path: &str) -> Command {
+    let mut command = Command::new("xdg-open");
+    command.arg(path);
+    command
+}
+
+// Open a file with the default application, without waiting for it to exit.
+pub fn open(path: &str) {
+    info!("Opening {:?}", path);
+    if let Err(e) = spawn(open_command(path)) {
+        error!("unable to open {:?}: {:?}", path, e);
+    }
+}
+
+// Run `command` without waiting for it. It's waited for on a thread, so it doesn't linger as a
+// zombie until pix exits.
+fn spawn(mut command: Command) -> std::io::Result<()> {
+    let mut child = command.spawn()?;
+    std::thread::spawn(move || {
+        if let Err(e) = child.wait() {
+            warn!("error waiting for {:?}: {:?}", command.get_program(), e);
+        }
+    });
+    Ok(())
+}
+
+// A file:// uri with everything but unreserved characters and separators percent encoded.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn file_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+// The paths of pasted text, one per line as plain paths or file:// uris like file managers copy
+// them. Comments of text/uri-list and other schemes are skipped.
+pub fn parse_paths(text: &str) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(uri) = line.strip_prefix("file://") {
+            // An empty or localhost authority, the path starts after it.
+            let path = uri.strip_prefix("localhost").unwrap_or(uri);
+            match percent_decode(path) {
+                Some(path) => paths.push(PathBuf::from(uri_path(&path, cfg!(windows)))),
+                None => warn!("Skipping undecodable uri {:?}", line),
+            }
+        } else if line.contains("://") {
+            warn!("Skipping non-file uri {:?}", line);
+        } else {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    paths
+}
+
+// Windows file uris put a slash before the drive letter, file:///C:/photos.
+fn uri_path(path: &str, windows: bool) -> &str {
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if windows && drive.is_ascii_alphabetic() => &path[1..],
+        _ => path,
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(target_os = "macos")]
+fn reveal_command(path: &str) -> Option<Command> {
+    let mut command = Command::new("open");
+    command.args(["-R", path]);
+    Some(command)
+}
+
+// Explorer parses its own command line and only finds paths with spaces quoted after the comma,
+// not around the whole argument.
+#[cfg(target_os = "windows")]
+fn reveal_command(path: &str) -> Option<Command> {
+    use std::os::windows::process::CommandExt;
+    let mut command = Command::new("explorer");
+    command.raw_arg(format!("/select,\"{}\"", path.replace('/', "\\")));
+    Some(command)
+}
+
+// Ask the file manager to highlight the file over D-Bus, falling back to opening the directory.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn reveal_command(path: &str) -> Option<Command> {
+    let status = Command::new("dbus-send")
+        .args([
+            "--session",
+            "--print-reply",
+            "--dest=org.freedesktop.FileManager1",
+            "--type=method_call",
+            "/org/freedesktop/FileManager1",
+            "org.freedesktop.FileManager1.ShowItems",
+            &format!("array:string:{}", file_uri(path)),
+            "string:",
+        ])
+        .stdout(std::process::Stdio::null())
+        .status();
+
+    match status {
+        Ok(status) if status.success() => None,
+        _ => {
+            let dir = std::path::Path::new(path).parent()?;
+            Some(open_command(&dir.to_string_lossy()))
+        }
+    }
+}
+
+// Show a file in the system file manager, highlighted where supported.
+pub fn reveal(path: &str) {
+    info!("Revealing {:?}", path);
+    // On a thread as the D-Bus call blocks until the file manager has started.
+    let path = path.to_owned();
+    std::thread::spawn(move || {
+        if let Some(mut command) = reveal_command(&path) {
+            if let Err(e) = command.status() {
+                error!("unable to reveal {:?}: {:?}", path, e);
+            }
+        }
+    });
+}
+
+// An AppleScript string literal. Rust's debug quoting escapes the combining accents of the
+// decomposed file names macOS uses, which AppleScript doesn't understand.
+#[cfg(target_os = "macos")]
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(target_os = "macos")]
+fn trash_command(path: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command.args([
+        "-e",
+        &format!(
+            "tell application \"Finder\" to delete POSIX file {}",
+            applescript_string(path)
+        ),
+    ]);
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn trash_command(path: &str) -> Command {
+    let mut command = Command::new("powershell");
+    command.args([
+        "-NoProfile",
+        "-Command",
+        &format!(
+            "Add-Type -AssemblyName Microsoft.VisualBasic; \
+             [Microsoft.VisualBasic.FileIO.FileSystem]::DeleteFile('{}', 'OnlyErrorDialogs', \
+             'SendToRecycleBin')",
+            path.replace('\'', "''")
+        ),
+    ]);
+    command
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn trash_command(path: &str) -> Command {
+    let mut command = Command::new("gio");
+    command.args(["trash", path]);
+    command
+}
+
+// Move a file to the desktop's trash, so it can still be restored from the file manager.
+pub fn trash(path: &str) -> Result<(), String> {
+    info!("Trashing {:?}", path);
+    let output = trash_command(path)
+        .output()
+        .map_err(|e| format!("{:?}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+    if std::path::Path::new(path).exists() {
+        return Err("still there after trashing".to_owned());
+    }
+    Ok(())
+}
+
+// The system clipboard, kept open because on X11 the copied data is only served while the
+// clipboard is alive.
+pub struct Clipboard(Option<arboard::Clipboard>);
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        match arboard::Clipboard::new() {
+            Ok(clipboard) => Self(Some(clipboard)),
+            Err(e) => {
+                warn!("Clipboard unavailable: {:?}", e);
+                Self(None)
+            }
+        }
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        if let Some(clipboard) = &mut self.0 {
+            if let Err(e) = clipboard.set_text(text) {
+                error!("unable to copy to the clipboard: {:?}", e);
+            }
+        }
+    }
+
+    pub fn get_text(&mut self) -> Option<String> {
+        let clipboard = self.0.as_mut()?;
+        match clipboard.get_text() {
+            Ok(text) => Some(text),
+            Err(e) => {
+                warn!("unable to paste from the clipboard: {:?}", e);
+                None
+            }
+        }
+    }
+
+    // The text selected last, what middle clicking pastes on X11.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    pub fn get_primary(&mut self) -> Option<String> {
+        use arboard::{GetExtLinux, LinuxClipboardKind};
+        let clipboard = self.0.as_mut()?;
+        clipboard
+            .get()
+            .clipboard(LinuxClipboardKind::Primary)
+            .text()
+            .ok()
+    }
+
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    pub fn get_primary(&mut self) -> Option<String> {
+        None
+    }
+
+    pub fn set_image(&mut self, image: ::image::RgbaImage) {
+        if let Some(clipboard) = &mut self.0 {
+            let image = arboard::ImageData {
+                width: image.width() as usize,
+                height: image.height() as usize,
+                bytes: image.into_raw().into(),
+            };
+            if let Err(e) = clipboard.set_image(image) {
+                error!("unable to copy to the clipboard: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_paths() {
+        let text = "# copied\r\nfile:///photos/caf%C3%A9%201%23.jpg\r\n\n/tmp/a b.png\nhttps://example.com/c.jpg\nfile://localhost/d.jpg\nfile:///bad%zz\n";
+        assert_eq!(
+            super::parse_paths(text),
+            vec![
+                PathBuf::from("/photos/café 1#.jpg"),
+                PathBuf::from("/tmp/a b.png"),
+                PathBuf::from("/d.jpg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn uri_path() {
+        assert_eq!(super::uri_path("/C:/photos/a.jpg", true), "C:/photos/a.jpg");
+        assert_eq!(
+            super::uri_path("/C:/photos/a.jpg", false),
+            "/C:/photos/a.jpg"
+        );
+        assert_eq!(super::uri_path("/photos/a.jpg", true), "/photos/a.jpg");
+    }
+
+    #[test]
+    fn slashes() {
+        assert_eq!(
+            super::to_slashes("C:\\photos\\a.jpg", '\\'),
+            "C:/photos/a.jpg"
+        );
+        assert_eq!(
+            super::to_slashes("/photos/a\\b.jpg", '/'),
+            "/photos/a\\b.jpg"
+        );
+        assert!(super::db_path().ends_with(std::path::Path::new("pix").join("thumbs.db")));
+    }
+
+    #[test]
+    fn pick_root() {
+        use super::pick_root;
+        let tmp = std::env::temp_dir();
+        let drive = tmp.join("drive");
+        let photos = [drive.join("photos")];
+        assert_eq!(pick_root(Some(drive.clone()), &photos), drive);
+        assert_eq!(pick_root(None, std::slice::from_ref(&tmp)), tmp);
+        assert_eq!(pick_root(Some(drive), &[tmp.join("a.jpg")]), tmp);
+        assert_eq!(pick_root(None, &[]), PathBuf::from("."));
+    }
+
+    #[test]
+    fn common_dir() {
+        use super::common_dir;
+        let p = PathBuf::from;
+        assert_eq!(
+            common_dir(&[p("/photos/2022"), p("/photos/2023/may")]),
+            Some(p("/photos"))
+        );
+        assert_eq!(common_dir(&[p("/photos/2022")]), Some(p("/photos/2022")));
+        assert_eq!(common_dir(&[p("/photos"), p("/media/a")]), None);
+        assert_eq!(common_dir(&[]), None);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn applescript_string() {
+        assert_eq!(
+            super::applescript_string("/a \"b\"\\cafe\u{301}.jpg"),
+            "\"/a \\\"b\\\"\\\\cafe\u{301}.jpg\""
+        );
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    #[test]
+    fn file_uri() {
+        assert_eq!(
+            super::file_uri("/photos/café 1#.jpg"),
+            "file:///photos/caf%C3%A9%201%23.jpg"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Token bucket limiting how fast the thumbnailer reads source files, and lowering their disk
+// priority, so a large library doesn't saturate the disk.
+
+use log::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct Throttle {
+    // Bytes per second, also the most that can be saved up while idle.
+    rate: f64,
+
+    // Available bytes, negative while readers wait for a large file, and when it was counted.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = f64::max(1.0, bytes_per_sec as f64);
+        Self {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    // Take `bytes` from the bucket, returns how long to wait before reading them.
+    fn reserve(&self, bytes: u64, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, last) = *bucket;
+
+        let refilled = now.saturating_duration_since(last).as_secs_f64() * self.rate;
+        let tokens = f64::min(self.rate, tokens + refilled) - bytes as f64;
+        *bucket = (tokens, now);
+
+        if tokens < 0.0 {
+            Duration::from_secs_f64(-tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    // Block the calling thumbnailer thread until `bytes` may be read.
+    pub fn acquire(&self, bytes: u64) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+// Put the calling thread in the idle I/O class, it only gets the disk when nothing else wants it.
+// Child processes such as converters inherit it. I/O priorities are per thread on Linux only.
+#[cfg(target_os = "linux")]
+pub fn io_nice() {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+    // Safe as it only changes how the kernel schedules this thread's reads.
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    if res != 0 {
+        warn!("ioprio_set: {}", std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn io_nice() {
+    warn!("--io-nice is only supported on Linux");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve() {
+        let throttle = Throttle::new(100);
+        let start = throttle.bucket.lock().unwrap().1;
+
+        // A second of reads is saved up, then readers queue behind each other.
+        assert_eq!(throttle.reserve(100, start), Duration::ZERO);
+        assert_eq!(throttle.reserve(50, start), Duration::from_millis(500));
+        assert_eq!(throttle.reserve(50, start), Duration::from_secs(1));
+
+        // Idle time refills the bucket, up to a second worth.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(throttle.reserve(100, later), Duration::ZERO);
+        assert_eq!(throttle.reserve(1, later), Duration::from_millis(10));
+    }
+}
@@ -12,125 +12,861 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::animation;
+use crate::budget::{self, Budget};
+use crate::captions;
+use crate::checksum;
+use crate::concurrency::Gate;
 use crate::database::Database;
-use crate::image;
+use crate::depth::{self, Depth};
+use crate::filter;
+use crate::fingerprint;
+use crate::quality;
+use crate::scene;
+use crate::screenshot;
+use crate::throttle::{self, Throttle};
+use crate::tonemap::{self, ToneMap};
 use crate::File;
+use crate::Info;
 use crate::Metadata;
 use crate::TileMap;
-use crate::TileRef;
-use crate::R;
+use crate::{E, R};
 use ::image::GenericImage;
 use ::image::GenericImageView;
-use futures::future::Fuse;
-use futures::future::FutureExt;
-use futures::future::RemoteHandle;
-use futures::select;
-use futures::task::SpawnExt;
-use std::collections::BTreeMap;
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use log::*;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-type Handle<T> = Fuse<RemoteHandle<T>>;
+type Thumbs = (Arc<File>, Metadata, TileMap<Cursor<Vec<u8>>>);
 
-pub type MakeThumbRet = R<Metadata>;
+// Upper bound on the number of pages thumbnailed from multi-page documents.
+pub static MAX_PAGES: usize = 64;
 
+// How source images are converted into thumbnail tiles.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Settings {
+    pub tone_map: ToneMap,
+    pub depth: Depth,
+
+    // Bytes per second read from source files, unlimited when None.
+    pub io_limit: Option<u64>,
+
+    // Read and decode at idle disk priority.
+    pub io_nice: bool,
+
+    // Bytes of estimated memory the images being decoded may take together.
+    pub memory_limit: Option<u64>,
+
+    // Long edge above which JPEGs are decoded downsampled, 0 to always decode in full.
+    pub max_decode: u32,
+
+    // How long a file may take to decode before it is quarantined, zero to wait forever.
+    pub decode_timeout: Duration,
+
+    // Store the SHA-256 of source files as they are read.
+    pub checksum: bool,
+
+    // Resizes an image to the smaller levels of its pyramid down to a bucket, e.g. on the GPU.
+    // The levels are resized on the CPU when None or when it returns None.
+    pub scaler: Option<fn(&::image::DynamicImage, u32) -> Option<Vec<::image::DynamicImage>>>,
+}
+
+#[cfg(feature = "video")]
+static VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "mpg", "mpeg", "3gp",
+];
+
+// Thumbnailed with ffmpeg.
+#[cfg(feature = "video")]
+pub fn is_video(path: &str) -> bool {
+    let path = path.to_lowercase();
+    VIDEO_EXTENSIONS
+        .iter()
+        .any(|ext| path.strip_suffix(ext).is_some_and(|p| p.ends_with('.')))
+}
+
+struct Job {
+    i: usize,
+    // Results of jobs queued before a rebase are dropped.
+    generation: u64,
+    file: Arc<File>,
+    uid: u64,
+    // Estimated memory taken from the budget while decoding.
+    cost: u64,
+}
+
+// Thumbnails are made in three stages connected by bounded channels: one thread reads source
+// files, `threads` threads decode and resize them, and one thread writes the tiles to the
+// database. A slow stage blocks the ones before it, capping the source files held in memory.
 pub struct Thumbnailer {
-    db: Arc<Database>,
     threads: usize,
+    // Decoders allowed to work at once, fewer while the user interacts.
+    gate: Arc<Gate>,
     uid_base: u64,
-    executor: futures::executor::ThreadPool,
-    handles: BTreeMap<usize, Handle<MakeThumbRet>>,
+    generation: u64,
+    jobs: Sender<Job>,
+    // Kept to drop the queued jobs on rebase.
+    queued: Receiver<Job>,
+    results: Receiver<(usize, u64, R<Metadata>)>,
+    in_flight: BTreeSet<usize>,
+
+    // Set on shutdown, queued and read jobs are skipped.
+    cancel: Arc<AtomicBool>,
+    writer: Option<JoinHandle<()>>,
 }
 
 impl Thumbnailer {
-    pub fn new(db: Arc<Database>, uid_base: u64, threads: usize) -> Self {
+    pub fn new(db: Arc<Database>, uid_base: u64, threads: usize, settings: Settings) -> Self {
+        let threads = usize::max(1, threads);
+        let (jobs_tx, jobs_rx) = unbounded::<Job>();
+        let (read_tx, read_rx) = bounded::<(Job, Option<Vec<u8>>)>(threads);
+        let (decoded_tx, decoded_rx) = bounded::<(Job, R<Thumbs>)>(threads);
+        let (results_tx, results_rx) = unbounded();
+
+        let throttle = settings.io_limit.map(Throttle::new);
+        let budget = settings
+            .memory_limit
+            .map(|limit| Arc::new(Budget::new(limit)));
+        let queued = jobs_rx.clone();
+        let gate = Arc::new(Gate::new(threads));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let read_budget = budget.clone();
+        let read_db = Arc::clone(&db);
+        let read_cancel = Arc::clone(&cancel);
+        Self::spawn("thumbnailer-read", move || {
+            if settings.io_nice {
+                throttle::io_nice();
+            }
+            for mut job in jobs_rx {
+                if read_cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(throttle) = &throttle {
+                    throttle.acquire(job.file.file_size);
+                }
+                // Errors are reported by the decoders, which open the file again.
+                let bytes = if Self::opened_by_path(&job.file.path) {
+                    None
+                } else {
+                    std::fs::read(&job.file.path).ok()
+                };
+                if settings.checksum {
+                    let sha256 = match &bytes {
+                        Some(bytes) => Ok(checksum::sha256(bytes)),
+                        None => checksum::sha256_file(&job.file.path),
+                    };
+                    match sha256 {
+                        Ok(sha256) => {
+                            checksum::record(&read_db, &job.file, sha256);
+                        }
+                        Err(e) => error!("checksum {:?}: {}", job.file.path, e),
+                    }
+                }
+                if let Some(budget) = &read_budget {
+                    job.cost = budget::estimate(
+                        &job.file.path,
+                        bytes.as_deref().unwrap_or_default(),
+                        settings.max_decode,
+                    );
+                    budget.acquire(job.cost);
+                }
+                if read_tx.send((job, bytes)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        for _ in 0..threads {
+            let read_rx = read_rx.clone();
+            let decoded_tx = decoded_tx.clone();
+            let budget = budget.clone();
+            let gate = Arc::clone(&gate);
+            let cancel = Arc::clone(&cancel);
+            Self::spawn("thumbnailer-decode", move || {
+                // Decoders open files again, e.g. for converters and multi-page formats.
+                if settings.io_nice {
+                    throttle::io_nice();
+                }
+                for (job, bytes) in read_rx {
+                    if cancel.load(Ordering::Relaxed) {
+                        if let Some(budget) = &budget {
+                            budget.release(job.cost);
+                        }
+                        continue;
+                    }
+                    gate.enter();
+                    // Held until the decoder is done, even after timing out, so hanging
+                    // decoders still count against the thread limit and the memory budget.
+                    let (gate, budget, cost) = (Arc::clone(&gate), budget.clone(), job.cost);
+                    let release = move || {
+                        gate.leave();
+                        if let Some(budget) = &budget {
+                            budget.release(cost);
+                        }
+                    };
+                    let res = Self::make_thumb_guarded(
+                        Arc::clone(&job.file),
+                        job.uid,
+                        settings,
+                        bytes,
+                        release,
+                    );
+                    if decoded_tx.send((job, res)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        // Decoded thumbnails are still written on shutdown, images being decoded aren't waited
+        // for as decoders can take long.
+        let write_cancel = Arc::clone(&cancel);
+        let writer = Self::spawn("thumbnailer-write", move || loop {
+            let (job, res) = match decoded_rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(decoded) => decoded,
+                Err(RecvTimeoutError::Timeout) if write_cancel.load(Ordering::Relaxed) => return,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            let res = Self::update_db(res, &job.file, &db);
+            // The app stops receiving on shutdown.
+            let _ = results_tx.send((job.i, job.generation, res));
+        });
+
         Self {
-            db,
             threads,
+            gate,
             uid_base,
-            executor: futures::executor::ThreadPool::builder()
-                .pool_size(threads)
-                .name_prefix("thumbnailer")
-                .create()
-                .unwrap(),
-            handles: BTreeMap::new(),
+            generation: 0,
+            jobs: jobs_tx,
+            queued,
+            results: results_rx,
+            in_flight: BTreeSet::new(),
+            cancel,
+            writer: Some(writer),
+        }
+    }
+
+    fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(f)
+            .expect("thumbnailer thread")
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    // Let `n` of the decoder threads work.
+    pub fn set_active(&self, n: usize) {
+        debug!("Thumbnailer decoders {} of {}", n, self.threads);
+        self.gate.set_limit(n);
+    }
+
+    // Drop the queued jobs and wait until the thumbnails decoded so far are written.
+    pub fn shutdown(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        // Paused decoders skip their jobs.
+        self.gate.set_limit(self.threads);
+        self.queued.try_iter().for_each(drop);
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                error!("thumbnailer-write panicked");
+            }
         }
     }
 
+    // Thumbnail a new set of images, work on the old ones is dropped.
+    pub fn rebase(&mut self, uid_base: u64) {
+        self.uid_base = uid_base;
+        self.generation += 1;
+        self.queued.try_iter().for_each(drop);
+        self.in_flight.clear();
+    }
+
+    // Enough work is queued to keep every stage busy.
     pub fn is_full(&self) -> bool {
-        self.handles.len() > self.threads
+        self.in_flight.len() > 2 * self.threads
     }
 
     pub fn contains(&self, i: usize) -> bool {
-        self.handles.contains_key(&i)
+        self.in_flight.contains(&i)
     }
 
-    async fn update_db(
-        res: R<(Arc<File>, Metadata, TileMap<Cursor<Vec<u8>>>)>,
-        db: Arc<Database>,
-    ) -> R<Metadata> {
+    fn update_db(res: R<Thumbs>, file: &File, db: &Database) -> R<Metadata> {
+        let _s = crate::stats::ScopedDuration::new("db_write");
         match res {
             Ok((file, metadata, tiles)) => {
-                // Do before metadata write to prevent invalid metadata references.
-                for (id, tile) in tiles {
-                    db.set(id, tile.get_ref()).expect("db set");
-                }
+                crate::errlog::event(
+                    Level::Debug,
+                    "thumbnail",
+                    serde_json::json!({"path": file.path, "tiles": tiles.len()}),
+                );
+
+                let tiles = tiles
+                    .iter()
+                    .map(|(id, tile)| (*id, tile.get_ref().as_slice()));
+                db.set_thumbnail(&file, &metadata, tiles)
+                    .expect("set thumbnail");
 
-                db.set_metadata(&*file, &metadata).expect("set metadata");
+                if let Err(e) = db.remove_failure(&file) {
+                    error!("remove failure for {:?}: {:?}", file.path, e);
+                }
 
                 Ok(metadata)
             }
-            Err(e) => Err(e),
+            Err(e @ E::Quarantined(_)) => {
+                crate::errlog::event(
+                    Level::Warn,
+                    "quarantine",
+                    serde_json::json!({"path": file.path, "error": e.to_string()}),
+                );
+                if let Err(e) = db.quarantine(file, &e) {
+                    error!("quarantine {:?}: {:?}", file.path, e);
+                }
+                Err(e)
+            }
+            Err(e) => {
+                // Remembered so files that keep failing aren't retried on every start.
+                match db.add_failure(file, &e) {
+                    Ok(failure) => crate::errlog::event(
+                        Level::Info,
+                        "thumbnail_failed",
+                        serde_json::json!({
+                            "path": file.path,
+                            "error": e.to_string(),
+                            "attempts": failure.attempts,
+                            "permanent": failure.is_permanent(),
+                        }),
+                    ),
+                    Err(e) => error!("add failure for {:?}: {:?}", file.path, e),
+                }
+                Err(e)
+            }
         }
     }
 
     pub fn recv(&mut self) -> Vec<(usize, R<Metadata>)> {
         let mut ret = Vec::new();
 
-        // TODO: make more efficient
-        for (&i, mut handle) in &mut self.handles {
-            select! {
-                thumb_res = handle => {
-                    ret.push((i, thumb_res));
-                }
-                default => {}
+        for (i, generation, res) in self.results.try_iter() {
+            if generation != self.generation {
+                continue;
             }
-        }
-
-        for (i, _) in &ret {
-            self.handles.remove(i);
+            self.in_flight.remove(&i);
+            ret.push((i, res));
         }
 
         ret
     }
 
-    pub fn make_thumbs(&mut self, image: &image::Image) -> bool {
+    // Queue the `i`th file of the images thumbnailed since the last rebase, unless it is queued
+    // already.
+    pub fn make_thumbs(&mut self, i: usize, file: &Arc<File>) -> bool {
         assert!(!self.is_full());
 
-        if !image.is_missing() || self.contains(image.i) {
+        if self.contains(i) {
             return false;
         }
 
-        let uid = self.uid_base + image.i as u64;
+        let job = Job {
+            i,
+            generation: self.generation,
+            file: Arc::clone(file),
+            uid: self.uid_base + i as u64,
+            cost: 0,
+        };
+        self.jobs.send(job).expect("thumbnailer jobs");
 
-        let db = Arc::clone(&self.db);
+        self.in_flight.insert(i);
 
-        let fut =
-            Self::make_thumb(Arc::clone(&image.file), uid).then(move |r| Self::update_db(r, db));
+        true
+    }
 
-        let handle = self.executor.spawn_with_handle(fut).unwrap().fuse();
+    pub fn is_jpeg(path: &str) -> bool {
+        let path = path.to_lowercase();
+        path.ends_with(".jpg") || path.ends_with(".jpeg")
+    }
 
-        self.handles.insert(image.i, handle);
+    // Decode a JPEG larger than `max_size` at the smallest DCT scale that keeps the long edge at
+    // least `max_size`, returns the image and the full size. None to decode normally.
+    fn open_scaled(bytes: &[u8], max_size: u32) -> Option<(::image::DynamicImage, [u32; 2])> {
+        use ::image::{DynamicImage, ImageBuffer};
+        use jpeg_decoder::PixelFormat;
 
-        true
+        if max_size == 0 {
+            return None;
+        }
+
+        let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(bytes));
+        decoder.read_info().ok()?;
+        let info = decoder.info()?;
+        let full_size = [info.width as u32, info.height as u32];
+        if u32::max(full_size[0], full_size[1]) <= max_size {
+            return None;
+        }
+
+        let max_size = u16::try_from(max_size).unwrap_or(u16::MAX);
+        let (w, h) = decoder.scale(max_size, max_size).ok()?;
+        let (w, h) = (w as u32, h as u32);
+        if [w, h] == full_size {
+            return None;
+        }
+
+        // Other pixel formats need conversions the image crate already does.
+        let pixels = match info.pixel_format {
+            PixelFormat::L8 | PixelFormat::RGB24 => decoder.decode().ok()?,
+            _ => return None,
+        };
+        let image = match info.pixel_format {
+            PixelFormat::L8 => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+            _ => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8),
+        }?;
+
+        Some((image, full_size))
+    }
+
+    pub fn is_tiff(path: &str) -> bool {
+        let path = path.to_lowercase();
+        path.ends_with(".tif") || path.ends_with(".tiff")
     }
 
-    async fn make_thumb(
+    #[cfg(feature = "pdf")]
+    fn is_pdf(path: &str) -> bool {
+        path.to_lowercase().ends_with(".pdf")
+    }
+
+    #[cfg(feature = "xcf")]
+    fn is_xcf(path: &str) -> bool {
+        path.to_lowercase().ends_with(".xcf")
+    }
+
+    #[cfg(feature = "jxl")]
+    fn is_jxl(path: &str) -> bool {
+        path.to_lowercase().ends_with(".jxl")
+    }
+
+    // Formats opened by path, by converters or from archives, which gain nothing from being
+    // read first. Videos and documents can be gigabytes.
+    fn opened_by_path(path: &str) -> bool {
+        #[cfg(feature = "pdf")]
+        if Self::is_pdf(path) {
+            return true;
+        }
+        #[cfg(feature = "xcf")]
+        if Self::is_xcf(path) {
+            return true;
+        }
+        #[cfg(feature = "jxl")]
+        if Self::is_jxl(path) {
+            return true;
+        }
+        #[cfg(feature = "video")]
+        if is_video(path) {
+            return true;
+        }
+        crate::archive::is_archive(path)
+    }
+
+    // Decode the image an external converter writes to stdout.
+    #[cfg(any(feature = "pdf", feature = "xcf", feature = "jxl", feature = "video"))]
+    fn convert(program: &str, args: &[&str]) -> R<::image::DynamicImage> {
+        let output = std::process::Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|e| crate::E::CommandError(format!("{}: {}", program, e)))?;
+
+        if !output.status.success() {
+            return Err(crate::E::CommandError(format!(
+                "{}: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        ::image::load_from_memory(&output.stdout).map_err(crate::E::ImageError)
+    }
+
+    // Decode the first page, along with any format specific info worth showing.
+    pub fn open(path: &str) -> R<(::image::DynamicImage, Info)> {
+        Self::open_with(path, None)
+    }
+
+    // Like `open`, decoding common formats from `bytes` already read instead of the file.
+    fn open_with(path: &str, bytes: Option<&[u8]>) -> R<(::image::DynamicImage, Info)> {
+        #[cfg(feature = "dicom")]
+        if crate::dicom::is_dicom(path) {
+            return match bytes {
+                Some(bytes) => crate::dicom::decode(bytes),
+                None => crate::dicom::open(path),
+            };
+        }
+
+        Ok((Self::open_image(path, bytes)?, Info::new()))
+    }
+
+    fn open_image(path: &str, bytes: Option<&[u8]>) -> R<::image::DynamicImage> {
+        if crate::archive::is_archive(path) {
+            return crate::archive::cover(path);
+        }
+
+        // Render the first page with poppler.
+        #[cfg(feature = "pdf")]
+        if Self::is_pdf(path) {
+            let args = [
+                "-f",
+                "1",
+                "-l",
+                "1",
+                "-r",
+                "150",
+                "-png",
+                "-singlefile",
+                path,
+            ];
+            return Self::convert("pdftoppm", &args);
+        }
+
+        // GIMP files have no merged image, flatten them with xcftools.
+        #[cfg(feature = "xcf")]
+        if Self::is_xcf(path) {
+            return Self::convert("xcf2png", &[path]);
+        }
+
+        // Decode JPEG XL with libjxl, as PNG to keep 16-bit and alpha.
+        #[cfg(feature = "jxl")]
+        if Self::is_jxl(path) {
+            return Self::convert("djxl", &[path, "-", "--output_format=png"]);
+        }
+
+        // Pick a representative frame from the start of videos with ffmpeg.
+        #[cfg(feature = "video")]
+        if is_video(path) {
+            let args = [
+                "-v",
+                "error",
+                "-i",
+                path,
+                "-vf",
+                "thumbnail",
+                "-frames:v",
+                "1",
+                "-f",
+                "image2pipe",
+                "-c:v",
+                "png",
+                "-",
+            ];
+            return Self::convert("ffmpeg", &args);
+        }
+
+        if crate::psd::is_psd(path) {
+            return match bytes {
+                Some(bytes) => crate::psd::decode(bytes),
+                None => crate::psd::open(path),
+            };
+        }
+
+        #[cfg(feature = "fits")]
+        if crate::fits::is_fits(path) {
+            return match bytes {
+                Some(bytes) => crate::fits::decode(bytes),
+                None => crate::fits::open(path),
+            };
+        }
+
+        match bytes {
+            Some(bytes) => {
+                // Pick the decoder by extension like `::image::open`, by content for unknown ones.
+                let mut reader = ::image::io::Reader::new(Cursor::new(bytes));
+                match ::image::ImageFormat::from_path(path) {
+                    Ok(format) => reader.set_format(format),
+                    Err(_) => {
+                        reader = reader
+                            .with_guessed_format()
+                            .map_err(|e| crate::E::ImageError(e.into()))?
+                    }
+                }
+                reader.decode().map_err(crate::E::ImageError)
+            }
+            None => ::image::open(path).map_err(crate::E::ImageError),
+        }
+    }
+
+    // Decode the current page of a multi-page TIFF.
+    fn tiff_page(
+        decoder: &mut tiff::decoder::Decoder<std::io::BufReader<std::fs::File>>,
+    ) -> R<::image::DynamicImage> {
+        use ::image::{DynamicImage, ImageBuffer};
+        use tiff::decoder::DecodingResult;
+        use tiff::ColorType;
+
+        let (w, h) = decoder.dimensions().map_err(crate::E::TiffError)?;
+        let color_type = decoder.colortype().map_err(crate::E::TiffError)?;
+        let data = decoder.read_image().map_err(crate::E::TiffError)?;
+
+        let image = match (color_type, data) {
+            (ColorType::Gray(8), DecodingResult::U8(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageLuma8)
+            }
+            (ColorType::GrayA(8), DecodingResult::U8(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageLumaA8)
+            }
+            (ColorType::RGB(8), DecodingResult::U8(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageRgb8)
+            }
+            (ColorType::RGBA(8), DecodingResult::U8(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageRgba8)
+            }
+            (ColorType::Gray(16), DecodingResult::U16(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageLuma16)
+            }
+            (ColorType::GrayA(16), DecodingResult::U16(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageLumaA16)
+            }
+            (ColorType::RGB(16), DecodingResult::U16(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageRgb16)
+            }
+            (ColorType::RGBA(16), DecodingResult::U16(v)) => {
+                ImageBuffer::from_raw(w, h, v).map(DynamicImage::ImageRgba16)
+            }
+            (color_type, _) => {
+                return Err(crate::E::TiffError(tiff::TiffError::UnsupportedError(
+                    tiff::TiffUnsupportedError::UnsupportedColorType(color_type),
+                )))
+            }
+        };
+
+        image.ok_or_else(|| {
+            crate::E::TiffError(tiff::TiffError::FormatError(
+                tiff::TiffFormatError::InconsistentSizesEncountered,
+            ))
+        })
+    }
+
+    // Pages after the first of a multi-page TIFF, up to MAX_PAGES or until `f` returns false.
+    fn tiff_pages(path: &str, mut f: impl FnMut(::image::DynamicImage) -> bool) -> R<()> {
+        let file = std::fs::File::open(path).map_err(|e| crate::E::TiffError(e.into()))?;
+        let mut decoder = tiff::decoder::Decoder::new(std::io::BufReader::new(file))
+            .map_err(crate::E::TiffError)?;
+
+        let mut page = 1;
+        while page < MAX_PAGES && decoder.more_images() {
+            decoder.next_image().map_err(crate::E::TiffError)?;
+            if !f(Self::tiff_page(&mut decoder)?) {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(())
+    }
+
+    // Converters and multi-page documents are given longer to decode.
+    fn timeout(path: &str, timeout: Duration) -> Duration {
+        let path = path.to_lowercase();
+        let slow = [
+            ".pdf", ".xcf", ".jxl", ".tif", ".tiff", ".psd", ".psb", ".kra", ".ora",
+        ];
+        if slow.iter().any(|ext| path.ends_with(ext)) {
+            timeout * 4
+        } else {
+            timeout
+        }
+    }
+
+    // Run `make_thumb` on its own thread, a decoder that panics only takes that thread down and
+    // the file is quarantined. One that hangs is given up on, `release` runs once it finishes
+    // or panics after all.
+    fn make_thumb_guarded(
+        file: Arc<File>,
+        uid: u64,
+        settings: Settings,
+        bytes: Option<Vec<u8>>,
+        release: impl FnOnce() + Send + 'static,
+    ) -> R<Thumbs> {
+        struct OnDrop<F: FnOnce()>(Option<F>);
+        impl<F: FnOnce()> Drop for OnDrop<F> {
+            fn drop(&mut self) {
+                if let Some(f) = self.0.take() {
+                    f();
+                }
+            }
+        }
+        let release = OnDrop(Some(release));
+
+        if settings.decode_timeout.is_zero() {
+            return Self::make_thumb(file, uid, settings, bytes);
+        }
+
+        let timeout = Self::timeout(&file.path, settings.decode_timeout);
+        let (tx, rx) = bounded(1);
+        Self::spawn("thumbnailer-task", move || {
+            let _release = release;
+            let _ = tx.send(Self::make_thumb(file, uid, settings, bytes));
+        });
+
+        match rx.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(E::TimedOut(format!(
+                "decoding took over {}s",
+                timeout.as_secs()
+            ))),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(E::Quarantined("decoder crashed".to_owned()))
+            }
+        }
+    }
+
+    // Decode and tone map the first page, along with the full size if it was downsampled.
+    pub fn decode(
+        path: &str,
+        bytes: Option<&[u8]>,
+        settings: Settings,
+    ) -> R<(::image::DynamicImage, Info, Option<[u32; 2]>)> {
+        let scaled = bytes
+            .filter(|_| Self::is_jpeg(path))
+            .and_then(|bytes| Self::open_scaled(bytes, settings.max_decode));
+        let (image, info, full_size) = match scaled {
+            Some((image, full_size)) => (image, Info::new(), Some(full_size)),
+            None => {
+                let (image, info) = Self::open_with(path, bytes)?;
+                (image, info, None)
+            }
+        };
+        Ok((tonemap::apply(image, settings.tone_map), info, full_size))
+    }
+
+    // An image with more tiles than a bucket has chunk ids.
+    fn too_many_tiles() -> E {
+        use ::image::error::{ImageError, LimitError, LimitErrorKind};
+        E::ImageError(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )))
+    }
+
+    // `bytes` are the contents of the file if the read stage could read it.
+    fn make_thumb(
         file: Arc<File>,
         uid: u64,
-    ) -> R<(Arc<File>, Metadata, TileMap<Cursor<Vec<u8>>>)> {
-        let mut image = ::image::open(&file.path).map_err(crate::E::ImageError)?;
+        settings: Settings,
+        bytes: Option<Vec<u8>>,
+    ) -> R<Thumbs> {
+        let _s = crate::stats::ScopedDuration::new("make_thumb");
+
+        let fingerprint = match &bytes {
+            Some(bytes) => Some(fingerprint::of_bytes(bytes)),
+            None => fingerprint::of_file(&file.path).ok(),
+        };
+
+        let captions = bytes.as_deref().map(captions::read).unwrap_or_default();
+        let taken = bytes.as_deref().and_then(filter::taken);
+
+        // Chunk ids are shared by all pages of a bucket size.
+        let mut chunk_ids: BTreeMap<u32, u16> = BTreeMap::new();
+
+        let mut tiles: TileMap<Cursor<Vec<u8>>> = BTreeMap::new();
+
+        let frames = animation::frames(&file.path).unwrap_or_else(|e| {
+            warn!("unable to read frames of {:?}: {}", file.path, e);
+            None
+        });
+
+        if let Some(frames) = frames {
+            let mut pages: Vec<Vec<crate::Thumb>> = Vec::new();
+            let mut delays: Vec<u32> = Vec::new();
+            for (frame, delay) in frames {
+                pages.push(
+                    Self::make_pyramid(frame, uid, settings, &mut chunk_ids, &mut tiles)
+                        .ok_or_else(Self::too_many_tiles)?,
+                );
+                delays.push(delay);
+            }
+
+            let metadata = Metadata {
+                thumbs: pages.remove(0),
+                pages,
+                info: Info::new(),
+                delays,
+                full_size: None,
+                fingerprint,
+                captions,
+                quality: None,
+                screenshot: false,
+                scene: None,
+                taken,
+            };
+
+            return Ok((file, metadata, tiles));
+        }
+
+        let (image, info, full_size) = Self::decode(&file.path, bytes.as_deref(), settings)?;
 
+        let quality = Some(quality::measure(&image));
+        let size = full_size.unwrap_or([image.width(), image.height()]);
+        let screenshot = screenshot::detect(&file.path, bytes.as_deref(), size);
+        let scene = Some(scene::classify(&image));
+
+        let thumbs = Self::make_pyramid(image, uid, settings, &mut chunk_ids, &mut tiles)
+            .ok_or_else(Self::too_many_tiles)?;
+
+        let mut pages: Vec<Vec<crate::Thumb>> = Vec::new();
+
+        if Self::is_tiff(&file.path) {
+            let res = Self::tiff_pages(&file.path, |page| {
+                let page = tonemap::apply(page, settings.tone_map);
+                match Self::make_pyramid(page, uid, settings, &mut chunk_ids, &mut tiles) {
+                    Some(thumbs) => {
+                        pages.push(thumbs);
+                        true
+                    }
+                    None => {
+                        warn!(
+                            "out of tile ids after {} pages of {:?}",
+                            pages.len() + 1,
+                            file.path
+                        );
+                        false
+                    }
+                }
+            });
+            if let Err(e) = res {
+                warn!("unable to read pages of {:?}: {}", file.path, e);
+            }
+        }
+
+        let metadata = Metadata {
+            thumbs,
+            pages,
+            info,
+            delays: Vec::new(),
+            full_size,
+            fingerprint,
+            captions,
+            quality,
+            screenshot,
+            scene,
+            taken,
+        };
+
+        Ok((file, metadata, tiles))
+    }
+
+    // The thumbnails of every level, None when a bucket would run out of chunk ids, which all
+    // pages of an image share. Nothing is added to `chunk_ids` and `tiles` then.
+    pub fn make_pyramid(
+        mut image: ::image::DynamicImage,
+        uid: u64,
+        settings: Settings,
+        chunk_ids: &mut BTreeMap<u32, u16>,
+        tiles: &mut TileMap<Cursor<Vec<u8>>>,
+    ) -> Option<Vec<crate::Thumb>> {
         let (w, h) = image.dimensions();
 
         let orig_bucket = std::cmp::max(w, h).next_power_of_two();
@@ -140,8 +876,16 @@ impl Thumbnailer {
         let mut bucket = orig_bucket;
 
         let mut thumbs: Vec<crate::Thumb> = Vec::new();
+        let used_chunk_ids = chunk_ids.clone();
+
+        let levels = depth::levels(&image, settings.depth);
 
-        let mut tiles: BTreeMap<TileRef, Cursor<Vec<u8>>> = BTreeMap::new();
+        // Resized by the scaler if it can, taken in order as the buckets get smaller.
+        let mut scaled_levels = settings
+            .scaler
+            .and_then(|scale| scale(&image, min_bucket))
+            .unwrap_or_default()
+            .into_iter();
 
         while min_bucket <= bucket {
             let current_bucket = {
@@ -151,14 +895,19 @@ impl Thumbnailer {
 
             // Downsample if needed.
             if bucket < current_bucket {
-                image = image.thumbnail(bucket, bucket);
+                image = match scaled_levels.next() {
+                    Some(level) => level,
+                    None => image.thumbnail(bucket, bucket),
+                };
             }
 
             let lossy = bucket != orig_bucket;
 
-            let (w, h) = image.dimensions();
+            // Resizing is done at full depth, reduce to 8-bit only for the tiles.
+            let mut level = depth::to_8bit(&image, settings.depth, levels);
+            let level = level.as_mut().unwrap_or(&mut image);
 
-            let mut chunk_id = 0u16;
+            let (w, h) = level.dimensions();
 
             let mut thumb = crate::Thumb {
                 img_size: [w, h],
@@ -167,6 +916,16 @@ impl Thumbnailer {
 
             let spec = thumb.tile_spec();
 
+            let chunk_id = chunk_ids.entry(bucket).or_insert(0);
+            let count = spec.grid_size[0] as usize * spec.grid_size[1] as usize;
+            if *chunk_id as usize + count > u16::MAX as usize {
+                for tile_ref in thumbs.iter().flat_map(|thumb| &thumb.tile_refs) {
+                    tiles.remove(tile_ref);
+                }
+                *chunk_ids = used_chunk_ids;
+                return None;
+            }
+
             for (min_y, max_y) in spec.y_ranges() {
                 let y_range = max_y - min_y;
 
@@ -174,7 +933,7 @@ impl Thumbnailer {
                     let x_range = max_x - min_x;
 
                     let sub_image = ::image::DynamicImage::ImageRgba8(
-                        image.sub_image(min_x, min_y, x_range, y_range).to_image(),
+                        level.sub_image(min_x, min_y, x_range, y_range).to_image(),
                     );
 
                     let format = if lossy {
@@ -186,8 +945,8 @@ impl Thumbnailer {
                     let mut buf = Cursor::new(Vec::with_capacity((2 * x_range * y_range) as usize));
                     sub_image.write_to(&mut buf, format).expect("write_to");
 
-                    let tile_id = crate::TileRef::new(crate::Pow2::from(bucket), uid, chunk_id);
-                    chunk_id += 1;
+                    let tile_id = crate::TileRef::new(crate::Pow2::from(bucket), uid, *chunk_id);
+                    *chunk_id += 1;
 
                     tiles.insert(tile_id, buf);
 
@@ -202,8 +961,112 @@ impl Thumbnailer {
 
         thumbs.reverse();
 
-        let metadata = Metadata { thumbs };
+        Some(thumbs)
+    }
+}
 
-        Ok((file, metadata, tiles))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn pipeline() {
+        let dir = TestDir::new("thumbnailer");
+        let path = dir.join("a.png");
+        ::image::RgbImage::new(40, 30).save(&path).unwrap();
+
+        let db = Arc::new(Database::open(&dir.join("db")).unwrap());
+        let mut thumbnailer = Thumbnailer::new(Arc::clone(&db), 0, 2, Settings::default());
+
+        let files = [
+            File::stat(path.to_str().unwrap()).unwrap(),
+            File::stat(dir.join("missing.png").to_str().unwrap()).unwrap_or_default(),
+        ];
+        for (i, file) in files.into_iter().enumerate() {
+            let file = Arc::new(file);
+            assert!(thumbnailer.make_thumbs(i, &file));
+            assert!(thumbnailer.contains(i));
+            assert!(!thumbnailer.make_thumbs(i, &file));
+        }
+
+        let mut results = Vec::new();
+        while results.len() < 2 {
+            results.extend(thumbnailer.recv());
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        results.sort_by_key(|(i, _)| *i);
+        assert_eq!(results[0].1.as_ref().unwrap().img_size(), [40, 30]);
+        assert!(results[1].1.is_err());
+        assert!(!thumbnailer.contains(0));
+
+        // Returns once the writer is done.
+        thumbnailer.shutdown();
+    }
+
+    #[cfg(feature = "video")]
+    #[test]
+    fn videos() {
+        assert!(is_video("/a/clip.MP4"));
+        assert!(is_video("/a/screen recording.webm"));
+        assert!(!is_video("/a/mp4"));
+        assert!(!is_video("/a/photo.jpg"));
+    }
+
+    #[test]
+    fn timeout() {
+        let timeout = Duration::from_secs(30);
+        assert_eq!(Thumbnailer::timeout("/a.jpg", timeout), timeout);
+        assert_eq!(Thumbnailer::timeout("/a.TIF", timeout), timeout * 4);
+
+        // Errors other than hangs and crashes pass through.
+        let file = Arc::new(File::default());
+        let settings = Settings {
+            decode_timeout: timeout,
+            ..Default::default()
+        };
+        let (released_tx, released_rx) = bounded(1);
+        let release = move || released_tx.send(()).unwrap();
+        let res = Thumbnailer::make_thumb_guarded(file, 0, settings, None, release);
+        assert!(matches!(res, Err(E::ImageError(_))));
+        // The decoder's slot is given back once its thread is done.
+        released_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn chunk_ids_run_out() {
+        let image = ::image::DynamicImage::new_rgb8(40, 30);
+        let mut chunk_ids = BTreeMap::new();
+        let mut tiles = BTreeMap::new();
+        let settings = Settings::default();
+        let thumbs =
+            Thumbnailer::make_pyramid(image.clone(), 0, settings, &mut chunk_ids, &mut tiles);
+        assert!(thumbs.is_some());
+
+        // Levels already made are taken back.
+        let used = BTreeMap::from([(8, u16::MAX)]);
+        let (mut chunk_ids, mut tiles) = (used.clone(), BTreeMap::new());
+        let thumbs = Thumbnailer::make_pyramid(image, 0, settings, &mut chunk_ids, &mut tiles);
+        assert!(thumbs.is_none());
+        assert_eq!(chunk_ids, used);
+        assert!(tiles.is_empty());
+    }
+
+    #[test]
+    fn open_scaled() {
+        let mut jpeg = Cursor::new(Vec::new());
+        ::image::RgbImage::new(800, 600)
+            .write_to(&mut jpeg, ::image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let jpeg = jpeg.into_inner();
+
+        // Small enough, or too little to gain.
+        assert!(Thumbnailer::open_scaled(&jpeg, 0).is_none());
+        assert!(Thumbnailer::open_scaled(&jpeg, 800).is_none());
+        assert!(Thumbnailer::open_scaled(&jpeg, 700).is_none());
+
+        let (image, full_size) = Thumbnailer::open_scaled(&jpeg, 300).unwrap();
+        assert_eq!(full_size, [800, 600]);
+        assert_eq!(image.dimensions(), (400, 300));
     }
 }
@@ -0,0 +1,158 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Strip along the bottom of the grid showing how many photos were taken when. Each bar counts the
+// images of a stretch of time, dragging over the strip centers the grid on the image taken
+// closest to the time under the cursor. Capture times come from EXIF, images without one are
+// placed at their modification time.
+
+use crate::filter::date_from_secs;
+use crate::vec::*;
+use piston_window::{rectangle, Context, G2d};
+use std::time::{Duration, Instant};
+
+// Height of the strip and width of its bars in window pixels.
+static HEIGHT: f64 = 48.0;
+static BAR: f64 = 4.0;
+
+// Rebuilt at most this often while thumbnailing brings in capture times.
+static REBUILD: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct Timeline {
+    // Capture times and image indices in time order.
+    index: Vec<(u64, usize)>,
+
+    // Jumping while the left button is held.
+    pub dragging: bool,
+
+    built: Instant,
+    stale: bool,
+}
+
+impl Timeline {
+    pub fn new(times: impl IntoIterator<Item = (u64, usize)>) -> Self {
+        let mut index: Vec<(u64, usize)> = times.into_iter().collect();
+        index.sort_unstable();
+        Self {
+            index,
+            dragging: false,
+            built: Instant::now(),
+            stale: false,
+        }
+    }
+
+    // Times changed, e.g. new thumbnails were made.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn rebuild_due(&self, now: Instant) -> bool {
+        self.stale && now >= self.built + REBUILD
+    }
+
+    pub fn contains(&self, [_, win_h]: Vector2<f64>, [_, y]: Vector2<f64>) -> bool {
+        y >= win_h - HEIGHT && y < win_h
+    }
+
+    pub fn rect(&self, [win_w, win_h]: Vector2<f64>) -> [f64; 4] {
+        [0.0, win_h - HEIGHT, win_w, HEIGHT]
+    }
+
+    fn span(&self) -> Option<(u64, u64)> {
+        Some((self.index.first()?.0, self.index.last()?.0))
+    }
+
+    // Time at window x.
+    pub fn time_at(&self, win_w: f64, x: f64) -> Option<u64> {
+        let (first, last) = self.span()?;
+        let t = (x / win_w).clamp(0.0, 1.0);
+        Some(first + ((last - first) as f64 * t).round() as u64)
+    }
+
+    // Index of the image taken closest to `time`.
+    pub fn nearest(&self, time: u64) -> Option<usize> {
+        let after = self.index.partition_point(|&(t, _)| t < time);
+        let candidates = [after.checked_sub(1), Some(after)];
+        candidates
+            .into_iter()
+            .flatten()
+            .filter_map(|k| self.index.get(k))
+            .min_by_key(|(t, _)| t.abs_diff(time))
+            .map(|&(_, i)| i)
+    }
+
+    // The dates of the first and last image.
+    pub fn describe(&self) -> Option<String> {
+        let (first, last) = self.span()?;
+        Some(format!(
+            "{} - {}",
+            date_from_secs(first),
+            date_from_secs(last)
+        ))
+    }
+
+    // Bar heights from 0 to 1, one per `BAR` pixels.
+    fn counts(&self, win_w: f64) -> Vec<f64> {
+        let n = usize::max(1, (win_w / BAR) as usize);
+        let mut counts = vec![0usize; n];
+        if let Some((first, last)) = self.span() {
+            let len = u64::max(1, last - first) as f64;
+            for &(t, _) in &self.index {
+                let k = ((t - first) as f64 / len * (n - 1) as f64).round() as usize;
+                counts[k] += 1;
+            }
+        }
+        let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+        counts.into_iter().map(|count| count as f64 / max).collect()
+    }
+
+    pub fn draw(&self, win_size: Vector2<f64>, c: &Context, g: &mut G2d) {
+        let rect = self.rect(win_size);
+        rectangle([0.0, 0.0, 0.0, 0.8], rect, c.transform, g);
+        let [_, y, _, h] = rect;
+        let color = crate::night::tint([1.0, 1.0, 1.0, 0.7]);
+        for (k, height) in self.counts(win_size[0]).into_iter().enumerate() {
+            if height > 0.0 {
+                let bar_h = f64::max(1.0, height * (h - 4.0));
+                let bar = [k as f64 * BAR, y + h - bar_h, BAR - 1.0, bar_h];
+                rectangle(color, bar, c.transform, g);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumps() {
+        let timeline = Timeline::new([(300, 0), (100, 1), (110, 2), (1000, 3)]);
+        assert_eq!(timeline.describe().unwrap(), "1970-01-01 - 1970-01-01");
+        assert_eq!(timeline.time_at(900.0, 0.0), Some(100));
+        assert_eq!(timeline.time_at(900.0, 450.0), Some(550));
+        assert_eq!(timeline.time_at(900.0, 2000.0), Some(1000));
+        assert_eq!(timeline.nearest(0), Some(1));
+        assert_eq!(timeline.nearest(250), Some(0));
+        assert_eq!(timeline.nearest(700), Some(3));
+        assert_eq!(Timeline::new([]).nearest(5), None);
+
+        let counts = timeline.counts(40.0);
+        assert_eq!(counts.len(), 10);
+        assert_eq!(counts[0], 1.0);
+        assert_eq!(counts[2], 0.5);
+        assert_eq!(counts[9], 0.5);
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The window title and icon.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// How long a message replaces the title.
+static MESSAGE_TIME: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Default)]
+pub struct Title {
+    // Shown until it expires, e.g. where a file was saved.
+    message: Option<(String, Instant)>,
+
+    // Shown while a mode is on, e.g. the keys of crop mode.
+    mode: Option<String>,
+
+    // What the window shows, it's only set on changes.
+    shown: String,
+}
+
+impl Title {
+    pub fn message(&mut self, message: String) {
+        self.message = Some((message, Instant::now() + MESSAGE_TIME));
+    }
+
+    pub fn set_mode(&mut self, mode: Option<String>) {
+        self.mode = mode;
+    }
+
+    // The title to set if it differs from the shown one, `status` is shown when there is no
+    // message or mode.
+    pub fn update(&mut self, now: Instant, status: String) -> Option<String> {
+        if self
+            .message
+            .as_ref()
+            .is_some_and(|(_, until)| now >= *until)
+        {
+            self.message = None;
+        }
+        let title = match (&self.message, &self.mode) {
+            (Some((message, _)), _) => format!("pix - {}", message),
+            (None, Some(mode)) => format!("pix - {}", mode),
+            (None, None) => status,
+        };
+        if title == self.shown {
+            return None;
+        }
+        self.shown = title.clone();
+        Some(title)
+    }
+}
+
+// The name and position of the focused image and how many images wait for thumbnails, and
+// whether they wait because thumbnailing is `paused`.
+pub fn status(
+    focused: Option<(&str, usize)>,
+    num_images: usize,
+    num_missing: usize,
+    paused: bool,
+) -> String {
+    let mut title = "pix".to_owned();
+    if let Some((path, i)) = focused {
+        let name = Path::new(path)
+            .file_name()
+            .map_or(path.into(), |name| name.to_string_lossy());
+        title += &format!(" - {} ({}/{})", name, i + 1, num_images);
+    }
+    if num_missing > 0 {
+        let done = num_images.saturating_sub(num_missing);
+        title += " - ";
+        let progress = match paused {
+            true => "thumbnailing paused {}/{}",
+            false => "thumbnailing {}/{}",
+        };
+        title += &crate::i18n::trf(progress, &[&done, &num_images]);
+    }
+    title
+}
+
+// Four thumbnails on a dark square, like the grid.
+pub fn icon() -> (Vec<u8>, u32) {
+    const SIZE: u32 = 32;
+    const COLORS: [[u8; 3]; 4] = [
+        [0x42, 0xa5, 0xf5],
+        [0x66, 0xbb, 0x6a],
+        [0xff, 0xa7, 0x26],
+        [0xef, 0x53, 0x50],
+    ];
+
+    let mut rgba = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            // 2px border and gap around 13px squares.
+            let cell = |v: u32| match v {
+                2..=14 => Some(0),
+                17..=29 => Some(1),
+                _ => None,
+            };
+            let [r, g, b] = match (cell(x), cell(y)) {
+                (Some(cx), Some(cy)) => COLORS[(cy * 2 + cx) as usize],
+                _ => [0x21, 0x21, 0x21],
+            };
+            rgba.extend_from_slice(&[r, g, b, 0xff]);
+        }
+    }
+    (rgba, SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_line() {
+        assert_eq!(status(None, 0, 0, false), "pix");
+        assert_eq!(
+            status(Some(("/photos/a.jpg", 4)), 10, 3, false),
+            "pix - a.jpg (5/10) - thumbnailing 7/10"
+        );
+        assert_eq!(status(None, 10, 0, true), "pix");
+        assert_eq!(status(None, 10, 3, true), "pix - thumbnailing paused 7/10");
+    }
+
+    #[test]
+    fn message() {
+        let mut title = Title::default();
+        let now = Instant::now();
+        assert_eq!(title.update(now, "pix".to_owned()), Some("pix".to_owned()));
+        assert_eq!(title.update(now, "pix".to_owned()), None);
+
+        title.set_mode(Some("crop".to_owned()));
+        title.message("saved a.png".to_owned());
+        assert_eq!(
+            title.update(now, "pix".to_owned()),
+            Some("pix - saved a.png".to_owned())
+        );
+        assert_eq!(
+            title.update(now + MESSAGE_TIME * 2, "pix".to_owned()),
+            Some("pix - crop".to_owned())
+        );
+
+        let (rgba, size) = icon();
+        assert_eq!(rgba.len(), (size * size * 4) as usize);
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Map high dynamic range (OpenEXR, Radiance HDR) images to displayable 8-bit sRGB.
+
+use ::image::{DynamicImage, Rgba32FImage, RgbaImage};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMap {
+    Clamp,
+    #[default]
+    Reinhard,
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone map operator {:?}", s)),
+        }
+    }
+}
+
+impl ToneMap {
+    fn map(&self, v: f32) -> f32 {
+        let v = f32::max(0.0, v);
+        match self {
+            ToneMap::Clamp => v,
+            ToneMap::Reinhard => v / (1.0 + v),
+            // Narkowicz's fit of the ACES filmic curve.
+            ToneMap::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        }
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn is_hdr(image: &DynamicImage) -> bool {
+    matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    )
+}
+
+// Tone map linear float images, everything else is returned unchanged. Exposure isn't applied
+// here, thumbnails are tone mapped once and the exposure key scales them when drawing.
+pub fn apply(image: DynamicImage, tone_map: ToneMap) -> DynamicImage {
+    if !is_hdr(&image) {
+        return image;
+    }
+
+    let src: Rgba32FImage = image.into_rgba32f();
+
+    let dst = RgbaImage::from_fn(src.width(), src.height(), |x, y| {
+        let [r, g, b, a] = src.get_pixel(x, y).0;
+        let encode = |v: f32| (linear_to_srgb(tone_map.map(v)) * 255.0).round() as u8;
+        ::image::Rgba([
+            encode(r),
+            encode(g),
+            encode(b),
+            (a.clamp(0.0, 1.0) * 255.0) as u8,
+        ])
+    });
+
+    DynamicImage::ImageRgba8(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators() {
+        assert_eq!(ToneMap::Clamp.map(-1.0), 0.0);
+        assert_eq!(ToneMap::Reinhard.map(1.0), 0.5);
+        assert!(ToneMap::Aces.map(100.0) > 0.99);
+        assert!(ToneMap::Reinhard.map(1000.0) < 1.0);
+    }
+
+    #[test]
+    fn apply_float() {
+        let image = DynamicImage::ImageRgba32F(Rgba32FImage::from_pixel(
+            1,
+            1,
+            ::image::Rgba([0.0, 1.0, 16.0, 1.0]),
+        ));
+        let image = apply(image, ToneMap::Clamp);
+        assert_eq!(image.to_rgba8().get_pixel(0, 0).0, [0, 255, 255, 255]);
+    }
+}
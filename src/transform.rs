@@ -0,0 +1,154 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Affine 2D transforms for mapping between grid, image and window coordinates, convertible to
+// the matrices piston draws with.
+
+use crate::vec::*;
+use piston_window::math::Matrix2d;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform2D(Matrix2d);
+
+impl Default for Transform2D {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl From<Matrix2d> for Transform2D {
+    fn from(m: Matrix2d) -> Self {
+        Self(m)
+    }
+}
+
+impl Transform2D {
+    pub fn identity() -> Self {
+        Self([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+    }
+
+    pub fn translate([x, y]: Vector2<f64>) -> Self {
+        Self([[1.0, 0.0, x], [0.0, 1.0, y]])
+    }
+
+    pub fn scale([sx, sy]: Vector2<f64>) -> Self {
+        Self([[sx, 0.0, 0.0], [0.0, sy, 0.0]])
+    }
+
+    // Exact rotation by multiples of 90 degrees, clockwise on screen where y points down.
+    pub fn quarter_turns(turns: u8) -> Self {
+        let (sin, cos) = match turns % 4 {
+            0 => (0.0, 1.0),
+            1 => (1.0, 0.0),
+            2 => (0.0, -1.0),
+            _ => (-1.0, 0.0),
+        };
+        Self([[cos, -sin, 0.0], [sin, cos, 0.0]])
+    }
+
+    // Scale by `ratio` keeping `point` in place.
+    pub fn scale_about(point: Vector2<f64>, ratio: f64) -> Self {
+        Self::translate(vec2_scale(point, -1.0))
+            .then(Self::scale([ratio, ratio]))
+            .then(Self::translate(point))
+    }
+
+    // Apply `self`, then `next`.
+    pub fn then(self, next: Self) -> Self {
+        let [a, b] = next.0;
+        let [c, d] = self.0;
+        Self([
+            [
+                a[0] * c[0] + a[1] * d[0],
+                a[0] * c[1] + a[1] * d[1],
+                a[0] * c[2] + a[1] * d[2] + a[2],
+            ],
+            [
+                b[0] * c[0] + b[1] * d[0],
+                b[0] * c[1] + b[1] * d[1],
+                b[0] * c[2] + b[1] * d[2] + b[2],
+            ],
+        ])
+    }
+
+    // Translate in the local coordinates, like `Transformed::trans`.
+    pub fn trans(self, x: f64, y: f64) -> Self {
+        Self::translate([x, y]).then(self)
+    }
+
+    // Scale in the local coordinates, like `Transformed::zoom`.
+    pub fn zoom(self, ratio: f64) -> Self {
+        Self::scale([ratio, ratio]).then(self)
+    }
+
+    pub fn invert(self) -> Option<Self> {
+        let [[a, b, c], [d, e, f]] = self.0;
+        let det = a * e - b * d;
+        if det == 0.0 {
+            return None;
+        }
+        Some(Self([
+            [e / det, -b / det, (b * f - c * e) / det],
+            [-d / det, a / det, (c * d - a * f) / det],
+        ]))
+    }
+
+    pub fn apply(self, [x, y]: Vector2<f64>) -> Vector2<f64> {
+        let [a, b] = self.0;
+        [a[0] * x + a[1] * y + a[2], b[0] * x + b[1] * y + b[2]]
+    }
+
+    pub fn matrix(self) -> Matrix2d {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piston_window::Transformed;
+
+    #[test]
+    fn compose() {
+        let t = Transform2D::translate([10.0, 20.0]).then(Transform2D::scale([2.0, 3.0]));
+        assert_eq!(t.apply([1.0, 1.0]), [22.0, 63.0]);
+        assert_eq!(t.invert().unwrap().apply([22.0, 63.0]), [1.0, 1.0]);
+        assert_eq!(Transform2D::scale([0.0, 1.0]).invert(), None);
+
+        // Same as piston's builder methods.
+        let m = [[1.0, 0.0, 5.0], [0.0, 1.0, 7.0]];
+        assert_eq!(
+            Transform2D::from(m).trans(3.0, 4.0).zoom(2.0).matrix(),
+            m.trans(3.0, 4.0).zoom(2.0)
+        );
+    }
+
+    #[test]
+    fn rotate() {
+        let t = Transform2D::quarter_turns(1);
+        assert_eq!(t.apply([1.0, 0.0]), [0.0, 1.0]);
+        assert_eq!(Transform2D::quarter_turns(5), t);
+        assert_eq!(
+            t.then(Transform2D::quarter_turns(3)),
+            Transform2D::identity()
+        );
+    }
+
+    #[test]
+    fn scale_about() {
+        let t = Transform2D::scale_about([10.0, 10.0], 2.0);
+        assert_eq!(t.apply([10.0, 10.0]), [10.0, 10.0]);
+        assert_eq!(t.apply([11.0, 12.0]), [12.0, 14.0]);
+    }
+}
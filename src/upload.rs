@@ -0,0 +1,120 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tile uploads. Tiles are expanded to RGBA in a staging buffer reused for every tile, instead
+// of allocating a decoded image and an RGBA copy of it each time. gfx copies the pixels into
+// the texture when it is created, so the buffer is free again right after. Tiles larger than the
+// driver's textures are scaled down, callers draw them into their original rectangle.
+
+use crate::database::Database;
+use crate::TileRef;
+use ::image::imageops::FilterType;
+use ::image::RgbaImage;
+use jpeg_decoder::PixelFormat;
+use piston_window::{G2dTexture, G2dTextureContext, Texture, TextureSettings};
+use std::cell::RefCell;
+
+thread_local! {
+    static STAGING: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Decode a tile into `buf`. Tiles are written as RGB JPEGs, anything else takes the slow path.
+fn decode(data: &[u8], mut buf: Vec<u8>) -> RgbaImage {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder.decode().ok();
+    let pixels = pixels.zip(decoder.info());
+
+    buf.clear();
+    match pixels {
+        Some((pixels, info)) if info.pixel_format == PixelFormat::RGB24 => {
+            buf.reserve(pixels.len() / 3 * 4);
+            for rgb in pixels.chunks_exact(3) {
+                buf.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+        Some((pixels, info)) if info.pixel_format == PixelFormat::L8 => {
+            buf.reserve(pixels.len() * 4);
+            for &l in &pixels {
+                buf.extend_from_slice(&[l, l, l, 255]);
+            }
+        }
+        _ => {
+            return ::image::load_from_memory(data)
+                .expect("load image")
+                .into_rgba8()
+        }
+    }
+    let info = decoder.info().expect("decoded");
+    RgbaImage::from_raw(info.width as u32, info.height as u32, buf).expect("tile size")
+}
+
+// Load a tile from the database and upload it, `edit` changes the pixels first, e.g. with the
+// adjustments of the single image.
+pub fn tile(
+    db: &Database,
+    tile_ref: TileRef,
+    texture_context: &mut G2dTextureContext,
+    edit: impl FnOnce(&mut RgbaImage),
+) -> G2dTexture {
+    let _s = crate::stats::ScopedDuration::new("load_tile");
+    crate::pacing::changed();
+
+    let data = db.get(tile_ref).expect("db get").expect("missing tile");
+
+    STAGING.with(|staging| {
+        let mut tile = decode(&data, staging.take());
+        edit(&mut tile);
+        if crate::mono::enabled() {
+            crate::mono::dither(&mut tile);
+        }
+        let max_texture = crate::glcompat::max_texture();
+        if let Some([w, h]) = crate::glcompat::fit([tile.width(), tile.height()], max_texture) {
+            let small = ::image::imageops::resize(&tile, w, h, FilterType::Triangle);
+            return Texture::from_image(texture_context, &small, &TextureSettings::new())
+                .expect("texture");
+        }
+        let texture =
+            Texture::from_image(texture_context, &tile, &TextureSettings::new()).expect("texture");
+        staging.replace(tile.into_raw());
+        texture
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{DynamicImage, ImageOutputFormat, Rgba};
+
+    #[test]
+    fn reused() {
+        let tile = RgbaImage::from_pixel(16, 8, Rgba([200, 100, 50, 255]));
+        let mut data = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(tile)
+            .write_to(&mut data, ImageOutputFormat::Jpeg(100))
+            .unwrap();
+        let data = data.into_inner();
+
+        let buf = Vec::with_capacity(16 * 8 * 4);
+        let ptr = buf.as_ptr();
+        let decoded = decode(&data, buf);
+        assert_eq!(decoded.dimensions(), (16, 8));
+        let [r, g, b, a] = decoded.get_pixel(3, 3).0;
+        assert!(r.abs_diff(200) <= 2 && g.abs_diff(100) <= 2 && b.abs_diff(50) <= 2);
+        assert_eq!(a, 255);
+
+        // Decoded in place, without a new allocation.
+        let raw = decoded.into_raw();
+        assert_eq!(raw.as_ptr(), ptr);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub static MAX_RATING: u8 = 5;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Label {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "red" => Ok(Label::Red),
+            "yellow" => Ok(Label::Yellow),
+            "green" => Ok(Label::Green),
+            "blue" => Ok(Label::Blue),
+            "purple" => Ok(Label::Purple),
+            _ => Err(format!("unknown label {:?}", s)),
+        }
+    }
+}
+
+// Curation state added by the user, stored separately from the thumbnail metadata so it survives
+// files being modified.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct UserData {
+    // Star rating, 0 is unrated.
+    pub rating: u8,
+
+    pub label: Option<Label>,
+}
+
+impl UserData {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Set the rating, or clear it when it's already set to the same value.
+    pub fn toggle_rating(&mut self, rating: u8) {
+        assert!(rating <= MAX_RATING);
+        self.rating = if self.rating == rating { 0 } else { rating };
+    }
+
+    // Set the label, or clear it when it's already set to the same value.
+    pub fn toggle_label(&mut self, label: Label) {
+        self.label = if self.label == Some(label) {
+            None
+        } else {
+            Some(label)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle() {
+        let mut user_data = UserData::default();
+        assert!(user_data.is_empty());
+
+        user_data.toggle_rating(3);
+        user_data.toggle_label(Label::Red);
+        assert_eq!(user_data.rating, 3);
+        assert_eq!(user_data.label, Some(Label::Red));
+
+        user_data.toggle_label(Label::Blue);
+        assert_eq!(user_data.label, Some(Label::Blue));
+
+        user_data.toggle_rating(3);
+        user_data.toggle_label(Label::Blue);
+        assert!(user_data.is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Video previews. When the cursor rests on a video in the grid, the first seconds are decoded
+// small and without sound by ffmpeg on a worker thread, then loop over the thumbnail until the
+// cursor moves on. Thumbnails of videos are made with ffmpeg by the thumbnailer.
+
+use crate::groups::Groups;
+use crate::thumbnailer::is_video;
+use crate::vec::*;
+use crate::view::View;
+use crate::{E, R};
+use ::image::{DynamicImage, RgbImage, RgbaImage};
+use crossbeam_channel::{bounded, Receiver};
+use log::*;
+use piston_window::{Context, G2d, G2dTexture, G2dTextureContext, Image, Texture, TextureSettings};
+use std::time::{Duration, Instant};
+
+// The cursor rests this long before a preview is decoded.
+static DWELL: Duration = Duration::from_millis(500);
+
+// Length, frame rate and long edge of previews.
+static SECONDS: u32 = 3;
+static FPS: u32 = 10;
+static SIZE: u32 = 256;
+
+// Decode the first seconds of the video, small and at a low frame rate.
+fn decode(path: &str) -> R<Vec<RgbaImage>> {
+    let _s = crate::stats::ScopedDuration::new("video_preview");
+    let filter = format!(
+        "fps={},scale={}:{}:force_original_aspect_ratio=decrease",
+        FPS, SIZE, SIZE
+    );
+    let seconds = SECONDS.to_string();
+    let args = [
+        "-v",
+        "error",
+        "-t",
+        &seconds,
+        "-i",
+        path,
+        "-an",
+        "-vf",
+        &filter,
+        "-f",
+        "image2pipe",
+        "-c:v",
+        "ppm",
+        "-",
+    ];
+    let output = std::process::Command::new("ffmpeg")
+        .args(args)
+        .output()
+        .map_err(|e| E::CommandError(format!("ffmpeg: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(E::CommandError(format!(
+            "ffmpeg: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    Ok(ppm_frames(&output.stdout)
+        .into_iter()
+        .map(|frame| DynamicImage::ImageRgb8(frame).into_rgba8())
+        .collect())
+}
+
+// Frames ffmpeg writes as concatenated binary PPMs.
+fn ppm_frames(mut data: &[u8]) -> Vec<RgbImage> {
+    let mut frames = Vec::new();
+    while let Some((frame, rest)) = ppm_frame(data) {
+        frames.push(frame);
+        data = rest;
+    }
+    frames
+}
+
+fn ppm_frame(data: &[u8]) -> Option<(RgbImage, &[u8])> {
+    // Width, height and maximum value, separated by whitespace.
+    let mut fields = [0usize; 3];
+    let mut rest = data.strip_prefix(b"P6")?;
+    for field in &mut fields {
+        let start = rest.iter().position(|b| !b.is_ascii_whitespace())?;
+        let len = rest[start..].iter().position(|b| !b.is_ascii_digit())?;
+        *field = std::str::from_utf8(&rest[start..start + len])
+            .ok()?
+            .parse()
+            .ok()?;
+        rest = &rest[start + len..];
+    }
+    let [w, h, max] = fields;
+    if max != 255 {
+        return None;
+    }
+    // A single whitespace before the pixels.
+    let rest = rest.get(1..)?;
+    let len = w * h * 3;
+    let frame = RgbImage::from_raw(w as u32, h as u32, rest.get(..len)?.to_vec())?;
+    Some((frame, &rest[len..]))
+}
+
+#[derive(Default)]
+pub struct Preview {
+    // Grid coordinates of the video under the cursor and when the cursor got there.
+    target: Option<(Vector2<u32>, Instant)>,
+
+    decoding: Option<Receiver<R<Vec<RgbaImage>>>>,
+
+    frames: Vec<G2dTexture>,
+    frame_size: Vector2<u32>,
+
+    // When the first frame was shown.
+    started: Option<Instant>,
+}
+
+impl Preview {
+    // Follow the cursor over the grid, decode after it rests on a video and upload the frames.
+    pub fn update(
+        &mut self,
+        view: &View,
+        groups: &Groups,
+        texture_context: &mut G2dTextureContext,
+        now: Instant,
+    ) {
+        let coords = view
+            .mouse_coords()
+            .filter(|&coords| groups.get(coords).is_some_and(|i| is_video(&i.file.path)));
+        let coords = match coords {
+            Some(coords) => coords,
+            None => {
+                *self = Self::default();
+                return;
+            }
+        };
+
+        let since = match self.target {
+            Some((target, since)) if target == coords => since,
+            _ => {
+                *self = Self {
+                    target: Some((coords, now)),
+                    ..Default::default()
+                };
+                return;
+            }
+        };
+
+        if self.decoding.is_none() && self.started.is_none() && now >= since + DWELL {
+            let path = groups.get(coords).expect("video").file.path.clone();
+            let (tx, rx) = bounded(1);
+            std::thread::spawn(move || {
+                let _ = tx.send(decode(&path));
+            });
+            self.decoding = Some(rx);
+        }
+
+        if let Some(Ok(frames)) = self.decoding.as_ref().map(|rx| rx.try_recv()) {
+            self.decoding = None;
+            self.started = Some(now);
+            match frames {
+                Ok(frames) => {
+                    self.frame_size = frames
+                        .first()
+                        .map_or([0, 0], |frame| [frame.width(), frame.height()]);
+                    self.frames = frames
+                        .into_iter()
+                        .map(|mut frame| {
+                            if crate::mono::enabled() {
+                                crate::mono::dither(&mut frame);
+                            }
+                            Texture::from_image(texture_context, &frame, &TextureSettings::new())
+                                .expect("texture")
+                        })
+                        .collect();
+                }
+                Err(e) => warn!("unable to preview video: {}", e),
+            }
+        }
+
+        if !self.frames.is_empty() {
+            crate::pacing::changed();
+        }
+    }
+
+    // Draw the current frame over the thumbnail, centered within the grid square.
+    pub fn draw(&self, view: &View, now: Instant, c: &Context, g: &mut G2d) {
+        let (coords, started) = match (self.target, self.started) {
+            (Some((coords, _)), Some(started)) if !self.frames.is_empty() => (coords, started),
+            _ => return,
+        };
+        let elapsed = now.saturating_duration_since(started).as_secs_f64();
+        let frame = &self.frames[(elapsed * FPS as f64) as usize % self.frames.len()];
+
+        let [w, h] = vec2_f64(self.frame_size);
+        let scale = view.zoom / f64::max(w, h);
+        let [x, y] = view.trans(coords);
+        let rect = [
+            x + (view.zoom - w * scale) / 2.0,
+            y + (view.zoom - h * scale) / 2.0,
+            w * scale,
+            h * scale,
+        ];
+        let image = match crate::night::enabled() {
+            true => Image::new().color(crate::night::tint([1.0; 4])),
+            false => Image::new(),
+        };
+        image.rect(rect).draw(frame, &c.draw_state, c.transform, g);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppm() {
+        let mut data = b"P6\n2 1\n255\n".to_vec();
+        data.extend([255, 0, 0, 0, 255, 0]);
+        data.extend(b"P6 1 1 255\n");
+        data.extend([0, 0, 255]);
+        data.extend(b"P6\n9 9\n255\n");
+
+        let frames = ppm_frames(&data);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].dimensions(), (2, 1));
+        assert_eq!(frames[0].get_pixel(1, 0).0, [0, 255, 0]);
+        assert_eq!(frames[1].get_pixel(0, 0).0, [0, 0, 255]);
+    }
+}
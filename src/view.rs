@@ -12,9 +12,120 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::transform::Transform2D;
 use crate::vec::*;
 
-#[derive(Debug, Default)]
+// Grid squares smaller than this many window pixels show directory clusters.
+static CLUSTER_ZOOM: f64 = 12.0;
+
+// What the grid renders at the current zoom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Clusters,
+    Thumbs,
+
+    // A grid square covers the window, load the largest thumbnails.
+    Full,
+}
+
+// How the single image view sizes the image to the window.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    #[default]
+    Contain,
+    Fill,
+    Width,
+    Height,
+
+    // One image pixel per window pixel.
+    Actual,
+}
+
+impl Fit {
+    pub fn next(self) -> Self {
+        match self {
+            Fit::Contain => Fit::Fill,
+            Fit::Fill => Fit::Width,
+            Fit::Width => Fit::Height,
+            Fit::Height => Fit::Actual,
+            Fit::Actual => Fit::Contain,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Fit::Contain => "fit",
+            Fit::Fill => "fill",
+            Fit::Width => "fit width",
+            Fit::Height => "fit height",
+            Fit::Actual => "1:1",
+        }
+    }
+}
+
+// Rotation and mirroring of the single image view, the file is left alone.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    // Clockwise quarter turns.
+    turns: u8,
+
+    flip_h: bool,
+    flip_v: bool,
+}
+
+impl Orientation {
+    pub fn rotate(&mut self, turns: i8) {
+        self.turns = (self.turns as i8 + turns).rem_euclid(4) as u8;
+    }
+
+    // Flips are along the window axes, whatever the rotation.
+    pub fn flip_h(&mut self) {
+        self.flip_h = !self.flip_h;
+    }
+
+    pub fn flip_v(&mut self) {
+        self.flip_v = !self.flip_v;
+    }
+
+    // Width and height of an image after rotating it.
+    pub fn size(&self, [w, h]: Vector2<u32>) -> Vector2<u32> {
+        if self.turns % 2 == 1 {
+            [h, w]
+        } else {
+            [w, h]
+        }
+    }
+
+    // Window to window transform turning the view around `center`.
+    pub fn transform(&self, center: Vector2<f64>) -> Transform2D {
+        let flip = [
+            if self.flip_h { -1.0 } else { 1.0 },
+            if self.flip_v { -1.0 } else { 1.0 },
+        ];
+        Transform2D::translate(vec2_scale(center, -1.0))
+            .then(Transform2D::quarter_turns(self.turns))
+            .then(Transform2D::scale(flip))
+            .then(Transform2D::translate(center))
+    }
+
+    // What is drawn at the window position `pos`.
+    pub fn unmap(&self, pos: Vector2<f64>, center: Vector2<f64>) -> Vector2<f64> {
+        let inverse = self
+            .transform(center)
+            .invert()
+            .expect("invertible orientation");
+        inverse.apply(pos)
+    }
+}
+
+// Zoom and pan of a single image view relative to the fitted image, in fitted grid squares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    zoom: f64,
+    offset: Vector2<f64>,
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct View {
     num_images: f64,
 
@@ -35,8 +146,24 @@ pub struct View {
     // Mouse coordinates.
     mouse: Vector2<f64>,
 
+    pub orientation: Orientation,
+
     // Has the user panned or zoomed?
     auto: bool,
+
+    // Keep the grid a multiple of this many squares wide so each set of compared images shares a
+    // row, 0 for no sets.
+    sets: u32,
+
+    // Single image of a set shown next to the rest of its set. The others are drawn over its
+    // square in cells of their own, so they share its zoom and pan.
+    synced: Option<Vector2<u32>>,
+
+    // Grid locked to this many columns filling the window width, scrolled only vertically.
+    columns: Option<u32>,
+
+    // Grid repeated in every direction, panning past an edge shows the opposite one.
+    wrap: bool,
 }
 
 impl View {
@@ -52,10 +179,110 @@ impl View {
         ret
     }
 
+    // Grid of sets of `n` images, e.g. pairs.
+    pub fn sets(num_images: usize, n: u32) -> Self {
+        let mut ret = Self {
+            sets: n,
+            ..Self::new(num_images)
+        };
+        ret.reset();
+        ret
+    }
+
+    // Lock the grid to `columns` filling the window width, or unlock it with None.
+    pub fn set_columns(&mut self, columns: Option<u32>) {
+        // Sets share a row.
+        let sets = self.sets.max(1);
+        self.columns = columns.map(|n| n.max(1).div_ceil(sets) * sets);
+        if self.columns.is_some() {
+            self.reset();
+        }
+    }
+
+    // Squares of the set of `coords`, including it. Only `coords` without sets.
+    pub fn set_of(&self, [x, y]: Vector2<u32>) -> Vec<Vector2<u32>> {
+        let n = self.sets.max(1);
+        let first = x - x % n;
+        (first..first + n).map(|x| [x, y]).collect()
+    }
+
+    pub fn set_synced(&mut self, synced: Option<Vector2<u32>>) {
+        self.synced = synced;
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.columns.is_some()
+    }
+
+    // Keep a locked grid across the window, its top row at most at the top of the window and its
+    // bottom row at least at the bottom.
+    fn clamp_locked(&mut self) {
+        let [_, h] = self.win_size;
+        let bottom = f64::min(0.0, h - self.grid_size[1] * self.zoom);
+        self.trans = [0.0, self.trans[1].clamp(bottom, 0.0)];
+    }
+
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    // Window pixels covered by the grid.
+    fn grid_px(&self) -> Vector2<f64> {
+        vec2_scale(self.grid_size, self.zoom)
+    }
+
+    // Keep the top left corner of a wrapped grid within one grid size left of and above the
+    // window, the copies drawn by `tiles` fill the rest.
+    fn wrap_trans(&mut self) {
+        if self.wrap {
+            let grid_px = self.grid_px();
+            self.trans = [0, 1].map(|i| {
+                let [t, px] = [self.trans[i], grid_px[i]];
+                t - px * (t / px).ceil()
+            });
+        }
+    }
+
+    // Views of the copies of the grid that may be visible, only this one without wrapping.
+    pub fn tiles(&self) -> Vec<View> {
+        if !self.wrap {
+            return vec![self.clone()];
+        }
+        let [w, h] = self.win_size;
+        let grid_px = self.grid_px();
+        let copies = |i: usize, size: f64| {
+            let first = (-self.trans[i] / grid_px[i]).floor() as i64;
+            let last = ((size - self.trans[i]) / grid_px[i]).ceil() as i64 - 1;
+            first..=last
+        };
+        let mut ret = Vec::new();
+        for y in copies(1, h) {
+            for x in copies(0, w) {
+                let offset = [x as f64 * grid_px[0], y as f64 * grid_px[1]];
+                ret.push(View {
+                    trans: vec2_add(self.trans, offset),
+                    ..self.clone()
+                });
+            }
+        }
+        ret
+    }
+
     pub fn target_size(&self) -> u32 {
         ((self.zoom * 1.5) as u32).next_power_of_two()
     }
 
+    pub fn level(&self) -> Level {
+        let [w, h] = self.win_size;
+        if self.zoom < CLUSTER_ZOOM {
+            Level::Clusters
+        } else if self.zoom >= f64::min(w, h) {
+            Level::Full
+        } else {
+            Level::Thumbs
+        }
+    }
+
     pub fn center_mouse(&mut self) {
         self.mouse = vec2_scale(self.win_size, 0.5);
     }
@@ -65,13 +292,26 @@ impl View {
 
         let [w, h] = self.win_size;
 
+        if let Some(columns) = self.columns {
+            let grid_w = columns as f64;
+            self.grid_size = [grid_w, f64::max(1.0, (self.num_images / grid_w).ceil())];
+            self.zoom = w / grid_w;
+            self.min_zoom = self.zoom;
+            self.trans = [0.0, 0.0];
+            return;
+        }
+
         self.zoom = {
             let px_per_image = (w * h) / self.num_images;
             px_per_image.sqrt()
         };
 
         self.grid_size = {
-            let grid_w = f64::max(1.0, (w / self.zoom).floor());
+            let mut grid_w = f64::max(1.0, (w / self.zoom).floor());
+            if self.sets > 1 {
+                let n = self.sets as f64;
+                grid_w = f64::max(n, grid_w - grid_w % n);
+            }
             let grid_h = (self.num_images / grid_w).ceil();
             [grid_w, grid_h]
         };
@@ -81,11 +321,16 @@ impl View {
         if h < grid_px[1] {
             self.zoom *= h / grid_px[1];
         }
+        let grid_px = vec2_scale(self.grid_size, self.zoom);
+        if w < grid_px[0] {
+            self.zoom *= w / grid_px[0];
+        }
 
         // Add black border.
         self.zoom *= 0.95;
 
-        self.min_zoom = self.zoom * 0.5;
+        // Far enough out to reach the directory clusters.
+        self.min_zoom = f64::min(self.zoom, CLUSTER_ZOOM) * 0.5;
 
         self.trans = {
             let grid_px = vec2_scale(self.grid_size, self.zoom);
@@ -94,14 +339,109 @@ impl View {
         };
     }
 
+    // Fit a single grid square to the window.
+    pub fn zoom_to(&mut self, coords: Vector2<u32>) {
+        self.auto = false;
+
+        let [w, h] = self.win_size;
+        self.zoom = f64::min(w, h);
+
+        let square_px = vec2_scale(vec2_f64(coords), self.zoom);
+        let border_px = vec2_sub(self.win_size, [self.zoom, self.zoom]);
+        self.trans = vec2_sub(vec2_scale(border_px, 0.5), square_px);
+    }
+
+    // Size an image of `img_size` in a grid square to the window and center it.
+    pub fn fit_to(&mut self, coords: Vector2<u32>, img_size: Vector2<u32>, fit: Fit) {
+        self.auto = false;
+
+        let [w, h] = self.win_size;
+        let [img_w, img_h] = vec2_f64(img_size);
+        let scale = match fit {
+            Fit::Contain => f64::min(w / img_w, h / img_h),
+            Fit::Fill => f64::max(w / img_w, h / img_h),
+            Fit::Width => w / img_w,
+            Fit::Height => h / img_h,
+            Fit::Actual => 1.0,
+        };
+        self.zoom = scale * f64::max(img_w, img_h);
+
+        let center = vec2_scale(vec2_add(vec2_f64(coords), [0.5, 0.5]), self.zoom);
+        self.trans = vec2_sub(vec2_scale(self.win_size, 0.5), center);
+    }
+
+    // Zoom to `zoom` window pixels per grid square with the grid position `center` in the middle
+    // of the window, by default the one there now.
+    pub fn look_at(&mut self, zoom: f64, center: Option<Vector2<f64>>) {
+        self.auto = false;
+
+        let mid = vec2_scale(self.win_size, 0.5);
+        let center =
+            center.unwrap_or_else(|| vec2_scale(vec2_sub(mid, self.trans), 1.0 / self.zoom));
+        if !self.is_locked() {
+            self.zoom = f64::max(self.min_zoom, zoom);
+        }
+        self.trans = vec2_sub(mid, vec2_scale(center, self.zoom));
+        if self.is_locked() {
+            self.clamp_locked();
+        }
+    }
+
+    // Zoom to `zoom` window pixels per grid square with grid square `coords` at window position
+    // `pos`.
+    pub fn pin(&mut self, zoom: f64, coords: Vector2<u32>, pos: Vector2<f64>) {
+        self.auto = false;
+        if !self.is_locked() {
+            self.zoom = f64::max(self.min_zoom, zoom);
+        }
+        self.trans = vec2_sub(pos, vec2_scale(vec2_f64(coords), self.zoom));
+        if self.is_locked() {
+            self.clamp_locked();
+        }
+    }
+
+    // Whether the view still fits the whole grid to the window.
+    pub fn is_auto(&self) -> bool {
+        self.auto
+    }
+
+    pub fn placement(&self, coords: Vector2<u32>) -> Placement {
+        let mut fit = self.clone();
+        fit.zoom_to(coords);
+        let offset = vec2_sub(self.trans(coords), fit.trans(coords));
+        Placement {
+            zoom: self.zoom / fit.zoom,
+            offset: vec2_scale(offset, 1.0 / fit.zoom),
+        }
+    }
+
+    // Fit a single grid square to the window, then zoom and pan it like before.
+    pub fn place(&mut self, coords: Vector2<u32>, placement: Placement) {
+        self.zoom_to(coords);
+        let origin = vec2_add(self.trans(coords), vec2_scale(placement.offset, self.zoom));
+        self.zoom *= placement.zoom;
+        self.trans = vec2_sub(origin, vec2_scale(vec2_f64(coords), self.zoom));
+    }
+
     pub fn resize_to(&mut self, win_size: Vector2<u32>) {
+        // A locked grid keeps the row at the top of the window.
+        let row = -self.trans[1] / self.zoom;
         self.win_size = vec2_f64(win_size);
         if self.auto {
             self.reset();
+        } else if let Some(columns) = self.columns {
+            self.zoom = self.win_size[0] / columns as f64;
+            self.min_zoom = self.zoom;
+            self.trans = [0.0, -row * self.zoom];
+            self.clamp_locked();
         }
     }
 
-    pub fn _mouse(&self) -> Vector2<f64> {
+    pub fn win_size(&self) -> Vector2<f64> {
+        self.win_size
+    }
+
+    pub fn mouse(&self) -> Vector2<f64> {
         self.mouse
     }
 
@@ -112,30 +452,40 @@ impl View {
     pub fn trans_by(&mut self, trans: Vector2<f64>) {
         self.auto = false;
         self.trans = vec2_add(self.trans, trans);
+        if self.is_locked() {
+            self.clamp_locked();
+        }
+        self.wrap_trans();
     }
 
     pub fn zoom_by(&mut self, ratio: f64) {
+        if self.is_locked() {
+            return;
+        }
         self.auto = false;
 
-        let zoom = self.zoom;
-        self.zoom = f64::max(self.min_zoom, zoom * ratio);
-
-        let bias = {
-            let grid_pos = vec2_sub(self.mouse, self.trans);
-            let grid_px = vec2_scale(self.grid_size, zoom);
-            vec2_div(grid_pos, grid_px)
-        };
+        // Keep the grid position under the mouse cursor in place.
+        let ratio = f64::max(self.min_zoom, self.zoom * ratio) / self.zoom;
+        let transform = self
+            .transform()
+            .then(Transform2D::scale_about(self.mouse, ratio));
 
-        let trans = {
-            let grid_delta = vec2_scale(self.grid_size, self.zoom - zoom);
-            vec2_mul(grid_delta, bias)
-        };
+        self.zoom *= ratio;
+        self.trans = transform.apply([0.0, 0.0]);
+        self.wrap_trans();
+    }
 
-        self.trans = vec2_sub(self.trans, trans);
+    // Grid coordinates to window coordinates.
+    pub fn transform(&self) -> Transform2D {
+        Transform2D::scale([self.zoom, self.zoom]).then(Transform2D::translate(self.trans))
     }
 
     pub fn trans(&self, image_coords: Vector2<u32>) -> Vector2<f64> {
-        vec2_add(self.trans, vec2_scale(vec2_f64(image_coords), self.zoom))
+        let image_coords = match self.synced {
+            Some(synced) if self.set_of(synced).contains(&image_coords) => synced,
+            _ => image_coords,
+        };
+        self.transform().apply(vec2_f64(image_coords))
     }
 
     pub fn mouse_dist(&self, image_coords: Vector2<u32>) -> Vector2<f64> {
@@ -144,12 +494,78 @@ impl View {
         vec2_sub(mid_coords, self.mouse)
     }
 
+    // Grid coordinates of the square under the mouse cursor.
+    pub fn mouse_coords(&self) -> Option<Vector2<u32>> {
+        let [mut x, mut y] = vec2_scale(vec2_sub(self.mouse, self.trans), 1.0 / self.zoom);
+        let [w, h] = self.grid_size;
+        if self.wrap {
+            x = x.rem_euclid(w);
+            y = y.rem_euclid(h);
+        }
+        if x < 0.0 || y < 0.0 || x >= w || y >= h {
+            return None;
+        }
+        Some([x as u32, y as u32])
+    }
+
+    // Window position of the top left corner of an image drawn in a grid square, and the window
+    // pixels per image pixel. Images are fitted and centered like the thumbnails.
+    pub fn image_trans(&self, coords: Vector2<u32>, img_size: Vector2<u32>) -> (Vector2<f64>, f64) {
+        let img_size = vec2_f64(img_size);
+        let max_dimension = f64::max(img_size[0], img_size[1]);
+        let scale = self.zoom / max_dimension;
+        let gaps = vec2_sub([max_dimension, max_dimension], img_size);
+        let origin = vec2_add(self.trans(coords), vec2_scale(gaps, 0.5 * scale));
+        (origin, scale)
+    }
+
+    // Window position of the center of a grid square.
+    pub fn center(&self, coords: Vector2<u32>) -> Vector2<f64> {
+        let half = self.zoom / 2.0;
+        vec2_add(self.trans(coords), [half, half])
+    }
+
+    // Position of the mouse cursor in image pixels, may be outside the image.
+    pub fn mouse_image_pos(&self, coords: Vector2<u32>, img_size: Vector2<u32>) -> Vector2<f64> {
+        let (origin, scale) = self.image_trans(coords, img_size);
+        let mouse = self.orientation.unmap(self.mouse, self.center(coords));
+        vec2_scale(vec2_sub(mouse, origin), 1.0 / scale)
+    }
+
+    // Image pixel under the mouse cursor.
+    pub fn mouse_pixel(
+        &self,
+        coords: Vector2<u32>,
+        img_size: Vector2<u32>,
+    ) -> Option<Vector2<u32>> {
+        let [x, y] = self.mouse_image_pos(coords, img_size);
+        let [w, h] = vec2_f64(img_size);
+        if x < 0.0 || y < 0.0 || x >= w || y >= h {
+            return None;
+        }
+        Some([x as u32, y as u32])
+    }
+
     pub fn is_visible(&self, min: Vector2<f64>) -> bool {
         let max = vec2_add(min, [self.zoom, self.zoom]);
         let [w, h] = self.win_size;
         (max[0] > 0.0 && min[0] < w) && (max[1] > 0.0 && min[1] < h)
     }
 
+    // Grid squares that may be visible, as min and exclusive max. A little larger than the
+    // squares passing `is_visible`, to cull the rest of the grid before testing each square.
+    pub fn visible_coords(&self) -> [Vector2<u32>; 2] {
+        let [w, h] = self.win_size;
+        let coords = |pos: Vector2<f64>| vec2_scale(vec2_sub(pos, self.trans), 1.0 / self.zoom);
+        let [x_min, y_min] = coords([0.0, 0.0]);
+        let [x_max, y_max] = coords([w, h]);
+        // Casts saturate, squares left of or above the grid start at 0.
+        [
+            vec2_u32([x_min.floor() - 1.0, y_min.floor() - 1.0]),
+            vec2_u32([x_max.ceil() + 1.0, y_max.ceil() + 1.0]),
+        ]
+    }
+
     pub fn visible_ratio(&self, [x_min, y_min]: Vector2<f64>) -> f64 {
         let [x_max, y_max] = vec2_add([x_min, y_min], [self.zoom, self.zoom]);
         let [w, h] = self.win_size;
@@ -158,11 +574,41 @@ impl View {
             f64::min(((y_max / h) - 0.5).abs(), ((y_min / h) - 0.5).abs()),
         ) + 0.5
     }
+
+    // Least and greatest `visible_ratio` of the squares from `min` to exclusive `max`. Along
+    // each axis the ratio falls towards the window center and rises past it, so the extremes
+    // are at the ends and around the square over the center.
+    pub fn visible_ratio_range(&self, [min, max]: [Vector2<u32>; 2]) -> [f64; 2] {
+        let center = vec2_scale(
+            vec2_sub(vec2_scale(self.win_size, 0.5), self.trans),
+            1.0 / self.zoom,
+        );
+        let candidates = |i: usize| {
+            let last = max[i].saturating_sub(1).max(min[i]);
+            let c = (center[i].floor().max(0.0) as u32).clamp(min[i], last);
+            [
+                min[i],
+                last,
+                c.saturating_sub(1).max(min[i]),
+                c,
+                (c + 1).min(last),
+            ]
+        };
+        let (xs, ys) = (candidates(0), candidates(1));
+        let ratios = xs
+            .iter()
+            .flat_map(|&x| ys.iter().map(move |&y| [x, y]))
+            .map(|coords| self.visible_ratio(self.trans(coords)));
+        ratios.fold([f64::INFINITY, f64::NEG_INFINITY], |[lo, hi], r| {
+            [lo.min(r), hi.max(r)]
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::View;
+    use super::{Fit, Level, Orientation, View};
+    use crate::vec::*;
 
     #[test]
     fn is_visible() {
@@ -184,6 +630,277 @@ mod tests {
         assert!(!view.is_visible([0.0, 110.0]));
     }
 
+    #[test]
+    fn visible_ratio_range() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            grid_size: [100.0, 100.0],
+            zoom: 10.0,
+            ..Default::default()
+        };
+        for trans in [[0.0, 0.0], [-35.0, -5.5], [42.0, 17.25], [-990.0, -995.0]] {
+            view.trans = trans;
+            for extents in [[[0, 0], [9, 9]], [[5, 0], [14, 9]], [[90, 90], [100, 100]]] {
+                let [min, max] = extents;
+                let ratios: Vec<f64> = (min[0]..max[0])
+                    .flat_map(|x| (min[1]..max[1]).map(move |y| [x, y]))
+                    .map(|coords| view.visible_ratio(view.trans(coords)))
+                    .collect();
+                let lo = ratios.iter().copied().fold(f64::INFINITY, f64::min);
+                let hi = ratios.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                assert_eq!(view.visible_ratio_range(extents), [lo, hi]);
+            }
+        }
+    }
+
+    #[test]
+    fn visible_coords() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            grid_size: [100.0, 100.0],
+            zoom: 10.0,
+            ..Default::default()
+        };
+        for trans in [[0.0, 0.0], [-35.0, -5.5], [42.0, 17.25], [-990.0, -995.0]] {
+            view.trans = trans;
+            let [min, max] = view.visible_coords();
+            for x in 0..100 {
+                for y in 0..100 {
+                    let inside = min[0] <= x && x < max[0] && min[1] <= y && y < max[1];
+                    if view.is_visible(view.trans([x, y])) {
+                        assert!(inside, "{:?} {:?}", trans, [x, y]);
+                    }
+                }
+            }
+            // Only a square around the window.
+            assert!(max[0] - min[0] <= 200 / 10 + 3 && max[1] - min[1] <= 100 / 10 + 3);
+        }
+    }
+
+    #[test]
+    fn columns() {
+        let mut view = View::new(100);
+        view.resize_to([800, 600]);
+        view.set_columns(Some(4));
+        assert_eq!(view.grid_size, [4.0, 25.0]);
+        assert_eq!(view.zoom, 200.0);
+        assert_eq!(view.trans, [0.0, 0.0]);
+
+        // Only vertical scrolling, within the grid.
+        view.zoom_by(2.0);
+        assert_eq!(view.zoom, 200.0);
+        view.trans_by([50.0, 100.0]);
+        assert_eq!(view.trans, [0.0, 0.0]);
+        view.trans_by([0.0, -1000.0]);
+        assert_eq!(view.trans, [0.0, -1000.0]);
+        view.trans_by([0.0, -10_000.0]);
+        assert_eq!(view.trans, [0.0, 600.0 - 25.0 * 200.0]);
+
+        // Wider windows make larger squares, the top row stays.
+        view.trans = [0.0, -1000.0];
+        view.resize_to([400, 600]);
+        assert_eq!(view.zoom, 100.0);
+        assert_eq!(view.trans, [0.0, -500.0]);
+
+        view.look_at(10.0, Some([0.5, 0.5]));
+        assert_eq!(view.zoom, 100.0);
+        assert_eq!(view.trans, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn sets() {
+        let mut view = View::sets(30, 3);
+        assert_eq!(view.grid_size[0] % 3.0, 0.0);
+        assert_eq!(view.set_of([4, 1]), vec![[3, 1], [4, 1], [5, 1]]);
+        view.set_columns(Some(4));
+        assert_eq!(view.grid_size[0], 6.0);
+
+        // The rest of the set is drawn over the square of the synced image.
+        view.set_synced(Some([4, 1]));
+        assert_eq!(view.trans([3, 1]), view.trans([4, 1]));
+        assert_eq!(view.trans([5, 1]), view.trans([4, 1]));
+        assert_ne!(view.trans([2, 1]), view.trans([4, 1]));
+        assert_eq!(View::new(30).set_of([4, 1]), vec![[4, 1]]);
+    }
+
+    #[test]
+    fn wrap() {
+        let mut view = View {
+            win_size: [100.0, 100.0],
+            grid_size: [4.0, 2.0],
+            zoom: 20.0,
+            min_zoom: 1.0,
+            ..Default::default()
+        };
+        view.set_wrap(true);
+
+        // Panning past the left and top edges brings in the right and bottom ones.
+        view.trans_by([30.0, 10.0]);
+        assert_eq!(view.trans, [-50.0, -30.0]);
+        view.trans_by([-60.0, -20.0]);
+        assert_eq!(view.trans, [-30.0, -10.0]);
+
+        // 80x40 pixels of grid from -30,-10 cover the window in 2 by 3 copies.
+        let tiles = view.tiles();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[0].trans, [-30.0, -10.0]);
+        assert_eq!(tiles[5].trans, [50.0, 70.0]);
+
+        // The copies are hovered as the grid.
+        view.mouse_to([95.0, 95.0]);
+        assert_eq!(view.mouse_coords(), Some([2, 1]));
+        view.set_wrap(false);
+        assert_eq!(view.mouse_coords(), None);
+        assert_eq!(view.tiles().len(), 1);
+    }
+
+    #[test]
+    fn level() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            zoom: 10.0,
+            ..Default::default()
+        };
+        assert_eq!(view.level(), Level::Clusters);
+
+        view.zoom = 50.0;
+        assert_eq!(view.level(), Level::Thumbs);
+
+        view.zoom = 100.0;
+        assert_eq!(view.level(), Level::Full);
+    }
+
+    #[test]
+    fn fit_to() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            grid_size: [20.0, 10.0],
+            ..Default::default()
+        };
+
+        let zoom = |view: &mut View, fit| {
+            view.fit_to([1, 1], [400, 100], fit);
+            view.zoom
+        };
+        assert_eq!(zoom(&mut view, Fit::Contain), 200.0);
+        assert_eq!(zoom(&mut view, Fit::Fill), 400.0);
+        assert_eq!(zoom(&mut view, Fit::Width), 200.0);
+        assert_eq!(zoom(&mut view, Fit::Height), 400.0);
+        assert_eq!(zoom(&mut view, Fit::Actual), 400.0);
+
+        // Centered.
+        let (origin, scale) = view.image_trans([1, 1], [400, 100]);
+        assert_eq!(origin, [-100.0, 0.0]);
+        assert_eq!(scale, 1.0);
+    }
+
+    #[test]
+    fn orientation() {
+        let mut orientation = Orientation::default();
+        orientation.rotate(1);
+        orientation.flip_h();
+        assert_eq!(orientation.size([40, 30]), [30, 40]);
+
+        let center = [100.0, 100.0];
+        let transform = orientation.transform(center);
+        // Right of the center turns down, then stays down.
+        assert_eq!(transform.apply([110.0, 100.0]), [100.0, 110.0]);
+        // Below the center turns left, then flips right.
+        assert_eq!(transform.apply([100.0, 110.0]), [110.0, 100.0]);
+
+        let pos = [123.0, 87.0];
+        assert_eq!(orientation.unmap(transform.apply(pos), center), pos);
+
+        orientation.rotate(-5);
+        assert_eq!(orientation.turns, 0);
+    }
+
+    #[test]
+    fn placement() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            grid_size: [20.0, 10.0],
+            ..Default::default()
+        };
+        view.zoom_to([3, 2]);
+        view.mouse_to([120.0, 40.0]);
+        view.zoom_by(2.0);
+        view.trans_by([5.0, -7.0]);
+        let placement = view.placement([3, 2]);
+
+        // Same crop after the window was resized.
+        let mut other = View {
+            win_size: [400.0, 200.0],
+            grid_size: [20.0, 10.0],
+            ..Default::default()
+        };
+        other.place([3, 2], placement);
+        assert_eq!(other.zoom, view.zoom * 2.0);
+        let [x, y] = vec2_sub(other.trans([3, 2]), vec2_scale(view.trans([3, 2]), 2.0));
+        assert!(x.abs() < 1e-9 && y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn look_at() {
+        let mut view = View::new(100);
+        view.resize_to([200, 100]);
+
+        view.look_at(50.0, Some([3.5, 2.5]));
+        assert_eq!(view.zoom, 50.0);
+        assert_eq!(view.trans([3, 2]), [75.0, 25.0]);
+
+        // Zoom about the middle of the window.
+        view.look_at(25.0, None);
+        assert_eq!(view.trans([3, 2]), [87.5, 37.5]);
+    }
+
+    #[test]
+    fn mouse_coords() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            grid_size: [20.0, 10.0],
+            trans: [10.0, 0.0],
+            zoom: 10.0,
+            ..Default::default()
+        };
+
+        view.mouse_to([5.0, 5.0]);
+        assert_eq!(view.mouse_coords(), None);
+
+        view.mouse_to([15.0, 5.0]);
+        assert_eq!(view.mouse_coords(), Some([0, 0]));
+
+        view.mouse_to([205.0, 99.0]);
+        assert_eq!(view.mouse_coords(), Some([19, 9]));
+
+        view.mouse_to([215.0, 99.0]);
+        assert_eq!(view.mouse_coords(), None);
+    }
+
+    #[test]
+    fn mouse_pixel() {
+        let mut view = View {
+            win_size: [200.0, 100.0],
+            grid_size: [1.0, 1.0],
+            trans: [50.0, 0.0],
+            zoom: 100.0,
+            ..Default::default()
+        };
+
+        // 400x200 image scaled by 1/4 and centered vertically.
+        view.mouse_to([50.0, 10.0]);
+        assert_eq!(view.mouse_pixel([0, 0], [400, 200]), None);
+
+        view.mouse_to([50.0, 25.0]);
+        assert_eq!(view.mouse_pixel([0, 0], [400, 200]), Some([0, 0]));
+
+        view.mouse_to([149.0, 74.0]);
+        assert_eq!(view.mouse_pixel([0, 0], [400, 200]), Some([396, 196]));
+
+        view.mouse_to([150.0, 50.0]);
+        assert_eq!(view.mouse_pixel([0, 0], [400, 200]), None);
+    }
+
     #[test]
     fn visible_ratio() {
         let view = View {
@@ -0,0 +1,212 @@
+// Copyright 2019-2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Watermarks and captions burned into exported images, e.g. for client proof sets.
+
+use ::image::imageops::FilterType;
+use ::image::RgbaImage;
+use rusttype::{point, Font, PositionedGlyph, Scale};
+
+// How much of the image shows through a watermark.
+static WATERMARK_OPACITY: f32 = 0.4;
+
+// Watermarks span this part of the image width.
+static WATERMARK_WIDTH: f32 = 0.6;
+
+// Caption text height relative to the short edge.
+static CAPTION_SIZE: f32 = 0.035;
+
+// Where an image file's caption comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caption {
+    // The file name.
+    Name,
+    // An EXIF field, e.g. ImageDescription or Artist.
+    Exif(String),
+}
+
+impl std::str::FromStr for Caption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("empty caption source".to_owned()),
+            "name" => Ok(Self::Name),
+            tag => Ok(Self::Exif(tag.to_owned())),
+        }
+    }
+}
+
+impl Caption {
+    // The caption of a file, None if its EXIF lacks the field.
+    pub fn text(&self, path: &str, bytes: &[u8]) -> Option<String> {
+        match self {
+            Self::Name => Some(
+                std::path::Path::new(path)
+                    .file_name()?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            Self::Exif(tag) => {
+                let exif = exif::Reader::new()
+                    .read_from_container(&mut std::io::Cursor::new(bytes))
+                    .ok()?;
+                let field = exif.fields().find(|field| {
+                    field.ifd_num == exif::In::PRIMARY && field.tag.to_string() == *tag
+                })?;
+                let text = match &field.value {
+                    exif::Value::Ascii(strings) => strings
+                        .iter()
+                        .map(|s| String::from_utf8_lossy(s).trim().to_owned())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    _ => field.display_value().to_string(),
+                };
+                Some(text).filter(|text| !text.is_empty())
+            }
+        }
+    }
+}
+
+fn blend(image: &mut RgbaImage, x: i32, y: i32, color: [u8; 3], alpha: f32) {
+    if x < 0 || y < 0 || x as u32 >= image.width() || y as u32 >= image.height() {
+        return;
+    }
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    for c in 0..3 {
+        let v = pixel[c] as f32 * (1.0 - alpha) + color[c] as f32 * alpha;
+        pixel[c] = v.round() as u8;
+    }
+}
+
+fn layout<'a>(font: &'a Font, text: &str, height: f32) -> (Vec<PositionedGlyph<'a>>, f32) {
+    let scale = Scale::uniform(height);
+    let ascent = font.v_metrics(scale).ascent;
+    let glyphs: Vec<PositionedGlyph> = font.layout(text, scale, point(0.0, ascent)).collect();
+    let width = glyphs.last().map_or(0.0, |g| {
+        g.position().x + g.unpositioned().h_metrics().advance_width
+    });
+    (glyphs, width)
+}
+
+fn draw_glyphs(
+    image: &mut RgbaImage,
+    glyphs: &[PositionedGlyph],
+    pos: [i32; 2],
+    color: [u8; 3],
+    opacity: f32,
+) {
+    for glyph in glyphs {
+        if let Some(bb) = glyph.pixel_bounding_box() {
+            glyph.draw(|gx, gy, coverage| {
+                let x = pos[0] + bb.min.x + gx as i32;
+                let y = pos[1] + bb.min.y + gy as i32;
+                blend(image, x, y, color, coverage * opacity);
+            });
+        }
+    }
+}
+
+// Large translucent text across the middle of the image.
+pub fn text(image: &mut RgbaImage, font: &Font, text: &str) {
+    let (_, width) = layout(font, text, 100.0);
+    if width <= 0.0 {
+        return;
+    }
+    let height = 100.0 * image.width() as f32 * WATERMARK_WIDTH / width;
+    let (glyphs, width) = layout(font, text, height);
+    let pos = [
+        ((image.width() as f32 - width) / 2.0) as i32,
+        ((image.height() as f32 - height) / 2.0) as i32,
+    ];
+    draw_glyphs(image, &glyphs, pos, [255, 255, 255], WATERMARK_OPACITY);
+}
+
+// A translucent logo across the middle of the image.
+pub fn logo(image: &mut RgbaImage, logo: &RgbaImage) {
+    let w = ((image.width() as f32 * WATERMARK_WIDTH) as u32).max(1);
+    let h = ((logo.height() as u64 * w as u64 / logo.width().max(1) as u64) as u32).max(1);
+    let mut logo = ::image::imageops::resize(logo, w, h, FilterType::Triangle);
+    for pixel in logo.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * WATERMARK_OPACITY).round() as u8;
+    }
+    let x = (image.width() as i64 - w as i64) / 2;
+    let y = (image.height() as i64 - h as i64) / 2;
+    ::image::imageops::overlay(image, &logo, x, y);
+}
+
+// A line of text on a dark band along the bottom of the image.
+pub fn caption(image: &mut RgbaImage, font: &Font, text: &str) {
+    let short = image.width().min(image.height()) as f32;
+    let height = (short * CAPTION_SIZE).max(8.0);
+    let pad = (height / 2.0) as i32;
+    let band = height as i32 + 2 * pad;
+    let top = image.height() as i32 - band;
+    for y in top..image.height() as i32 {
+        for x in 0..image.width() as i32 {
+            blend(image, x, y, [0, 0, 0], 0.6);
+        }
+    }
+    let (glyphs, _) = layout(font, text, height);
+    draw_glyphs(image, &glyphs, [pad, top + pad], [255, 255, 255], 1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::Rgba;
+
+    #[test]
+    fn sources() {
+        assert_eq!("name".parse(), Ok(Caption::Name));
+        assert_eq!(
+            "ImageDescription".parse(),
+            Ok(Caption::Exif("ImageDescription".to_owned()))
+        );
+        assert_eq!(
+            Caption::Name.text("/photos/a.jpg", b""),
+            Some("a.jpg".to_owned())
+        );
+        assert_eq!(Caption::Exif("Artist".to_owned()).text("/a.jpg", b""), None);
+    }
+
+    #[test]
+    fn logo_in_the_middle() {
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        let white = RgbaImage::from_pixel(2, 1, Rgba([255, 255, 255, 255]));
+        logo(&mut image, &white);
+
+        // 6x3 in the middle, at 40%.
+        assert_eq!(image.get_pixel(5, 5)[0], 102);
+        assert_eq!(image.get_pixel(5, 2), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(1, 5), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn caption_band() {
+        // Needs a system font.
+        let font = match crate::overlay::font_path(None).and_then(|path| std::fs::read(path).ok()) {
+            Some(bytes) => Font::try_from_vec(bytes).unwrap(),
+            None => return,
+        };
+        let mut image = RgbaImage::from_pixel(400, 300, Rgba([255, 255, 255, 255]));
+        caption(&mut image, &font, "IMG_0001.jpg");
+
+        // Darkened at the bottom, some of the text is white.
+        assert_eq!(image.get_pixel(399, 299), &Rgba([102, 102, 102, 255]));
+        assert_eq!(image.get_pixel(200, 100), &Rgba([255, 255, 255, 255]));
+        let band = (300 - 30..300).flat_map(|y| (0..200).map(move |x| (x, y)));
+        assert!(band.clone().any(|(x, y)| image.get_pixel(x, y)[0] > 200));
+    }
+}